@@ -0,0 +1,262 @@
+//! CAR file API endpoints
+//!
+//! This module defines the HTTP API routes moving content between IPFS
+//! networks as CAR files: importing a partner's archive and exporting the
+//! DAG of an upload. See [`crate::domain::car`].
+
+use crate::api::with_state;
+use crate::domain::car::{self, CarError};
+use crate::domain::services;
+use crate::state::AppState;
+use log::warn;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::http::{HeaderMap, StatusCode};
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Media type of CAR files
+const CAR_CONTENT_TYPE: &str = "application/vnd.ipld.car";
+
+/// Create CAR routes with the given application state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state holding the IPFS client and the
+///   metadata store
+///
+/// # Returns
+///
+/// Returns a warp filter handling the CAR endpoints.
+///
+/// # Route Details
+///
+/// - **POST** `/car`: imports the CARv1 or CARv2 file in the request body,
+///   pins its roots and records them. The optional `X-Tenant-Id` header
+///   names the tenant the content belongs to. Returns the roots and the size
+///   of the file; `400 Bad Request` if the body exceeds `MAX_FILE_SIZE` or
+///   is not a valid CAR file, and `502 Bad Gateway` if the IPFS node fails.
+/// - **GET** `/files/{cid}.car`: exports the DAG below the CID of an upload
+///   or an imported root as a CARv1 file. Returns `404 Not Found` for CIDs
+///   the service has not stored, and `502 Bad Gateway` if the IPFS node
+///   cannot start the export.
+///
+/// # Examples
+///
+/// ```bash
+/// curl -X POST --data-binary @archive.car \
+///   -H "Content-Type: application/vnd.ipld.car" \
+///   http://localhost:8080/car
+/// curl -o photo.car \
+///   http://localhost:8080/files/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn.car
+/// ```
+///
+/// Expected response of the import:
+///
+/// ```json
+/// {
+///   "roots": ["bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"],
+///   "size": 102400
+/// }
+/// ```
+pub fn car_routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let import = warp::path!("car")
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and(with_state(state.clone()))
+        .then(|headers: HeaderMap, body, state: AppState| async move {
+            let tenant = match services::tenant(&headers) {
+                Ok(tenant) => tenant,
+                Err(e) => return error_reply(CarError::Body(e.to_string())),
+            };
+
+            match car::import(&state, body, tenant).await {
+                Ok(imported) => warp::reply::json(&imported).into_response(),
+                Err(e) => error_reply(e),
+            }
+        });
+
+    let export = warp::path!("files" / String)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(|file: String, state: AppState| async move {
+            let cid = file
+                .strip_suffix(".car")
+                .ok_or_else(warp::reject::not_found)?;
+
+            let reply = match car::export(&state, cid).await {
+                Ok(stream) => {
+                    let mut response = Response::new(Body::wrap_stream(stream));
+                    let headers = response.headers_mut();
+                    headers.insert(CONTENT_TYPE, CAR_CONTENT_TYPE.parse().unwrap());
+                    if let Ok(disposition) = format!("attachment; filename=\"{}\"", file).parse() {
+                        headers.insert(CONTENT_DISPOSITION, disposition);
+                    }
+                    response
+                }
+                Err(e) => error_reply(e),
+            };
+
+            Ok::<_, warp::Rejection>(reply)
+        });
+
+    import.or(export).unify()
+}
+
+fn error_reply(error: CarError) -> Response {
+    let status = match &error {
+        CarError::Body(_) | CarError::Invalid(_) => StatusCode::BAD_REQUEST,
+        CarError::NotFound(_) => StatusCode::NOT_FOUND,
+        CarError::Node(_) => StatusCode::BAD_GATEWAY,
+        CarError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if status.is_server_error() {
+        warn!("CAR request failed: {}", error);
+    }
+
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    warp::reply::with_status(body, status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::domain::metadata::{FileOrigin, FileRecord};
+    use bytes::Bytes;
+    use warp::test::request;
+
+    const ROOT: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+    const ROOT_V0: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+
+    /// Serve a fake RPC API accepting CAR files that start with "CAR" and
+    /// exporting every DAG as "CAR:{cid}"
+    fn mock_node() -> Config {
+        let import = warp::path!("api" / "v0" / "dag" / "import")
+            .and(warp::query::raw())
+            .and(warp::body::bytes())
+            .map(|query: String, body: Bytes| {
+                assert!(query.contains("pin-roots=true"));
+                if !body.windows(4).any(|window| window == b"CAR\x01") {
+                    return warp::reply::with_status(
+                        r#"{"Message":"invalid header: malformed stream"}"#.to_string(),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    );
+                }
+                let line = serde_json::json!({
+                    "Root": { "Cid": { "/": ROOT }, "PinErrorMsg": "" }
+                });
+                warp::reply::with_status(format!("{}\n", line), StatusCode::OK)
+            });
+        let export = warp::path!("api" / "v0" / "dag" / "export")
+            .and(warp::query::<Vec<(String, String)>>())
+            .map(|query: Vec<(String, String)>| format!("CAR:{}", query[0].1));
+
+        let (addr, server) = warp::serve(import.or(export)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut config = Config::default();
+        config.ipfs.api_url = format!("http://{}", addr);
+        config.retry.ipfs.max_attempts = 1;
+        config.upload.max_file_size = 32;
+        config.metadata.dir = std::env::temp_dir()
+            .join(format!("metadata-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+
+        config
+    }
+
+    #[tokio::test]
+    async fn test_import_and_export() {
+        let config = mock_node();
+        let dir = config.metadata.dir.clone();
+        let state = AppState::new(config).await.unwrap();
+        let routes = car_routes(state.clone());
+
+        let response = request()
+            .method("POST")
+            .path("/car")
+            .header("x-tenant-id", "acme")
+            .body("CAR\x01blocks")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["roots"], serde_json::json!([ROOT]));
+        assert_eq!(body["size"], 10);
+
+        let record = state.metadata.file(ROOT).unwrap();
+        assert_eq!(record.origin, FileOrigin::Car);
+        assert_eq!(record.tenant.as_deref(), Some("acme"));
+
+        // The root is found in any encoding
+        let response = request()
+            .path(&format!("/files/{}.car", ROOT_V0))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], CAR_CONTENT_TYPE);
+        assert_eq!(response.body().as_ref(), format!("CAR:{}", ROOT).as_bytes());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_exports_uploads_only() {
+        let config = mock_node();
+        let dir = config.metadata.dir.clone();
+        let state = AppState::new(config).await.unwrap();
+        let routes = car_routes(state.clone());
+
+        let response = request()
+            .path(&format!("/files/{}.car", ROOT_V0))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let record = FileRecord::new(ROOT_V0.to_string(), FileOrigin::Upload);
+        state.metadata.put_file(record).await.unwrap();
+        let response = request()
+            .path(&format!("/files/{}.car", ROOT))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), format!("CAR:{}", ROOT).as_bytes());
+
+        let response = request()
+            .path(&format!("/files/{}", ROOT))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_imports() {
+        let state = AppState::new(mock_node()).await.unwrap();
+        let routes = car_routes(state);
+
+        for (body, tenant) in [
+            ("not a car", "acme"),
+            ("CAR\x01blocks", "../acme"),
+            (
+                "CAR\x01 and far too many blocks for the configured maximum size",
+                "acme",
+            ),
+        ] {
+            let response = request()
+                .method("POST")
+                .path("/car")
+                .header("x-tenant-id", tenant)
+                .body(body)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! IPFS Cluster API endpoints
+//!
+//! This module defines the HTTP API route tracking how far the pin of an
+//! upload has been replicated across an IPFS Cluster. It is only served
+//! when uploads are added through a cluster (`IPFS_CLUSTER_API_URL`).
+
+use crate::api::with_state;
+use crate::infrastructure::ipfs_cluster::{ClusterPinState, ClusterPinStatus};
+use crate::infrastructure::ipfs_rpc::IpfsError;
+use crate::state::AppState;
+use log::warn;
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// Response of the pin status endpoint
+#[derive(Debug, Serialize)]
+pub struct PinStatusResponse {
+    /// Status of the pin on each peer
+    #[serde(flatten)]
+    pub status: ClusterPinStatus,
+    /// Number of peers that have pinned the content
+    pub pinned: usize,
+    /// Whether enough peers have pinned the content to satisfy the
+    /// configured minimum replication factor
+    pub replicated: bool,
+}
+
+impl PinStatusResponse {
+    /// Summarize a pin's status against the minimum replication factor
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Status of the pin on each peer
+    /// * `replication_min` - Configured minimum replication factor; -1 asks
+    ///   for every peer, and unset for at least one
+    pub fn new(status: ClusterPinStatus, replication_min: Option<i32>) -> Self {
+        let pinned = status.count(ClusterPinState::Pinned);
+        let replicated = match replication_min {
+            Some(-1) => pinned == status.peer_map.len(),
+            Some(min) => pinned >= usize::try_from(min).unwrap_or(usize::MAX),
+            None => pinned > 0,
+        };
+
+        Self {
+            status,
+            pinned,
+            replicated,
+        }
+    }
+}
+
+/// Create IPFS Cluster routes with the given application state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state holding the cluster client
+///
+/// # Returns
+///
+/// Returns a warp filter handling the cluster endpoints.
+///
+/// # Route Details
+///
+/// - **Path**: `/cluster/pins/{cid}`
+/// - **Method**: GET
+/// - **Response**: JSON object with the status of the pin on each peer, the
+///   number of peers that have pinned it and whether the minimum
+///   replication factor is met. Returns `404 Not Found` if no cluster is
+///   configured or the cluster has no pin for the CID, and
+///   `502 Bad Gateway` if the cluster cannot be queried.
+///
+/// # Examples
+///
+/// ```bash
+/// curl http://localhost:8080/cluster/pins/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn
+/// ```
+///
+/// Expected response while the second peer is still fetching the content:
+///
+/// ```json
+/// {
+///   "cid": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+///   "name": "photo.jpg",
+///   "peer_map": {
+///     "12D3KooWA...": { "peername": "cluster-0", "status": "pinned" },
+///     "12D3KooWB...": { "peername": "cluster-1", "status": "pinning" }
+///   },
+///   "pinned": 1,
+///   "replicated": false
+/// }
+/// ```
+pub fn cluster_routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("cluster" / "pins" / String)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(|cid: String, state: AppState| async move {
+            let cluster = state
+                .ipfs
+                .cluster
+                .as_ref()
+                .ok_or_else(warp::reject::not_found)?;
+
+            match cluster.status(&cid, state.config.ipfs.timeout()).await {
+                Ok(status) => {
+                    let replication_min = state.config.ipfs.cluster.replication_min;
+                    let response = PinStatusResponse::new(status, replication_min);
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&response),
+                        StatusCode::OK,
+                    ))
+                }
+                // The cluster speaks a newer `http` than warp
+                Err(IpfsError::Api {
+                    status: reqwest::StatusCode::NOT_FOUND,
+                    ..
+                }) => Err(warp::reject::not_found()),
+                Err(e) => {
+                    warn!("Failed to read cluster pin status of {}: {}", cid, e);
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                        StatusCode::BAD_GATEWAY,
+                    ))
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::infrastructure::ipfs_cluster::mock;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_not_found_without_cluster() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let response = request()
+            .path(&format!("/cluster/pins/{}", mock::CID))
+            .reply(&cluster_routes(state))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_pin_status() {
+        let (addr, _queries) = mock::spawn();
+        let mut config = Config::default();
+        config.ipfs.cluster.api_url = Some(format!("http://{}", addr));
+        config.ipfs.cluster.bearer_token = Some(String::from("token"));
+        config.ipfs.cluster.replication_min = Some(1);
+        let state = AppState::new(config).await.unwrap();
+        let routes = cluster_routes(state.clone());
+
+        // Nothing has been added yet
+        let response = request()
+            .path(&format!("/cluster/pins/{}", mock::CID))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let cluster = state.ipfs.cluster.as_ref().unwrap();
+        cluster
+            .add(
+                reqwest::Body::from("hello"),
+                "hello.txt",
+                "hello.txt",
+                &state.config.ipfs.add,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let response = request()
+            .path(&format!("/cluster/pins/{}", mock::CID))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["name"], "hello.txt");
+        assert_eq!(body["pinned"], 1);
+        assert_eq!(body["replicated"], true);
+        assert_eq!(body["peer_map"]["peer-b"]["status"], "pinning");
+    }
+
+    #[test]
+    fn test_replicated() {
+        let status: ClusterPinStatus = serde_json::from_value(serde_json::json!({
+            "cid": mock::CID,
+            "peer_map": {
+                "peer-a": { "status": "pinned" },
+                "peer-b": { "status": "pinning" }
+            }
+        }))
+        .unwrap();
+
+        assert!(PinStatusResponse::new(status.clone(), None).replicated);
+        assert!(PinStatusResponse::new(status.clone(), Some(1)).replicated);
+        assert!(!PinStatusResponse::new(status.clone(), Some(2)).replicated);
+        assert!(!PinStatusResponse::new(status, Some(-1)).replicated);
+    }
+}
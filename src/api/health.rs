@@ -0,0 +1,206 @@
+//! Health API endpoints
+//!
+//! This module defines the liveness and readiness endpoints used by load
+//! balancers and container orchestrators. Readiness reflects the circuit
+//! breakers of the storage backends: the service is not ready while too many
+//! circuits are open to satisfy the write policy.
+
+use crate::api::with_state;
+use crate::config::Backend;
+use crate::infrastructure::circuit_breaker::{CircuitState, CircuitStatus};
+use crate::state::AppState;
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// Overall health of the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Every backend is available
+    Ok,
+    /// Some backends are unavailable, but uploads can still satisfy the
+    /// write policy
+    Degraded,
+    /// Uploads are rejected until more backends recover
+    Unavailable,
+}
+
+/// Response of the readiness endpoint
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    /// Overall health
+    pub status: HealthStatus,
+    /// Circuit state of each backend
+    pub backends: Vec<BackendHealth>,
+}
+
+/// Circuit state of a single backend
+#[derive(Debug, Serialize)]
+pub struct BackendHealth {
+    /// The backend
+    pub backend: Backend,
+    /// Its circuit
+    #[serde(flatten)]
+    pub circuit: CircuitStatus,
+}
+
+/// Create health routes with the given application state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state holding the circuit breakers
+///
+/// # Returns
+///
+/// Returns a warp filter handling the health endpoints.
+///
+/// # Route Details
+///
+/// - **GET `/health/live`**: Always `200 OK` while the process is serving
+///   requests
+/// - **GET `/health/ready`**: `200 OK` with the state of each backend's
+///   circuit, or `503 Service Unavailable` when uploads would be rejected
+///
+/// # Examples
+///
+/// ```bash
+/// curl http://localhost:8080/health/ready
+/// ```
+///
+/// Expected response while IPFS is down under `WRITE_POLICY=any`:
+///
+/// ```json
+/// {
+///   "status": "degraded",
+///   "backends": [
+///     { "backend": "s3", "state": "closed", "consecutive_failures": 0 },
+///     { "backend": "ipfs", "state": "open", "consecutive_failures": 5 }
+///   ]
+/// }
+/// ```
+pub fn health_routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let live = warp::path!("health" / "live")
+        .and(warp::get())
+        .map(|| warp::reply::json(&serde_json::json!({ "status": HealthStatus::Ok })));
+
+    let ready = warp::path!("health" / "ready")
+        .and(warp::get())
+        .and(with_state(state))
+        .map(|state: AppState| {
+            let response = readiness(&state);
+            let status = match response.status {
+                HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+                HealthStatus::Ok | HealthStatus::Degraded => StatusCode::OK,
+            };
+            warp::reply::with_status(warp::reply::json(&response), status)
+        });
+
+    live.or(ready)
+}
+
+/// Assess readiness from the circuit breakers and the write policy
+fn readiness(state: &AppState) -> HealthResponse {
+    let backends: Vec<BackendHealth> = Backend::ALL
+        .into_iter()
+        .map(|backend| BackendHealth {
+            backend,
+            circuit: state.breakers.get(backend).status(),
+        })
+        .collect();
+
+    let status = if backends
+        .iter()
+        .all(|health| health.circuit.state == CircuitState::Closed)
+    {
+        HealthStatus::Ok
+    } else if state
+        .config
+        .upload
+        .write_policy
+        .is_satisfied(&state.breakers.available())
+    {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Unavailable
+    };
+
+    HealthResponse { status, backends }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CircuitBreakerConfig, Config};
+    use crate::infrastructure::circuit_breaker::Breakers;
+    use crate::infrastructure::retry;
+    use warp::test::request;
+
+    async fn state_with_open_ipfs() -> AppState {
+        let mut state = AppState::new(Config::default()).await.unwrap();
+        state.breakers = Breakers::new(&CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_secs: 60,
+        });
+
+        let _ = state
+            .breakers
+            .ipfs
+            .call(async { Err::<(), _>(retry::transient(std::io::Error::other("refused"))) })
+            .await;
+        state
+    }
+
+    #[tokio::test]
+    async fn test_live() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let response = request()
+            .path("/health/live")
+            .reply(&health_routes(state))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ready_when_all_closed() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let response = request()
+            .path("/health/ready")
+            .reply(&health_routes(state))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["backends"][1]["state"], "closed");
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_when_required_circuit_open() {
+        let state = state_with_open_ipfs().await;
+        let response = request()
+            .path("/health/ready")
+            .reply(&health_routes(state))
+            .await;
+
+        // The default write policy requires every backend
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["backends"][1]["backend"], "ipfs");
+        assert_eq!(body["backends"][1]["state"], "open");
+    }
+
+    #[tokio::test]
+    async fn test_degraded_under_best_effort_policy() {
+        let mut state = state_with_open_ipfs().await;
+        let mut config = (*state.config).clone();
+        config.upload.write_policy.quorum = crate::config::Quorum::Any;
+        state.config = std::sync::Arc::new(config);
+
+        assert_eq!(readiness(&state).status, HealthStatus::Degraded);
+    }
+}
@@ -0,0 +1,440 @@
+//! IPNS API endpoints
+//!
+//! This module defines the HTTP API routes giving collections of uploads a
+//! stable IPNS name: creating keys, publishing a directory of uploads under
+//! a key and resolving names. See [`crate::domain::ipns`].
+
+use crate::api::with_state;
+use crate::domain::ipns::{self, IpnsError};
+use crate::domain::metadata::CollectionFile;
+use crate::state::AppState;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+use warp::reply::{Json, WithStatus};
+use warp::Filter;
+
+/// Largest accepted JSON request body in bytes
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Request body of the key creation endpoint
+#[derive(Debug, Deserialize)]
+pub struct CreateKeyRequest {
+    /// Name of the key
+    pub name: String,
+}
+
+/// Request body of the publish endpoint
+#[derive(Debug, Deserialize)]
+pub struct PublishRequest {
+    /// Uploads to place in the collection's directory
+    pub files: Vec<CollectionFile>,
+}
+
+/// Response of the resolve endpoint
+#[derive(Debug, Serialize)]
+pub struct ResolveResponse {
+    /// The resolved name
+    pub name: String,
+    /// Path the name points to, e.g. `/ipfs/{cid}`
+    pub path: String,
+}
+
+/// Create IPNS routes with the given application state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state holding the IPFS client and the
+///   metadata store
+///
+/// # Returns
+///
+/// Returns a warp filter handling the IPNS endpoints.
+///
+/// # Route Details
+///
+/// - **POST** `/ipns/keys` with `{"name": "..."}`: creates a key and returns
+///   its record with `201 Created`, or `409 Conflict` if it exists
+/// - **GET** `/ipns/keys`: lists the keys and what they last published
+/// - **POST** `/ipns/keys/{name}/publish` with
+///   `{"files": [{"cid": "...", "filename": "..."}]}`: publishes a directory
+///   of the files under the key and returns the updated record, or
+///   `404 Not Found` if the key does not exist
+/// - **GET** `/ipns/resolve/{name}`: resolves a key name, IPNS name or
+///   DNSLink domain
+///
+/// Malformed names and collections are answered with `400 Bad Request`,
+/// and failures of the IPFS node with `502 Bad Gateway`.
+///
+/// # Examples
+///
+/// ```bash
+/// curl -X POST http://localhost:8080/ipns/keys -d '{"name": "favorites"}'
+/// curl -X POST http://localhost:8080/ipns/keys/favorites/publish \
+///   -d '{"files": [{"cid": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+///                   "filename": "cat.gif"}]}'
+/// curl http://localhost:8080/ipns/resolve/favorites
+/// ```
+///
+/// Expected response of the publish call:
+///
+/// ```json
+/// {
+///   "name": "favorites",
+///   "id": "k51qzi5uqu5dlvj2baxnqndepeb86cbk3ng7n3i46uzyxzyqj2xjonzllnv0v8",
+///   "cid": "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",
+///   "files": [
+///     { "cid": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", "filename": "cat.gif" }
+///   ],
+///   "created_at": 1760000000,
+///   "published_at": 1760000060
+/// }
+/// ```
+pub fn ipns_routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let create = warp::path!("ipns" / "keys")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .then(|request: CreateKeyRequest, state: AppState| async move {
+            match ipns::create_key(&state, &request.name).await {
+                Ok(record) => reply(&record, StatusCode::CREATED),
+                Err(e) => error_reply(e),
+            }
+        });
+
+    let list = warp::path!("ipns" / "keys")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .map(|state: AppState| reply(&state.metadata.names(), StatusCode::OK));
+
+    let publish = warp::path!("ipns" / "keys" / String / "publish")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .then(
+            |name: String, request: PublishRequest, state: AppState| async move {
+                match ipns::publish(&state, &name, request.files).await {
+                    Ok(record) => reply(&record, StatusCode::OK),
+                    Err(e) => error_reply(e),
+                }
+            },
+        );
+
+    let resolve = warp::path!("ipns" / "resolve" / String)
+        .and(warp::get())
+        .and(with_state(state))
+        .then(|name: String, state: AppState| async move {
+            match ipns::resolve(&state, &name).await {
+                Ok(path) => reply(&ResolveResponse { name, path }, StatusCode::OK),
+                Err(e) => error_reply(e),
+            }
+        });
+
+    create
+        .or(list)
+        .unify()
+        .or(publish)
+        .unify()
+        .or(resolve)
+        .unify()
+}
+
+fn reply<T: Serialize>(body: &T, status: StatusCode) -> WithStatus<Json> {
+    warp::reply::with_status(warp::reply::json(body), status)
+}
+
+fn error_reply(error: IpnsError) -> WithStatus<Json> {
+    let status = match &error {
+        IpnsError::InvalidName(_) | IpnsError::InvalidCollection(_) => StatusCode::BAD_REQUEST,
+        IpnsError::KeyExists(_) => StatusCode::CONFLICT,
+        IpnsError::UnknownKey(_) => StatusCode::NOT_FOUND,
+        IpnsError::Node(_) => StatusCode::BAD_GATEWAY,
+        IpnsError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if status.is_server_error() {
+        warn!("IPNS request failed: {}", error);
+    }
+
+    reply(&serde_json::json!({ "error": error.to_string() }), status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use warp::test::request;
+
+    const CID: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+    const OTHER_CID: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+
+    #[derive(Debug, Default)]
+    struct Node {
+        /// IDs of the keys by name
+        keys: BTreeMap<String, String>,
+        /// CID of each MFS file by path
+        files: BTreeMap<String, String>,
+        /// Pinned directories
+        pins: Vec<String>,
+        /// Path published under each key ID
+        records: BTreeMap<String, String>,
+    }
+
+    /// Serve a fake RPC API whose directory CIDs list their entries
+    fn mock_node() -> (Config, Arc<Mutex<Node>>) {
+        let node = Arc::new(Mutex::new(Node::default()));
+        let ok =
+            |body: serde_json::Value| warp::reply::with_status(body.to_string(), StatusCode::OK);
+        let fail = |message: &str| {
+            warp::reply::with_status(
+                serde_json::json!({ "Message": message }).to_string(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        };
+
+        let routes = {
+            let node = node.clone();
+            warp::path!("api" / "v0" / String / String)
+                .and(warp::query::<Vec<(String, String)>>())
+                .map(
+                    move |group: String, call: String, query: Vec<(String, String)>| {
+                        let param = |name: &str| {
+                            query
+                                .iter()
+                                .find(|(key, _)| key == name)
+                                .map(|(_, value)| value.clone())
+                                .unwrap_or_default()
+                        };
+                        let args: Vec<String> = query
+                            .iter()
+                            .filter(|(key, _)| key == "arg")
+                            .map(|(_, value)| value.clone())
+                            .collect();
+                        let mut node = node.lock().unwrap();
+
+                        match (group.as_str(), call.as_str()) {
+                            ("key", "list") => {
+                                let keys: Vec<_> = node
+                                    .keys
+                                    .iter()
+                                    .map(|(name, id)| serde_json::json!({ "Name": name, "Id": id }))
+                                    .collect();
+                                ok(serde_json::json!({ "Keys": keys }))
+                            }
+                            ("key", "gen") => {
+                                let id = format!("k51-{}", args[0]);
+                                node.keys.insert(args[0].clone(), id.clone());
+                                ok(serde_json::json!({ "Name": args[0], "Id": id }))
+                            }
+                            ("files", "cp") => {
+                                let cid = args[0].trim_start_matches("/ipfs/").to_string();
+                                node.files.insert(args[1].clone(), cid);
+                                ok(serde_json::json!({}))
+                            }
+                            ("files", "stat") => {
+                                let prefix = format!("{}/", args[0]);
+                                let entries: Vec<String> = node
+                                    .files
+                                    .iter()
+                                    .filter_map(|(path, cid)| {
+                                        let name = path.strip_prefix(&prefix)?;
+                                        Some(format!("{}={}", name, cid))
+                                    })
+                                    .collect();
+                                if entries.is_empty() {
+                                    fail("file does not exist")
+                                } else {
+                                    ok(serde_json::json!({ "Hash": entries.join(",") }))
+                                }
+                            }
+                            ("files", "rm") => {
+                                let prefix = format!("{}/", args[0]);
+                                node.files.retain(|path, _| !path.starts_with(&prefix));
+                                ok(serde_json::json!({}))
+                            }
+                            ("pin", "add") => {
+                                node.pins.push(args[0].clone());
+                                ok(serde_json::json!({ "Pins": [args[0]] }))
+                            }
+                            ("pin", "update") if node.pins.contains(&args[0]) => {
+                                node.pins.retain(|pin| *pin != args[0]);
+                                node.pins.push(args[1].clone());
+                                ok(serde_json::json!({ "Pins": args }))
+                            }
+                            ("name", "publish") => {
+                                let id = node.keys.get(&param("key")).cloned();
+                                match id {
+                                    Some(id) if param("lifetime") == "172800s" => {
+                                        node.records.insert(id.clone(), args[0].clone());
+                                        ok(serde_json::json!({ "Name": id, "Value": args[0] }))
+                                    }
+                                    _ => fail("no key by the given name was found"),
+                                }
+                            }
+                            ("name", "resolve") => {
+                                let id = args[0].trim_start_matches("/ipns/");
+                                match node.records.get(id) {
+                                    Some(path) => ok(serde_json::json!({ "Path": path })),
+                                    None => fail("could not resolve name"),
+                                }
+                            }
+                            _ => fail("unexpected call"),
+                        }
+                    },
+                )
+        };
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut config = Config::default();
+        config.ipfs.api_url = format!("http://{}", addr);
+        config.retry.ipfs.max_attempts = 1;
+        config.metadata.dir = std::env::temp_dir()
+            .join(format!("metadata-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+
+        (config, node)
+    }
+
+    async fn post(
+        routes: &(impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + 'static),
+        path: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = request()
+            .method("POST")
+            .path(path)
+            .json(&body)
+            .reply(routes)
+            .await;
+        let body = serde_json::from_slice(response.body()).unwrap_or_default();
+
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn test_create_publish_and_resolve() {
+        let (config, node) = mock_node();
+        let dir = config.metadata.dir.clone();
+        let state = AppState::new(config).await.unwrap();
+        let routes = ipns_routes(state.clone());
+
+        let (status, key) = post(
+            &routes,
+            "/ipns/keys",
+            serde_json::json!({ "name": "faves" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(key["id"], "k51-faves");
+        assert_eq!(key["cid"], serde_json::Value::Null);
+
+        let (status, _) = post(
+            &routes,
+            "/ipns/keys",
+            serde_json::json!({ "name": "faves" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let files = serde_json::json!({ "files": [{ "cid": CID, "filename": "cat.gif" }] });
+        let (status, record) = post(&routes, "/ipns/keys/faves/publish", files).await;
+        assert_eq!(status, StatusCode::OK);
+        let first = format!("cat.gif={}", CID);
+        assert_eq!(record["cid"], first);
+
+        // A new collection replaces the pin of the previous one
+        let files = serde_json::json!({ "files": [
+            { "cid": CID, "filename": "cat.gif" },
+            { "cid": OTHER_CID, "filename": "memes/dog.gif" }
+        ] });
+        let (status, record) = post(&routes, "/ipns/keys/faves/publish", files).await;
+        assert_eq!(status, StatusCode::OK);
+        let second = format!("cat.gif={},memes_dog.gif={}", CID, OTHER_CID);
+        assert_eq!(record["cid"], second);
+        assert_eq!(record["files"][1]["filename"], "memes_dog.gif");
+        {
+            let node = node.lock().unwrap();
+            assert_eq!(node.pins, vec![second.clone()]);
+            // Scratch directories are removed
+            assert!(node.files.is_empty());
+        }
+
+        for name in ["faves", "k51-faves"] {
+            let response = request()
+                .path(&format!("/ipns/resolve/{}", name))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["path"], format!("/ipfs/{}", second));
+        }
+
+        let response = request().path("/ipns/keys").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body[0]["name"], "faves");
+        assert_eq!(body[0]["cid"], second);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_requests() {
+        let (config, _node) = mock_node();
+        let state = AppState::new(config).await.unwrap();
+        let routes = ipns_routes(state);
+
+        for name in ["self", "../faves", ""] {
+            let (status, _) =
+                post(&routes, "/ipns/keys", serde_json::json!({ "name": name })).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", name);
+        }
+
+        let files = serde_json::json!({ "files": [{ "cid": CID, "filename": "cat.gif" }] });
+        let (status, _) = post(&routes, "/ipns/keys/unknown/publish", files).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let response = request()
+            .path("/ipns/resolve/k51-unknown")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_collections() {
+        let (config, _node) = mock_node();
+        let state = AppState::new(config).await.unwrap();
+        let dir = state.config.metadata.dir.clone();
+        let routes = ipns_routes(state);
+        post(
+            &routes,
+            "/ipns/keys",
+            serde_json::json!({ "name": "faves" }),
+        )
+        .await;
+
+        for files in [
+            serde_json::json!([]),
+            serde_json::json!([{ "cid": "not-a-cid", "filename": "cat.gif" }]),
+            serde_json::json!([{ "cid": CID, "filename": ".." }]),
+            serde_json::json!([
+                { "cid": CID, "filename": "cat.gif" },
+                { "cid": OTHER_CID, "filename": "cat.gif" }
+            ]),
+        ] {
+            let body = serde_json::json!({ "files": files });
+            let (status, _) = post(&routes, "/ipns/keys/faves/publish", body.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
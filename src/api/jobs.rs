@@ -0,0 +1,133 @@
+//! Job API endpoints
+//!
+//! This module defines the HTTP API route for polling background upload
+//! jobs, created when a large upload is accepted before it is stored.
+
+use crate::api::with_state;
+use crate::state::AppState;
+use uuid::Uuid;
+use warp::Filter;
+
+/// Create job routes with the given application state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state holding the job store
+///
+/// # Returns
+///
+/// Returns a warp filter handling the job endpoints.
+///
+/// # Route Details
+///
+/// - **Path**: `/jobs/{id}`
+/// - **Method**: GET
+/// - **Response**: JSON object with the job state, the progress on each
+///   backend and, once the job has succeeded, the upload response. Unknown
+///   job IDs return `404 Not Found`.
+///
+/// # Examples
+///
+/// ```bash
+/// curl http://localhost:8080/jobs/5f0c6a0e-8f0b-4d43-a2f1-3c9e2b7d6a10
+/// ```
+///
+/// Expected response while the file is being stored:
+///
+/// ```json
+/// {
+///   "id": "5f0c6a0e-8f0b-4d43-a2f1-3c9e2b7d6a10",
+///   "state": "running",
+///   "filename": "video.mp4",
+///   "key": "uploads/uuid_video.mp4",
+///   "size": 104857600,
+///   "backends": [
+///     { "backend": "s3", "state": "stored" },
+///     { "backend": "ipfs", "state": "uploading" }
+///   ],
+///   "created_at": 1760000000,
+///   "updated_at": 1760000042
+/// }
+/// ```
+pub fn job_routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("jobs" / Uuid)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(|id: Uuid, state: AppState| async move {
+            state
+                .jobs
+                .get(id)
+                .map(|job| warp::reply::json(&job))
+                .ok_or_else(warp::reject::not_found)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::domain::jobs::JobState;
+    use warp::http::StatusCode;
+    use warp::test::request;
+
+    #[tokio::test]
+    async fn test_unknown_job() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let response = request()
+            .path(&format!("/jobs/{}", Uuid::new_v4()))
+            .reply(&job_routes(state))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_job_id() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let response = request()
+            .path("/jobs/not-a-uuid")
+            .reply(&job_routes(state))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_job_status() {
+        let mut config = Config::default();
+        config.jobs.dir = std::env::temp_dir()
+            .join(format!("jobs-{}", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let state = AppState::new(config).await.unwrap();
+
+        let job: crate::domain::jobs::Job = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "state": "failed",
+            "filename": "video.mp4",
+            "key": "uploads/abc_video.mp4",
+            "size": 1024,
+            "backends": [],
+            "error": "Upload error: connection refused",
+            "created_at": 0,
+            "updated_at": 0
+        }))
+        .unwrap();
+        state.jobs.store.insert(job.clone()).await.unwrap();
+
+        let response = request()
+            .path(&format!("/jobs/{}", job.id))
+            .reply(&job_routes(state.clone()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["state"], "failed");
+        assert_eq!(body["filename"], "video.mp4");
+        assert_eq!(state.jobs.get(job.id).unwrap().state, JobState::Failed);
+
+        let _ = tokio::fs::remove_dir_all(&state.config.jobs.dir).await;
+    }
+}
@@ -5,6 +5,37 @@
 //!
 //! # Submodules
 //!
+//! - `car`: Contains the CAR file import and export endpoints
+//! - `cluster`: Contains the IPFS Cluster pin status endpoint
+//! - `health`: Contains the liveness and readiness endpoints
+//! - `ipns`: Contains the IPNS key, publish and resolve endpoints
+//! - `jobs`: Contains the background upload job status endpoint
 //! - `upload`: Contains the file upload endpoint
 
+use crate::state::AppState;
+use warp::Filter;
+
+pub mod car;
+pub mod cluster;
+pub mod health;
+pub mod ipns;
+pub mod jobs;
 pub mod upload;
+
+/// Helper filter to inject the application state into route handlers
+///
+/// This creates a warp filter that clones the state and makes it available
+/// to downstream handlers. Cloning only bumps reference counts.
+///
+/// # Arguments
+///
+/// * `state` - Application state to inject
+///
+/// # Returns
+///
+/// Returns a filter that extracts the application state
+pub(crate) fn with_state(
+    state: AppState,
+) -> impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
@@ -4,19 +4,28 @@
 //! It provides a REST endpoint that accepts multipart form data containing
 //! files to be uploaded to S3 and IPFS.
 
-use crate::config::Config;
-use crate::domain::services::handle_upload;
+use crate::api::with_state;
+use crate::config::IpfsAddOptions;
+use crate::domain::services::{handle_upload, REQUEST_ID_HEADER};
+use crate::state::AppState;
+use uuid::Uuid;
 use warp::Filter;
 
-/// Create upload routes with the given configuration
+/// Maximum accepted length of a client-supplied request ID
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Create upload routes with the given application state
 ///
 /// This function constructs a warp filter that handles file upload requests.
 /// It configures the multipart form parser with the maximum file size from
-/// the configuration and sets up the POST /upload endpoint.
+/// the configuration and sets up the POST /upload endpoint. Request headers
+/// are forwarded to the handler so that W3C `traceparent` context propagates,
+/// and every request is assigned a correlation ID (see [`with_request_id`]).
 ///
 /// # Arguments
 ///
-/// * `config` - Application configuration containing upload settings
+/// * `state` - Shared application state holding the configuration, storage
+///   clients and in-flight upload tracker
 ///
 /// # Returns
 ///
@@ -27,8 +36,15 @@ use warp::Filter;
 /// - **Path**: `/upload`
 /// - **Method**: POST
 /// - **Content-Type**: multipart/form-data
+/// - **Query Parameters**: Optional IPFS add options overriding the
+///   configured ones: `cid-version`, `raw-leaves`, `chunker`, `hash`, `pin`,
+///   `wrap-with-directory` and `cid-base` (see [`IpfsAddOptions`])
+/// - **Headers**: Optional `X-Tenant-Id` naming the tenant the upload
+///   belongs to, which selects its remote pinning account, and optional
+///   `X-Object-Tags` adding tags to the S3 object, as a URL query string
 /// - **Request Body**: Form field named "file" containing the file to upload
-/// - **Response**: JSON object with S3 URL, IPFS hash, filename, and file size
+/// - **Response**: JSON object with S3 URL, IPFS hash, filename, and file size,
+///   plus an `X-Request-Id` header
 ///
 /// # Examples
 ///
@@ -40,6 +56,14 @@ use warp::Filter;
 ///   http://localhost:8080/upload
 /// ```
 ///
+/// Requesting a CIDv1 with raw leaves, encoded in base32:
+///
+/// ```bash
+/// curl -X POST \
+///   -F "file=@/path/to/image.jpg" \
+///   "http://localhost:8080/upload?cid-version=1&raw-leaves=true&cid-base=base32"
+/// ```
+///
 /// Expected response:
 ///
 /// ```json
@@ -55,47 +79,65 @@ use warp::Filter;
 ///
 /// The endpoint will return an error (HTTP 400 or 500) if:
 /// - No file is provided in the request
+/// - The `X-Tenant-Id` header is not a valid tenant ID
+/// - The `X-Object-Tags` header is not a valid set of S3 object tags
+/// - The query parameters are not valid IPFS add options
 /// - The file exceeds the maximum size limit
 /// - The upload to S3 or IPFS fails
 /// - The multipart form data is malformed
 pub fn upload_routes(
-    config: Config,
+    state: AppState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("upload")
         .and(warp::post())
-        .and(warp::multipart::form().max_length(config.upload.max_file_size as u64))
-        .and(with_config(config))
+        .and(warp::multipart::form().max_length(state.config.upload.max_file_size as u64))
+        .and(warp::query::<IpfsAddOptions>())
+        .and(warp::header::headers_cloned())
+        .and(with_request_id())
+        .and(with_state(state))
         .and_then(handle_upload)
 }
 
-/// Helper filter to inject configuration into route handlers
-///
-/// This creates a warp filter that clones the configuration and makes it
-/// available to downstream handlers.
-///
-/// # Arguments
+/// Filter extracting the request correlation ID
 ///
-/// * `config` - Configuration to inject
+/// Uses the client-supplied `X-Request-Id` header when present and
+/// well-formed (at most 128 characters of `[A-Za-z0-9._:-]`), otherwise
+/// generates a new UUID. Restricting the character set keeps untrusted
+/// values from corrupting log lines.
 ///
 /// # Returns
 ///
-/// Returns a filter that extracts the configuration
-fn with_config(
-    config: Config,
-) -> impl Filter<Extract = (Config,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || config.clone())
+/// Returns a filter that extracts the request ID
+fn with_request_id() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::header::optional::<String>(REQUEST_ID_HEADER)
+        .or(warp::any().map(|| None))
+        .unify()
+        .map(|id: Option<String>| {
+            id.filter(|id| is_valid_request_id(id))
+                .unwrap_or_else(|| Uuid::new_v4().to_string())
+        })
+}
+
+/// Check whether a client-supplied request ID is safe to use
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use warp::http::StatusCode;
     use warp::test::request;
 
     #[tokio::test]
     async fn test_upload_route_requires_post() {
-        let config = Config::default();
-        let routes = upload_routes(config);
+        let state = AppState::new(Config::default()).await.unwrap();
+        let routes = upload_routes(state);
 
         // GET request should not match
         let response = request()
@@ -109,8 +151,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_upload_route_path() {
-        let config = Config::default();
-        let routes = upload_routes(config);
+        let state = AppState::new(Config::default()).await.unwrap();
+        let routes = upload_routes(state);
 
         // Wrong path should not match
         let response = request()
@@ -121,4 +163,79 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[tokio::test]
+    async fn test_error_reply_carries_request_id() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let routes = upload_routes(state);
+
+        let body = "--boundary\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n\
+                    hello\r\n\
+                    --boundary--\r\n";
+        let response = request()
+            .method("POST")
+            .path("/upload")
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .header(REQUEST_ID_HEADER, "req-42")
+            .header("x-tenant-id", "not a tenant")
+            .body(body)
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-42");
+    }
+
+    #[tokio::test]
+    async fn test_add_options_from_query() {
+        let options = request()
+            .path("/upload?cid-version=1&raw-leaves=true&chunker=size-1048576&cid-base=base36")
+            .filter(&warp::query::<IpfsAddOptions>())
+            .await
+            .unwrap();
+
+        assert_eq!(options.cid_version, Some(1));
+        assert_eq!(options.raw_leaves, Some(true));
+        assert_eq!(options.chunker.as_deref(), Some("size-1048576"));
+        assert_eq!(options.cid_base, Some(crate::config::CidBase::Base36));
+
+        let options = request()
+            .path("/upload")
+            .filter(&warp::query::<IpfsAddOptions>())
+            .await
+            .unwrap();
+        assert_eq!(options, IpfsAddOptions::default());
+
+        let invalid = request()
+            .path("/upload?cid-version=one")
+            .filter(&warp::query::<IpfsAddOptions>())
+            .await;
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_echoed() {
+        let id = request()
+            .header("x-request-id", "client-123")
+            .filter(&with_request_id())
+            .await
+            .unwrap();
+
+        assert_eq!(id, "client-123");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_generated() {
+        let id = request().filter(&with_request_id()).await.unwrap();
+        assert!(Uuid::parse_str(&id).is_ok());
+
+        // Malformed IDs are replaced rather than logged verbatim
+        let id = request()
+            .header("x-request-id", "bad id with spaces")
+            .filter(&with_request_id())
+            .await
+            .unwrap();
+        assert!(Uuid::parse_str(&id).is_ok());
+    }
 }
@@ -0,0 +1,908 @@
+//! IPFS settings
+//!
+//! The connection to the IPFS node, the options of `ipfs add`, and the
+//! optional IPFS Cluster, MFS placement and IPNS publishing of uploads.
+
+use super::sources::{env_opt, env_or, env_strings, env_var};
+use crate::error::{StorageError, StorageResult};
+use crate::utils::urls;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// IPFS API connection settings
+///
+/// Points the service at the IPFS node storing uploads, which may be a
+/// remote node behind an authenticating proxy. Credentials are never
+/// serialized or logged.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpfsConfig {
+    /// Base URL of the IPFS HTTP API (default: "http://127.0.0.1:5001")
+    pub api_url: String,
+    /// Username for HTTP basic authentication
+    pub username: Option<String>,
+    /// Password for HTTP basic authentication
+    #[serde(skip_serializing, default)]
+    pub password: Option<String>,
+    /// Token for HTTP bearer authentication
+    #[serde(skip_serializing, default)]
+    pub bearer_token: Option<String>,
+    /// Timeout of an IPFS API request in seconds (default: 300)
+    pub timeout_secs: u64,
+    /// Default options of the add call, overridable per upload
+    pub add: IpfsAddOptions,
+    /// IPFS Cluster adding and pinning uploads in place of the node
+    pub cluster: IpfsClusterConfig,
+    /// Placement of uploads in the node's MFS
+    pub mfs: MfsConfig,
+    /// Publishing of IPNS records
+    pub ipns: IpnsConfig,
+    /// What to do when the CID returned for a spooled upload differs from
+    /// the one computed locally (default: warn)
+    pub verify_cid: CidVerification,
+    /// Skip adding spooled uploads whose locally computed CID is already
+    /// recorded and pinned (default: false)
+    pub dedup: bool,
+    /// Gateway URL templates of uploads (`IPFS_GATEWAY_URLS`,
+    /// comma-separated), e.g. `https://ipfs.io/ipfs/{cid}` or, for a
+    /// subdomain gateway, `https://{cid}.ipfs.dweb.link`
+    pub gateways: Vec<String>,
+}
+
+impl IpfsConfig {
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            api_url: env_var("IPFS_API_URL")?.unwrap_or(self.api_url),
+            username: env_var("IPFS_API_USERNAME")?.or(self.username),
+            password: env_var("IPFS_API_PASSWORD")?.or(self.password),
+            bearer_token: env_var("IPFS_API_BEARER_TOKEN")?.or(self.bearer_token),
+            timeout_secs: env_or("IPFS_TIMEOUT_SECS", self.timeout_secs)?,
+            add: IpfsAddOptions {
+                cid_version: env_opt("IPFS_CID_VERSION")?.or(self.add.cid_version),
+                raw_leaves: env_opt("IPFS_RAW_LEAVES")?.or(self.add.raw_leaves),
+                chunker: env_var("IPFS_CHUNKER")?.or(self.add.chunker),
+                hash: env_var("IPFS_HASH")?.or(self.add.hash),
+                pin: env_opt("IPFS_PIN")?.or(self.add.pin),
+                wrap_with_directory: env_opt("IPFS_WRAP_WITH_DIRECTORY")?
+                    .or(self.add.wrap_with_directory),
+                cid_base: env_opt("IPFS_CID_BASE")?.or(self.add.cid_base),
+            },
+            cluster: IpfsClusterConfig {
+                api_url: env_var("IPFS_CLUSTER_API_URL")?.or(self.cluster.api_url),
+                username: env_var("IPFS_CLUSTER_USERNAME")?.or(self.cluster.username),
+                password: env_var("IPFS_CLUSTER_PASSWORD")?.or(self.cluster.password),
+                bearer_token: env_var("IPFS_CLUSTER_BEARER_TOKEN")?.or(self.cluster.bearer_token),
+                replication_min: env_opt("IPFS_CLUSTER_REPLICATION_MIN")?
+                    .or(self.cluster.replication_min),
+                replication_max: env_opt("IPFS_CLUSTER_REPLICATION_MAX")?
+                    .or(self.cluster.replication_max),
+            },
+            mfs: MfsConfig {
+                path: env_var("IPFS_MFS_PATH")?.or(self.mfs.path),
+                snapshot_interval_secs: env_or(
+                    "IPFS_MFS_SNAPSHOT_INTERVAL_SECS",
+                    self.mfs.snapshot_interval_secs,
+                )?,
+            },
+            ipns: IpnsConfig {
+                lifetime_secs: env_or("IPNS_LIFETIME_SECS", self.ipns.lifetime_secs)?,
+                ttl_secs: env_opt("IPNS_TTL_SECS")?.or(self.ipns.ttl_secs),
+            },
+            verify_cid: env_or("IPFS_VERIFY_CID", self.verify_cid)?,
+            dedup: env_or("IPFS_DEDUP", self.dedup)?,
+            gateways: env_strings("IPFS_GATEWAY_URLS", self.gateways)?,
+        })
+    }
+
+    /// Timeout of an IPFS API request
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Validate the IPFS API connection settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if !(self.api_url.starts_with("http://") || self.api_url.starts_with("https://")) {
+            return Err(StorageError::ConfigError(format!(
+                "ipfs.api_url (IPFS_API_URL) must be an http(s) URL: {}",
+                self.api_url
+            )));
+        }
+
+        if self.username.is_some() != self.password.is_some() {
+            return Err(StorageError::ConfigError(
+                "ipfs.username and ipfs.password (IPFS_API_USERNAME and IPFS_API_PASSWORD) \
+                 must be set together"
+                    .to_string(),
+            ));
+        }
+
+        if self.bearer_token.is_some() && self.username.is_some() {
+            return Err(StorageError::ConfigError(
+                "ipfs.bearer_token (IPFS_API_BEARER_TOKEN) cannot be combined with basic \
+                 authentication"
+                    .to_string(),
+            ));
+        }
+
+        if self.timeout_secs == 0 {
+            return Err(StorageError::ConfigError(
+                "ipfs.timeout_secs (IPFS_TIMEOUT_SECS) must be greater than 0".to_string(),
+            ));
+        }
+
+        let invalid = |template: &String| {
+            !template.contains("{cid}") || !urls::is_base_url(&template.replace("{cid}", "cid"))
+        };
+        if let Some((i, template)) = self
+            .gateways
+            .iter()
+            .enumerate()
+            .find(|(_, template)| invalid(template))
+        {
+            return Err(StorageError::ConfigError(format!(
+                "ipfs.gateways[{}] (IPFS_GATEWAY_URLS) must be an http(s) URL containing \
+                 {{cid}}: {}",
+                i, template
+            )));
+        }
+
+        self.check_add(&self.add)
+            .map_err(|e| StorageError::ConfigError(format!("Invalid ipfs.add (IPFS_*): {}", e)))?;
+
+        self.cluster.validate()?;
+        self.mfs.validate()?;
+        self.ipns.validate()
+    }
+
+    /// Check add options against the options and the backend
+    ///
+    /// A cluster always pins what it adds, so `pin=false` is only
+    /// accepted without one.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options of an add, merged with the configured ones
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first invalid option
+    pub fn check_add(&self, options: &IpfsAddOptions) -> Result<(), String> {
+        options.validate()?;
+
+        if self.cluster.is_enabled() && !options.pins() {
+            return Err("pin=false is not supported by IPFS Cluster".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for IpfsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| "<redacted>");
+
+        f.debug_struct("IpfsConfig")
+            .field("api_url", &self.api_url)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("bearer_token", &redacted(&self.bearer_token))
+            .field("timeout_secs", &self.timeout_secs)
+            .field("add", &self.add)
+            .field("cluster", &self.cluster)
+            .field("mfs", &self.mfs)
+            .field("ipns", &self.ipns)
+            .field("verify_cid", &self.verify_cid)
+            .field("dedup", &self.dedup)
+            .field("gateways", &self.gateways)
+            .finish()
+    }
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        Self {
+            api_url: String::from("http://127.0.0.1:5001"),
+            username: None,
+            password: None,
+            bearer_token: None,
+            timeout_secs: 300,
+            add: IpfsAddOptions::default(),
+            cluster: IpfsClusterConfig::default(),
+            mfs: MfsConfig::default(),
+            ipns: IpnsConfig::default(),
+            verify_cid: CidVerification::Warn,
+            dedup: false,
+            gateways: Vec::new(),
+        }
+    }
+}
+
+/// IPFS Cluster settings
+///
+/// When `api_url` is set, uploads are added and pinned through the REST API
+/// of an IPFS Cluster, which allocates the pin to several peers, instead of
+/// through the single node at [`IpfsConfig::api_url`]. Content is still read
+/// from that node, which should be one of the cluster's peers. Credentials
+/// are never serialized or logged.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpfsClusterConfig {
+    /// Base URL of the cluster REST API, e.g. "http://127.0.0.1:9094"
+    /// (default: none, uploads are added to the node)
+    pub api_url: Option<String>,
+    /// Username for HTTP basic authentication
+    pub username: Option<String>,
+    /// Password for HTTP basic authentication
+    #[serde(skip_serializing, default)]
+    pub password: Option<String>,
+    /// Token for HTTP bearer authentication
+    #[serde(skip_serializing, default)]
+    pub bearer_token: Option<String>,
+    /// Minimum number of peers pinning an upload, or -1 for every peer
+    /// (default: the cluster's)
+    pub replication_min: Option<i32>,
+    /// Maximum number of peers pinning an upload, or -1 for every peer
+    /// (default: the cluster's)
+    pub replication_max: Option<i32>,
+}
+
+impl IpfsClusterConfig {
+    /// Whether uploads go through the cluster
+    pub fn is_enabled(&self) -> bool {
+        self.api_url.is_some()
+    }
+
+    /// Check the settings
+    fn validate(&self) -> StorageResult<()> {
+        let Some(api_url) = &self.api_url else {
+            return Ok(());
+        };
+
+        if !(api_url.starts_with("http://") || api_url.starts_with("https://")) {
+            return Err(StorageError::ConfigError(format!(
+                "ipfs.cluster.api_url (IPFS_CLUSTER_API_URL) must be an http(s) URL: {}",
+                api_url
+            )));
+        }
+
+        if self.username.is_some() != self.password.is_some() {
+            return Err(StorageError::ConfigError(
+                "ipfs.cluster.username and ipfs.cluster.password (IPFS_CLUSTER_USERNAME and \
+                 IPFS_CLUSTER_PASSWORD) must be set together"
+                    .to_string(),
+            ));
+        }
+
+        if self.bearer_token.is_some() && self.username.is_some() {
+            return Err(StorageError::ConfigError(
+                "ipfs.cluster.bearer_token (IPFS_CLUSTER_BEARER_TOKEN) cannot be combined \
+                 with basic authentication"
+                    .to_string(),
+            ));
+        }
+
+        for (name, factor) in [
+            (
+                "ipfs.cluster.replication_min (IPFS_CLUSTER_REPLICATION_MIN)",
+                self.replication_min,
+            ),
+            (
+                "ipfs.cluster.replication_max (IPFS_CLUSTER_REPLICATION_MAX)",
+                self.replication_max,
+            ),
+        ] {
+            if matches!(factor, Some(factor) if factor == 0 || factor < -1) {
+                return Err(StorageError::ConfigError(format!(
+                    "{} must be -1 (every peer) or greater than 0",
+                    name
+                )));
+            }
+        }
+
+        let consistent = match (self.replication_min, self.replication_max) {
+            (Some(-1), Some(max)) => max == -1,
+            (Some(min), Some(max)) => max == -1 || min <= max,
+            _ => true,
+        };
+        if !consistent {
+            return Err(StorageError::ConfigError(
+                "ipfs.cluster.replication_min (IPFS_CLUSTER_REPLICATION_MIN) cannot exceed \
+                 ipfs.cluster.replication_max (IPFS_CLUSTER_REPLICATION_MAX)"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for IpfsClusterConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| "<redacted>");
+
+        f.debug_struct("IpfsClusterConfig")
+            .field("api_url", &self.api_url)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("bearer_token", &redacted(&self.bearer_token))
+            .field("replication_min", &self.replication_min)
+            .field("replication_max", &self.replication_max)
+            .finish()
+    }
+}
+
+/// Placement of uploads in the node's MFS
+///
+/// The IPFS node's Mutable File System (MFS) is a directory tree built from
+/// CIDs. When `path` is set, every upload is copied to the path it
+/// describes once stored, so operators can browse uploads by tenant and
+/// date, e.g. with `ipfs files ls /memenow`. The CID of the directory above
+/// the first placeholder, the MFS root, is periodically pinned as a
+/// snapshot.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::MfsConfig;
+///
+/// let mfs = MfsConfig {
+///     path: Some(String::from("/memenow/{tenant}/{yyyy}/{mm}/{filename}")),
+///     ..MfsConfig::default()
+/// };
+/// assert_eq!(mfs.root().as_deref(), Some("/memenow"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MfsConfig {
+    /// Template of the MFS path of an upload, with the placeholders
+    /// `{tenant}`, `{yyyy}`, `{mm}`, `{dd}`, `{filename}` and `{cid}`
+    /// (default: none, uploads are not placed in MFS)
+    pub path: Option<String>,
+    /// Interval between snapshots of the MFS root in seconds, 0 to disable
+    /// them (default: 3600)
+    pub snapshot_interval_secs: u64,
+}
+
+impl MfsConfig {
+    /// Placeholders a path template may contain
+    pub const PLACEHOLDERS: [&'static str; 6] =
+        ["{tenant}", "{yyyy}", "{mm}", "{dd}", "{filename}", "{cid}"];
+
+    /// The MFS root: the directory of the path template above its first
+    /// placeholder
+    ///
+    /// # Returns
+    ///
+    /// Returns the root, or `None` if uploads are not placed in MFS
+    pub fn root(&self) -> Option<String> {
+        let path = self.path.as_deref()?;
+        let fixed = &path[..path.find('{').unwrap_or(path.len())];
+        let root = &fixed[..fixed.rfind('/').unwrap_or(0)];
+
+        Some(if root.is_empty() { "/" } else { root }.to_string())
+    }
+
+    /// Interval between snapshots of the MFS root, if they are taken
+    pub fn snapshot_interval(&self) -> Option<std::time::Duration> {
+        (self.path.is_some() && self.snapshot_interval_secs > 0)
+            .then(|| std::time::Duration::from_secs(self.snapshot_interval_secs))
+    }
+
+    /// Check the path template
+    fn validate(&self) -> StorageResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let invalid = |reason: &str| {
+            Err(StorageError::ConfigError(format!(
+                "Invalid ipfs.mfs.path (IPFS_MFS_PATH) {}: {}",
+                path, reason
+            )))
+        };
+
+        if !path.starts_with('/') {
+            return invalid("must be absolute");
+        }
+
+        let segments: Vec<&str> = path[1..].split('/').collect();
+        if segments
+            .iter()
+            .any(|segment| matches!(*segment, "" | "." | ".."))
+        {
+            return invalid("contains an empty, '.' or '..' segment");
+        }
+
+        let mut rest = path.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return invalid("contains an unterminated placeholder");
+            };
+            let placeholder = &rest[start..start + end + 1];
+            if !Self::PLACEHOLDERS.contains(&placeholder) {
+                return invalid(&format!("unknown placeholder {}", placeholder));
+            }
+            rest = &rest[start + end + 1..];
+        }
+
+        // Uploads with the same name would otherwise share a path
+        let name = segments.last().copied().unwrap_or_default();
+        if !name.contains("{filename}") && !name.contains("{cid}") {
+            return invalid("the last segment must contain {filename} or {cid}");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for MfsConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            snapshot_interval_secs: 3600,
+        }
+    }
+}
+
+/// IPNS publishing settings
+///
+/// Applied to the records published for collections, which point a stable
+/// IPNS name at the latest directory of their uploads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpnsConfig {
+    /// How long a published record stays valid in seconds (default: 172800,
+    /// i.e. 48 hours)
+    pub lifetime_secs: u64,
+    /// How long resolvers may cache a record in seconds (default: unset, the
+    /// node's default)
+    pub ttl_secs: Option<u64>,
+}
+
+impl IpnsConfig {
+    /// How long a published record stays valid
+    pub fn lifetime(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lifetime_secs)
+    }
+
+    /// How long resolvers may cache a record, if set
+    pub fn ttl(&self) -> Option<std::time::Duration> {
+        self.ttl_secs.map(std::time::Duration::from_secs)
+    }
+
+    /// Check the record lifetime
+    fn validate(&self) -> StorageResult<()> {
+        if self.lifetime_secs == 0 {
+            return Err(StorageError::ConfigError(
+                "ipfs.ipns.lifetime_secs (IPNS_LIFETIME_SECS) must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for IpnsConfig {
+    fn default() -> Self {
+        Self {
+            lifetime_secs: 172_800,
+            ttl_secs: None,
+        }
+    }
+}
+
+/// Options of the IPFS add call
+///
+/// Unset options are left to the IPFS node, which by default adds CIDv0
+/// with `sha2-256` and 256 KiB chunks and pins the content. Matching
+/// options yield matching CIDs, so set them to reproduce CIDs computed
+/// elsewhere. The configured options (`IPFS_*`) can be overridden per upload
+/// with query parameters of the same name in kebab case, e.g.
+/// `POST /upload?cid-version=1&raw-leaves=true`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::{CidBase, IpfsAddOptions};
+///
+/// let configured = IpfsAddOptions {
+///     cid_version: Some(1),
+///     raw_leaves: Some(true),
+///     ..IpfsAddOptions::default()
+/// };
+/// let request = IpfsAddOptions {
+///     cid_base: Some(CidBase::Base36),
+///     ..IpfsAddOptions::default()
+/// };
+///
+/// let options = configured.merged(&request);
+/// assert_eq!(options.cid_version, Some(1));
+/// assert_eq!(options.cid_base, Some(CidBase::Base36));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpfsAddOptions {
+    /// CID version, 0 or 1 (`IPFS_CID_VERSION`)
+    #[serde(alias = "cid-version", skip_serializing_if = "Option::is_none")]
+    pub cid_version: Option<u32>,
+    /// Use raw blocks for leaf nodes (`IPFS_RAW_LEAVES`)
+    #[serde(alias = "raw-leaves", skip_serializing_if = "Option::is_none")]
+    pub raw_leaves: Option<bool>,
+    /// Chunking algorithm: `size-{bytes}`, `rabin`, `rabin-{min}-{avg}-{max}`
+    /// or `buzhash` (`IPFS_CHUNKER`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunker: Option<String>,
+    /// Hash function, e.g. `sha2-256` or `blake2b-256` (`IPFS_HASH`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Pin the content when adding it (`IPFS_PIN`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<bool>,
+    /// Wrap the file in a directory, so it is addressed as `{cid}/{filename}`
+    /// (`IPFS_WRAP_WITH_DIRECTORY`)
+    #[serde(alias = "wrap-with-directory", skip_serializing_if = "Option::is_none")]
+    pub wrap_with_directory: Option<bool>,
+    /// Multibase encoding of the returned CID (`IPFS_CID_BASE`). CIDv0 can
+    /// only be written in base58btc, so other encodings return the
+    /// equivalent CIDv1.
+    #[serde(alias = "cid-base", skip_serializing_if = "Option::is_none")]
+    pub cid_base: Option<CidBase>,
+}
+
+impl IpfsAddOptions {
+    /// Apply overrides on top of these options
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - Options taking precedence where they are set
+    pub fn merged(&self, overrides: &Self) -> Self {
+        Self {
+            cid_version: overrides.cid_version.or(self.cid_version),
+            raw_leaves: overrides.raw_leaves.or(self.raw_leaves),
+            chunker: overrides.chunker.clone().or_else(|| self.chunker.clone()),
+            hash: overrides.hash.clone().or_else(|| self.hash.clone()),
+            pin: overrides.pin.or(self.pin),
+            wrap_with_directory: overrides.wrap_with_directory.or(self.wrap_with_directory),
+            cid_base: overrides.cid_base.or(self.cid_base),
+        }
+    }
+
+    /// Whether the content is pinned when added
+    pub fn pins(&self) -> bool {
+        self.pin.unwrap_or(true)
+    }
+
+    /// Whether the file is wrapped in a directory
+    pub fn wraps(&self) -> bool {
+        self.wrap_with_directory.unwrap_or(false)
+    }
+
+    /// Check the options before they are sent to the IPFS node
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first invalid option
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(version) = self.cid_version {
+            if version > 1 {
+                return Err(format!("cid-version must be 0 or 1, got {}", version));
+            }
+        }
+
+        if let Some(chunker) = &self.chunker {
+            if !is_valid_chunker(chunker) {
+                return Err(format!("Invalid chunker: {}", chunker));
+            }
+        }
+
+        if let Some(hash) = &self.hash {
+            let valid = !hash.is_empty()
+                && hash
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                return Err(format!("Invalid hash function: {}", hash));
+            }
+
+            if self.cid_version == Some(0) && hash != "sha2-256" {
+                return Err("CIDv0 only supports the sha2-256 hash function".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check a chunker specification
+fn is_valid_chunker(chunker: &str) -> bool {
+    let sizes = |params: &str, count: usize| {
+        let sizes: Vec<&str> = params.split('-').collect();
+        sizes.len() == count
+            && sizes
+                .iter()
+                .all(|size| size.parse::<u64>().is_ok_and(|size| size > 0))
+    };
+
+    match chunker {
+        "rabin" | "buzhash" => true,
+        _ => {
+            if let Some(size) = chunker.strip_prefix("size-") {
+                sizes(size, 1)
+            } else if let Some(params) = chunker.strip_prefix("rabin-") {
+                sizes(params, 3)
+            } else {
+                false
+            }
+        }
+    }
+}
+
+/// Handling of CIDs that differ from the locally computed one
+///
+/// The CID of a spooled upload is computed while it is spooled, with the
+/// add options of the upload, and compared with the CID the IPFS node
+/// returns. See [`crate::infrastructure::unixfs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CidVerification {
+    /// Do not compute CIDs
+    Off,
+    /// Log a mismatch and flag it in the upload response
+    Warn,
+    /// Fail the upload on a mismatch, rolling back its copies
+    Reject,
+}
+
+impl FromStr for CidVerification {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid IPFS_VERIFY_CID: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Multibase encoding of a CID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CidBase {
+    /// Lowercase hexadecimal
+    Base16,
+    /// Lowercase RFC 4648 base32, the default of CIDv1 and the encoding
+    /// subdomain gateways require
+    Base32,
+    /// Lowercase base36, short enough for IPNS keys in subdomains
+    Base36,
+    /// Bitcoin base58, the only encoding of CIDv0
+    Base58btc,
+    /// RFC 4648 base64 without padding
+    Base64,
+    /// RFC 4648 URL-safe base64 without padding
+    Base64url,
+}
+
+impl std::fmt::Display for CidBase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base16 => write!(f, "base16"),
+            Self::Base32 => write!(f, "base32"),
+            Self::Base36 => write!(f, "base36"),
+            Self::Base58btc => write!(f, "base58btc"),
+            Self::Base64 => write!(f, "base64"),
+            Self::Base64url => write!(f, "base64url"),
+        }
+    }
+}
+
+impl FromStr for CidBase {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "base16" => Ok(Self::Base16),
+            "base32" => Ok(Self::Base32),
+            "base36" => Ok(Self::Base36),
+            "base58btc" => Ok(Self::Base58btc),
+            "base64" => Ok(Self::Base64),
+            "base64url" => Ok(Self::Base64url),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid CID base: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_cid_verification_from_str() {
+        assert_eq!(
+            "Reject".parse::<CidVerification>().unwrap(),
+            CidVerification::Reject
+        );
+        assert_eq!(
+            "off".parse::<CidVerification>().unwrap(),
+            CidVerification::Off
+        );
+        assert!("strict".parse::<CidVerification>().is_err());
+    }
+
+    #[test]
+    fn test_validate_ipfs() {
+        let mut config = Config::default();
+        config.ipfs.api_url = String::from("https://ipfs.example.com:5001");
+        config.ipfs.username = Some(String::from("storage"));
+        assert!(config.validate().is_err());
+
+        config.ipfs.password = Some(String::from("hunter2"));
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.ipfs).contains("hunter2"));
+
+        config.ipfs.bearer_token = Some(String::from("s3cr3t"));
+        assert!(config.validate().is_err());
+
+        config.ipfs.username = None;
+        config.ipfs.password = None;
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.ipfs).contains("s3cr3t"));
+
+        let mut config = Config::default();
+        config.ipfs.api_url = String::from("127.0.0.1:5001");
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.ipfs.timeout_secs = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_ipfs_cluster() {
+        let mut config = Config::default();
+        config.ipfs.cluster.api_url = Some(String::from("http://127.0.0.1:9094"));
+        config.ipfs.cluster.bearer_token = Some(String::from("s3cr3t"));
+        config.ipfs.cluster.replication_min = Some(2);
+        config.ipfs.cluster.replication_max = Some(3);
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.ipfs).contains("s3cr3t"));
+
+        config.ipfs.cluster.replication_max = Some(1);
+        assert!(config.validate().is_err());
+        config.ipfs.cluster.replication_max = Some(-1);
+        assert!(config.validate().is_ok());
+        config.ipfs.cluster.replication_min = Some(-1);
+        assert!(config.validate().is_ok());
+        config.ipfs.cluster.replication_max = Some(3);
+        assert!(config.validate().is_err());
+        config.ipfs.cluster.replication_min = Some(0);
+        assert!(config.validate().is_err());
+
+        // The cluster pins everything it adds
+        let mut config = Config::default();
+        config.ipfs.add.pin = Some(false);
+        assert!(config.validate().is_ok());
+        config.ipfs.cluster.api_url = Some(String::from("http://127.0.0.1:9094"));
+        assert!(config.validate().is_err());
+        assert!(config.ipfs.check_add(&IpfsAddOptions::default()).is_ok());
+
+        let mut config = Config::default();
+        config.ipfs.cluster.api_url = Some(String::from("127.0.0.1:9094"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_mfs() {
+        let mut config = Config::default();
+        config.ipfs.mfs.path = Some(String::from("/memenow/{tenant}/{yyyy}/{mm}/{filename}"));
+        assert!(config.validate().is_ok());
+        assert_eq!(config.ipfs.mfs.root().as_deref(), Some("/memenow"));
+
+        config.ipfs.mfs.path = Some(String::from("/{cid}"));
+        assert!(config.validate().is_ok());
+        assert_eq!(config.ipfs.mfs.root().as_deref(), Some("/"));
+
+        for path in [
+            "memenow/{filename}",
+            "/memenow//{filename}",
+            "/memenow/../{filename}",
+            "/memenow/{user}/{filename}",
+            "/memenow/{filename",
+            "/memenow/{filename}/{yyyy}",
+        ] {
+            config.ipfs.mfs.path = Some(path.to_string());
+            assert!(config.validate().is_err(), "{}", path);
+        }
+
+        config.ipfs.mfs.path = None;
+        assert_eq!(config.ipfs.mfs.root(), None);
+        assert_eq!(config.ipfs.mfs.snapshot_interval(), None);
+    }
+
+    #[test]
+    fn test_validate_ipns() {
+        let mut config = Config::default();
+        assert_eq!(config.ipfs.ipns.lifetime().as_secs(), 172_800);
+        assert_eq!(config.ipfs.ipns.ttl(), None);
+
+        config.ipfs.ipns.lifetime_secs = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_ipfs_add_options_merged() {
+        let configured = IpfsAddOptions {
+            cid_version: Some(1),
+            chunker: Some(String::from("size-1048576")),
+            ..IpfsAddOptions::default()
+        };
+        let request = IpfsAddOptions {
+            cid_version: Some(0),
+            pin: Some(false),
+            ..IpfsAddOptions::default()
+        };
+
+        let options = configured.merged(&request);
+        assert_eq!(options.cid_version, Some(0));
+        assert_eq!(options.chunker.as_deref(), Some("size-1048576"));
+        assert!(!options.pins());
+        assert!(!options.wraps());
+        assert_eq!(configured.merged(&IpfsAddOptions::default()), configured);
+    }
+
+    #[test]
+    fn test_validate_ipfs_add_options() {
+        let valid = ["size-262144", "rabin", "rabin-16-32-64", "buzhash"];
+        for chunker in valid {
+            let options = IpfsAddOptions {
+                chunker: Some(chunker.to_string()),
+                ..IpfsAddOptions::default()
+            };
+            assert!(options.validate().is_ok(), "{}", chunker);
+        }
+
+        let invalid = ["size-0", "size-", "rabin-16-32", "fixed"];
+        for chunker in invalid {
+            let options = IpfsAddOptions {
+                chunker: Some(chunker.to_string()),
+                ..IpfsAddOptions::default()
+            };
+            assert!(options.validate().is_err(), "{}", chunker);
+        }
+
+        let mut config = Config::default();
+        config.ipfs.add.hash = Some(String::from("blake2b-256"));
+        assert!(config.validate().is_ok());
+        config.ipfs.add.cid_version = Some(0);
+        assert!(config.validate().is_err());
+        config.ipfs.add.cid_version = Some(2);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_ipfs_add_options_deserialize() {
+        let options: IpfsAddOptions = serde_json::from_value(serde_json::json!({
+            "cid-version": 1,
+            "raw-leaves": true,
+            "wrap-with-directory": false,
+            "cid-base": "base32"
+        }))
+        .unwrap();
+
+        assert_eq!(options.cid_version, Some(1));
+        assert_eq!(options.raw_leaves, Some(true));
+        assert_eq!(options.wrap_with_directory, Some(false));
+        assert_eq!(options.cid_base, Some(CidBase::Base32));
+        assert_eq!(" Base36".parse::<CidBase>().unwrap(), CidBase::Base36);
+        assert!("base2".parse::<CidBase>().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! Asynchronous upload job settings
+
+use super::sources::{env_opt, env_or, env_var};
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+
+/// Asynchronous upload job settings
+///
+/// Uploads at least `async_threshold` bytes large are accepted as soon as they
+/// are spooled to `dir`, and stored on the backends by a pool of `workers`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Size in bytes from which uploads are processed as background jobs
+    /// (default: unset, every upload is processed synchronously)
+    pub async_threshold: Option<u64>,
+    /// Directory holding job records and spooled files; must survive restarts
+    pub dir: String,
+    /// Number of jobs processed concurrently (default: 4)
+    pub workers: usize,
+}
+
+impl JobsConfig {
+    /// Whether an upload of `size` bytes is processed as a background job
+    pub fn is_async(&self, size: u64) -> bool {
+        self.async_threshold
+            .is_some_and(|threshold| size >= threshold)
+    }
+
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            async_threshold: env_opt("ASYNC_UPLOAD_THRESHOLD")?.or(self.async_threshold),
+            dir: env_var("JOBS_DIR")?.unwrap_or(self.dir),
+            workers: env_or("JOB_WORKERS", self.workers)?,
+        })
+    }
+
+    /// Check the settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.workers == 0 {
+            return Err(StorageError::ConfigError(
+                "jobs.workers (JOB_WORKERS) must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            async_threshold: None,
+            dir: String::from("./jobs"),
+            workers: 4,
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Metadata store settings
+
+use super::sources::env_var;
+use crate::error::StorageResult;
+use serde::{Deserialize, Serialize};
+
+/// Metadata store settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataConfig {
+    /// Directory holding the metadata records; must survive restarts
+    /// (default: "./metadata")
+    pub dir: String,
+}
+
+impl MetadataConfig {
+    /// Override the directory with `METADATA_DIR` if it is set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            dir: env_var("METADATA_DIR")?.unwrap_or(self.dir),
+        })
+    }
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            dir: String::from("./metadata"),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Configuration module for the MemeNow Storage Service
+//!
+//! This module handles loading and validating configuration from an optional
+//! TOML, YAML or JSON file and from environment variables. It provides a
+//! centralized configuration structure for the entire application.
+//!
+//! Settings are layered: built-in defaults, then the config file, then
+//! environment variables, each layer overriding the settings the one before
+//! set. Every variable `NAME` can instead be given as `NAME_FILE`, the path
+//! of a file holding its value, so secrets can be mounted as files.
+//!
+//! # Submodules
+//!
+//! - `sources`: Reading of the config file, command-line arguments and environment variables
+//! - `s3`: Amazon S3 bucket, object and storage settings
+//! - `ipfs`: IPFS node, cluster, MFS, IPNS and add settings
+//! - `pinning`: Remote pinning service settings
+//! - `server`: Network settings of the HTTP server
+//! - `upload`: Upload limits, modes and write policies
+//! - `retry`: Retry policies and circuit breakers of the storage backends
+//! - `jobs`: Asynchronous upload job settings
+//! - `metadata`: Metadata store settings
+//! - `replication`: Background replication settings
+//! - `webhooks`: Webhook notification settings
+//! - `telemetry`: Logging and tracing settings
+
+mod ipfs;
+mod jobs;
+mod metadata;
+mod pinning;
+mod replication;
+mod retry;
+mod s3;
+mod server;
+mod sources;
+mod telemetry;
+mod upload;
+mod webhooks;
+
+pub use ipfs::*;
+pub use jobs::*;
+pub use metadata::*;
+pub use pinning::*;
+pub use replication::*;
+pub use retry::*;
+pub use s3::*;
+pub use server::*;
+pub use sources::config_path_from_args;
+pub use telemetry::*;
+pub use upload::*;
+pub use webhooks::*;
+
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use sources::config_file;
+use std::path::Path;
+
+/// Main configuration structure for the storage service
+///
+/// This structure holds all configuration values needed to run the service,
+/// including AWS S3 settings, server configuration, and upload limits.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::Config;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Config::load(None)?;
+/// println!("Server will run on {}:{}", config.server.host, config.server.port);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default = "Config::unset")]
+pub struct Config {
+    /// AWS S3 configuration
+    pub s3: S3Config,
+    /// Server configuration
+    pub server: ServerConfig,
+    /// Upload configuration
+    pub upload: UploadConfig,
+    /// Tracing configuration
+    pub telemetry: TelemetryConfig,
+    /// Retry policies of the storage backends
+    pub retry: RetryConfig,
+    /// Circuit breaker settings shared by the storage backends
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Asynchronous upload job settings
+    pub jobs: JobsConfig,
+    /// Webhook notification settings
+    pub webhooks: WebhookConfig,
+    /// IPFS API connection settings
+    pub ipfs: IpfsConfig,
+    /// Remote pinning service settings
+    pub pinning: PinningConfig,
+    /// Metadata store settings
+    pub metadata: MetadataConfig,
+    /// Background replication settings
+    pub replication: ReplicationConfig,
+}
+
+impl Config {
+    /// Load configuration from a config file and environment variables
+    ///
+    /// Settings missing from the file keep their defaults, and environment
+    /// variables override the settings of the file. Without a file, the
+    /// configuration comes from environment variables alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the config file, e.g. given with `--config`; if
+    ///   `None`, the file named by `CONFIG_FILE` is read, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` if the file cannot be read or
+    /// parsed, an environment variable is invalid, or no bucket is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use memenow_storage_service::config::Config;
+    /// use std::path::Path;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = Config::load(Some(Path::new("config.toml")))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn load(path: Option<&Path>) -> StorageResult<Self> {
+        // Load .env file if it exists
+        dotenv::dotenv().ok();
+
+        let config = match config_file(path) {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::unset(),
+        }
+        .with_env()?;
+
+        if config.s3.bucket.is_empty() {
+            return Err(StorageError::ConfigError(
+                "S3_BUCKET not set (or s3.bucket in the config file)".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Default settings, without the bucket that must be configured
+    fn unset() -> Self {
+        Self {
+            s3: S3Config::default(),
+            ..Self::default()
+        }
+    }
+
+    /// Override settings with the environment variables that are set
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` if a variable is invalid.
+    fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            s3: self.s3.with_env()?,
+            server: self.server.with_env()?,
+            upload: self.upload.with_env()?,
+            telemetry: self.telemetry.with_env()?,
+            retry: self.retry.with_env()?,
+            circuit_breaker: self.circuit_breaker.with_env()?,
+            jobs: self.jobs.with_env()?,
+            webhooks: self.webhooks.with_env()?,
+            ipfs: self.ipfs.with_env()?,
+            pinning: self.pinning.with_env()?,
+            metadata: self.metadata.with_env()?,
+            replication: self.replication.with_env()?,
+        })
+    }
+
+    /// Validate the configuration
+    ///
+    /// Ensures that all configuration values are valid and within acceptable ranges.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` naming the invalid setting, by
+    /// its path in the config file and its environment variable.
+    pub fn validate(&self) -> StorageResult<()> {
+        self.s3.validate()?;
+        self.server.validate()?;
+        self.upload.validate()?;
+        self.circuit_breaker.validate()?;
+        self.jobs.validate()?;
+
+        // Jobs replay the spooled file, which streaming uploads never write
+        if self.jobs.async_threshold.is_some() && self.upload.mode == UploadMode::Streaming {
+            return Err(StorageError::ConfigError(
+                "jobs.async_threshold (ASYNC_UPLOAD_THRESHOLD) requires upload.mode \
+                 (UPLOAD_MODE) to be spooled"
+                    .to_string(),
+            ));
+        }
+
+        self.retry.validate()?;
+        self.webhooks.validate()?;
+        self.ipfs.validate()?;
+        self.pinning.validate()?;
+        self.telemetry.validate()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            s3: S3Config {
+                bucket: String::from("default-bucket"),
+                ..S3Config::default()
+            },
+            server: ServerConfig::default(),
+            upload: UploadConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            retry: RetryConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            jobs: JobsConfig::default(),
+            webhooks: WebhookConfig::default(),
+            ipfs: IpfsConfig::default(),
+            pinning: PinningConfig::default(),
+            metadata: MetadataConfig::default(),
+            replication: ReplicationConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = Config::default();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.upload.max_file_size, 5_242_880);
+    }
+
+    #[test]
+    fn test_validate_async_jobs() {
+        let mut config = Config::default();
+        config.jobs.async_threshold = Some(1024);
+        assert!(config.validate().is_ok());
+        assert!(!config.jobs.is_async(1023));
+        assert!(config.jobs.is_async(1024));
+
+        // Streaming uploads have no spooled file to hand to a job
+        config.upload.mode = UploadMode::Streaming;
+        assert!(config.validate().is_err());
+
+        config.upload.mode = UploadMode::Spooled;
+        config.jobs.workers = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_valid_config() {
+        let config = Config::default();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validation_errors_name_settings() {
+        let mut config = Config::default();
+        config.s3.storage.tenants.insert(
+            "acme".to_string(),
+            S3Storage {
+                bucket_key: Some(true),
+                ..S3Storage::default()
+            },
+        );
+        let message = config.validate().unwrap_err().to_string();
+        assert!(
+            message.contains("s3.storage.tenants.acme (S3_TENANT_STORAGE)"),
+            "{}",
+            message
+        );
+
+        let mut config = Config::default();
+        config.webhooks.retry.max_attempts = 0;
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("webhooks.retry.max_attempts (WEBHOOK_RETRY_MAX_ATTEMPTS)"));
+
+        let mut config = Config::default();
+        config.s3.access_key_id = Some("AKIAEXAMPLE".to_string());
+        assert!(config.validate().is_err());
+        config.s3.secret_access_key = Some("wJalrXUtnFEMI".to_string());
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.s3).contains("wJalrXUtnFEMI"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Remote pinning service settings
+//!
+//! Uploads can additionally be pinned with a service implementing the IPFS
+//! Pinning Service API, with a token per tenant.
+
+use super::sources::{env_map, env_or, env_strings, env_var};
+use super::{is_valid_tenant, RetryPolicy};
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Remote pinning service settings
+///
+/// Uploads added to IPFS are also pinned with a service implementing the
+/// IPFS Pinning Service API, so they stay available if the service's own
+/// node goes away. Each tenant can use its own access token; uploads of
+/// tenants without one use the default token, and are not pinned remotely
+/// if there is none. Tokens are never serialized or logged.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinningConfig {
+    /// Base URL of the pinning service, e.g. "https://api.example.com/psa"
+    /// (default: none, remote pinning disabled)
+    pub endpoint: Option<String>,
+    /// Access token used for uploads without a tenant-specific token
+    #[serde(skip_serializing, default)]
+    pub token: Option<String>,
+    /// Access tokens by tenant ID
+    #[serde(skip_serializing, default)]
+    pub tenant_tokens: BTreeMap<String, String>,
+    /// Multiaddrs of the IPFS node, sent as pin origins so the service can
+    /// fetch the content directly
+    pub origins: Vec<String>,
+    /// Delay between pin status checks in seconds (default: 10)
+    pub poll_interval_secs: u64,
+    /// How long a pin may stay queued or pinning before it is given up, in
+    /// seconds (default: 3600)
+    pub pin_timeout_secs: u64,
+    /// Timeout of a single pinning service request in seconds (default: 30)
+    pub timeout_secs: u64,
+    /// Retries of failed pinning service requests (`PINNING_RETRY_*`)
+    pub retry: RetryPolicy,
+}
+
+impl PinningConfig {
+    /// Access token of a tenant
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant` - Tenant ID of the upload, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns the tenant's token, or the default token if the tenant has
+    /// none, or `None` if the upload is not to be pinned remotely
+    pub fn token_for(&self, tenant: Option<&str>) -> Option<&str> {
+        tenant
+            .and_then(|tenant| self.tenant_tokens.get(tenant))
+            .or(self.token.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Delay between pin status checks
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_secs)
+    }
+
+    /// How long a pin may take
+    pub fn pin_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.pin_timeout_secs)
+    }
+
+    /// Timeout of a single pinning service request
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            endpoint: env_var("PINNING_SERVICE_ENDPOINT")?.or(self.endpoint),
+            token: env_var("PINNING_SERVICE_TOKEN")?.or(self.token),
+            tenant_tokens: env_map("PINNING_SERVICE_TENANT_TOKENS", self.tenant_tokens)?,
+            origins: env_strings("PINNING_ORIGINS", self.origins)?,
+            poll_interval_secs: env_or("PINNING_POLL_INTERVAL_SECS", self.poll_interval_secs)?,
+            pin_timeout_secs: env_or("PINNING_PIN_TIMEOUT_SECS", self.pin_timeout_secs)?,
+            timeout_secs: env_or("PINNING_TIMEOUT_SECS", self.timeout_secs)?,
+            retry: RetryPolicy::from_env("PINNING", self.retry)?,
+        })
+    }
+
+    /// Check the settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(());
+        };
+
+        if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+            return Err(StorageError::ConfigError(format!(
+                "pinning.endpoint (PINNING_SERVICE_ENDPOINT) must be an http(s) URL: {}",
+                endpoint
+            )));
+        }
+
+        if self.token.is_none() && self.tenant_tokens.is_empty() {
+            return Err(StorageError::ConfigError(
+                "pinning.token or pinning.tenant_tokens (PINNING_SERVICE_TOKEN or \
+                 PINNING_SERVICE_TENANT_TOKENS) must be set when pinning.endpoint is set"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(tenant) = self
+            .tenant_tokens
+            .keys()
+            .find(|tenant| !is_valid_tenant(tenant))
+        {
+            return Err(StorageError::ConfigError(format!(
+                "Invalid tenant ID in pinning.tenant_tokens (PINNING_SERVICE_TENANT_TOKENS): {}",
+                tenant
+            )));
+        }
+
+        for (name, secs) in [
+            (
+                "pinning.poll_interval_secs (PINNING_POLL_INTERVAL_SECS)",
+                self.poll_interval_secs,
+            ),
+            (
+                "pinning.pin_timeout_secs (PINNING_PIN_TIMEOUT_SECS)",
+                self.pin_timeout_secs,
+            ),
+            (
+                "pinning.timeout_secs (PINNING_TIMEOUT_SECS)",
+                self.timeout_secs,
+            ),
+        ] {
+            if secs == 0 {
+                return Err(StorageError::ConfigError(format!(
+                    "{} must be greater than 0",
+                    name
+                )));
+            }
+        }
+
+        self.retry.validate("pinning.retry", "PINNING")
+    }
+}
+
+impl std::fmt::Debug for PinningConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tenants: Vec<&String> = self.tenant_tokens.keys().collect();
+
+        f.debug_struct("PinningConfig")
+            .field("endpoint", &self.endpoint)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("tenant_tokens", &tenants)
+            .field("origins", &self.origins)
+            .field("poll_interval_secs", &self.poll_interval_secs)
+            .field("pin_timeout_secs", &self.pin_timeout_secs)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl Default for PinningConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            token: None,
+            tenant_tokens: BTreeMap::new(),
+            origins: Vec::new(),
+            poll_interval_secs: 10,
+            pin_timeout_secs: 3600,
+            timeout_secs: 30,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_pinning() {
+        let mut config = Config::default();
+        config.pinning.endpoint = Some(String::from("https://pins.example.com/psa"));
+        assert!(config.validate().is_err());
+
+        config.pinning.tenant_tokens =
+            BTreeMap::from([(String::from("acme"), String::from("hunter2"))]);
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.pinning).contains("hunter2"));
+
+        config
+            .pinning
+            .tenant_tokens
+            .insert(String::from("../acme"), String::from("x"));
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.pinning.endpoint = Some(String::from("pins.example.com"));
+        config.pinning.token = Some(String::from("token"));
+        assert!(config.validate().is_err());
+
+        config.pinning.endpoint = Some(String::from("http://127.0.0.1:8000"));
+        config.pinning.poll_interval_secs = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_pinning_token_for() {
+        let mut pinning = PinningConfig {
+            tenant_tokens: BTreeMap::from([(String::from("acme"), String::from("acme-token"))]),
+            ..PinningConfig::default()
+        };
+        assert_eq!(pinning.token_for(Some("acme")), Some("acme-token"));
+        assert_eq!(pinning.token_for(Some("globex")), None);
+        assert_eq!(pinning.token_for(None), None);
+
+        pinning.token = Some(String::from("default-token"));
+        assert_eq!(pinning.token_for(Some("globex")), Some("default-token"));
+        assert_eq!(pinning.token_for(None), Some("default-token"));
+    }
+}
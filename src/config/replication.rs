@@ -0,0 +1,31 @@
+//! Background replication settings
+
+use super::sources::env_var;
+use crate::error::StorageResult;
+use serde::{Deserialize, Serialize};
+
+/// Background replication settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    /// Directory holding the queued replicas; must survive restarts
+    /// (default: "./replication")
+    pub dir: String,
+}
+
+impl ReplicationConfig {
+    /// Override the directory with `REPLICATION_DIR` if it is set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            dir: env_var("REPLICATION_DIR")?.unwrap_or(self.dir),
+        })
+    }
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            dir: String::from("./replication"),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Retry and circuit breaker settings of the storage backends
+
+use super::sources::env_or;
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+
+/// Retry policies of the storage backends
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Policy for Amazon S3 calls (`S3_RETRY_*`)
+    pub s3: RetryPolicy,
+    /// Policy for IPFS daemon calls (`IPFS_RETRY_*`)
+    pub ipfs: RetryPolicy,
+}
+
+impl RetryConfig {
+    /// Override the policies with the `S3_RETRY_*` and `IPFS_RETRY_*`
+    /// environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            s3: RetryPolicy::from_env("S3", self.s3)?,
+            ipfs: RetryPolicy::from_env("IPFS", self.ipfs)?,
+        })
+    }
+
+    /// Check both policies
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        self.s3.validate("retry.s3", "S3")?;
+        self.ipfs.validate("retry.ipfs", "IPFS")
+    }
+}
+
+/// Retry behaviour for calls to a storage backend
+///
+/// Only failures classified as transient are retried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first (default: 3)
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds (default: 100)
+    pub initial_backoff_ms: u64,
+    /// Upper bound of the delay between attempts in milliseconds (default: 5000)
+    pub max_backoff_ms: u64,
+    /// Randomize delays so concurrent retries spread out (default: true)
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Load a retry policy from `{prefix}_RETRY_*` environment variables
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Variable prefix of the backend (e.g., "S3")
+    /// * `defaults` - Values used for unset variables
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` if a variable cannot be parsed.
+    pub(super) fn from_env(prefix: &str, defaults: Self) -> StorageResult<Self> {
+        Ok(Self {
+            max_attempts: env_or(
+                &format!("{}_RETRY_MAX_ATTEMPTS", prefix),
+                defaults.max_attempts,
+            )?,
+            initial_backoff_ms: env_or(
+                &format!("{}_RETRY_INITIAL_BACKOFF_MS", prefix),
+                defaults.initial_backoff_ms,
+            )?,
+            max_backoff_ms: env_or(
+                &format!("{}_RETRY_MAX_BACKOFF_MS", prefix),
+                defaults.max_backoff_ms,
+            )?,
+            jitter: env_or(&format!("{}_RETRY_JITTER", prefix), defaults.jitter)?,
+        })
+    }
+
+    /// Validate the policy
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the policy in the config file (e.g., "retry.s3")
+    /// * `prefix` - Variable prefix of the backend (e.g., "S3")
+    pub(super) fn validate(&self, path: &str, prefix: &str) -> StorageResult<()> {
+        if self.max_attempts == 0 {
+            return Err(StorageError::ConfigError(format!(
+                "{}.max_attempts ({}_RETRY_MAX_ATTEMPTS) must be greater than 0",
+                path, prefix
+            )));
+        }
+
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err(StorageError::ConfigError(format!(
+                "{}.initial_backoff_ms ({}_RETRY_INITIAL_BACKOFF_MS) must not exceed \
+                 {}.max_backoff_ms ({}_RETRY_MAX_BACKOFF_MS)",
+                path, prefix, path, prefix
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 5000,
+            jitter: true,
+        }
+    }
+}
+
+/// Circuit breaker settings
+///
+/// Each backend has its own breaker; these settings apply to all of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive transient failures that open a circuit (default: 5)
+    pub failure_threshold: u32,
+    /// Seconds an open circuit fails fast before probing the backend (default: 30)
+    pub open_secs: u64,
+}
+
+impl CircuitBreakerConfig {
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            failure_threshold: env_or("CIRCUIT_BREAKER_FAILURE_THRESHOLD", self.failure_threshold)?,
+            open_secs: env_or("CIRCUIT_BREAKER_OPEN_SECS", self.open_secs)?,
+        })
+    }
+
+    /// Check the settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.failure_threshold == 0 {
+            return Err(StorageError::ConfigError(
+                "circuit_breaker.failure_threshold (CIRCUIT_BREAKER_FAILURE_THRESHOLD) must be \
+                 greater than 0"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_secs: 30,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_retry_policy() {
+        let mut config = Config::default();
+        config.retry.s3.max_attempts = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.retry.ipfs.initial_backoff_ms = 10_000;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_zero_failure_threshold() {
+        let mut config = Config::default();
+        config.circuit_breaker.failure_threshold = 0;
+        assert!(config.validate().is_err());
+    }
+}
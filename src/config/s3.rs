@@ -0,0 +1,662 @@
+//! Amazon S3 settings
+//!
+//! The bucket and credentials, the headers, metadata and tags given to
+//! uploaded objects, and their storage class and server-side encryption,
+//! optionally per tenant.
+
+use super::is_valid_tenant;
+use super::sources::{env_json, env_map, env_opt, env_or, env_strings, env_var};
+use crate::error::{StorageError, StorageResult};
+use crate::utils::urls;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// AWS S3 configuration
+///
+/// Contains all settings required to interact with Amazon S3,
+/// including bucket name, key prefix, and region information. Credentials
+/// are never serialized or logged.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// The S3 bucket name where files will be uploaded
+    pub bucket: String,
+    /// The key prefix for uploaded files (e.g., "uploads/")
+    pub key_prefix: String,
+    /// AWS region (e.g., "us-east-1")
+    pub region: String,
+    /// Access key ID (`AWS_ACCESS_KEY_ID`, default: the AWS SDK's
+    /// credential chain, e.g. a profile or instance role)
+    pub access_key_id: Option<String>,
+    /// Secret access key (`AWS_SECRET_ACCESS_KEY`)
+    #[serde(skip_serializing)]
+    pub secret_access_key: Option<String>,
+    /// Session token of temporary credentials (`AWS_SESSION_TOKEN`)
+    #[serde(skip_serializing)]
+    pub session_token: Option<String>,
+    /// Base URL of an S3-compatible store such as MinIO, Ceph or
+    /// Cloudflare R2 (`S3_ENDPOINT_URL`, default: AWS)
+    pub endpoint_url: Option<String>,
+    /// Address buckets by path rather than by subdomain
+    /// (`S3_FORCE_PATH_STYLE`, default: false)
+    pub force_path_style: bool,
+    /// Base URLs of CDNs or custom domains serving the bucket, such as a
+    /// CloudFront distribution (`S3_CDN_BASE_URLS`, comma-separated)
+    pub cdn_base_urls: Vec<String>,
+    /// Headers, metadata and tags of uploaded objects
+    pub object: S3ObjectConfig,
+    /// Storage class and server-side encryption of uploaded objects
+    pub storage: S3StorageConfig,
+}
+
+impl S3Config {
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            bucket: env_var("S3_BUCKET")?.unwrap_or(self.bucket),
+            key_prefix: env_var("S3_KEY")?.unwrap_or(self.key_prefix),
+            region: env_var("AWS_REGION")?.unwrap_or(self.region),
+            access_key_id: env_var("AWS_ACCESS_KEY_ID")?.or(self.access_key_id),
+            secret_access_key: env_var("AWS_SECRET_ACCESS_KEY")?.or(self.secret_access_key),
+            session_token: env_var("AWS_SESSION_TOKEN")?.or(self.session_token),
+            endpoint_url: env_var("S3_ENDPOINT_URL")?.or(self.endpoint_url),
+            force_path_style: env_or("S3_FORCE_PATH_STYLE", self.force_path_style)?,
+            cdn_base_urls: env_strings("S3_CDN_BASE_URLS", self.cdn_base_urls)?,
+            object: S3ObjectConfig {
+                cache_control: env_var("S3_CACHE_CONTROL")?.or(self.object.cache_control),
+                content_disposition: env_or(
+                    "S3_CONTENT_DISPOSITION",
+                    self.object.content_disposition,
+                )?,
+                tags: env_map("S3_TAGS", self.object.tags)?,
+                rules: env_json("S3_OBJECT_RULES")?.unwrap_or(self.object.rules),
+            },
+            storage: S3StorageConfig {
+                default: S3Storage {
+                    storage_class: env_var("S3_STORAGE_CLASS")?
+                        .or(self.storage.default.storage_class),
+                    encryption: env_opt("S3_SSE")?.or(self.storage.default.encryption),
+                    kms_key_id: env_var("S3_SSE_KMS_KEY_ID")?.or(self.storage.default.kms_key_id),
+                    bucket_key: env_opt("S3_BUCKET_KEY_ENABLED")?
+                        .or(self.storage.default.bucket_key),
+                    customer_key: env_var("S3_SSE_C_KEY")?.or(self.storage.default.customer_key),
+                },
+                tenants: env_json("S3_TENANT_STORAGE")?.unwrap_or(self.storage.tenants),
+            },
+        })
+    }
+
+    /// Check the bucket, credentials, endpoint, object and storage settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.bucket.is_empty() {
+            return Err(StorageError::ConfigError(
+                "s3.bucket (S3_BUCKET) cannot be empty".to_string(),
+            ));
+        }
+
+        if self.access_key_id.is_some() != self.secret_access_key.is_some() {
+            return Err(StorageError::ConfigError(
+                "s3.access_key_id and s3.secret_access_key (AWS_ACCESS_KEY_ID and \
+                 AWS_SECRET_ACCESS_KEY) must be set together"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(endpoint) = self
+            .endpoint_url
+            .as_ref()
+            .filter(|url| !urls::is_base_url(url))
+        {
+            return Err(StorageError::ConfigError(format!(
+                "s3.endpoint_url (S3_ENDPOINT_URL) must be an http(s) URL without query: {}",
+                endpoint
+            )));
+        }
+        if let Some((i, base)) = self
+            .cdn_base_urls
+            .iter()
+            .enumerate()
+            .find(|(_, url)| !urls::is_base_url(url))
+        {
+            return Err(StorageError::ConfigError(format!(
+                "s3.cdn_base_urls[{}] (S3_CDN_BASE_URLS) must be an http(s) URL without \
+                 query: {}",
+                i, base
+            )));
+        }
+
+        self.object.validate()?;
+        self.storage.validate()
+    }
+}
+
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| "<redacted>");
+
+        f.debug_struct("S3Config")
+            .field("bucket", &self.bucket)
+            .field("key_prefix", &self.key_prefix)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &redacted(&self.secret_access_key))
+            .field("session_token", &redacted(&self.session_token))
+            .field("endpoint_url", &self.endpoint_url)
+            .field("force_path_style", &self.force_path_style)
+            .field("cdn_base_urls", &self.cdn_base_urls)
+            .field("object", &self.object)
+            .field("storage", &self.storage)
+            .finish()
+    }
+}
+
+/// Settings without a bucket, which must be configured
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            key_prefix: String::from("uploads"),
+            region: String::from("us-east-1"),
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            endpoint_url: None,
+            force_path_style: false,
+            cdn_base_urls: Vec::new(),
+            object: S3ObjectConfig::default(),
+            storage: S3StorageConfig::default(),
+        }
+    }
+}
+
+/// Maximum number of tags of an S3 object
+pub const MAX_OBJECT_TAGS: usize = 10;
+
+/// Headers and tags of uploaded S3 objects
+///
+/// Every object gets a `Content-Type` (declared with the file or guessed
+/// from its extension), a `Content-Disposition` naming the original
+/// filename, and the tags below. The first rule matching the content type
+/// overrides the defaults and adds its tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3ObjectConfig {
+    /// `Cache-Control` of every object (`S3_CACHE_CONTROL`, default: none)
+    pub cache_control: Option<String>,
+    /// Disposition of every object (`S3_CONTENT_DISPOSITION`, default:
+    /// inline)
+    pub content_disposition: Disposition,
+    /// Tags of every object (`S3_TAGS`, e.g. "env=prod,team=media")
+    pub tags: BTreeMap<String, String>,
+    /// Rules by content type (`S3_OBJECT_RULES`, a JSON array)
+    pub rules: Vec<S3ObjectRule>,
+}
+
+impl S3ObjectConfig {
+    /// The first rule matching a content type
+    pub fn rule(&self, content_type: &str) -> Option<&S3ObjectRule> {
+        self.rules.iter().find(|rule| rule.matches(content_type))
+    }
+
+    /// Check the tags and rules
+    fn validate(&self) -> StorageResult<()> {
+        let invalid =
+            |name: &str, e: String| StorageError::ConfigError(format!("Invalid {}: {}", name, e));
+
+        check_tags(&self.tags).map_err(|e| invalid("s3.object.tags (S3_TAGS)", e))?;
+        for (i, rule) in self.rules.iter().enumerate() {
+            let name = format!("s3.object.rules[{}] (S3_OBJECT_RULES)", i);
+            let valid = rule.content_type == "*"
+                || rule
+                    .content_type
+                    .split_once('/')
+                    .is_some_and(|(kind, subtype)| {
+                        !kind.is_empty() && !subtype.is_empty() && kind != "*"
+                    });
+            if !valid {
+                return Err(invalid(&name, "expected a media type".to_string()));
+            }
+
+            let mut tags = self.tags.clone();
+            tags.extend(rule.tags.clone());
+            check_tags(&tags).map_err(|e| invalid(&name, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Object settings for files of a content type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3ObjectRule {
+    /// Media type the rule applies to: "image/png", "image/*" or "*"
+    pub content_type: String,
+    /// `Cache-Control` of matching objects
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    /// Disposition of matching objects
+    #[serde(default)]
+    pub content_disposition: Option<Disposition>,
+    /// Tags added to matching objects
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl S3ObjectRule {
+    /// Whether the rule applies to a content type; parameters such as
+    /// `charset` are ignored
+    pub fn matches(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match self.content_type.strip_suffix("/*") {
+            _ if self.content_type == "*" => true,
+            Some(kind) => essence
+                .split_once('/')
+                .is_some_and(|(other, _)| other.eq_ignore_ascii_case(kind)),
+            None => essence.eq_ignore_ascii_case(&self.content_type),
+        }
+    }
+}
+
+/// `Content-Disposition` type of S3 objects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    /// Display the object in the browser, naming the original filename
+    #[default]
+    Inline,
+    /// Download the object under its original filename
+    Attachment,
+    /// Send no `Content-Disposition`
+    Off,
+}
+
+impl FromStr for Disposition {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "inline" => Ok(Self::Inline),
+            "attachment" => Ok(Self::Attachment),
+            "off" => Ok(Self::Off),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid S3_CONTENT_DISPOSITION: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Check a set of S3 object tags
+///
+/// An object has at most 10 tags. Keys are 1 to 128 characters and values
+/// at most 256, made of letters, digits, spaces and `+ - = . _ : / @`; keys
+/// starting with `aws:` are reserved.
+///
+/// # Errors
+///
+/// Returns a description of the first invalid tag
+pub fn check_tags(tags: &BTreeMap<String, String>) -> Result<(), String> {
+    if tags.len() > MAX_OBJECT_TAGS {
+        return Err(format!(
+            "at most {} tags are allowed, got {}",
+            MAX_OBJECT_TAGS,
+            tags.len()
+        ));
+    }
+
+    let allowed = |text: &str| {
+        text.chars().all(|c| {
+            c.is_alphanumeric() || matches!(c, ' ' | '+' | '-' | '=' | '.' | '_' | ':' | '/' | '@')
+        })
+    };
+    for (key, value) in tags {
+        let key_len = key.chars().count();
+        if key_len == 0 || key_len > 128 || !allowed(key) || key.starts_with("aws:") {
+            return Err(format!("invalid tag key {:?}", key));
+        }
+        if value.chars().count() > 256 || !allowed(value) {
+            return Err(format!("invalid value of tag {}", key));
+        }
+    }
+
+    Ok(())
+}
+
+/// Storage class and server-side encryption of uploaded S3 objects
+///
+/// The `S3_*` settings apply to every object; a tenant listed in
+/// `S3_TENANT_STORAGE` overrides the settings it sets. Customer-provided
+/// keys are never serialized or logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3StorageConfig {
+    /// Settings of every object
+    pub default: S3Storage,
+    /// Overrides by tenant ID (`S3_TENANT_STORAGE`, a JSON object)
+    pub tenants: BTreeMap<String, S3Storage>,
+}
+
+impl S3StorageConfig {
+    /// Settings of a tenant's objects
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant` - Tenant ID of the upload, if any
+    pub fn for_tenant(&self, tenant: Option<&str>) -> S3Storage {
+        match tenant.and_then(|tenant| self.tenants.get(tenant)) {
+            Some(overrides) => self.default.merged(overrides),
+            None => self.default.clone(),
+        }
+    }
+
+    /// Check the settings of every object and of each tenant
+    fn validate(&self) -> StorageResult<()> {
+        self.default.validate().map_err(|e| {
+            StorageError::ConfigError(format!(
+                "Invalid s3.storage.default (S3_STORAGE_CLASS, S3_SSE*): {}",
+                e
+            ))
+        })?;
+
+        for (tenant, overrides) in &self.tenants {
+            if !is_valid_tenant(tenant) {
+                return Err(StorageError::ConfigError(format!(
+                    "Invalid tenant ID in s3.storage.tenants (S3_TENANT_STORAGE): {}",
+                    tenant
+                )));
+            }
+            self.default.merged(overrides).validate().map_err(|e| {
+                StorageError::ConfigError(format!(
+                    "Invalid s3.storage.tenants.{} (S3_TENANT_STORAGE): {}",
+                    tenant, e
+                ))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Storage class and server-side encryption of an S3 object
+///
+/// Unset fields leave the choice to the bucket's defaults. Returned in
+/// upload responses, without the customer-provided key.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3Storage {
+    /// Storage class, e.g. `STANDARD_IA` or `INTELLIGENT_TIERING`
+    /// (`S3_STORAGE_CLASS`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    /// Server-side encryption (`S3_SSE`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// KMS key ID, ARN or alias of SSE-KMS encryption
+    /// (`S3_SSE_KMS_KEY_ID`, default: the AWS managed key)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kms_key_id: Option<String>,
+    /// Use an S3 Bucket Key for SSE-KMS encryption, cutting KMS requests
+    /// (`S3_BUCKET_KEY_ENABLED`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_key: Option<bool>,
+    /// Base64-encoded 256-bit key of SSE-C encryption (`S3_SSE_C_KEY`)
+    #[serde(skip_serializing)]
+    pub customer_key: Option<String>,
+}
+
+impl S3Storage {
+    /// Apply overrides on top of these settings
+    ///
+    /// Overrides selecting a different encryption do not inherit the key
+    /// settings of the one they replace.
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - Settings taking precedence where they are set
+    pub fn merged(&self, overrides: &Self) -> Self {
+        let base = match overrides.encryption {
+            Some(encryption) if Some(encryption) != self.encryption => Self {
+                storage_class: self.storage_class.clone(),
+                ..Self::default()
+            },
+            _ => self.clone(),
+        };
+
+        Self {
+            storage_class: overrides.storage_class.clone().or(base.storage_class),
+            encryption: overrides.encryption.or(base.encryption),
+            kms_key_id: overrides.kms_key_id.clone().or(base.kms_key_id),
+            bucket_key: overrides.bucket_key.or(base.bucket_key),
+            customer_key: overrides.customer_key.clone().or(base.customer_key),
+        }
+    }
+
+    /// Decode the customer-provided key of SSE-C encryption
+    ///
+    /// # Returns
+    ///
+    /// Returns the 32 bytes of the key, or `None` if objects are not
+    /// encrypted with a customer-provided key or the key is malformed
+    pub fn customer_key_bytes(&self) -> Option<Vec<u8>> {
+        use base64::Engine;
+
+        if self.encryption != Some(Encryption::SseC) {
+            return None;
+        }
+        let key = self.customer_key.as_ref()?;
+        base64::engine::general_purpose::STANDARD
+            .decode(key)
+            .ok()
+            .filter(|key| key.len() == 32)
+    }
+
+    /// Check the settings
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first invalid setting
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(class) = &self.storage_class {
+            let known = aws_sdk_s3::types::StorageClass::values();
+            if !known.contains(&class.as_str()) {
+                return Err(format!(
+                    "unknown storage class {}, expected one of {}",
+                    class,
+                    known.join(", ")
+                ));
+            }
+        }
+
+        let kms = self.encryption == Some(Encryption::SseKms);
+        if self.kms_key_id.is_some() && !kms {
+            return Err("a KMS key ID requires sse-kms encryption".to_string());
+        }
+        if self.bucket_key.is_some() && !kms {
+            return Err("a bucket key requires sse-kms encryption".to_string());
+        }
+
+        match (self.encryption, &self.customer_key) {
+            (Some(Encryption::SseC), None) => {
+                Err("sse-c encryption requires a customer-provided key".to_string())
+            }
+            (Some(Encryption::SseC), Some(_)) if self.customer_key_bytes().is_none() => {
+                Err("the customer-provided key must be 32 bytes, base64 encoded".to_string())
+            }
+            (_, Some(_)) if self.encryption != Some(Encryption::SseC) => {
+                Err("a customer-provided key requires sse-c encryption".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for S3Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Storage")
+            .field("storage_class", &self.storage_class)
+            .field("encryption", &self.encryption)
+            .field("kms_key_id", &self.kms_key_id)
+            .field("bucket_key", &self.bucket_key)
+            .field(
+                "customer_key",
+                &self.customer_key.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// Server-side encryption of S3 objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encryption {
+    /// Keys managed by S3 (`AES256`)
+    SseS3,
+    /// Keys managed by AWS KMS (`aws:kms`)
+    SseKms,
+    /// A customer-provided key sent with every request (SSE-C)
+    SseC,
+}
+
+impl FromStr for Encryption {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sse-s3" | "aes256" => Ok(Self::SseS3),
+            "sse-kms" | "aws:kms" => Ok(Self::SseKms),
+            "sse-c" => Ok(Self::SseC),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid S3_SSE: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_empty_bucket() {
+        let mut config = Config::default();
+        config.s3.bucket = String::new();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_s3_endpoint_validation() {
+        let mut config = Config::default();
+        for endpoint in [
+            "http://localhost:9000",
+            "https://acct.r2.cloudflarestorage.com/",
+        ] {
+            config.s3.endpoint_url = Some(endpoint.to_string());
+            assert!(config.validate().is_ok(), "{}", endpoint);
+        }
+        for endpoint in [
+            "localhost:9000",
+            "https://",
+            "http://minio?x=1",
+            "ftp://minio",
+        ] {
+            config.s3.endpoint_url = Some(endpoint.to_string());
+            assert!(config.validate().is_err(), "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn test_s3_storage_for_tenant() {
+        let mut config = Config::default();
+        config.s3.storage.default = S3Storage {
+            storage_class: Some("INTELLIGENT_TIERING".to_string()),
+            encryption: Some(Encryption::SseKms),
+            kms_key_id: Some("alias/uploads".to_string()),
+            ..S3Storage::default()
+        };
+        config.s3.storage.tenants.insert(
+            "acme".to_string(),
+            S3Storage {
+                kms_key_id: Some("alias/acme".to_string()),
+                bucket_key: Some(true),
+                ..S3Storage::default()
+            },
+        );
+        assert!(config.validate().is_ok());
+
+        let acme = config.s3.storage.for_tenant(Some("acme"));
+        assert_eq!(acme.storage_class.as_deref(), Some("INTELLIGENT_TIERING"));
+        assert_eq!(acme.kms_key_id.as_deref(), Some("alias/acme"));
+        assert_eq!(acme.bucket_key, Some(true));
+        assert_eq!(
+            config.s3.storage.for_tenant(Some("other")),
+            config.s3.storage.default
+        );
+
+        // A tenant switching to SSE-C drops the default KMS key ID
+        config.s3.storage.tenants.insert(
+            "globex".to_string(),
+            S3Storage {
+                encryption: Some(Encryption::SseC),
+                customer_key: Some("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+                ..S3Storage::default()
+            },
+        );
+        assert!(config.validate().is_ok());
+        let globex = config.s3.storage.for_tenant(Some("globex"));
+        assert_eq!(globex.storage_class.as_deref(), Some("INTELLIGENT_TIERING"));
+        assert_eq!(globex.kms_key_id, None);
+
+        config.s3.storage.tenants.insert(
+            "initech".to_string(),
+            S3Storage {
+                encryption: Some(Encryption::SseC),
+                ..S3Storage::default()
+            },
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_s3_storage_validate() {
+        let valid = S3Storage {
+            encryption: Some(Encryption::SseC),
+            customer_key: Some("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+            ..S3Storage::default()
+        };
+        assert!(valid.validate().is_ok());
+        assert!(!format!("{:?}", valid).contains("MDEy"));
+
+        for invalid in [
+            S3Storage {
+                storage_class: Some("COLD".to_string()),
+                ..S3Storage::default()
+            },
+            S3Storage {
+                kms_key_id: Some("alias/uploads".to_string()),
+                ..S3Storage::default()
+            },
+            S3Storage {
+                encryption: Some(Encryption::SseS3),
+                bucket_key: Some(true),
+                ..S3Storage::default()
+            },
+            S3Storage {
+                customer_key: Some("c2hvcnQ=".to_string()),
+                ..valid.clone()
+            },
+            S3Storage {
+                encryption: Some(Encryption::SseKms),
+                ..valid.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+
+        assert_eq!("aws:kms".parse::<Encryption>().unwrap(), Encryption::SseKms);
+        assert_eq!("SSE-S3".parse::<Encryption>().unwrap(), Encryption::SseS3);
+        assert!("kms".parse::<Encryption>().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! HTTP server settings
+
+use super::sources::{env_or, env_var};
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+
+/// Server configuration
+///
+/// Defines the server's network settings including host and port.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The host address to bind to (e.g., "0.0.0.0")
+    pub host: String,
+    /// The port number to listen on (e.g., 8080)
+    pub port: u16,
+    /// Seconds to wait for in-flight uploads after a shutdown signal (default: 30)
+    pub shutdown_timeout_secs: u64,
+}
+
+impl ServerConfig {
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            host: env_var("SERVER_HOST")?.unwrap_or(self.host),
+            port: env_or("SERVER_PORT", self.port)?,
+            shutdown_timeout_secs: env_or("SHUTDOWN_TIMEOUT_SECS", self.shutdown_timeout_secs)?,
+        })
+    }
+
+    /// Check the settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.port == 0 {
+            return Err(StorageError::ConfigError(
+                "server.port (SERVER_PORT) must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("0.0.0.0"),
+            port: 8080,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_zero_port() {
+        let mut config = Config::default();
+        config.server.port = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! Sources of configuration settings
+//!
+//! Settings are read from a TOML, YAML or JSON config file, named on the
+//! command line or by `CONFIG_FILE`, and from environment variables. Each
+//! section of [`Config`] overrides its settings with the variables it reads
+//! through the helpers here.
+
+use super::Config;
+use crate::error::{StorageError, StorageResult};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+impl Config {
+    /// Read configuration from a TOML, YAML or JSON file
+    ///
+    /// The format is chosen by the file's extension. Sections mirror the
+    /// fields of [`Config`], e.g. `[s3.storage.tenants.acme]` in TOML, and
+    /// settings missing from the file keep their defaults. Unknown settings
+    /// are rejected, so a misspelt one is not silently ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` naming the path of the first
+    /// invalid or unknown setting, e.g. `server.port`
+    pub fn from_file(path: &Path) -> StorageResult<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            StorageError::ConfigError(format!("Cannot read config file {}: {}", path.display(), e))
+        })?;
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => parse_file(toml::Deserializer::new(&contents)),
+            "yaml" | "yml" => parse_file(serde_yaml::Deserializer::from_str(&contents)),
+            "json" => parse_file(&mut serde_json::Deserializer::from_str(&contents)),
+            _ => Err("unsupported format, expected .toml, .yaml, .yml or .json".to_string()),
+        }
+        .map_err(|e| {
+            StorageError::ConfigError(format!("Invalid config file {}: {}", path.display(), e))
+        })
+    }
+}
+
+/// Read the config file path from command-line arguments
+///
+/// Accepts `--config <path>` and `--config=<path>`.
+///
+/// # Errors
+///
+/// Returns a `StorageError::ConfigError` on an unknown argument or a
+/// missing path
+pub fn config_path_from_args(
+    args: impl IntoIterator<Item = String>,
+) -> StorageResult<Option<PathBuf>> {
+    let mut args = args.into_iter();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let value = args
+                .next()
+                .ok_or_else(|| StorageError::ConfigError("--config requires a path".to_string()))?;
+            path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(PathBuf::from(value));
+        } else {
+            return Err(StorageError::ConfigError(format!(
+                "Unknown argument: {}",
+                arg
+            )));
+        }
+    }
+
+    Ok(path)
+}
+
+/// Path of the config file: the one given, or the one named by
+/// `CONFIG_FILE`
+pub(super) fn config_file(path: Option<&Path>) -> Option<PathBuf> {
+    path.map(Path::to_path_buf)
+        .or_else(|| env::var_os("CONFIG_FILE").map(PathBuf::from))
+}
+
+/// Deserialize a config file, rejecting unknown settings
+///
+/// # Errors
+///
+/// Returns a description of the first error, prefixed with the path of
+/// the setting it concerns
+fn parse_file<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Config, String> {
+    let mut unknown = Vec::new();
+    let mut track = |path: serde_ignored::Path| unknown.push(path.to_string());
+    let deserializer = serde_ignored::Deserializer::new(deserializer, &mut track);
+
+    // YAML errors already start with the path
+    let config: Config = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let (path, message) = (e.path().to_string(), e.inner().to_string());
+        if path == "." || message.starts_with(&format!("{}:", path)) {
+            message
+        } else {
+            format!("{}: {}", path, message)
+        }
+    })?;
+
+    match unknown.first() {
+        Some(path) => Err(format!("{}: unknown setting", path)),
+        None => Ok(config),
+    }
+}
+
+/// Read an optional environment variable, or the file named by `{name}_FILE`
+///
+/// Files let secrets mounted by Docker or Kubernetes be used without
+/// exposing them in the environment. A trailing newline is stripped.
+///
+/// # Errors
+///
+/// Returns a `StorageError::ConfigError` if both variables are set or the
+/// file cannot be read.
+pub(super) fn env_var(name: &str) -> StorageResult<Option<String>> {
+    let file_var = format!("{}_FILE", name);
+    match (env::var(name), env::var(&file_var)) {
+        (Ok(_), Ok(_)) => Err(StorageError::ConfigError(format!(
+            "{} and {} cannot both be set",
+            name, file_var
+        ))),
+        (Ok(value), Err(_)) => Ok(Some(value)),
+        (Err(_), Ok(path)) => fs::read_to_string(&path)
+            .map(|value| Some(value.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|e| {
+                StorageError::ConfigError(format!("Cannot read {} {}: {}", file_var, path, e))
+            }),
+        (Err(_), Err(_)) => Ok(None),
+    }
+}
+
+/// Parse a comma-separated list from an optional environment variable
+pub(super) fn env_list<T>(name: &str, default: Vec<T>) -> StorageResult<Vec<T>>
+where
+    T: FromStr<Err = StorageError>,
+{
+    match env_var(name)? {
+        Some(value) => value
+            .split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(str::parse)
+            .collect(),
+        None => Ok(default),
+    }
+}
+
+/// Read a comma-separated list of strings from an optional environment
+/// variable, falling back to a default
+pub(super) fn env_strings(name: &str, default: Vec<String>) -> StorageResult<Vec<String>> {
+    Ok(match env_var(name)? {
+        Some(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+        None => default,
+    })
+}
+
+/// Parse a comma-separated list of `key=value` pairs from an optional
+/// environment variable, falling back to a default
+pub(super) fn env_map(
+    name: &str,
+    default: BTreeMap<String, String>,
+) -> StorageResult<BTreeMap<String, String>> {
+    let Some(value) = env_var(name)? else {
+        return Ok(default);
+    };
+
+    value
+        .split(',')
+        .filter(|item| !item.trim().is_empty())
+        .map(|item| match item.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(StorageError::ConfigError(format!(
+                "Invalid {}: expected key=value, got {}",
+                name,
+                item.split('=').next().unwrap_or_default()
+            ))),
+        })
+        .collect()
+}
+
+/// Parse an optional environment variable holding JSON
+///
+/// Errors name the path of the offending value, e.g. `[0].content_type`.
+pub(super) fn env_json<T: serde::de::DeserializeOwned>(name: &str) -> StorageResult<Option<T>> {
+    match env_var(name)? {
+        Some(value) => {
+            serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&value))
+                .map(Some)
+                .map_err(|e| StorageError::ConfigError(format!("Invalid {}: {}", name, e)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Parse an optional environment variable that has no default
+pub(super) fn env_opt<T>(name: &str) -> StorageResult<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env_var(name)? {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| StorageError::ConfigError(format!("Invalid {}: {}", name, e))),
+        None => Ok(None),
+    }
+}
+
+/// Parse an optional environment variable, falling back to a default
+pub(super) fn env_or<T>(name: &str, default: T) -> StorageResult<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env_var(name)? {
+        Some(value) => value
+            .parse()
+            .map_err(|e| StorageError::ConfigError(format!("Invalid {}: {}", name, e))),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Quorum, TelemetryConfig};
+
+    /// Write a config file to a fresh temporary path
+    fn config_file_with(extension: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "config-{}-file.{}",
+            uuid::Uuid::new_v4(),
+            extension
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_config_from_toml_file() {
+        let path = config_file_with(
+            "toml",
+            r#"
+            [s3]
+            bucket = "media"
+            cdn_base_urls = ["https://cdn.example.com"]
+
+            [s3.storage.tenants.acme]
+            encryption = "sse-kms"
+            kms_key_id = "alias/acme"
+
+            [server]
+            port = 9000
+
+            [upload.write_policy]
+            quorum = "any"
+            required = ["s3"]
+
+            [ipfs.add]
+            cid_version = 1
+            "#,
+        );
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.s3.bucket, "media");
+        assert_eq!(config.s3.key_prefix, "uploads");
+        assert_eq!(config.s3.cdn_base_urls, ["https://cdn.example.com"]);
+        let acme = config.s3.storage.for_tenant(Some("acme"));
+        assert_eq!(acme.kms_key_id.as_deref(), Some("alias/acme"));
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.upload.write_policy.quorum, Quorum::Any);
+        assert_eq!(config.ipfs.add.cid_version, Some(1));
+        assert_eq!(config.ipfs.timeout_secs, 300);
+        assert!(config.validate().is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_example_config_file() {
+        let example = include_str!("../../config.example.toml");
+        let mut config = parse_file(toml::Deserializer::new(example)).unwrap();
+        config.pinning.token = Some("token".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.s3.object.rules.len(), 1);
+    }
+
+    #[test]
+    fn test_config_from_yaml_and_json_files() {
+        let path = config_file_with(
+            "yaml",
+            "s3:\n  bucket: media\npinning:\n  endpoint: https://psa.example.com\n  \
+             tenant_tokens:\n    acme: secret\n",
+        );
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.s3.bucket, "media");
+        assert_eq!(config.pinning.token_for(Some("acme")), Some("secret"));
+        fs::remove_file(path).unwrap();
+
+        let path = config_file_with("json", r#"{"jobs": {"workers": 8}}"#);
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.jobs.workers, 8);
+        assert!(config.s3.bucket.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_config_file_errors() {
+        let error = |extension: &str, contents: &str| {
+            let path = config_file_with(extension, contents);
+            let error = Config::from_file(&path).unwrap_err().to_string();
+            fs::remove_file(path).unwrap();
+            error
+        };
+
+        let message = error("toml", "[ipfs.add]\ncid_verison = 1\n");
+        assert!(
+            message.contains("ipfs.add.cid_verison: unknown setting"),
+            "{}",
+            message
+        );
+
+        let message = error("yaml", "server:\n  port: eighty\n");
+        assert!(
+            message.contains("file.yaml: server.port: invalid type"),
+            "{}",
+            message
+        );
+
+        let message = error("toml", "[server]\nport = \"eighty\"\n");
+        assert!(message.contains("server.port: "), "{}", message);
+
+        let message = error(
+            "json",
+            r#"{"s3": {"storage": {"default": {"encryption": "rot13"}}}}"#,
+        );
+        assert!(
+            message.contains("s3.storage.default.encryption"),
+            "{}",
+            message
+        );
+
+        let message = error("ini", "bucket = media");
+        assert!(message.contains("unsupported format"), "{}", message);
+
+        let missing = Config::from_file(Path::new("/nonexistent/config.toml"));
+        assert!(missing.is_err());
+        assert!(TelemetryConfig::load(Some(Path::new("/nonexistent/config.toml"))).is_err());
+    }
+
+    #[test]
+    fn test_config_path_from_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(config_path_from_args(args(&[])).unwrap(), None);
+        assert_eq!(
+            config_path_from_args(args(&["--config", "config.toml"])).unwrap(),
+            Some(PathBuf::from("config.toml"))
+        );
+        assert_eq!(
+            config_path_from_args(args(&["--config=/etc/memenow.yaml"])).unwrap(),
+            Some(PathBuf::from("/etc/memenow.yaml"))
+        );
+        assert!(config_path_from_args(args(&["--config"])).is_err());
+        assert!(config_path_from_args(args(&["--verbose"])).is_err());
+    }
+
+    #[test]
+    fn test_env_var_from_file() {
+        let path = config_file_with("secret", "hunter2\n");
+        env::set_var("MEMENOW_TEST_SECRET_FILE", &path);
+        assert_eq!(
+            env_var("MEMENOW_TEST_SECRET").unwrap().as_deref(),
+            Some("hunter2")
+        );
+
+        env::set_var("MEMENOW_TEST_SECRET", "s3cr3t");
+        assert!(env_var("MEMENOW_TEST_SECRET").is_err());
+        env::remove_var("MEMENOW_TEST_SECRET");
+
+        fs::remove_file(&path).unwrap();
+        assert!(env_var("MEMENOW_TEST_SECRET").is_err());
+        env::remove_var("MEMENOW_TEST_SECRET_FILE");
+        assert_eq!(env_var("MEMENOW_TEST_SECRET").unwrap(), None);
+    }
+}
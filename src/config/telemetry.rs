@@ -0,0 +1,183 @@
+//! Logging and tracing settings
+
+use super::sources::{config_file, env_var};
+use super::Config;
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Logging and tracing configuration
+///
+/// Controls the log output format and where the spans produced by the upload
+/// pipeline are exported. Tracing variable names follow the OpenTelemetry SDK
+/// conventions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// Log line format (default: text)
+    pub log_format: LogFormat,
+    /// Span exporter to use (default: none)
+    pub exporter: TraceExporter,
+    /// Base URL of the OTLP/HTTP collector (e.g., "http://localhost:4318")
+    pub otlp_endpoint: String,
+    /// Service name reported with every span
+    pub service_name: String,
+}
+
+impl TelemetryConfig {
+    /// Load logging and tracing configuration from the config file and
+    /// environment variables
+    ///
+    /// This is separate from [`Config::load`] so that logging can be
+    /// initialized before the rest of the configuration is loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the config file, as given to [`Config::load`]
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::ConfigError` if the config file cannot be
+    /// read or is invalid, or if `LOG_FORMAT` or `OTEL_TRACES_EXPORTER`
+    /// holds an unknown value.
+    pub fn load(path: Option<&Path>) -> StorageResult<Self> {
+        dotenv::dotenv().ok();
+
+        let telemetry = match config_file(path) {
+            Some(path) => Config::from_file(&path)?.telemetry,
+            None => Self::default(),
+        };
+
+        telemetry.with_env()
+    }
+
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            log_format: env_var("LOG_FORMAT")?
+                .map(|format| format.parse())
+                .transpose()?
+                .unwrap_or(self.log_format),
+            exporter: env_var("OTEL_TRACES_EXPORTER")?
+                .map(|exporter| exporter.parse())
+                .transpose()?
+                .unwrap_or(self.exporter),
+            otlp_endpoint: env_var("OTEL_EXPORTER_OTLP_ENDPOINT")?.unwrap_or(self.otlp_endpoint),
+            service_name: env_var("OTEL_SERVICE_NAME")?.unwrap_or(self.service_name),
+        })
+    }
+
+    /// Check the settings
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.exporter == TraceExporter::Otlp
+            && !(self.otlp_endpoint.starts_with("http://")
+                || self.otlp_endpoint.starts_with("https://"))
+        {
+            return Err(StorageError::ConfigError(
+                "telemetry.otlp_endpoint (OTEL_EXPORTER_OTLP_ENDPOINT) must be an http(s) URL"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::Text,
+            exporter: TraceExporter::None,
+            otlp_endpoint: String::from("http://localhost:4318"),
+            service_name: String::from("memenow-storage-service"),
+        }
+    }
+}
+
+/// Log output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines with span context
+    Text,
+    /// One JSON object per line, including the fields of all enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid LOG_FORMAT: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Span exporter selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceExporter {
+    /// Tracing is disabled
+    None,
+    /// Export spans to an OTLP collector over HTTP
+    Otlp,
+    /// Print spans to stdout (useful for local debugging)
+    Stdout,
+}
+
+impl FromStr for TraceExporter {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "otlp" => Ok(Self::Otlp),
+            "stdout" | "console" => Ok(Self::Stdout),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid OTEL_TRACES_EXPORTER: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_invalid_otlp_endpoint() {
+        let mut config = Config::default();
+        config.telemetry.exporter = TraceExporter::Otlp;
+        config.telemetry.otlp_endpoint = String::from("localhost:4318");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_trace_exporter_from_str() {
+        assert_eq!(
+            "OTLP".parse::<TraceExporter>().unwrap(),
+            TraceExporter::Otlp
+        );
+        assert_eq!(
+            "console".parse::<TraceExporter>().unwrap(),
+            TraceExporter::Stdout
+        );
+        assert!("jaeger".parse::<TraceExporter>().is_err());
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
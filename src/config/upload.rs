@@ -0,0 +1,334 @@
+//! Upload settings
+//!
+//! Size limits, the spooled and streaming upload modes, and the write
+//! policy deciding which backends must store a file.
+
+use super::sources::{env_list, env_or, env_var};
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Check whether a tenant ID is well formed
+///
+/// Tenant IDs are 1 to 64 ASCII letters, digits, `-` and `_`, so they are
+/// safe to use in logs, paths and keys.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::is_valid_tenant;
+///
+/// assert!(is_valid_tenant("acme-prod"));
+/// assert!(!is_valid_tenant("../acme"));
+/// ```
+pub fn is_valid_tenant(tenant: &str) -> bool {
+    (1..=64).contains(&tenant.len())
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+/// Upload configuration
+///
+/// Controls upload behavior such as file size limits, temporary storage paths
+/// and whether uploads are spooled to disk or streamed to the backends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// Maximum file size in bytes (default: 5MB)
+    pub max_file_size: usize,
+    /// Directory for temporary file storage
+    pub temp_dir: String,
+    /// How uploads are handed to the backends (default: spooled)
+    pub mode: UploadMode,
+    /// Number of chunks buffered per backend in streaming mode (default: 16)
+    pub stream_buffer_chunks: usize,
+    /// Undo the uploads that succeeded when another backend fails, so no
+    /// orphaned S3 object or pin is left behind (default: true)
+    pub rollback_on_partial_failure: bool,
+    /// Which backends must store a file for the upload to succeed
+    /// (default: all)
+    pub write_policy: WritePolicy,
+}
+
+impl UploadConfig {
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            max_file_size: env_or("MAX_FILE_SIZE", self.max_file_size)?,
+            temp_dir: env_var("TEMP_DIR")?.unwrap_or(self.temp_dir),
+            mode: env_var("UPLOAD_MODE")?
+                .map(|mode| mode.parse())
+                .transpose()?
+                .unwrap_or(self.mode),
+            stream_buffer_chunks: env_or("STREAM_BUFFER_CHUNKS", self.stream_buffer_chunks)?,
+            rollback_on_partial_failure: env_or(
+                "ROLLBACK_ON_PARTIAL_FAILURE",
+                self.rollback_on_partial_failure,
+            )?,
+            write_policy: WritePolicy {
+                quorum: env_var("WRITE_POLICY")?
+                    .map(|quorum| quorum.parse())
+                    .transpose()?
+                    .unwrap_or(self.write_policy.quorum),
+                required: env_list("WRITE_REQUIRED_BACKENDS", self.write_policy.required)?,
+            },
+        })
+    }
+
+    /// Check the size limit, stream buffer and write quorum
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        if self.max_file_size == 0 {
+            return Err(StorageError::ConfigError(
+                "upload.max_file_size (MAX_FILE_SIZE) must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.stream_buffer_chunks == 0 {
+            return Err(StorageError::ConfigError(
+                "upload.stream_buffer_chunks (STREAM_BUFFER_CHUNKS) must be greater than 0"
+                    .to_string(),
+            ));
+        }
+
+        if let Quorum::Count(count) = self.write_policy.quorum {
+            if count == 0 || count > Backend::ALL.len() {
+                return Err(StorageError::ConfigError(format!(
+                    "upload.write_policy.quorum (WRITE_POLICY) must be between 1 and {}",
+                    Backend::ALL.len()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 5_242_880, // 5MB
+            temp_dir: String::from("/tmp"),
+            mode: UploadMode::Spooled,
+            stream_buffer_chunks: 16,
+            rollback_on_partial_failure: true,
+            write_policy: WritePolicy::default(),
+        }
+    }
+}
+
+/// Storage backend an upload is written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Amazon S3
+    S3,
+    /// IPFS daemon
+    Ipfs,
+}
+
+impl Backend {
+    /// Every backend an upload is written to
+    pub const ALL: [Backend; 2] = [Backend::S3, Backend::Ipfs];
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::S3 => write!(f, "s3"),
+            Self::Ipfs => write!(f, "ipfs"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "s3" => Ok(Self::S3),
+            "ipfs" => Ok(Self::Ipfs),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid backend: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Number of backends that must store a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quorum {
+    /// Every backend
+    All,
+    /// At least one backend
+    Any,
+    /// At least this many backends
+    Count(usize),
+}
+
+impl FromStr for Quorum {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "any" => Ok(Self::Any),
+            other => other
+                .parse()
+                .map(Self::Count)
+                .map_err(|_| StorageError::ConfigError(format!("Invalid WRITE_POLICY: {}", other))),
+        }
+    }
+}
+
+/// Rule deciding whether an upload succeeded
+///
+/// An upload succeeds when at least `quorum` backends stored the file and
+/// every backend listed in `required` is among them. Backends that failed
+/// while the policy was still met are replicated later.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::{Backend, Quorum, WritePolicy};
+///
+/// // Accept the upload as long as S3 has it; IPFS is best effort
+/// let policy = WritePolicy {
+///     quorum: Quorum::Any,
+///     required: vec![Backend::S3],
+/// };
+/// assert!(policy.is_satisfied(&[Backend::S3]));
+/// assert!(!policy.is_satisfied(&[Backend::Ipfs]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WritePolicy {
+    /// Minimum number of backends that must store the file
+    pub quorum: Quorum,
+    /// Backends that must store the file regardless of the quorum
+    pub required: Vec<Backend>,
+}
+
+impl WritePolicy {
+    /// Check whether the policy is met
+    ///
+    /// # Arguments
+    ///
+    /// * `stored` - Backends that stored the file
+    pub fn is_satisfied(&self, stored: &[Backend]) -> bool {
+        let quorum = match self.quorum {
+            Quorum::All => Backend::ALL.len(),
+            Quorum::Any => 1,
+            Quorum::Count(count) => count,
+        };
+
+        stored.len() >= quorum && self.required.iter().all(|backend| stored.contains(backend))
+    }
+}
+
+impl Default for WritePolicy {
+    fn default() -> Self {
+        Self {
+            quorum: Quorum::All,
+            required: Vec::new(),
+        }
+    }
+}
+
+/// Upload processing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadMode {
+    /// Write the file to `temp_dir`, then upload it to each backend
+    Spooled,
+    /// Fan the request body out to the backends as it arrives, without
+    /// touching the disk
+    Streaming,
+}
+
+impl FromStr for UploadMode {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "spooled" => Ok(Self::Spooled),
+            "streaming" => Ok(Self::Streaming),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid UPLOAD_MODE: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_validate_zero_stream_buffer() {
+        let mut config = Config::default();
+        config.upload.stream_buffer_chunks = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_upload_mode_from_str() {
+        assert_eq!(
+            "Streaming".parse::<UploadMode>().unwrap(),
+            UploadMode::Streaming
+        );
+        assert_eq!(
+            "spooled".parse::<UploadMode>().unwrap(),
+            UploadMode::Spooled
+        );
+        assert!("tee".parse::<UploadMode>().is_err());
+    }
+
+    #[test]
+    fn test_quorum_from_str() {
+        assert_eq!("ALL".parse::<Quorum>().unwrap(), Quorum::All);
+        assert_eq!("any".parse::<Quorum>().unwrap(), Quorum::Any);
+        assert_eq!("1".parse::<Quorum>().unwrap(), Quorum::Count(1));
+        assert!("most".parse::<Quorum>().is_err());
+        assert_eq!(" IPFS".parse::<Backend>().unwrap(), Backend::Ipfs);
+        assert!("gcs".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_write_policy_is_satisfied() {
+        let all = WritePolicy::default();
+        assert!(all.is_satisfied(&[Backend::S3, Backend::Ipfs]));
+        assert!(!all.is_satisfied(&[Backend::S3]));
+
+        let s3_required = WritePolicy {
+            quorum: Quorum::Count(1),
+            required: vec![Backend::S3],
+        };
+        assert!(s3_required.is_satisfied(&[Backend::S3]));
+        assert!(!s3_required.is_satisfied(&[Backend::Ipfs]));
+        assert!(!s3_required.is_satisfied(&[]));
+    }
+
+    #[test]
+    fn test_validate_write_quorum_out_of_range() {
+        let mut config = Config::default();
+        config.upload.write_policy.quorum = Quorum::Count(3);
+        assert!(config.validate().is_err());
+        config.upload.write_policy.quorum = Quorum::Count(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_is_valid_tenant() {
+        assert!(is_valid_tenant("acme"));
+        assert!(is_valid_tenant("acme_prod-2"));
+        assert!(!is_valid_tenant(""));
+        assert!(!is_valid_tenant("acme/prod"));
+        assert!(!is_valid_tenant("acme.prod"));
+        assert!(!is_valid_tenant(&"a".repeat(65)));
+    }
+}
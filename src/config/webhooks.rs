@@ -0,0 +1,200 @@
+//! Webhook notification settings
+
+use super::sources::{env_list, env_or, env_strings, env_var};
+use super::RetryPolicy;
+use crate::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Webhook notification settings
+///
+/// Every enabled event is signed with `secret` and delivered to each URL.
+/// Deliveries are kept in an outbox in `outbox_dir` until they succeed, so
+/// events survive a crash.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Endpoints notified of events (default: none, webhooks disabled)
+    pub urls: Vec<String>,
+    /// Shared secret signing the payloads with HMAC-SHA256
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    /// Events delivered (default: all)
+    pub events: Vec<WebhookEvent>,
+    /// Directory holding undelivered events; must survive restarts
+    pub outbox_dir: String,
+    /// Timeout of a single delivery in seconds (default: 10)
+    pub timeout_secs: u64,
+    /// Redelivery of failed deliveries (`WEBHOOK_RETRY_*`)
+    pub retry: RetryPolicy,
+}
+
+impl WebhookConfig {
+    /// Whether `event` is delivered to the webhook endpoints
+    pub fn is_enabled(&self, event: WebhookEvent) -> bool {
+        !self.urls.is_empty() && self.events.contains(&event)
+    }
+
+    /// Override settings with the environment variables that are set
+    pub(super) fn with_env(self) -> StorageResult<Self> {
+        Ok(Self {
+            urls: env_strings("WEBHOOK_URLS", self.urls)?,
+            secret: env_var("WEBHOOK_SECRET")?.unwrap_or(self.secret),
+            events: env_list("WEBHOOK_EVENTS", self.events)?,
+            outbox_dir: env_var("WEBHOOK_OUTBOX_DIR")?.unwrap_or(self.outbox_dir),
+            timeout_secs: env_or("WEBHOOK_TIMEOUT_SECS", self.timeout_secs)?,
+            retry: RetryPolicy::from_env("WEBHOOK", self.retry)?,
+        })
+    }
+
+    /// Check the redelivery policy, the secret and the endpoints
+    pub(super) fn validate(&self) -> StorageResult<()> {
+        self.retry.validate("webhooks.retry", "WEBHOOK")?;
+
+        if !self.urls.is_empty() && self.secret.is_empty() {
+            return Err(StorageError::ConfigError(
+                "webhooks.secret (WEBHOOK_SECRET) must be set when webhooks.urls \
+                 (WEBHOOK_URLS) is set"
+                    .to_string(),
+            ));
+        }
+
+        if let Some((i, url)) = self
+            .urls
+            .iter()
+            .enumerate()
+            .find(|(_, url)| !(url.starts_with("http://") || url.starts_with("https://")))
+        {
+            return Err(StorageError::ConfigError(format!(
+                "webhooks.urls[{}] (WEBHOOK_URLS) must be an http(s) URL: {}",
+                i, url
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("urls", &self.urls)
+            .field("secret", &"<redacted>")
+            .field("events", &self.events)
+            .field("outbox_dir", &self.outbox_dir)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            secret: String::new(),
+            events: WebhookEvent::ALL.to_vec(),
+            outbox_dir: String::from("./outbox"),
+            timeout_secs: 10,
+            // Endpoints can be down for a while, so back off up to 5 minutes
+            retry: RetryPolicy {
+                max_attempts: 10,
+                initial_backoff_ms: 1000,
+                max_backoff_ms: 300_000,
+                jitter: true,
+            },
+        }
+    }
+}
+
+/// Upload lifecycle or MFS event delivered to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    /// A file was stored on enough backends to satisfy the write policy
+    #[serde(rename = "upload.completed")]
+    UploadCompleted,
+    /// A file could not be stored on enough backends
+    #[serde(rename = "upload.failed")]
+    UploadFailed,
+    /// A stored copy of a file was deleted
+    #[serde(rename = "file.deleted")]
+    FileDeleted,
+    /// The MFS root changed and a new snapshot of it was pinned
+    #[serde(rename = "mfs.snapshot")]
+    MfsSnapshot,
+}
+
+impl WebhookEvent {
+    /// Every event type
+    pub const ALL: [WebhookEvent; 4] = [
+        WebhookEvent::UploadCompleted,
+        WebhookEvent::UploadFailed,
+        WebhookEvent::FileDeleted,
+        WebhookEvent::MfsSnapshot,
+    ];
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UploadCompleted => write!(f, "upload.completed"),
+            Self::UploadFailed => write!(f, "upload.failed"),
+            Self::FileDeleted => write!(f, "file.deleted"),
+            Self::MfsSnapshot => write!(f, "mfs.snapshot"),
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "upload.completed" => Ok(Self::UploadCompleted),
+            "upload.failed" => Ok(Self::UploadFailed),
+            "file.deleted" => Ok(Self::FileDeleted),
+            "mfs.snapshot" => Ok(Self::MfsSnapshot),
+            other => Err(StorageError::ConfigError(format!(
+                "Invalid webhook event: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_webhook_event_from_str() {
+        assert_eq!(
+            "upload.completed".parse::<WebhookEvent>().unwrap(),
+            WebhookEvent::UploadCompleted
+        );
+        assert_eq!(
+            " File.Deleted".parse::<WebhookEvent>().unwrap(),
+            WebhookEvent::FileDeleted
+        );
+        assert!("upload.started".parse::<WebhookEvent>().is_err());
+    }
+
+    #[test]
+    fn test_validate_webhooks() {
+        let mut config = Config::default();
+        config.webhooks.urls = vec!["https://hooks.example.com/storage".to_string()];
+        assert!(config.validate().is_err());
+
+        config.webhooks.secret = "s3cr3t".to_string();
+        assert!(config.validate().is_ok());
+        assert!(config.webhooks.is_enabled(WebhookEvent::FileDeleted));
+        assert!(!format!("{:?}", config.webhooks).contains("s3cr3t"));
+
+        config
+            .webhooks
+            .urls
+            .push("ftp://hooks.example.com".to_string());
+        assert!(config.validate().is_err());
+    }
+}
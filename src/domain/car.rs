@@ -0,0 +1,219 @@
+//! CAR file import and export
+//!
+//! A CAR (Content Addressable aRchive) file carries the blocks of one or
+//! more DAGs together with their root CIDs, so content can move between
+//! IPFS networks without being re-chunked: the CIDs stay the same.
+//!
+//! [`import`] streams a CARv1 or CARv2 file into the IPFS node, which pins
+//! every root recursively; with an IPFS Cluster the roots are also pinned
+//! through the cluster so they are replicated like uploads. Each root is
+//! recorded in the metadata store. [`export`] streams the DAG below a
+//! recorded CID back out as a CARv1 file.
+//!
+//! Imported files are bounded by `MAX_FILE_SIZE`, like uploads.
+
+use crate::domain::metadata::{FileOrigin, FileRecord};
+use crate::infrastructure::ipfs;
+use crate::infrastructure::ipfs_rpc::IpfsError;
+use crate::state::AppState;
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::io;
+use thiserror::Error;
+use tokio::join;
+use tokio::sync::mpsc;
+
+/// Name of the cluster pins of imported roots
+const PIN_NAME: &str = "car-import";
+
+/// Errors of CAR operations
+#[derive(Debug, Error)]
+pub enum CarError {
+    /// The request body could not be read or is too large
+    #[error("Invalid request body: {0}")]
+    Body(String),
+
+    /// The IPFS node rejected the file
+    #[error("Invalid CAR file: {0}")]
+    Invalid(String),
+
+    /// The CID is not the root of an upload or import
+    #[error("Unknown CID: {0}")]
+    NotFound(String),
+
+    /// The IPFS node or cluster failed the operation
+    #[error("IPFS operation failed: {0:#}")]
+    Node(anyhow::Error),
+
+    /// A root could not be recorded
+    #[error("Failed to record CAR root: {0}")]
+    Store(#[from] io::Error),
+}
+
+/// Result of a CAR import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CarImport {
+    /// Root CIDs of the file, each pinned and recorded
+    pub roots: Vec<String>,
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+/// Import a CAR file, pinning and recording its roots
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `body` - The CAR file as it arrives
+/// * `tenant` - Tenant the content belongs to
+///
+/// # Errors
+///
+/// Returns an error if the body cannot be read or exceeds `MAX_FILE_SIZE`,
+/// the node rejects the file or cannot pin a root, or a root cannot be
+/// recorded
+pub async fn import<S, B>(
+    state: &AppState,
+    body: S,
+    tenant: Option<String>,
+) -> Result<CarImport, CarError>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let config = &state.config;
+    let (tx, rx) = mpsc::channel(config.upload.stream_buffer_chunks);
+
+    let (size, imported) = join!(
+        forward(body, tx, config.upload.max_file_size as u64),
+        ipfs::import_car(&state.ipfs, rx)
+    );
+    // A body error aborts the import, so it explains the import's failure
+    let size = size?;
+    let imported = imported.map_err(|e| match e.downcast_ref::<IpfsError>() {
+        // The node answers a file it cannot read with a 500
+        Some(IpfsError::Api {
+            status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+            message,
+        }) => CarError::Invalid(message.clone()),
+        _ => CarError::Node(e),
+    })?;
+
+    if let Some(root) = imported.iter().find(|root| !root.pin_error_msg.is_empty()) {
+        return Err(CarError::Invalid(format!(
+            "cannot pin root {}: {}",
+            root.cid, root.pin_error_msg
+        )));
+    }
+    if imported.is_empty() {
+        return Err(CarError::Invalid(String::from("the file has no roots")));
+    }
+
+    let (policy, timeout) = (&config.retry.ipfs, config.ipfs.timeout());
+    let mut roots = Vec::with_capacity(imported.len());
+    for root in imported {
+        if state.ipfs.cluster.is_some() {
+            ipfs::pin(&state.ipfs, policy, timeout, &root.cid, PIN_NAME)
+                .await
+                .map_err(CarError::Node)?;
+        }
+
+        let record = FileRecord {
+            size: Some(size),
+            tenant: tenant.clone(),
+            pinned: true,
+            ..FileRecord::new(root.cid.clone(), FileOrigin::Car)
+        };
+        state.metadata.put_file(record).await?;
+        roots.push(root.cid);
+    }
+
+    info!(
+        "Imported CAR file of {} bytes with roots {}",
+        size,
+        roots.join(", ")
+    );
+
+    Ok(CarImport { roots, size })
+}
+
+/// Export the DAG below a recorded CID as a CARv1 file
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `cid` - CID of an upload or of a root of an imported CAR file, in any
+///   encoding
+///
+/// # Returns
+///
+/// Returns the file as a stream of chunks
+///
+/// # Errors
+///
+/// Returns an error if the CID is not recorded or the node cannot start the
+/// export
+pub async fn export(
+    state: &AppState,
+    cid: &str,
+) -> Result<impl Stream<Item = io::Result<Bytes>>, CarError> {
+    let record = state
+        .metadata
+        .file(cid)
+        .ok_or_else(|| CarError::NotFound(cid.to_string()))?;
+
+    ipfs::export_car(&state.ipfs, &record.cid)
+        .await
+        .map_err(CarError::Node)
+}
+
+/// Forward a request body to a channel, enforcing a size limit
+///
+/// On failure an error is sent down the channel so the import aborts.
+///
+/// # Returns
+///
+/// Returns the number of bytes read
+async fn forward<S, B>(
+    body: S,
+    sink: mpsc::Sender<io::Result<Bytes>>,
+    max_size: u64,
+) -> Result<u64, CarError>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let mut size = 0u64;
+
+    let result = async {
+        futures::pin_mut!(body);
+        while let Some(chunk) = body.next().await {
+            let mut data = chunk.map_err(|e| CarError::Body(e.to_string()))?;
+            let bytes = data.copy_to_bytes(data.remaining());
+
+            size += bytes.len() as u64;
+            if size > max_size {
+                return Err(CarError::Body(format!(
+                    "file size exceeds maximum allowed size of {} bytes",
+                    max_size
+                )));
+            }
+
+            // A closed channel means the import has already failed
+            if sink.send(Ok(bytes)).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(size)
+    }
+    .await;
+
+    if let Err(e) = &result {
+        let _ = sink.send(Err(io::Error::other(e.to_string()))).await;
+    }
+
+    result
+}
@@ -0,0 +1,296 @@
+//! IPNS names for collections of uploads
+//!
+//! A CID changes with its content, so a curated collection of uploads
+//! cannot be shared by CID while it keeps growing. This module gives a
+//! collection a stable IPNS name instead:
+//!
+//! 1. [`create_key`] generates a key in the IPFS node's keystore; the key's
+//!    ID is the collection's IPNS name.
+//! 2. [`publish`] builds a directory holding a set of uploads, pins it in
+//!    place of the previously published directory and points the name at
+//!    it. The directory is assembled in a scratch MFS path that is removed
+//!    afterwards; its entries only reference the uploads' blocks.
+//! 3. [`resolve`] reads where a name currently points.
+//!
+//! Keys and the CID last published under them are recorded in the
+//! [`MetadataStore`](crate::domain::metadata::MetadataStore), so the
+//! previous directory can be unpinned on the next publish.
+
+use crate::config::is_valid_tenant;
+use crate::domain::metadata::{CollectionFile, IpnsName};
+use crate::infrastructure::ipfs;
+use crate::state::AppState;
+use cid::Cid;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// MFS directory below which collections are assembled
+const SCRATCH_DIR: &str = "/.memenow-ipns";
+
+/// Name of the node's own key, which is not handed out
+const SELF_KEY: &str = "self";
+
+/// Errors of IPNS operations
+#[derive(Debug, Error)]
+pub enum IpnsError {
+    /// The key name or IPNS name is malformed
+    #[error("Invalid IPNS name: {0}")]
+    InvalidName(String),
+
+    /// The files to publish are not a valid directory
+    #[error("Invalid collection: {0}")]
+    InvalidCollection(String),
+
+    /// A key with this name has already been created
+    #[error("IPNS key already exists: {0}")]
+    KeyExists(String),
+
+    /// No key with this name has been created
+    #[error("Unknown IPNS key: {0}")]
+    UnknownKey(String),
+
+    /// The IPFS node failed the operation
+    #[error("IPFS operation failed: {0:#}")]
+    Node(anyhow::Error),
+
+    /// The record could not be saved
+    #[error("Failed to save IPNS name: {0}")]
+    Store(#[from] io::Error),
+}
+
+/// Create an IPNS key for a collection
+///
+/// A key of the same name already in the node's keystore, e.g. one created
+/// before the metadata store was lost, is adopted rather than replaced.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `name` - Name of the key: 1 to 64 ASCII letters, digits, `-` and `_`
+///
+/// # Returns
+///
+/// Returns the record of the new key, not yet published
+///
+/// # Errors
+///
+/// Returns an error if the name is malformed or taken, the node cannot
+/// create the key or the record cannot be saved
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::domain::ipns::create_key;
+/// use memenow_storage_service::state::AppState;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let state = AppState::new(Config::default()).await?;
+/// let key = create_key(&state, "favorites").await?;
+/// println!("/ipns/{}", key.id);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn create_key(state: &AppState, name: &str) -> Result<IpnsName, IpnsError> {
+    if !is_valid_tenant(name) || name == SELF_KEY {
+        return Err(IpnsError::InvalidName(name.to_string()));
+    }
+    if state.metadata.name(name).is_some() {
+        return Err(IpnsError::KeyExists(name.to_string()));
+    }
+
+    let config = &state.config;
+    let key = ipfs::ensure_key(&state.ipfs, &config.retry.ipfs, config.ipfs.timeout(), name)
+        .await
+        .map_err(IpnsError::Node)?;
+
+    let record = IpnsName {
+        name: key.name,
+        id: key.id,
+        cid: None,
+        files: Vec::new(),
+        created_at: unix_secs(),
+        published_at: None,
+    };
+    state.metadata.put_name(record.clone()).await?;
+    info!("Created IPNS key {}: /ipns/{}", record.name, record.id);
+
+    Ok(record)
+}
+
+/// Publish a directory of uploads under a key
+///
+/// The directory replaces the one previously published under the key: it
+/// is pinned in its place and the name is pointed at it.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `name` - Name of a key created with [`create_key`]
+/// * `files` - Uploads to place in the directory; path separators in their
+///   filenames are replaced with `_`
+///
+/// # Returns
+///
+/// Returns the updated record, holding the CID of the directory
+///
+/// # Errors
+///
+/// Returns an error if the key is unknown, the files are empty, have an
+/// invalid CID or clashing filenames, the node fails to build, pin or
+/// publish the directory, or the record cannot be saved
+pub async fn publish(
+    state: &AppState,
+    name: &str,
+    files: Vec<CollectionFile>,
+) -> Result<IpnsName, IpnsError> {
+    let mut record = state
+        .metadata
+        .name(name)
+        .ok_or_else(|| IpnsError::UnknownKey(name.to_string()))?;
+    let files = collection(files)?;
+
+    let config = &state.config;
+    let (policy, timeout) = (&config.retry.ipfs, config.ipfs.timeout());
+
+    let cid = build_directory(state, &files)
+        .await
+        .map_err(IpnsError::Node)?;
+    if record.cid.as_deref() != Some(cid.as_str()) {
+        ipfs::pin_update(&state.ipfs, policy, timeout, record.cid.as_deref(), &cid)
+            .await
+            .map_err(IpnsError::Node)?;
+    }
+    ipfs::publish_name(&state.ipfs, policy, timeout, name, &cid, &config.ipfs.ipns)
+        .await
+        .map_err(IpnsError::Node)?;
+
+    record.cid = Some(cid);
+    record.files = files;
+    record.published_at = Some(unix_secs());
+    state.metadata.put_name(record.clone()).await?;
+    info!(
+        "Published {} files under /ipns/{}: {}",
+        record.files.len(),
+        record.id,
+        record.cid.as_deref().unwrap_or_default()
+    );
+
+    Ok(record)
+}
+
+/// Resolve an IPNS name
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `name` - Name of a key created with [`create_key`], or any IPNS name
+///   or DNSLink domain
+///
+/// # Returns
+///
+/// Returns the path the name points to, e.g. `/ipfs/{cid}`
+///
+/// # Errors
+///
+/// Returns an error if the name is malformed or the node cannot resolve it
+pub async fn resolve(state: &AppState, name: &str) -> Result<String, IpnsError> {
+    let valid = !name.is_empty()
+        && name.len() <= 255
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(IpnsError::InvalidName(name.to_string()));
+    }
+
+    let id = match state.metadata.name(name) {
+        Some(record) => record.id,
+        None => name.to_string(),
+    };
+
+    let config = &state.config;
+    ipfs::resolve_name(&state.ipfs, &config.retry.ipfs, config.ipfs.timeout(), &id)
+        .await
+        .map_err(IpnsError::Node)
+}
+
+/// Check the files of a collection and name their directory entries
+fn collection(files: Vec<CollectionFile>) -> Result<Vec<CollectionFile>, IpnsError> {
+    if files.is_empty() {
+        return Err(IpnsError::InvalidCollection(String::from("no files")));
+    }
+
+    let mut names = HashSet::new();
+    files
+        .into_iter()
+        .map(|file| {
+            if Cid::try_from(file.cid.as_str()).is_err() {
+                return Err(IpnsError::InvalidCollection(format!(
+                    "invalid CID {}",
+                    file.cid
+                )));
+            }
+
+            let filename = ipfs::entry_name(&file.filename);
+            if matches!(filename.as_str(), "" | "." | "..") {
+                return Err(IpnsError::InvalidCollection(format!(
+                    "invalid filename {:?}",
+                    file.filename
+                )));
+            }
+            if !names.insert(filename.clone()) {
+                return Err(IpnsError::InvalidCollection(format!(
+                    "duplicate filename {}",
+                    filename
+                )));
+            }
+
+            Ok(CollectionFile {
+                cid: file.cid,
+                filename,
+            })
+        })
+        .collect()
+}
+
+/// Assemble a directory of files in a scratch MFS path
+///
+/// # Returns
+///
+/// Returns the CID of the directory
+async fn build_directory(state: &AppState, files: &[CollectionFile]) -> anyhow::Result<String> {
+    let config = &state.config;
+    let (policy, timeout) = (&config.retry.ipfs, config.ipfs.timeout());
+    let dir = format!("{}/{}", SCRATCH_DIR, Uuid::new_v4());
+
+    let built = async {
+        for file in files {
+            let source = format!("/ipfs/{}", file.cid);
+            let path = format!("{}/{}", dir, file.filename);
+            ipfs::copy_to_mfs(&state.ipfs, policy, timeout, &source, &path).await?;
+        }
+
+        ipfs::stat_mfs(&state.ipfs, policy, timeout, &dir)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("MFS directory {} vanished", dir))
+    }
+    .await;
+
+    if let Err(e) = ipfs::remove_from_mfs(&state.ipfs, policy, timeout, &dir).await {
+        warn!("Failed to remove scratch directory {}: {:#}", dir, e);
+    }
+
+    built
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
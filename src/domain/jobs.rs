@@ -0,0 +1,736 @@
+//! Asynchronous upload jobs
+//!
+//! Large uploads can be accepted as soon as they are spooled to disk instead
+//! of keeping the client waiting while every backend stores them. Each such
+//! upload becomes a [`Job`]: the spooled file is moved into the jobs
+//! directory (`JOBS_DIR`), a JSON record of the job is written next to it and
+//! a pool of workers uploads the file to the backends. Clients poll
+//! `GET /jobs/{id}` for progress and the final [`UploadResponse`].
+//!
+//! Job records are rewritten atomically on every state change. On startup,
+//! jobs that had not finished are run again. Re-uploading a file under the
+//! same S3 key and re-adding it to IPFS are idempotent, so a job interrupted
+//! halfway can simply start over.
+//!
+//! Records of finished jobs are kept so their outcome can still be queried;
+//! their spooled files are removed.
+
+use crate::config::{Backend, IpfsAddOptions, JobsConfig};
+use crate::domain::objects::{self, ObjectInfo};
+use crate::domain::services::{self, SpooledFile, StoredFile, UploadResponse, UploadTarget};
+use crate::error::StorageError;
+use crate::state::AppState;
+use crate::utils::file;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::join;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Lifecycle state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Waiting for a worker
+    Pending,
+    /// Being uploaded to the backends
+    Running,
+    /// Stored on enough backends to satisfy the write policy
+    Succeeded,
+    /// Not stored on enough backends; stored copies were rolled back
+    Failed,
+}
+
+impl JobState {
+    /// Whether the job has reached a final state
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed)
+    }
+}
+
+/// Progress of a job on a single backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadProgress {
+    /// Not started yet
+    Pending,
+    /// Upload in progress
+    Uploading,
+    /// The backend stored the file
+    Stored,
+    /// The upload to the backend failed
+    Failed,
+}
+
+/// Progress of a job on one backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendProgress {
+    /// The backend
+    pub backend: Backend,
+    /// How far the upload to this backend got
+    pub state: UploadProgress,
+    /// Why the upload to this backend failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Bytes of the file the backend has processed, if it reports progress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+}
+
+/// An upload processed in the background
+///
+/// This is both the record persisted in the jobs directory and the body of
+/// `GET /jobs/{id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// Job ID
+    pub id: Uuid,
+    /// Lifecycle state
+    pub state: JobState,
+    /// The original filename
+    pub filename: String,
+    /// The S3 key the file is uploaded under
+    pub key: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// Options the file is added to IPFS with
+    #[serde(default)]
+    pub ipfs_add: IpfsAddOptions,
+    /// The CID computed while the file was spooled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_cid: Option<String>,
+    /// Tenant the upload belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// What the request and contents say about the S3 object
+    #[serde(default)]
+    pub object: ObjectInfo,
+    /// Progress on each backend
+    pub backends: Vec<BackendProgress>,
+    /// The upload result, once the job has succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<UploadResponse>,
+    /// Why the job failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Unix time the job was accepted, in seconds
+    pub created_at: u64,
+    /// Unix time of the last state change, in seconds
+    pub updated_at: u64,
+}
+
+impl Job {
+    /// Create a pending job
+    fn new(filename: String, key: String, size: u64) -> Self {
+        let now = unix_time();
+
+        Self {
+            id: Uuid::new_v4(),
+            state: JobState::Pending,
+            filename,
+            key,
+            size,
+            ipfs_add: IpfsAddOptions::default(),
+            expected_cid: None,
+            tenant: None,
+            object: ObjectInfo::default(),
+            backends: Backend::ALL
+                .into_iter()
+                .map(|backend| BackendProgress {
+                    backend,
+                    state: UploadProgress::Pending,
+                    error: None,
+                    bytes: None,
+                })
+                .collect(),
+            response: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Progress of the job on `backend`
+    fn backend_mut(&mut self, backend: Backend) -> Option<&mut BackendProgress> {
+        self.backends
+            .iter_mut()
+            .find(|progress| progress.backend == backend)
+    }
+}
+
+/// Response returned when an upload is accepted as a job
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobAccepted {
+    /// ID of the job processing the upload
+    pub job_id: Uuid,
+    /// State of the job
+    pub state: JobState,
+    /// Where to poll for the job's progress
+    pub status_url: String,
+}
+
+/// Durable store of job records
+///
+/// Every job is kept in memory and mirrored to `{dir}/{id}.json`; its spooled
+/// file lives at `{dir}/{id}.data`. Cloning the store is cheap; all clones
+/// share the same state.
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    dir: PathBuf,
+    jobs: Arc<Mutex<HashMap<Uuid, Job>>>,
+    /// Serializes record writes so an older snapshot never replaces a newer one
+    writes: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JobStore {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            jobs: Arc::default(),
+            writes: Arc::default(),
+        }
+    }
+
+    /// Open the store, loading the job records found in `dir`
+    ///
+    /// The directory is created when the first job is saved, so a missing
+    /// directory is an empty store.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory holding the job records
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory exists but cannot be read.
+    /// Unreadable records are skipped with a warning.
+    pub async fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let store = Self::new(dir.into());
+
+        let mut entries = match tokio::fs::read_dir(&store.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(store),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                // Left behind by a write that was interrupted
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+            if !path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+
+            let job = tokio::fs::read(&path)
+                .await
+                .and_then(|data| serde_json::from_slice::<Job>(&data).map_err(io::Error::other));
+            match job {
+                Ok(job) => {
+                    store.jobs().insert(job.id, job);
+                }
+                Err(e) => warn!("Skipping unreadable job record {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Look up a job
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.jobs().get(&id).cloned()
+    }
+
+    /// Add a job and persist its record
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written
+    pub async fn insert(&self, job: Job) -> io::Result<()> {
+        let _write = self.writes.lock().await;
+        self.jobs().insert(job.id, job.clone());
+        self.persist(&job).await
+    }
+
+    /// Modify a job and persist its record
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The job to modify
+    /// * `f` - Applied to the job; its update time is set afterwards
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job does not exist or its record cannot be
+    /// written
+    pub async fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) -> io::Result<()> {
+        let _write = self.writes.lock().await;
+        let job = {
+            let mut jobs = self.jobs();
+            let job = jobs
+                .get_mut(&id)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown job"))?;
+            f(job);
+            job.updated_at = unix_time();
+            job.clone()
+        };
+
+        self.persist(&job).await
+    }
+
+    /// Location of a job's spooled file
+    pub fn spool_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.data", id))
+    }
+
+    /// IDs of the jobs that have not finished, oldest first
+    pub fn unfinished(&self) -> Vec<Uuid> {
+        let mut jobs: Vec<(u64, Uuid)> = self
+            .jobs()
+            .values()
+            .filter(|job| !job.state.is_finished())
+            .map(|job| (job.created_at, job.id))
+            .collect();
+        jobs.sort();
+        jobs.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Atomically replace the record of a job
+    async fn persist(&self, job: &Job) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let path = self.dir.join(format!("{}.json", job.id));
+        let data = serde_json::to_vec_pretty(job).map_err(io::Error::other)?;
+        file::write_file_atomic(&path, &data).await
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, HashMap<Uuid, Job>> {
+        // The map stays consistent even if a holder panicked, so recover from poisoning
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Handle for submitting and querying jobs
+///
+/// Cloning the queue is cheap; all clones feed the same worker pool.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    pub(crate) store: JobStore,
+    tx: mpsc::UnboundedSender<Uuid>,
+}
+
+impl JobQueue {
+    /// Open the job store and create the queue feeding [`spawn_workers`]
+    ///
+    /// Jobs that had not finished when the service last stopped are queued
+    /// again. If the store cannot be read, the error is logged and the queue
+    /// starts empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Job settings
+    pub async fn open(config: &JobsConfig) -> (Self, mpsc::UnboundedReceiver<Uuid>) {
+        let store = JobStore::open(&config.dir).await.unwrap_or_else(|e| {
+            error!("Failed to load jobs from {}: {}", config.dir, e);
+            JobStore::new(PathBuf::from(&config.dir))
+        });
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let resumed = store.unfinished();
+        if !resumed.is_empty() {
+            info!("Resuming {} unfinished upload job(s)", resumed.len());
+        }
+        for id in resumed {
+            let _ = tx.send(id);
+        }
+
+        (Self { store, tx }, rx)
+    }
+
+    /// Look up a job
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.store.get(id)
+    }
+}
+
+/// Hand a spooled upload to a background job
+///
+/// The spooled file is moved into the jobs directory and synced to disk
+/// before the job record is written, so an accepted job survives a crash.
+///
+/// # Arguments
+///
+/// * `spooled` - The file saved to temporary storage
+/// * `ipfs_add` - Options of the IPFS add
+/// * `tenant` - Tenant the upload belongs to
+/// * `state` - Shared application state
+///
+/// # Returns
+///
+/// Returns the pending job
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be moved or the job record cannot be
+/// written. The spooled file is removed in that case.
+pub(crate) async fn submit(
+    spooled: SpooledFile,
+    ipfs_add: IpfsAddOptions,
+    tenant: Option<String>,
+    state: &AppState,
+) -> Result<Job, StorageError> {
+    let store = &state.jobs.store;
+    let key = services::generate_file_key(&spooled.filename, &state.config.s3.key_prefix);
+    let job = Job {
+        ipfs_add,
+        expected_cid: spooled.cid,
+        tenant,
+        object: spooled.object,
+        ..Job::new(spooled.filename, key, spooled.size)
+    };
+    let path = store.spool_path(job.id);
+
+    let persisted = async {
+        tokio::fs::create_dir_all(&store.dir).await?;
+        move_file(&spooled.path, &path).await?;
+        tokio::fs::File::open(&path).await?.sync_all().await?;
+        store.insert(job.clone()).await
+    }
+    .await;
+
+    if let Err(e) = persisted {
+        let _ = state.tracker.remove_temp_file(&spooled.path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(StorageError::IoError(e));
+    }
+    state.tracker.forget_temp_file(&spooled.path);
+
+    info!(
+        "Accepted upload of '{}' ({} bytes) as job {}",
+        job.filename, job.size, job.id
+    );
+
+    if state.jobs.tx.send(job.id).is_err() {
+        error!(
+            "Job workers have stopped, job {} will run after a restart",
+            job.id
+        );
+    }
+
+    Ok(job)
+}
+
+/// Start the pool of workers running queued jobs
+///
+/// At most `JOB_WORKERS` jobs run at a time. Once [`AppState::shutdown`] is
+/// stopped no further job is started, and the shutdown waits for the running
+/// ones; the others stay on disk and run after a restart.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state providing the storage clients
+/// * `jobs` - Receiver returned by [`JobQueue::open`]
+pub fn spawn_workers(state: AppState, mut jobs: mpsc::UnboundedReceiver<Uuid>) -> JoinHandle<()> {
+    let workers = Arc::new(Semaphore::new(state.config.jobs.workers));
+    let mut stopping = state.shutdown.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let id = tokio::select! {
+                id = jobs.recv() => id,
+                _ = stopping.changed() => None,
+            };
+            let Some(id) = id else {
+                break;
+            };
+            let Ok(permit) = workers.clone().acquire_owned().await else {
+                break;
+            };
+            if *stopping.borrow() {
+                break;
+            }
+
+            let state = state.clone();
+            // Held until the job ends, so the shutdown waits for it
+            let running = stopping.clone();
+            tokio::spawn(async move {
+                run(id, &state).await;
+                drop(permit);
+                drop(running);
+            });
+        }
+    })
+}
+
+/// Upload a job's spooled file to the backends and record the outcome
+#[tracing::instrument(skip(state))]
+async fn run(id: Uuid, state: &AppState) {
+    let store = &state.jobs.store;
+    let Some(job) = store.get(id) else {
+        warn!("Skipping unknown job {}", id);
+        return;
+    };
+    if job.state.is_finished() {
+        return;
+    }
+
+    info!("Running upload job {} for '{}'", id, job.filename);
+    record(store, id, |job| {
+        job.state = JobState::Running;
+        for progress in &mut job.backends {
+            progress.state = UploadProgress::Uploading;
+            progress.error = None;
+        }
+    })
+    .await;
+
+    let path = store.spool_path(id);
+    let progress = ipfs_progress(store, id, job.size);
+    let attributes = objects::attributes(
+        &state.config.s3,
+        &job.filename,
+        job.tenant.as_deref(),
+        job.expected_cid.as_deref(),
+        &job.object,
+    );
+    let target = UploadTarget {
+        key: &job.key,
+        filename: &job.filename,
+        ipfs_add: &job.ipfs_add,
+        cid: job.expected_cid.as_deref(),
+        object: &attributes,
+        progress: Some(&progress),
+    };
+    let (s3, ipfs) = join!(
+        upload(state, id, Backend::S3, &path, &target),
+        upload(state, id, Backend::Ipfs, &path, &target),
+    );
+
+    let stored = StoredFile {
+        filename: job.filename,
+        key: job.key,
+        size: job.size,
+        s3,
+        ipfs,
+        ipfs_add: job.ipfs_add,
+        expected_cid: job.expected_cid,
+        cid_rejected: false,
+        tenant: job.tenant,
+        object: job.object,
+    };
+
+    match services::complete_upload(stored, Some(id), state).await {
+        Ok(response) => {
+            info!("Upload job {} succeeded", id);
+            record(store, id, |job| {
+                job.state = JobState::Succeeded;
+                job.response = Some(response);
+            })
+            .await;
+        }
+        Err(e) => {
+            error!("Upload job {} failed: {}", id, e);
+            record(store, id, |job| {
+                job.state = JobState::Failed;
+                job.error = Some(e.to_string());
+            })
+            .await;
+        }
+    }
+
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        warn!("Failed to remove spooled file {}: {}", path.display(), e);
+    }
+}
+
+/// Upload a job's file to one backend, recording the outcome in the job
+async fn upload(
+    state: &AppState,
+    id: Uuid,
+    backend: Backend,
+    path: &Path,
+    target: &UploadTarget<'_>,
+) -> anyhow::Result<String> {
+    let result = services::upload_file(state, backend, path, target).await;
+
+    record(&state.jobs.store, id, |job| {
+        if let Some(progress) = job.backend_mut(backend) {
+            match &result {
+                Ok(_) => progress.state = UploadProgress::Stored,
+                Err(e) => {
+                    progress.state = UploadProgress::Failed;
+                    progress.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+    })
+    .await;
+
+    result
+}
+
+/// Progress callback recording how much of a job's file IPFS has added
+///
+/// The node reports progress for every chunk, so it is only recorded in
+/// steps of a tenth of the file to keep job record writes down.
+fn ipfs_progress(store: &JobStore, id: Uuid, size: u64) -> impl Fn(u64) + Send + Sync {
+    let store = store.clone();
+    let step = (size / 10).max(1);
+    let recorded = AtomicU64::new(0);
+
+    move |bytes| {
+        let last = recorded.load(Ordering::Relaxed);
+        if bytes < size && bytes < last.saturating_add(step) {
+            return;
+        }
+        if recorded.fetch_max(bytes, Ordering::Relaxed) >= bytes {
+            return;
+        }
+
+        let store = store.clone();
+        tokio::spawn(async move {
+            record(&store, id, |job| {
+                if let Some(progress) = job.backend_mut(Backend::Ipfs) {
+                    progress.bytes = progress.bytes.max(Some(bytes));
+                }
+            })
+            .await;
+        });
+    }
+}
+
+/// Update a job, logging rather than failing if its record cannot be written
+///
+/// The in-memory state is updated either way; an unsaved change only matters
+/// if the service restarts before the next successful write.
+async fn record(store: &JobStore, id: Uuid, f: impl FnOnce(&mut Job)) {
+    if let Err(e) = store.update(id, f).await {
+        error!("Failed to save job {}: {}", id, e);
+    }
+}
+
+/// Move a file, copying it when source and destination are on different
+/// filesystems
+async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::remove_file(from).await
+}
+
+/// Current Unix time in seconds
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("jobs-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_missing_dir_is_empty_store() -> io::Result<()> {
+        let store = JobStore::open(temp_dir()).await?;
+        assert!(store.unfinished().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_reopening() -> io::Result<()> {
+        let dir = temp_dir();
+        let store = JobStore::open(&dir).await?;
+
+        let pending = Job::new("a.bin".to_string(), "uploads/a.bin".to_string(), 10);
+        let finished = Job::new("b.bin".to_string(), "uploads/b.bin".to_string(), 20);
+        store.insert(pending.clone()).await?;
+        store.insert(finished.clone()).await?;
+        store
+            .update(finished.id, |job| {
+                job.state = JobState::Failed;
+                job.error = Some("ipfs unavailable".to_string());
+            })
+            .await?;
+
+        let reopened = JobStore::open(&dir).await?;
+        assert_eq!(reopened.unfinished(), vec![pending.id]);
+
+        let job = reopened.get(finished.id).unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.as_deref(), Some("ipfs unavailable"));
+        assert_eq!(job.key, "uploads/b.bin");
+
+        tokio::fs::remove_dir_all(&dir).await
+    }
+
+    #[tokio::test]
+    async fn test_update_unknown_job() -> io::Result<()> {
+        let store = JobStore::open(temp_dir()).await?;
+        let result = store.update(Uuid::new_v4(), |_| {}).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_serialization() {
+        let job = Job::new("a.bin".to_string(), "uploads/a.bin".to_string(), 10);
+        let json = serde_json::to_value(&job).unwrap();
+
+        assert_eq!(json["state"], "pending");
+        assert_eq!(json["backends"][0]["backend"], "s3");
+        assert_eq!(json["backends"][0]["state"], "pending");
+        assert!(json.get("response").is_none());
+        assert!(json.get("error").is_none());
+        assert!(json["backends"][0].get("bytes").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ipfs_progress_is_recorded_in_steps() -> io::Result<()> {
+        let dir = temp_dir();
+        let store = JobStore::open(&dir).await?;
+        let job = Job::new("a.bin".to_string(), "uploads/a.bin".to_string(), 100);
+        store.insert(job.clone()).await?;
+
+        let bytes = || {
+            let job = store.get(job.id).unwrap();
+            job.backends
+                .iter()
+                .find(|progress| progress.backend == Backend::Ipfs)
+                .and_then(|progress| progress.bytes)
+        };
+        let progress = ipfs_progress(&store, job.id, 100);
+
+        progress(5);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(bytes(), None);
+
+        progress(12);
+        progress(15);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(bytes(), Some(12));
+
+        progress(100);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(bytes(), Some(100));
+
+        tokio::fs::remove_dir_all(&dir).await
+    }
+}
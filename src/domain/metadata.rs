@@ -0,0 +1,382 @@
+//! Durable metadata store
+//!
+//! This module keeps the service's own records about content it manages,
+//! which neither S3 nor IPFS can hold. Records are kept in memory and
+//! mirrored to JSON files below `METADATA_DIR`, one file per record, so they
+//! survive restarts. Each kind of record lives in its own subdirectory:
+//!
+//! - `files/{cid}.json`: content stored on IPFS by an upload or a CAR
+//!   import. CIDs are normalized to CIDv1 in base32 before a record is
+//!   stored or looked up, so every encoding of a CID finds the same record
+//! - `ipns/{name}.json`: an IPNS key created for a collection, with the CID
+//!   it was last published with (see [`crate::domain::ipns`])
+
+use crate::config::MetadataConfig;
+use crate::infrastructure::ipfs;
+use crate::utils::file;
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Subdirectory of the file records
+const FILES_DIR: &str = "files";
+
+/// Subdirectory of the IPNS name records
+const IPNS_DIR: &str = "ipns";
+
+/// How content came to be stored on IPFS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileOrigin {
+    /// Added from an upload
+    Upload,
+    /// A root of an imported CAR file
+    Car,
+}
+
+/// Content stored on IPFS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// CID of the content, as CIDv1 in base32
+    pub cid: String,
+    /// The original filename, if known
+    pub filename: Option<String>,
+    /// Size of the original file in bytes, if known
+    pub size: Option<u64>,
+    /// Tenant the content belongs to
+    pub tenant: Option<String>,
+    /// How the content was stored
+    pub origin: FileOrigin,
+    /// Whether the content was pinned on the node
+    #[serde(default)]
+    pub pinned: bool,
+    /// Unix time the content was stored at, in seconds
+    pub created_at: u64,
+}
+
+impl FileRecord {
+    /// Create a record of content stored now, with nothing else known
+    pub fn new(cid: String, origin: FileOrigin) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            cid,
+            filename: None,
+            size: None,
+            tenant: None,
+            origin,
+            pinned: false,
+            created_at,
+        }
+    }
+}
+
+/// An IPNS key and the collection last published under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpnsName {
+    /// Name of the key in the node's keystore
+    pub name: String,
+    /// ID of the key, which is the IPNS name the collection is reachable at
+    pub id: String,
+    /// CID of the directory last published under the key
+    pub cid: Option<String>,
+    /// Files of the directory last published under the key
+    #[serde(default)]
+    pub files: Vec<CollectionFile>,
+    /// Unix time the key was created at, in seconds
+    pub created_at: u64,
+    /// Unix time the key was last published at, in seconds
+    pub published_at: Option<u64>,
+}
+
+/// A file of a published collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionFile {
+    /// CID of the upload
+    pub cid: String,
+    /// Name of the file in the collection's directory
+    pub filename: String,
+}
+
+/// Durable store of metadata records
+///
+/// Cloning the store is cheap; all clones share the same state.
+#[derive(Debug, Clone)]
+pub struct MetadataStore {
+    dir: PathBuf,
+    /// File records by CIDv1 in base32
+    files: Arc<Mutex<HashMap<String, FileRecord>>>,
+    names: Arc<Mutex<BTreeMap<String, IpnsName>>>,
+    /// Serializes record writes so an older snapshot never replaces a newer one
+    writes: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl MetadataStore {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            files: Arc::default(),
+            names: Arc::default(),
+            writes: Arc::default(),
+        }
+    }
+
+    /// Open the store, loading the records found in `config.dir`
+    ///
+    /// The directories are created when the first record is saved, so a
+    /// missing directory is an empty store. If the store cannot be read,
+    /// the error is logged and the store starts empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Metadata store settings
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use memenow_storage_service::config::MetadataConfig;
+    /// use memenow_storage_service::domain::metadata::MetadataStore;
+    ///
+    /// # async fn example() {
+    /// let store = MetadataStore::open(&MetadataConfig::default()).await;
+    /// for name in store.names() {
+    ///     println!("{}: /ipns/{}", name.name, name.id);
+    /// }
+    /// # }
+    /// ```
+    pub async fn open(config: &MetadataConfig) -> Self {
+        let store = Self::new(PathBuf::from(&config.dir));
+
+        match load::<FileRecord>(&store.dir.join(FILES_DIR)).await {
+            Ok(records) => {
+                let mut files = store.files();
+                for record in records {
+                    match ipfs::normalize_cid(&record.cid) {
+                        Ok(cid) => {
+                            files.insert(cid.clone(), FileRecord { cid, ..record });
+                        }
+                        Err(_) => warn!("Skipping file record of invalid CID {}", record.cid),
+                    }
+                }
+            }
+            Err(e) => error!("Failed to load file records from {}: {}", config.dir, e),
+        }
+
+        match load::<IpnsName>(&store.dir.join(IPNS_DIR)).await {
+            Ok(names) => {
+                store
+                    .ipns()
+                    .extend(names.into_iter().map(|name| (name.name.clone(), name)));
+            }
+            Err(e) => error!("Failed to load IPNS names from {}: {}", config.dir, e),
+        }
+
+        store
+    }
+
+    /// Look up the record of a CID, in any encoding
+    pub fn file(&self, cid: &str) -> Option<FileRecord> {
+        let cid = ipfs::normalize_cid(cid).ok()?;
+        self.files().get(&cid).cloned()
+    }
+
+    /// Add or replace the record of a CID and persist it
+    ///
+    /// The record's CID is normalized to CIDv1 in base32.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CID is invalid or the record cannot be
+    /// written
+    pub async fn put_file(&self, record: FileRecord) -> io::Result<()> {
+        let cid = ipfs::normalize_cid(&record.cid).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid CID: {}", record.cid),
+            )
+        })?;
+        let record = FileRecord { cid, ..record };
+
+        let _write = self.writes.lock().await;
+        self.files().insert(record.cid.clone(), record.clone());
+
+        let path = self
+            .dir
+            .join(FILES_DIR)
+            .join(format!("{}.json", record.cid));
+        persist(&path, &record).await
+    }
+
+    /// Look up an IPNS name by the name of its key
+    pub fn name(&self, name: &str) -> Option<IpnsName> {
+        self.ipns().get(name).cloned()
+    }
+
+    /// All IPNS names, ordered by the name of their key
+    pub fn names(&self) -> Vec<IpnsName> {
+        self.ipns().values().cloned().collect()
+    }
+
+    /// Add or replace an IPNS name and persist its record
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written
+    pub async fn put_name(&self, name: IpnsName) -> io::Result<()> {
+        let _write = self.writes.lock().await;
+        self.ipns().insert(name.name.clone(), name.clone());
+
+        let path = self.dir.join(IPNS_DIR).join(format!("{}.json", name.name));
+        persist(&path, &name).await
+    }
+
+    fn files(&self) -> MutexGuard<'_, HashMap<String, FileRecord>> {
+        // The map stays consistent even if a holder panicked, so recover from poisoning
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn ipns(&self) -> MutexGuard<'_, BTreeMap<String, IpnsName>> {
+        // The map stays consistent even if a holder panicked, so recover from poisoning
+        self.names
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Read the records of a directory
+///
+/// Unreadable records are skipped with a warning, and files left behind by
+/// an interrupted write are removed.
+///
+/// # Errors
+///
+/// Returns an error if the directory exists but cannot be read
+async fn load<T: DeserializeOwned>(dir: &Path) -> io::Result<Vec<T>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut records = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "tmp") {
+            let _ = tokio::fs::remove_file(&path).await;
+            continue;
+        }
+        if !path.extension().is_some_and(|ext| ext == "json") {
+            continue;
+        }
+
+        let record = tokio::fs::read(&path)
+            .await
+            .and_then(|data| serde_json::from_slice::<T>(&data).map_err(io::Error::other));
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping unreadable record {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Atomically replace a record
+async fn persist<T: Serialize>(path: &Path, record: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let data = serde_json::to_vec_pretty(record).map_err(io::Error::other)?;
+    file::write_file_atomic(path, &data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config() -> MetadataConfig {
+        let dir = std::env::temp_dir().join(format!("metadata-{}", Uuid::new_v4()));
+        MetadataConfig {
+            dir: dir.to_string_lossy().into_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_names_survive_reopening() -> io::Result<()> {
+        let config = config();
+        let store = MetadataStore::open(&config).await;
+        assert!(store.names().is_empty());
+
+        let name = IpnsName {
+            name: String::from("favorites"),
+            id: String::from("k51qzi5uqu5dlvj2baxnqndepeb86cbk3ng7n3i46uzyxzyqj2xjonzllnv0v8"),
+            cid: Some(String::from(
+                "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+            )),
+            files: vec![CollectionFile {
+                cid: String::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"),
+                filename: String::from("cat.gif"),
+            }],
+            created_at: 1,
+            published_at: Some(2),
+        };
+        store.put_name(name.clone()).await?;
+
+        // Interrupted writes are cleaned up
+        let tmp = Path::new(&config.dir).join(IPNS_DIR).join("x.json.tmp");
+        tokio::fs::write(&tmp, b"{").await?;
+
+        let reopened = MetadataStore::open(&config).await;
+        assert_eq!(reopened.name("favorites"), Some(name));
+        assert_eq!(reopened.names().len(), 1);
+        assert!(!tmp.exists());
+
+        tokio::fs::remove_dir_all(&config.dir).await
+    }
+
+    #[tokio::test]
+    async fn test_files_are_found_in_any_encoding() -> io::Result<()> {
+        let config = config();
+        let store = MetadataStore::open(&config).await;
+
+        let record = FileRecord {
+            filename: Some(String::from("cat.gif")),
+            size: Some(42),
+            ..FileRecord::new(
+                String::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"),
+                FileOrigin::Upload,
+            )
+        };
+        store.put_file(record.clone()).await?;
+        assert!(store
+            .put_file(FileRecord::new(String::from("not-a-cid"), FileOrigin::Car))
+            .await
+            .is_err());
+
+        let reopened = MetadataStore::open(&config).await;
+        let v1 = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+        let base36 = "k2jmtxtlhjl3fhmgndf92e48by79ryjuvqp3y2qgehpao6v3lurvnmcv";
+        let normalized = FileRecord {
+            cid: v1.to_string(),
+            ..record.clone()
+        };
+        assert_eq!(reopened.file(v1), Some(normalized.clone()));
+        assert_eq!(reopened.file(base36), Some(normalized.clone()));
+        assert_eq!(reopened.file(&record.cid), Some(normalized));
+        assert_eq!(reopened.file("not-a-cid"), None);
+
+        tokio::fs::remove_dir_all(&config.dir).await
+    }
+}
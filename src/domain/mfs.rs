@@ -0,0 +1,456 @@
+//! Placement of uploads in the IPFS node's MFS
+//!
+//! CIDs added to IPFS end up in the node's pinset with nothing to tell them
+//! apart. When `IPFS_MFS_PATH` is set, each upload stored on IPFS is also
+//! copied into the node's Mutable File System under the path the template
+//! describes, e.g. `/memenow/{tenant}/{yyyy}/{mm}/{filename}`, so operators
+//! can browse uploads with `ipfs files ls`. Uploads without a tenant are
+//! placed under `default`. A copy only references the upload's blocks, so
+//! it takes no extra space.
+//!
+//! A different file already placed at the same path is kept; the new upload
+//! is placed next to it with its CID prefixed to the filename.
+//!
+//! Every `IPFS_MFS_SNAPSHOT_INTERVAL_SECS`, the CID of the MFS root (the
+//! directory above the template's first placeholder) is read. When it has
+//! changed, the new root is pinned in place of the previous snapshot, so
+//! the snapshot survives later changes to MFS, and published as an
+//! `mfs.snapshot` webhook event.
+//!
+//! Placement runs in the background once the upload has completed, and a
+//! failure is logged without failing the upload.
+
+use crate::config::{CidBase, MfsConfig};
+use crate::domain::webhooks::{Event, MfsSnapshot};
+use crate::infrastructure::ipfs;
+use crate::state::AppState;
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// Directory of uploads without a tenant
+const DEFAULT_TENANT: &str = "default";
+
+/// An upload stored on IPFS, to place in MFS
+#[derive(Debug, Clone)]
+pub struct MfsPlacement {
+    /// Tenant the upload belongs to
+    pub tenant: Option<String>,
+    /// CID of the upload
+    pub cid: String,
+    /// The original filename
+    pub filename: String,
+    /// Whether the CID is a directory wrapping the file
+    pub wrapped: bool,
+}
+
+/// Place an upload in MFS in the background
+///
+/// Does nothing unless `IPFS_MFS_PATH` is set.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `placement` - The upload to place
+pub fn place(state: &AppState, placement: MfsPlacement) {
+    if state.config.ipfs.mfs.path.is_none() {
+        return;
+    }
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        match copy(&state, &placement, unix_secs()).await {
+            Ok(path) => info!("Placed {} in MFS at {}", placement.cid, path),
+            Err(e) => warn!(
+                "Failed to place {} ('{}') in MFS: {:#}",
+                placement.cid, placement.filename, e
+            ),
+        }
+    });
+}
+
+/// Start the background worker snapshotting the MFS root
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+///
+/// # Returns
+///
+/// Returns the worker's handle, or `None` if no snapshots are taken
+pub fn spawn_snapshots(state: AppState) -> Option<JoinHandle<()>> {
+    let interval = state.config.ipfs.mfs.snapshot_interval()?;
+    let root = state.config.ipfs.mfs.root()?;
+
+    let mut stopping = state.shutdown.subscribe();
+
+    Some(tokio::spawn(async move {
+        let mut previous = None;
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = stopping.changed() => return,
+            }
+            match snapshot(&state, &root, previous.as_deref()).await {
+                Ok(Some(cid)) => previous = Some(cid),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to snapshot MFS root {}: {:#}", root, e),
+            }
+        }
+    }))
+}
+
+/// Copy an upload to its MFS path
+///
+/// # Returns
+///
+/// Returns the MFS path of the upload
+async fn copy(state: &AppState, placement: &MfsPlacement, now: u64) -> Result<String> {
+    let config = &state.config;
+    let template = config.ipfs.mfs.path.as_deref().unwrap_or_default();
+    let (policy, timeout) = (&config.retry.ipfs, config.ipfs.timeout());
+
+    let source = if placement.wrapped {
+        format!(
+            "/ipfs/{}/{}",
+            placement.cid,
+            ipfs::entry_name(&placement.filename)
+        )
+    } else {
+        format!("/ipfs/{}", placement.cid)
+    };
+
+    let tenant = placement.tenant.as_deref();
+    let renamed = format!("{}_{}", placement.cid, placement.filename);
+    let candidates = [
+        mfs_path(template, tenant, &placement.filename, &placement.cid, now),
+        mfs_path(template, tenant, &renamed, &placement.cid, now),
+    ];
+
+    for path in &candidates {
+        match ipfs::stat_mfs(&state.ipfs, policy, timeout, path).await? {
+            None => {
+                ipfs::copy_to_mfs(&state.ipfs, policy, timeout, &source, path).await?;
+                return Ok(path.clone());
+            }
+            Some(existing) if same_cid(&existing, &placement.cid) => {
+                debug!("{} is already placed at {}", placement.cid, path);
+                return Ok(path.clone());
+            }
+            Some(existing) => debug!("{} is taken by {}", path, existing),
+        }
+    }
+
+    anyhow::bail!("MFS paths {} are taken", candidates.join(" and "))
+}
+
+/// Pin the MFS root if it changed since the previous snapshot
+///
+/// # Returns
+///
+/// Returns the CID of the new snapshot, or `None` if the root does not
+/// exist yet or is unchanged
+async fn snapshot(state: &AppState, root: &str, previous: Option<&str>) -> Result<Option<String>> {
+    let config = &state.config;
+    let (policy, timeout) = (&config.retry.ipfs, config.ipfs.timeout());
+
+    let Some(cid) = ipfs::stat_mfs(&state.ipfs, policy, timeout, root).await? else {
+        return Ok(None);
+    };
+    if previous == Some(cid.as_str()) {
+        return Ok(None);
+    }
+
+    ipfs::pin_update(&state.ipfs, policy, timeout, previous, &cid).await?;
+    info!("Snapshot of MFS root {}: {}", root, cid);
+
+    let snapshot = MfsSnapshot {
+        root: root.to_string(),
+        cid: cid.clone(),
+        previous: previous.map(String::from),
+    };
+    state.webhooks.emit(Event::mfs_snapshot(snapshot)).await;
+
+    Ok(Some(cid))
+}
+
+/// Fill in the placeholders of an MFS path template
+///
+/// # Arguments
+///
+/// * `template` - The template, see [`MfsConfig::path`]
+/// * `tenant` - Tenant of the upload, if any
+/// * `filename` - The original filename
+/// * `cid` - CID of the upload
+/// * `now` - Unix time of the upload in seconds, giving the date
+pub(crate) fn mfs_path(
+    template: &str,
+    tenant: Option<&str>,
+    filename: &str,
+    cid: &str,
+    now: u64,
+) -> String {
+    let (year, month, day) = civil_date(now);
+    let values = [
+        tenant.unwrap_or(DEFAULT_TENANT).to_string(),
+        format!("{:04}", year),
+        format!("{:02}", month),
+        format!("{:02}", day),
+        ipfs::entry_name(filename),
+        cid.to_string(),
+    ];
+
+    // Substitute in one pass, so values are never read as placeholders
+    let mut path = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map_or(rest.len(), |end| start + end + 1);
+        let placeholder = &rest[start..end];
+        match MfsConfig::PLACEHOLDERS
+            .iter()
+            .position(|known| *known == placeholder)
+        {
+            Some(index) => path.push_str(&values[index]),
+            None => path.push_str(placeholder),
+        }
+        rest = &rest[end..];
+    }
+    path.push_str(rest);
+
+    path
+}
+
+/// Whether two CIDs address the same content, whatever their encoding
+fn same_cid(a: &str, b: &str) -> bool {
+    match (
+        ipfs::encode_cid(a, CidBase::Base32),
+        ipfs::encode_cid(b, CidBase::Base32),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Convert a Unix time to a UTC calendar date
+///
+/// # Returns
+///
+/// Returns the year, month (1-12) and day (1-31)
+fn civil_date(unix_secs: u64) -> (i64, u32, u32) {
+    // Days since 0000-03-01, so leap days fall at the end of a year
+    let days = (unix_secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month as u32, day as u32)
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    const CID: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+    const CID_V1: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+    /// 2025-10-09T08:53:20Z
+    const NOW: u64 = 1_760_000_000;
+
+    #[derive(Debug, Default)]
+    struct Node {
+        /// CID of each MFS file by path
+        files: BTreeMap<String, String>,
+        /// Pinned snapshots
+        pins: Vec<String>,
+    }
+
+    /// Serve a fake RPC API with a flat MFS below `/memenow`, whose root
+    /// CID is the number of files in it
+    fn mock_node() -> (Config, Arc<Mutex<Node>>) {
+        use warp::http::StatusCode;
+        use warp::Filter;
+
+        let node = Arc::new(Mutex::new(Node::default()));
+        let reply =
+            |status, body: serde_json::Value| warp::reply::with_status(body.to_string(), status);
+
+        let routes = {
+            let node = node.clone();
+            warp::path!("api" / "v0" / String / String)
+                .and(warp::query::raw())
+                .map(move |group: String, call: String, query: String| {
+                    let args: Vec<String> = reqwest::Url::parse(&format!("http://node/?{}", query))
+                        .unwrap()
+                        .query_pairs()
+                        .filter(|(key, _)| key == "arg")
+                        .map(|(_, value)| value.into_owned())
+                        .collect();
+                    let mut node = node.lock().unwrap();
+
+                    match (group.as_str(), call.as_str()) {
+                        ("files", "stat") if args[0] == "/memenow" && !node.files.is_empty() => {
+                            let root = format!("root-{}", node.files.len());
+                            reply(StatusCode::OK, serde_json::json!({ "Hash": root }))
+                        }
+                        ("files", "stat") => match node.files.get(&args[0]) {
+                            Some(cid) => reply(StatusCode::OK, serde_json::json!({ "Hash": cid })),
+                            None => reply(
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                serde_json::json!({ "Message": "file does not exist" }),
+                            ),
+                        },
+                        ("files", "cp") => {
+                            let cid = args[0].trim_start_matches("/ipfs/").to_string();
+                            node.files.insert(args[1].clone(), cid);
+                            reply(StatusCode::OK, serde_json::json!({}))
+                        }
+                        ("pin", "add") => {
+                            node.pins.push(args[0].clone());
+                            reply(StatusCode::OK, serde_json::json!({ "Pins": [args[0]] }))
+                        }
+                        ("pin", "update") if node.pins.contains(&args[0]) => {
+                            node.pins.retain(|pin| *pin != args[0]);
+                            node.pins.push(args[1].clone());
+                            reply(StatusCode::OK, serde_json::json!({ "Pins": args }))
+                        }
+                        _ => reply(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            serde_json::json!({ "Message": "unexpected call" }),
+                        ),
+                    }
+                })
+        };
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut config = Config::default();
+        config.ipfs.api_url = format!("http://{}", addr);
+        config.ipfs.mfs.path = Some(String::from("/memenow/{tenant}/{yyyy}/{mm}/{filename}"));
+        config.retry.ipfs.max_attempts = 1;
+
+        (config, node)
+    }
+
+    fn placement(cid: &str, filename: &str) -> MfsPlacement {
+        MfsPlacement {
+            tenant: Some(String::from("acme")),
+            cid: cid.to_string(),
+            filename: filename.to_string(),
+            wrapped: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_places_uploads() {
+        let (config, node) = mock_node();
+        let state = AppState::new(config).await.unwrap();
+
+        let path = copy(&state, &placement(CID, "cat.gif"), NOW).await.unwrap();
+        assert_eq!(path, "/memenow/acme/2025/10/cat.gif");
+
+        // The same content is not copied again, even in another encoding
+        let path = copy(&state, &placement(CID_V1, "cat.gif"), NOW)
+            .await
+            .unwrap();
+        assert_eq!(path, "/memenow/acme/2025/10/cat.gif");
+        assert_eq!(node.lock().unwrap().files.len(), 1);
+
+        // Other content with the same name is placed next to it
+        let other = "QmPhoto";
+        let path = copy(&state, &placement(other, "cat.gif"), NOW)
+            .await
+            .unwrap();
+        assert_eq!(path, "/memenow/acme/2025/10/QmPhoto_cat.gif");
+
+        let wrapped = MfsPlacement {
+            tenant: None,
+            wrapped: true,
+            ..placement(CID, "dog.gif")
+        };
+        let path = copy(&state, &wrapped, NOW).await.unwrap();
+        assert_eq!(path, "/memenow/default/2025/10/dog.gif");
+        assert_eq!(
+            node.lock().unwrap().files[&path],
+            format!("{}/dog.gif", CID)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_pins_changed_roots() {
+        let (config, node) = mock_node();
+        let state = AppState::new(config).await.unwrap();
+
+        // Nothing to snapshot before the first upload
+        assert_eq!(snapshot(&state, "/memenow", None).await.unwrap(), None);
+
+        copy(&state, &placement(CID, "cat.gif"), NOW).await.unwrap();
+        let first = snapshot(&state, "/memenow", None).await.unwrap().unwrap();
+        assert_eq!(first, "root-1");
+        assert_eq!(
+            snapshot(&state, "/memenow", Some(&first)).await.unwrap(),
+            None
+        );
+
+        copy(&state, &placement("QmPhoto", "photo.jpg"), NOW)
+            .await
+            .unwrap();
+        let second = snapshot(&state, "/memenow", Some(&first))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second, "root-2");
+        assert_eq!(node.lock().unwrap().pins, vec![second]);
+    }
+
+    #[test]
+    fn test_mfs_path() {
+        let template = "/memenow/{tenant}/{yyyy}/{mm}/{dd}/{filename}";
+        assert_eq!(
+            mfs_path(template, Some("acme"), "a/b.txt", CID, NOW),
+            "/memenow/acme/2025/10/09/a_b.txt"
+        );
+        assert_eq!(
+            mfs_path("/by-cid/{cid}", None, "b.txt", CID, NOW),
+            format!("/by-cid/{}", CID)
+        );
+        assert_eq!(
+            mfs_path("/m/{filename}", None, "{cid}.txt", CID, NOW),
+            "/m/{cid}.txt"
+        );
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(951_782_400), (2000, 2, 29));
+        assert_eq!(civil_date(NOW), (2025, 10, 9));
+        assert_eq!(civil_date(1_798_761_599), (2026, 12, 31));
+    }
+}
@@ -6,7 +6,19 @@
 //!
 //! # Submodules
 //!
+//! - `car`: Import of CAR files and export of uploads as CAR files
+//! - `ipns`: IPNS names for collections of uploads
+//! - `jobs`: Durable background jobs for uploads accepted before they are stored
+//! - `metadata`: Durable store of the service's own records of files and IPNS names
+//! - `mfs`: Placement of uploads in the IPFS node's MFS and snapshots of its root
+//! - `objects`: Headers, metadata and tags of the S3 objects of uploads
+//! - `pinning`: Background pinning of uploads with a remote pinning service
+//! - `replication`: Background copying of replicas a backend missed
+//! - `rollback`: Compensating rollback of uploads that failed on one backend
 //! - `services`: Service layer implementing business operations for file uploads
+//! - `streaming`: Streaming upload pipeline that tees the request body to the backends
+//! - `tracker`: Tracking of in-flight uploads and their temporary files
+//! - `webhooks`: Webhook notifications of upload lifecycle events, with a durable outbox
 //!
 //! # Architecture
 //!
@@ -20,4 +32,16 @@
 //! This separation allows the business logic to remain clean and testable,
 //! independent of external service implementations.
 
+pub mod car;
+pub mod ipns;
+pub mod jobs;
+pub mod metadata;
+pub mod mfs;
+pub mod objects;
+pub mod pinning;
+pub mod replication;
+pub mod rollback;
 pub mod services;
+pub mod streaming;
+pub mod tracker;
+pub mod webhooks;
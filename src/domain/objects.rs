@@ -0,0 +1,275 @@
+//! Headers, metadata and tags of S3 objects
+//!
+//! S3 serves an object with the headers it was created with, so they are
+//! decided before the upload starts:
+//!
+//! - `Content-Type` is the media type declared for the file part, or one
+//!   guessed from the filename's extension when the part declares none or
+//!   `application/octet-stream`
+//! - `Cache-Control` and the disposition type come from the first
+//!   [`S3ObjectRule`](crate::config::S3ObjectRule) matching the content
+//!   type, or the `S3_CACHE_CONTROL` and `S3_CONTENT_DISPOSITION` defaults
+//! - `Content-Disposition` names the original filename
+//! - User metadata records the `uploader` (the tenant), and for spooled
+//!   uploads the `cid` computed while spooling and the file's `sha256`
+//! - Tags are `S3_TAGS`, then the tags of the matching rule, then the tags
+//!   given in the request's `X-Object-Tags` header, later ones winning
+//! - The storage class and server-side encryption are the tenant's (see
+//!   [`S3StorageConfig`](crate::config::S3StorageConfig))
+
+use crate::config::{check_tags, Disposition, S3Config};
+use crate::error::StorageError;
+use crate::infrastructure::s3::ObjectAttributes;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use warp::http::HeaderMap;
+
+/// Header carrying the tags of an upload, as a URL query string such as
+/// `project=alpha&owner=media%20team`
+pub const TAGS_HEADER: &str = "x-object-tags";
+
+/// Media type of files of unknown type
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Characters escaped in the `filename*` parameter (RFC 8187 `attr-char`)
+const FILENAME_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// What an upload's request and contents say about its S3 object
+///
+/// Kept with background jobs and replication tasks, so an object created
+/// later gets the same attributes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectInfo {
+    /// Media type declared for the file part
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// SHA-256 of the file, hex encoded, if it was computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Tags given in the request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Read the tags of an upload from the `X-Object-Tags` header
+///
+/// # Errors
+///
+/// Returns an error if the header is not a valid query string of valid
+/// tags (see [`check_tags`])
+pub(crate) fn request_tags(headers: &HeaderMap) -> Result<BTreeMap<String, String>, StorageError> {
+    let Some(value) = headers.get(TAGS_HEADER) else {
+        return Ok(BTreeMap::new());
+    };
+    let invalid =
+        |e: String| StorageError::UploadError(format!("Invalid X-Object-Tags header: {}", e));
+
+    let value = value.to_str().map_err(|e| invalid(e.to_string()))?;
+    let tags: BTreeMap<String, String> = form_urlencoded::parse(value.as_bytes())
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    check_tags(&tags).map_err(invalid)?;
+
+    Ok(tags)
+}
+
+/// Decide the attributes of an object
+///
+/// # Arguments
+///
+/// * `config` - S3 settings
+/// * `filename` - The original filename
+/// * `tenant` - Tenant the upload belongs to, recorded as its uploader
+/// * `cid` - CID of the file, if known
+/// * `info` - What the request and contents say about the object
+pub(crate) fn attributes(
+    config: &S3Config,
+    filename: &str,
+    tenant: Option<&str>,
+    cid: Option<&str>,
+    info: &ObjectInfo,
+) -> ObjectAttributes {
+    let storage = config.storage.for_tenant(tenant);
+    let config = &config.object;
+    let content_type = content_type(info.content_type.as_deref(), filename);
+    let rule = config.rule(&content_type);
+
+    let disposition = rule
+        .and_then(|rule| rule.content_disposition)
+        .unwrap_or(config.content_disposition);
+
+    let mut tags = config.tags.clone();
+    if let Some(rule) = rule {
+        tags.extend(rule.tags.clone());
+    }
+    tags.extend(info.tags.clone());
+
+    let metadata = [
+        ("uploader", tenant),
+        ("cid", cid),
+        ("sha256", info.sha256.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_string(), value?.to_string())))
+    .collect();
+
+    ObjectAttributes {
+        cache_control: rule
+            .and_then(|rule| rule.cache_control.clone())
+            .or_else(|| config.cache_control.clone()),
+        content_disposition: content_disposition(disposition, filename),
+        content_type: Some(content_type),
+        metadata,
+        tags,
+        storage,
+    }
+}
+
+/// Pick the media type of a file
+///
+/// # Arguments
+///
+/// * `declared` - Media type declared for the file part
+/// * `filename` - The original filename
+fn content_type(declared: Option<&str>, filename: &str) -> String {
+    match declared.filter(|declared| !declared.eq_ignore_ascii_case(OCTET_STREAM)) {
+        Some(declared) => declared.to_string(),
+        None => mime_guess::from_path(filename)
+            .first_raw()
+            .unwrap_or(OCTET_STREAM)
+            .to_string(),
+    }
+}
+
+/// Build a `Content-Disposition` naming the original filename
+///
+/// The `filename` parameter holds an ASCII fallback for old clients;
+/// `filename*` holds the exact name (RFC 6266).
+fn content_disposition(disposition: Disposition, filename: &str) -> Option<String> {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+        Disposition::Off => return None,
+    };
+
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' ' | '!' | '#'..='[' | ']'..='~' => c,
+            _ => '_',
+        })
+        .collect();
+
+    Some(format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind,
+        fallback,
+        utf8_percent_encode(filename, FILENAME_ESCAPES)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Encryption, S3ObjectConfig, S3ObjectRule, S3Storage};
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type(Some("image/webp"), "cat.png"), "image/webp");
+        assert_eq!(content_type(None, "cat.png"), "image/png");
+        assert_eq!(content_type(Some(OCTET_STREAM), "cat.png"), "image/png");
+        assert_eq!(content_type(None, "README"), OCTET_STREAM);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition(Disposition::Attachment, "café \"menu\".pdf").unwrap(),
+            "attachment; filename=\"caf_ _menu_.pdf\"; filename*=UTF-8''caf%C3%A9%20%22menu%22.pdf"
+        );
+        assert_eq!(content_disposition(Disposition::Off, "menu.pdf"), None);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut config = Config::default().s3;
+        config.object = S3ObjectConfig {
+            cache_control: Some("no-cache".to_string()),
+            tags: BTreeMap::from([
+                ("env".to_string(), "prod".to_string()),
+                ("class".to_string(), "file".to_string()),
+            ]),
+            rules: vec![S3ObjectRule {
+                content_type: "image/*".to_string(),
+                cache_control: Some("public, max-age=31536000".to_string()),
+                content_disposition: None,
+                tags: BTreeMap::from([("class".to_string(), "media".to_string())]),
+            }],
+            ..S3ObjectConfig::default()
+        };
+        config.storage.default.storage_class = Some("STANDARD_IA".to_string());
+        config.storage.tenants.insert(
+            "acme".to_string(),
+            S3Storage {
+                encryption: Some(Encryption::SseKms),
+                ..S3Storage::default()
+            },
+        );
+        let info = ObjectInfo {
+            content_type: None,
+            sha256: Some("ab12".to_string()),
+            tags: BTreeMap::from([("project".to_string(), "alpha".to_string())]),
+        };
+
+        let object = attributes(&config, "cat.png", Some("acme"), Some("QmCid"), &info);
+        assert_eq!(object.content_type.as_deref(), Some("image/png"));
+        assert_eq!(
+            object.cache_control.as_deref(),
+            Some("public, max-age=31536000")
+        );
+        assert!(object.content_disposition.unwrap().starts_with("inline;"));
+        assert_eq!(object.metadata["uploader"], "acme");
+        assert_eq!(object.metadata["cid"], "QmCid");
+        assert_eq!(object.metadata["sha256"], "ab12");
+        assert_eq!(object.tags["class"], "media");
+        assert_eq!(object.tags["env"], "prod");
+        assert_eq!(object.tags["project"], "alpha");
+        assert_eq!(object.storage.storage_class.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(object.storage.encryption, Some(Encryption::SseKms));
+
+        let object = attributes(&config, "notes.txt", None, None, &ObjectInfo::default());
+        assert_eq!(object.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(object.tags["class"], "file");
+        assert!(object.metadata.is_empty());
+        assert_eq!(object.storage.encryption, None);
+    }
+
+    #[test]
+    fn test_request_tags() {
+        let mut headers = HeaderMap::new();
+        assert!(request_tags(&headers).unwrap().is_empty());
+
+        headers.insert(
+            TAGS_HEADER,
+            "project=alpha&owner=media+team".parse().unwrap(),
+        );
+        let tags = request_tags(&headers).unwrap();
+        assert_eq!(tags["owner"], "media team");
+
+        headers.insert(TAGS_HEADER, "aws:secret=1".parse().unwrap());
+        assert!(request_tags(&headers).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Remote pinning of uploads
+//!
+//! Content added to the service's own IPFS node is lost if that node is.
+//! When a pinning service is configured (`PINNING_SERVICE_ENDPOINT`), every
+//! CID stored on IPFS is also pinned with it, using the access token of the
+//! upload's tenant. The pin is requested in the background once the upload
+//! has completed, and its status is polled until the service reports it
+//! `pinned` or `failed`. A request that fails, or is still not pinned after
+//! `PINNING_PIN_TIMEOUT_SECS`, is removed from the service again.
+//!
+//! A CID the tenant's account already has an active pin request for is not
+//! requested twice; that request is followed instead.
+//!
+//! Like the replication queue, the queue is held in memory, so pins still
+//! queued when the process stops are not requested.
+
+use crate::config::PinningConfig;
+use crate::infrastructure::pinning::{PinRequest, PinState, PinStatus, PinningService};
+use crate::infrastructure::retry::retry;
+use crate::utils::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Longest pin name the Pinning Service API accepts, in characters
+const MAX_PIN_NAME_LEN: usize = 255;
+
+/// Every state a pin request can be in
+const ALL_STATES: [PinState; 4] = [
+    PinState::Queued,
+    PinState::Pinning,
+    PinState::Pinned,
+    PinState::Failed,
+];
+
+/// A CID to pin with the remote service
+#[derive(Debug, Clone)]
+pub struct RemotePinTask {
+    /// Tenant the upload belongs to, selecting the access token
+    pub tenant: Option<String>,
+    /// CID to pin
+    pub cid: String,
+    /// The original filename, used as the pin's name
+    pub filename: String,
+}
+
+/// Handle for queueing remote pins
+///
+/// Cloning the handle is cheap; all clones feed the same worker.
+#[derive(Debug, Clone)]
+pub struct RemotePins {
+    config: Arc<PinningConfig>,
+    /// The pinning service, if one is configured
+    service: Option<PinningService>,
+    tx: mpsc::UnboundedSender<RemotePinTask>,
+}
+
+impl RemotePins {
+    /// Create the handle and the receiver to hand to [`spawn_worker`]
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Pinning service settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP client cannot be created, i.e. the TLS
+    /// backend cannot be initialized
+    pub fn new(config: &PinningConfig) -> Result<(Self, mpsc::UnboundedReceiver<RemotePinTask>)> {
+        let service = config
+            .endpoint
+            .as_deref()
+            .map(|endpoint| PinningService::new(endpoint, config.timeout()))
+            .transpose()?;
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let pins = Self {
+            config: Arc::new(config.clone()),
+            service,
+            tx,
+        };
+
+        Ok((pins, rx))
+    }
+
+    /// Queue a CID for remote pinning
+    ///
+    /// Nothing is queued if no pinning service is configured or the tenant
+    /// has no access token.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The CID to pin
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the pin was queued
+    pub fn enqueue(&self, task: RemotePinTask) -> bool {
+        if self.service.is_none() || self.config.token_for(task.tenant.as_deref()).is_none() {
+            return false;
+        }
+
+        info!("Queued remote pin of {} ('{}')", task.cid, task.filename);
+
+        if self.tx.send(task).is_err() {
+            error!("Remote pinning worker has stopped, dropping pin");
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Start the background worker pinning queued CIDs
+///
+/// Each pin is requested and followed concurrently, since a service may
+/// take minutes to fetch the content. Once `shutdown` is stopped no further
+/// pin is requested, and pins being followed are left to the service.
+///
+/// # Arguments
+///
+/// * `pins` - The handle whose queue is processed
+/// * `tasks` - Receiver returned by [`RemotePins::new`]
+/// * `shutdown` - Stops the worker when the service shuts down
+pub fn spawn_worker(
+    pins: RemotePins,
+    mut tasks: mpsc::UnboundedReceiver<RemotePinTask>,
+    shutdown: &Shutdown,
+) -> JoinHandle<()> {
+    let mut stopping = shutdown.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let task = tokio::select! {
+                task = tasks.recv() => task,
+                _ = stopping.changed() => None,
+            };
+            let Some(task) = task else {
+                break;
+            };
+
+            let pins = pins.clone();
+            let mut stopping = stopping.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    result = pin(&pins, &task) => match result {
+                        Ok(status) => info!(
+                            "Pinned {} remotely ('{}', request {})",
+                            task.cid, task.filename, status.requestid
+                        ),
+                        Err(e) => error!(
+                            "Failed to pin {} remotely ('{}'): {:#}",
+                            task.cid, task.filename, e
+                        ),
+                    },
+                    _ = stopping.changed() => warn!(
+                        "Stopped following the remote pin of {} ('{}') on shutdown",
+                        task.cid, task.filename
+                    ),
+                }
+            });
+        }
+    })
+}
+
+/// Pin a CID with the service and wait until it is pinned
+///
+/// # Returns
+///
+/// Returns the final status of the pin request
+///
+/// # Errors
+///
+/// Returns an error if the service cannot be reached, rejects the pin,
+/// fails to pin the content or does not pin it in time
+#[tracing::instrument(skip_all, fields(cid = %task.cid))]
+async fn pin(pins: &RemotePins, task: &RemotePinTask) -> Result<PinStatus> {
+    let config = &pins.config;
+    let service = pins
+        .service
+        .as_ref()
+        .context("No pinning service configured")?;
+    let token = config
+        .token_for(task.tenant.as_deref())
+        .context("No pinning service token for the tenant")?;
+    let policy = &config.retry;
+
+    let existing = retry(policy, "pinning.list", || {
+        service.list_pins(token, &task.cid, &ALL_STATES)
+    })
+    .await?;
+
+    // Follow an active request for the CID rather than pinning it twice
+    let mut status = match existing
+        .iter()
+        .find(|status| status.status != PinState::Failed)
+    {
+        Some(status) => {
+            info!(
+                "Following existing remote pin {} of {}",
+                status.requestid, task.cid
+            );
+            status.clone()
+        }
+        None => {
+            for failed in &existing {
+                remove(service, token, failed).await;
+            }
+
+            let request = pin_request(config, task);
+            retry(policy, "pinning.add", || service.add_pin(token, &request)).await?
+        }
+    };
+
+    let deadline = Instant::now() + config.pin_timeout();
+    while !status.status.is_finished() {
+        if Instant::now() >= deadline {
+            remove(service, token, &status).await;
+            anyhow::bail!(
+                "Remote pin {} still {} after {:?}",
+                status.requestid,
+                status.status,
+                config.pin_timeout()
+            );
+        }
+
+        tokio::time::sleep(config.poll_interval()).await;
+        status = retry(policy, "pinning.get", || {
+            service.get_pin(token, &status.requestid)
+        })
+        .await?;
+    }
+
+    if status.status == PinState::Failed {
+        remove(service, token, &status).await;
+        anyhow::bail!(
+            "Pinning service failed remote pin {}: {}",
+            status.requestid,
+            status
+                .info
+                .get("status_details")
+                .map_or("no details", String::as_str)
+        );
+    }
+
+    Ok(status)
+}
+
+/// Build the pin request of a task
+fn pin_request(config: &PinningConfig, task: &RemotePinTask) -> PinRequest {
+    let name = task.filename.chars().take(MAX_PIN_NAME_LEN).collect();
+    let meta = task
+        .tenant
+        .iter()
+        .map(|tenant| (String::from("tenant"), tenant.clone()))
+        .collect::<BTreeMap<_, _>>();
+
+    PinRequest {
+        cid: task.cid.clone(),
+        name: Some(name),
+        origins: config.origins.clone(),
+        meta,
+    }
+}
+
+/// Remove a pin request, logging rather than failing if it cannot be
+/// removed
+async fn remove(service: &PinningService, token: &str, status: &PinStatus) {
+    match service.remove_pin(token, &status.requestid).await {
+        Ok(()) => info!("Removed {} remote pin {}", status.status, status.requestid),
+        Err(e) => warn!("Failed to remove remote pin {}: {:#}", status.requestid, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::pinning::mock;
+    use std::time::Duration;
+
+    const CID: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+
+    fn config(addr: std::net::SocketAddr) -> PinningConfig {
+        PinningConfig {
+            endpoint: Some(format!("http://{}", addr)),
+            tenant_tokens: BTreeMap::from([(String::from("acme"), String::from("acme-token"))]),
+            poll_interval_secs: 1,
+            ..PinningConfig::default()
+        }
+    }
+
+    fn task(filename: &str) -> RemotePinTask {
+        RemotePinTask {
+            tenant: Some(String::from("acme")),
+            cid: CID.to_string(),
+            filename: filename.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pin_waits_until_pinned() {
+        let (addr, pins) = mock::spawn(&["acme-token"]);
+        let (remote, _tasks) = RemotePins::new(&config(addr)).unwrap();
+
+        let status = pin(&remote, &task("photo.jpg")).await.unwrap();
+        assert_eq!(status.status, PinState::Pinned);
+        assert_eq!(status.pin.name.as_deref(), Some("photo.jpg"));
+        assert_eq!(status.pin.meta["tenant"], "acme");
+
+        // Pinning the CID again follows the existing request
+        pin(&remote, &task("copy.jpg")).await.unwrap();
+        assert_eq!(pins.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_pin_is_removed() {
+        let (addr, pins) = mock::spawn(&["acme-token"]);
+        let (remote, _tasks) = RemotePins::new(&config(addr)).unwrap();
+
+        assert!(pin(&remote, &task("fail")).await.is_err());
+        assert!(pins.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_requires_a_token() {
+        let (remote, mut tasks) = RemotePins::new(&PinningConfig::default()).unwrap();
+        assert!(!remote.enqueue(task("photo.jpg")));
+
+        let (addr, _pins) = mock::spawn(&["acme-token"]);
+        let (remote, mut queued) = RemotePins::new(&config(addr)).unwrap();
+        assert!(remote.enqueue(task("photo.jpg")));
+        assert!(!remote.enqueue(RemotePinTask {
+            tenant: Some(String::from("globex")),
+            ..task("photo.jpg")
+        }));
+        assert!(!remote.enqueue(RemotePinTask {
+            tenant: None,
+            ..task("photo.jpg")
+        }));
+
+        assert!(queued.try_recv().is_ok());
+        assert!(queued.try_recv().is_err());
+        assert!(tasks.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pin_times_out() {
+        let (addr, pins) = mock::spawn(&["acme-token"]);
+        let (remote, _tasks) = RemotePins::new(&PinningConfig {
+            pin_timeout_secs: 1,
+            poll_interval_secs: 2,
+            ..config(addr)
+        })
+        .unwrap();
+        let started = Instant::now();
+        assert!(pin(&remote, &task("photo.jpg")).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(pins.lock().unwrap().is_empty());
+    }
+}
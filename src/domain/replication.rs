@@ -0,0 +1,507 @@
+//! Background replication of missing replicas
+//!
+//! Under a write policy weaker than `all`, an upload can succeed while one
+//! backend failed to store the file. The missing replica is queued here and
+//! copied from a backend that has the file by a background worker: an S3
+//! object is added to IPFS, or an IPFS CID is uploaded to S3.
+//!
+//! Every queued replica is mirrored to a JSON record in `REPLICATION_DIR`
+//! until it has been copied, so replicas still queued when the process stops
+//! are copied after a restart. A replica that still fails after
+//! [`MAX_REPLICATION_ATTEMPTS`] attempts is moved to the `dead` subdirectory.
+
+use crate::config::{Backend, IpfsAddOptions, ReplicationConfig};
+use crate::domain::metadata::{FileOrigin, FileRecord};
+use crate::domain::mfs::{self, MfsPlacement};
+use crate::domain::objects::{self, ObjectInfo};
+use crate::domain::pinning::RemotePinTask;
+use crate::domain::services;
+use crate::infrastructure::{ipfs, s3};
+use crate::state::AppState;
+use crate::utils::file;
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::join;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Number of times a replica is attempted before it is given up on
+pub const MAX_REPLICATION_ATTEMPTS: u32 = 5;
+
+/// Delay before a failed replication is attempted again
+pub const REPLICATION_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A replica to be copied to a backend that missed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationTask {
+    /// Task ID, naming the queue record
+    pub id: Uuid,
+    /// The original filename, for logging
+    pub filename: String,
+    /// The S3 key of the file
+    pub key: String,
+    /// The IPFS CID of the file, if IPFS stored it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    /// Options the file was added to IPFS with, so a new IPFS replica gets
+    /// the same CID the other uploads of this file would
+    pub ipfs_add: IpfsAddOptions,
+    /// Tenant the upload belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// What the upload's request and contents say about the S3 object, so
+    /// an S3 replica is created with the same attributes
+    #[serde(default)]
+    pub object: ObjectInfo,
+    /// The backend the file must be copied to
+    pub missing: Backend,
+    /// Attempts made so far
+    pub attempts: u32,
+    /// Unix time of the next attempt, in milliseconds
+    pub next_attempt_at: u64,
+    /// Why the last attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Durable queue of missing replicas
+///
+/// Every replica is kept in memory and mirrored to `{dir}/{id}.json` until
+/// it has been copied. Cloning the queue is cheap; all clones feed the same
+/// worker.
+#[derive(Debug, Clone)]
+pub struct ReplicationQueue {
+    dir: PathBuf,
+    tasks: Arc<Mutex<HashMap<Uuid, ReplicationTask>>>,
+    /// Wakes the worker when a replica is queued
+    added: Arc<Notify>,
+}
+
+impl ReplicationQueue {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            tasks: Arc::default(),
+            added: Arc::default(),
+        }
+    }
+
+    /// Open the queue, loading the replicas found in the replication
+    /// directory
+    ///
+    /// The directory is created when the first replica is queued, so a
+    /// missing directory is an empty queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Replication settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory exists but cannot be read.
+    /// Unreadable records are skipped with a warning.
+    pub async fn open(config: &ReplicationConfig) -> io::Result<Self> {
+        let queue = Self::new(PathBuf::from(&config.dir));
+
+        let mut entries = match tokio::fs::read_dir(&queue.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(queue),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                // Left behind by a write that was interrupted
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+            if !path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+
+            let task = tokio::fs::read(&path).await.and_then(|data| {
+                serde_json::from_slice::<ReplicationTask>(&data).map_err(io::Error::other)
+            });
+            match task {
+                Ok(task) => {
+                    queue.tasks().insert(task.id, task);
+                }
+                Err(e) => warn!("Skipping unreadable replica {}: {}", path.display(), e),
+            }
+        }
+
+        if !queue.is_empty() {
+            info!("Resuming {} queued replication(s)", queue.len());
+        }
+
+        Ok(queue)
+    }
+
+    /// Number of replicas not yet copied
+    pub fn len(&self) -> usize {
+        self.tasks().len()
+    }
+
+    /// Whether every replica has been copied
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist a replica and queue it for background replication
+    ///
+    /// Failures to write the queue are logged rather than returned, so they
+    /// never fail the upload that missed the replica.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The replica to copy
+    pub async fn enqueue(&self, task: ReplicationTask) {
+        if let Err(e) = self.save(&task).await {
+            error!(
+                "Failed to queue replication of '{}' to {}, dropping it: {}",
+                task.filename, task.missing, e
+            );
+            return;
+        }
+
+        info!(
+            "Queued replication of '{}' to {}",
+            task.filename, task.missing
+        );
+        self.tasks().insert(task.id, task);
+        self.added.notify_one();
+    }
+
+    /// Replicas whose next attempt is due at `now` (Unix milliseconds),
+    /// oldest first
+    fn due(&self, now: u64) -> Vec<ReplicationTask> {
+        let mut due: Vec<ReplicationTask> = self
+            .tasks()
+            .values()
+            .filter(|task| task.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|task| task.next_attempt_at);
+        due
+    }
+
+    /// Time until the next replica is due, if any
+    fn next_due_in(&self, now: u64) -> Option<Duration> {
+        self.tasks()
+            .values()
+            .map(|task| Duration::from_millis(task.next_attempt_at.saturating_sub(now)))
+            .min()
+    }
+
+    /// Persist a rescheduled replica
+    async fn reschedule(&self, task: ReplicationTask) -> io::Result<()> {
+        self.save(&task).await?;
+        self.tasks().insert(task.id, task);
+        Ok(())
+    }
+
+    /// Remove a copied replica
+    async fn complete(&self, id: Uuid) -> io::Result<()> {
+        self.tasks().remove(&id);
+        tokio::fs::remove_file(self.path(id)).await
+    }
+
+    /// Move a replica that will not be retried to the dead letter directory
+    async fn dead_letter(&self, task: ReplicationTask) -> io::Result<()> {
+        self.tasks().remove(&task.id);
+
+        let dead = self.dir.join("dead");
+        tokio::fs::create_dir_all(&dead).await?;
+        let data = serde_json::to_vec_pretty(&task).map_err(io::Error::other)?;
+        file::write_file_atomic(&dead.join(format!("{}.json", task.id)), &data).await?;
+        tokio::fs::remove_file(self.path(task.id)).await
+    }
+
+    async fn save(&self, task: &ReplicationTask) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let data = serde_json::to_vec_pretty(task).map_err(io::Error::other)?;
+        file::write_file_atomic(&self.path(task.id), &data).await
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, HashMap<Uuid, ReplicationTask>> {
+        // The map stays consistent even if a holder panicked, so recover from poisoning
+        self.tasks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Start the background worker copying queued replicas
+///
+/// Replicas are copied one at a time. A failed copy is attempted again after
+/// [`REPLICATION_RETRY_DELAY`], up to [`MAX_REPLICATION_ATTEMPTS`] attempts.
+/// The worker sleeps until the next replica is due or a new one is queued,
+/// and stops after the current copy once [`AppState::shutdown`] is stopped.
+/// Replicas still queued stay on disk and are copied after a restart.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state providing the storage clients and
+///   the replication queue
+pub fn spawn_worker(state: AppState) -> JoinHandle<()> {
+    let queue = state.replication.clone();
+    let mut stopping = state.shutdown.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            for task in queue.due(unix_millis()) {
+                if *stopping.borrow() {
+                    return;
+                }
+                attempt(&state, task).await;
+            }
+            if *stopping.borrow() {
+                return;
+            }
+
+            let wait = queue
+                .next_due_in(unix_millis())
+                .unwrap_or(REPLICATION_RETRY_DELAY)
+                .min(REPLICATION_RETRY_DELAY);
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = queue.added.notified() => {}
+                _ = stopping.changed() => {}
+            }
+        }
+    })
+}
+
+/// Attempt a replica and record the outcome in the queue
+async fn attempt(state: &AppState, mut task: ReplicationTask) {
+    let queue = &state.replication;
+    task.attempts += 1;
+
+    let saved = match replicate(&task, state).await {
+        Ok(location) => {
+            info!(
+                "Replicated '{}' to {}: {}",
+                task.filename, task.missing, location
+            );
+
+            // A new IPFS replica is pinned remotely, placed in MFS and
+            // recorded like any upload
+            if task.missing == Backend::Ipfs {
+                if task.ipfs_add.pins() {
+                    state.pinning.enqueue(RemotePinTask {
+                        tenant: task.tenant.clone(),
+                        cid: location.clone(),
+                        filename: task.filename.clone(),
+                    });
+                }
+                mfs::place(
+                    state,
+                    MfsPlacement {
+                        tenant: task.tenant.clone(),
+                        cid: location.clone(),
+                        filename: task.filename.clone(),
+                        wrapped: task.ipfs_add.wraps(),
+                    },
+                );
+                let record = FileRecord {
+                    filename: Some(task.filename.clone()),
+                    tenant: task.tenant.clone(),
+                    pinned: task.ipfs_add.pins(),
+                    ..FileRecord::new(location, FileOrigin::Upload)
+                };
+                services::record_upload(state, record).await;
+            }
+
+            queue.complete(task.id).await
+        }
+        Err(e) if task.attempts < MAX_REPLICATION_ATTEMPTS => {
+            warn!(
+                "Failed to replicate '{}' to {} (attempt {}/{}), retrying in {:?}: {:#}",
+                task.filename,
+                task.missing,
+                task.attempts,
+                MAX_REPLICATION_ATTEMPTS,
+                REPLICATION_RETRY_DELAY,
+                e
+            );
+            task.next_attempt_at = unix_millis() + REPLICATION_RETRY_DELAY.as_millis() as u64;
+            task.last_error = Some(format!("{:#}", e));
+            queue.reschedule(task.clone()).await
+        }
+        Err(e) => {
+            error!(
+                "Giving up replicating '{}' to {} after {} attempts: {:#}",
+                task.filename, task.missing, task.attempts, e
+            );
+            task.last_error = Some(format!("{:#}", e));
+            queue.dead_letter(task.clone()).await
+        }
+    };
+
+    if let Err(e) = saved {
+        error!("Failed to update replication queue for {}: {}", task.id, e);
+    }
+}
+
+/// Copy a file to the backend that is missing it
+///
+/// The copy goes through the destination's circuit breaker, so while the
+/// backend is still down the attempt fails immediately and is retried later.
+///
+/// # Returns
+///
+/// Returns the S3 URL or IPFS CID of the new replica
+#[tracing::instrument(skip_all, fields(filename = %task.filename, backend = %task.missing, attempt = task.attempts))]
+async fn replicate(task: &ReplicationTask, state: &AppState) -> Result<String> {
+    let bucket = &state.config.s3.bucket;
+    let retry = &state.config.retry;
+    let (tx, rx) = mpsc::channel(state.config.upload.stream_buffer_chunks);
+
+    match task.missing {
+        Backend::S3 => {
+            let cid = task
+                .cid
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("No IPFS replica to copy from"))?;
+            // A wrapped file is an entry of the directory the CID points at
+            let path = if task.ipfs_add.wraps() {
+                format!("{}/{}", cid, ipfs::entry_name(&task.filename))
+            } else {
+                cid.to_string()
+            };
+            let attributes = objects::attributes(
+                &state.config.s3,
+                &task.filename,
+                task.tenant.as_deref(),
+                Some(cid),
+                &task.object,
+            );
+            let (_, uploaded) = join!(
+                ipfs::cat_stream_from_ipfs(&state.ipfs, &path, tx),
+                state.breakers.s3.call(s3::upload_stream_to_s3(
+                    &state.s3,
+                    &retry.s3,
+                    &state.bucket,
+                    &task.key,
+                    &attributes,
+                    rx
+                )),
+            );
+            uploaded
+        }
+        Backend::Ipfs => {
+            // An object encrypted with SSE-C can only be read with its key
+            let storage = state.config.s3.storage.for_tenant(task.tenant.as_deref());
+            let customer_key = s3::CustomerKey::new(&storage);
+            let (_, uploaded) = join!(
+                s3::download_stream_from_s3(
+                    &state.s3,
+                    &retry.s3,
+                    bucket,
+                    &task.key,
+                    customer_key.as_ref(),
+                    tx
+                ),
+                state.breakers.ipfs.call(ipfs::upload_stream_to_ipfs(
+                    &state.ipfs,
+                    &task.ipfs_add,
+                    &task.filename,
+                    rx
+                )),
+            );
+            uploaded
+        }
+    }
+}
+
+/// Current Unix time in milliseconds
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ReplicationConfig {
+        ReplicationConfig {
+            dir: std::env::temp_dir()
+                .join(format!("replication-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    fn task() -> ReplicationTask {
+        ReplicationTask {
+            id: Uuid::new_v4(),
+            filename: "test.jpg".to_string(),
+            key: "uploads/abc_test.jpg".to_string(),
+            cid: None,
+            ipfs_add: IpfsAddOptions::default(),
+            tenant: None,
+            object: ObjectInfo::default(),
+            missing: Backend::Ipfs,
+            attempts: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queued_replicas_survive_restart() {
+        let config = config();
+        let queue = ReplicationQueue::open(&config).await.unwrap();
+        queue.enqueue(task()).await;
+        assert_eq!(queue.due(unix_millis()).len(), 1);
+
+        let reopened = ReplicationQueue::open(&config).await.unwrap();
+        let task = reopened.due(unix_millis()).pop().unwrap();
+        assert_eq!(task.missing, Backend::Ipfs);
+        assert_eq!(task.key, "uploads/abc_test.jpg");
+
+        reopened.complete(task.id).await.unwrap();
+        assert!(ReplicationQueue::open(&config).await.unwrap().is_empty());
+        tokio::fs::remove_dir_all(&config.dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rescheduled_and_dead_lettered_replicas() {
+        let config = config();
+        let queue = ReplicationQueue::open(&config).await.unwrap();
+        let mut task = task();
+        queue.enqueue(task.clone()).await;
+
+        // A rescheduled replica is not due until its next attempt
+        task.attempts = 1;
+        task.next_attempt_at = unix_millis() + 60_000;
+        queue.reschedule(task.clone()).await.unwrap();
+        assert!(queue.due(unix_millis()).is_empty());
+        assert!(queue.next_due_in(unix_millis()).unwrap() > Duration::from_secs(50));
+
+        queue.dead_letter(task.clone()).await.unwrap();
+        assert!(queue.is_empty());
+        let dead = std::path::Path::new(&config.dir)
+            .join("dead")
+            .join(format!("{}.json", task.id));
+        assert!(dead.exists());
+        assert!(ReplicationQueue::open(&config).await.unwrap().is_empty());
+        tokio::fs::remove_dir_all(&config.dir).await.unwrap();
+    }
+}
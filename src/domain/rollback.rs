@@ -0,0 +1,334 @@
+//! Compensating rollback of partially failed uploads
+//!
+//! An upload is handed to S3 and IPFS concurrently. When too few backends
+//! store the file to satisfy the write policy, the upload is rejected and the
+//! copies that did succeed would be left behind as orphaned S3 objects or
+//! pins that nothing refers to. This module undoes those copies: the S3
+//! object is deleted and the CID is unpinned.
+//!
+//! Rollback is enabled by default and can be turned off with
+//! `ROLLBACK_ON_PARTIAL_FAILURE=false`, in which case the orphaned copies are
+//! only reported. Every removed copy emits a `file.deleted` webhook event.
+
+use crate::config::Backend;
+use crate::domain::services::StoredFile;
+use crate::domain::webhooks::{DeletedFile, Event};
+use crate::infrastructure::{ipfs, s3};
+use crate::state::AppState;
+use log::{info, warn};
+use std::fmt;
+
+/// A copy that must be undone after a partial failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Compensation {
+    /// Delete the object stored under this S3 key
+    DeleteS3Object { key: String },
+    /// Unpin this IPFS CID
+    UnpinIpfs { cid: String },
+}
+
+impl Compensation {
+    /// Describe the removed copy for a `file.deleted` event
+    fn deleted_file(&self, stored: &StoredFile) -> DeletedFile {
+        let (backend, key, cid) = match self {
+            Self::DeleteS3Object { key } => (Backend::S3, Some(key.clone()), None),
+            Self::UnpinIpfs { cid } => (Backend::Ipfs, None, Some(cid.clone())),
+        };
+
+        DeletedFile {
+            filename: stored.filename.clone(),
+            backend,
+            key,
+            cid,
+            reason: "Rolled back after the upload failed".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Compensation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeleteS3Object { key } => write!(f, "S3 object {}", key),
+            Self::UnpinIpfs { cid } => write!(f, "IPFS pin {}", cid),
+        }
+    }
+}
+
+/// Outcome of rolling back a partially failed upload
+#[derive(Debug, Default)]
+pub(crate) struct RollbackReport {
+    /// Copies that were removed
+    pub rolled_back: Vec<Compensation>,
+    /// Copies that could not be removed, with the reason
+    pub failed: Vec<(Compensation, String)>,
+    /// Copies left in place because rollback is disabled
+    pub kept: Vec<Compensation>,
+    /// Pins left in place because an earlier upload holds the same CID
+    pub shared: Vec<Compensation>,
+}
+
+impl RollbackReport {
+    /// Whether there was nothing to roll back
+    pub fn is_empty(&self) -> bool {
+        self.rolled_back.is_empty()
+            && self.failed.is_empty()
+            && self.kept.is_empty()
+            && self.shared.is_empty()
+    }
+}
+
+impl fmt::Display for RollbackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sections = Vec::new();
+
+        if !self.rolled_back.is_empty() {
+            sections.push(format!("rolled back {}", join(&self.rolled_back)));
+        }
+        if !self.failed.is_empty() {
+            let failed: Vec<String> = self
+                .failed
+                .iter()
+                .map(|(compensation, reason)| format!("{} ({})", compensation, reason))
+                .collect();
+            sections.push(format!("failed to roll back {}", failed.join(", ")));
+        }
+        if !self.kept.is_empty() {
+            sections.push(format!("left in place {}", join(&self.kept)));
+        }
+        if !self.shared.is_empty() {
+            sections.push(format!(
+                "kept {} held by an earlier upload",
+                join(&self.shared)
+            ));
+        }
+
+        write!(f, "{}", sections.join("; "))
+    }
+}
+
+/// Determine which copies of a partially failed upload must be undone
+///
+/// # Arguments
+///
+/// * `stored` - Result of handing the file to every backend
+///
+/// # Returns
+///
+/// Returns the successful copies when at least one backend failed or the
+/// IPFS hash was rejected, or nothing when every backend succeeded or every
+/// backend failed. Content added to IPFS without a pin needs no undoing.
+pub(crate) fn plan(stored: &StoredFile) -> Vec<Compensation> {
+    if stored.s3.is_ok() && stored.ipfs.is_ok() && !stored.cid_rejected {
+        return Vec::new();
+    }
+
+    let mut compensations = Vec::new();
+    if stored.s3.is_ok() {
+        compensations.push(Compensation::DeleteS3Object {
+            key: stored.key.clone(),
+        });
+    }
+    if let (Ok(cid), true) = (&stored.ipfs, stored.ipfs_add.pins()) {
+        compensations.push(Compensation::UnpinIpfs { cid: cid.clone() });
+    }
+
+    compensations
+}
+
+/// Undo the successful copies of a partially failed upload
+///
+/// Every compensation is attempted even if an earlier one fails. Failures
+/// are logged and reported rather than returned, since the upload has
+/// already failed. A CID recorded in the metadata store by an earlier
+/// upload of the same content stays pinned.
+///
+/// # Arguments
+///
+/// * `stored` - Result of handing the file to every backend
+/// * `state` - Shared application state
+///
+/// # Returns
+///
+/// Returns a report of what was rolled back, what could not be, what was
+/// left in place because rollback is disabled, and which pins are shared
+/// with an earlier upload
+#[tracing::instrument(skip_all, fields(filename = %stored.filename))]
+pub(crate) async fn roll_back(stored: &StoredFile, state: &AppState) -> RollbackReport {
+    let mut report = RollbackReport::default();
+    let retry = &state.config.retry;
+
+    for compensation in plan(stored) {
+        if !state.config.upload.rollback_on_partial_failure {
+            warn!("Rollback disabled, leaving {} in place", compensation);
+            report.kept.push(compensation);
+            continue;
+        }
+
+        let result = match &compensation {
+            Compensation::DeleteS3Object { key } => {
+                let bucket = &state.config.s3.bucket;
+                let delete = s3::delete_object(&state.s3, &retry.s3, bucket, key);
+                state.breakers.s3.call(delete).await
+            }
+            Compensation::UnpinIpfs { cid } if state.metadata.file(cid).is_some() => {
+                info!("Keeping {}, an earlier upload holds it", compensation);
+                report.shared.push(compensation);
+                continue;
+            }
+            Compensation::UnpinIpfs { cid } => {
+                let timeout = state.config.ipfs.timeout();
+                let unpin = ipfs::unpin(&state.ipfs, &retry.ipfs, timeout, cid);
+                state.breakers.ipfs.call(unpin).await
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                info!("Rolled back {}", compensation);
+                state
+                    .webhooks
+                    .emit(Event::file_deleted(compensation.deleted_file(stored)))
+                    .await;
+                report.rolled_back.push(compensation);
+            }
+            Err(e) => {
+                warn!("Failed to roll back {}: {:#}", compensation, e);
+                report.failed.push((compensation, e.to_string()));
+            }
+        }
+    }
+
+    report
+}
+
+fn join(compensations: &[Compensation]) -> String {
+    compensations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, IpfsAddOptions};
+    use crate::domain::metadata::{FileOrigin, FileRecord};
+    use crate::domain::objects::ObjectInfo;
+
+    fn stored(s3: bool, ipfs: bool) -> StoredFile {
+        StoredFile {
+            filename: "test.jpg".to_string(),
+            key: "uploads/abc_test.jpg".to_string(),
+            size: 1024,
+            s3: if s3 {
+                Ok("https://bucket.s3.amazonaws.com/uploads/abc_test.jpg".to_string())
+            } else {
+                Err(anyhow::anyhow!("S3 unavailable"))
+            },
+            ipfs: if ipfs {
+                Ok("QmHash123".to_string())
+            } else {
+                Err(anyhow::anyhow!("IPFS unavailable"))
+            },
+            ipfs_add: IpfsAddOptions::default(),
+            expected_cid: None,
+            cid_rejected: false,
+            tenant: None,
+            object: ObjectInfo::default(),
+        }
+    }
+
+    #[test]
+    fn test_plan_nothing_when_all_succeed_or_all_fail() {
+        assert!(plan(&stored(true, true)).is_empty());
+        assert!(plan(&stored(false, false)).is_empty());
+    }
+
+    #[test]
+    fn test_plan_undoes_successful_backend() {
+        assert_eq!(
+            plan(&stored(true, false)),
+            vec![Compensation::DeleteS3Object {
+                key: "uploads/abc_test.jpg".to_string()
+            }]
+        );
+        assert_eq!(
+            plan(&stored(false, true)),
+            vec![Compensation::UnpinIpfs {
+                cid: "QmHash123".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_undoes_rejected_cid() {
+        let mut stored = stored(true, true);
+        stored.cid_rejected = true;
+        assert_eq!(plan(&stored).len(), 2);
+    }
+
+    #[test]
+    fn test_plan_skips_unpinned_content() {
+        let mut stored = stored(false, true);
+        stored.ipfs_add.pin = Some(false);
+        assert!(plan(&stored).is_empty());
+    }
+
+    #[test]
+    fn test_report_display() {
+        let report = RollbackReport {
+            rolled_back: vec![Compensation::DeleteS3Object {
+                key: "uploads/a.jpg".to_string(),
+            }],
+            failed: vec![(
+                Compensation::UnpinIpfs {
+                    cid: "QmHash123".to_string(),
+                },
+                "not pinned".to_string(),
+            )],
+            kept: Vec::new(),
+            shared: vec![Compensation::UnpinIpfs {
+                cid: "QmShared".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "rolled back S3 object uploads/a.jpg; failed to roll back IPFS pin QmHash123 \
+             (not pinned); kept IPFS pin QmShared held by an earlier upload"
+        );
+        assert!(!report.is_empty());
+        assert!(RollbackReport::default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_keeps_shared_pin() {
+        let mut config = Config::default();
+        config.metadata.dir = std::env::temp_dir()
+            .join(format!("metadata-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let dir = config.metadata.dir.clone();
+        let state = AppState::new(config).await.unwrap();
+        let cid = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string();
+        let record = FileRecord::new(cid.clone(), FileOrigin::Upload);
+        state.metadata.put_file(record).await.unwrap();
+        let mut stored = stored(false, true);
+        stored.ipfs = Ok(cid.clone());
+
+        let report = roll_back(&stored, &state).await;
+
+        assert_eq!(report.shared, vec![Compensation::UnpinIpfs { cid }]);
+        assert!(report.rolled_back.is_empty() && report.failed.is_empty());
+        assert!(!report.is_empty());
+
+        // The node returns the pin in the configured base, here base36
+        let cid = "k2jmtxtlhjl3fhmgndf92e48by79ryjuvqp3y2qgehpao6v3lurvnmcv".to_string();
+        stored.ipfs = Ok(cid.clone());
+        let report = roll_back(&stored, &state).await;
+        assert_eq!(report.shared, vec![Compensation::UnpinIpfs { cid }]);
+        assert!(report.rolled_back.is_empty() && report.failed.is_empty());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
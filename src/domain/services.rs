@@ -3,111 +3,716 @@
 //! This module contains the core business logic for processing file uploads,
 //! coordinating between the API layer and infrastructure services.
 
-use crate::config::Config;
+use crate::config::{
+    is_valid_tenant, Backend, CidVerification, Config, IpfsAddOptions, S3Storage, UploadMode,
+};
+use crate::domain::jobs::{self, Job, JobAccepted};
+use crate::domain::metadata::{FileOrigin, FileRecord};
+use crate::domain::mfs::{self, MfsPlacement};
+use crate::domain::objects::{self, ObjectInfo};
+use crate::domain::pinning::RemotePinTask;
+use crate::domain::replication::ReplicationTask;
+use crate::domain::tracker::UploadTracker;
+use crate::domain::webhooks::{Event, UploadFailure};
+use crate::domain::{rollback, streaming};
 use crate::error::StorageError;
-use crate::infrastructure::{ipfs, s3};
+use crate::infrastructure::ipfs;
+use crate::infrastructure::ipfs_rpc::AddProgress;
+use crate::infrastructure::s3::{self, ObjectAttributes};
+use crate::infrastructure::unixfs::{self, UnixfsHasher};
+use crate::state::AppState;
+use crate::telemetry;
 use bytes::Buf;
 use futures_util::stream::TryStreamExt;
 use log::{debug, error, info, warn};
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tokio::try_join;
+use tokio::join;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
+use warp::http::header::LOCATION;
+use warp::http::{HeaderMap, StatusCode};
 use warp::multipart::{FormData, Part};
+use warp::Reply;
+
+/// Header carrying the request correlation ID, both inbound and outbound
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Header identifying the tenant an upload belongs to
+pub const TENANT_HEADER: &str = "x-tenant-id";
 
 /// Response structure for successful file uploads
 ///
-/// This structure is returned when a file has been successfully uploaded
-/// to both S3 and IPFS.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// This structure is returned when a file has been stored on enough backends
+/// to satisfy the write policy. Backends that missed the file are listed as
+/// queued in `backends`; the S3 URL and IPFS hash are where the file will be
+/// once it is replicated.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct UploadResponse {
     /// The URL where the file can be accessed on S3
+    #[serde(default)]
     pub s3_url: String,
-    /// The IPFS hash (CID) of the uploaded file
+    /// The IPFS hash (CID) of the uploaded file; empty only if IPFS missed
+    /// the file and no CID was computed while it was spooled
+    #[serde(default)]
     pub ipfs_hash: String,
     /// The original filename
     pub filename: String,
     /// The size of the uploaded file in bytes
     pub size: u64,
+    /// The outcome of the upload on each backend
+    pub backends: Vec<BackendStatus>,
+    /// Whether the IPFS hash matches the CID computed while the file was
+    /// spooled; absent if no CID was computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid_verified: Option<bool>,
+    /// Storage class and server-side encryption of the S3 object; absent if
+    /// S3 missed the file or the bucket's defaults apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_storage: Option<S3Storage>,
+    /// Further URLs of the file, through CDNs and IPFS gateways
+    #[serde(default, skip_serializing_if = "UploadUrls::is_empty")]
+    pub urls: UploadUrls,
+}
+
+/// URLs of an upload besides its S3 URL, one per configured CDN base URL
+/// (`S3_CDN_BASE_URLS`) and IPFS gateway (`IPFS_GATEWAY_URLS`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UploadUrls {
+    /// URLs of the S3 object through each CDN
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cdn: Vec<String>,
+    /// URLs of the content on each IPFS gateway
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipfs: Vec<String>,
+}
+
+impl UploadUrls {
+    /// Whether no URL is configured
+    pub fn is_empty(&self) -> bool {
+        self.cdn.is_empty() && self.ipfs.is_empty()
+    }
+}
+
+/// Outcome of an upload on a single backend
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackendStatus {
+    /// The backend
+    pub backend: Backend,
+    /// Whether the backend holds the file
+    pub status: ReplicaStatus,
+    /// Why the upload to this backend failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Replication state of a file on a backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicaStatus {
+    /// The backend stored the file
+    Stored,
+    /// The backend failed; the file is queued for background replication
+    Queued,
+}
+
+/// Result of handing one file to every storage backend
+pub(crate) struct StoredFile {
+    /// The original filename
+    pub filename: String,
+    /// The S3 key the file was uploaded under
+    pub key: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// The S3 URL, or the reason the S3 upload failed
+    pub s3: anyhow::Result<String>,
+    /// The IPFS hash (CID), or the reason the IPFS upload failed
+    pub ipfs: anyhow::Result<String>,
+    /// The options the file was added to IPFS with
+    pub ipfs_add: IpfsAddOptions,
+    /// The CID computed while the file was spooled, if any
+    pub expected_cid: Option<String>,
+    /// Whether the IPFS hash was rejected for differing from the computed
+    /// CID, so every copy must be undone
+    pub cid_rejected: bool,
+    /// Tenant the upload belongs to
+    pub tenant: Option<String>,
+    /// What the request and contents say about the S3 object
+    pub object: ObjectInfo,
+}
+
+impl StoredFile {
+    /// Backends that stored the file
+    pub fn stored_on(&self) -> Vec<Backend> {
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| self.error(*backend).is_none())
+            .collect()
+    }
+
+    /// Why the upload to `backend` failed, if it did
+    pub fn error(&self, backend: Backend) -> Option<&anyhow::Error> {
+        match backend {
+            Backend::S3 => self.s3.as_ref().err(),
+            Backend::Ipfs => self.ipfs.as_ref().err(),
+        }
+    }
 }
 
 /// Handle file upload request
 ///
 /// This is the main entry point for processing file uploads. It performs the following steps:
-/// 1. Extracts the file from the multipart form data
-/// 2. Saves the file to a temporary location
-/// 3. Concurrently uploads the file to both S3 and IPFS
-/// 4. Returns the upload results
+/// 1. Resolves the IPFS add options: query parameters such as
+///    `cid-version=1` override the configured `IPFS_*` defaults
+/// 2. Extracts the file from the multipart form data
+/// 3. Saves the file to a temporary location, computing its CID, or in
+///    streaming mode (`UPLOAD_MODE=streaming`) fans the body out to the
+///    backends directly
+/// 4. Concurrently uploads the file to both S3 and IPFS. Spooled files of at
+///    least `ASYNC_UPLOAD_THRESHOLD` bytes are instead handed to a background
+///    job, and `202 Accepted` is returned with the job ID
+/// 5. Checks the results against the write policy (`WRITE_POLICY`,
+///    `WRITE_REQUIRED_BACKENDS`) and the IPFS hash against the computed CID
+///    (`IPFS_VERIFY_CID`), queueing replication to any backend that missed
+///    the file
+/// 6. Returns the upload results
+///
+/// The request is processed inside a `handle_upload` span carrying the
+/// request ID, so every log line emitted while handling it can be correlated.
+/// If the request carries a W3C `traceparent` header, the span joins the
+/// caller's trace.
 ///
 /// # Arguments
 ///
 /// * `form` - Multipart form data containing the file to upload
-/// * `config` - Application configuration containing upload settings
+/// * `ipfs_add` - IPFS add options given with the request
+/// * `headers` - Request headers, used for trace context propagation, the
+///   optional `X-Tenant-Id` of the upload and the optional `X-Object-Tags`
+///   of its S3 object
+/// * `request_id` - Correlation ID of the request
+/// * `state` - Shared application state (configuration, storage clients and
+///   in-flight upload tracker)
 ///
 /// # Returns
 ///
 /// Returns a JSON response containing the S3 URL and IPFS hash on success,
-/// or a warp rejection on failure.
+/// or a JSON `{"error": ...}` body on failure. Either way the request ID is
+/// echoed in the `X-Request-Id` header.
 ///
 /// # Errors
 ///
-/// This function will return an error if:
+/// Failures are replied with `400 Bad Request` for an invalid request,
+/// `503 Service Unavailable` when too many circuits are open and
+/// `500 Internal Server Error` otherwise. The upload fails if:
+/// - The `X-Tenant-Id` header is not a valid tenant ID
+/// - The `X-Object-Tags` header is not a valid set of tags
+/// - The circuits of too many backends are open to satisfy the write policy
+/// - The IPFS add options are invalid
 /// - No file is found in the form data
 /// - The file cannot be saved to temporary storage
-/// - The upload to S3 or IPFS fails
+/// - Too few backends stored the file to satisfy the write policy. Copies
+///   on the backends that succeeded are rolled back first and the error
+///   describes what was undone
 ///
 /// # Examples
 ///
 /// ```no_run
-/// use warp::multipart::FormData;
+/// use warp::{http::HeaderMap, multipart::FormData};
 /// use memenow_storage_service::domain::services::handle_upload;
-/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::config::{Config, IpfsAddOptions};
+/// use memenow_storage_service::state::AppState;
 ///
 /// # async fn example(form: FormData) -> Result<(), Box<dyn std::error::Error>> {
-/// let config = Config::default();
-/// let response = handle_upload(form, config).await?;
+/// let state = AppState::new(Config::default()).await?;
+/// let ipfs_add = IpfsAddOptions::default();
+/// let request_id = "req-1".to_string();
+/// let response = handle_upload(form, ipfs_add, HeaderMap::new(), request_id, state).await?;
 /// # Ok(())
 /// # }
 /// ```
 pub async fn handle_upload(
     form: FormData,
-    config: Config,
+    ipfs_add: IpfsAddOptions,
+    headers: HeaderMap,
+    request_id: String,
+    state: AppState,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let span = tracing::info_span!("handle_upload", otel.kind = "server", request_id = %request_id);
+    // Only fails when no OpenTelemetry layer is installed, i.e. tracing is disabled
+    let _ = span.set_parent(telemetry::extract_context(&headers));
+
+    // Errors become replies here so that every response carries the request ID
+    let result = async {
+        let tenant = tenant(&headers)?;
+        let tags = objects::request_tags(&headers)?;
+        process_upload(form, ipfs_add, tenant, tags, state).await
+    }
+    .instrument(span)
+    .await;
+    let reply = result.unwrap_or_else(error_reply);
+
+    Ok(warp::reply::with_header(
+        reply,
+        REQUEST_ID_HEADER,
+        request_id,
+    ))
+}
+
+/// Turn a failed upload into a JSON error reply with a matching status code
+fn error_reply(error: StorageError) -> warp::reply::Response {
+    let status = match &error {
+        StorageError::MultipartError(_)
+        | StorageError::UploadError(_)
+        | StorageError::NoFileError => StatusCode::BAD_REQUEST,
+        StorageError::BackendFailed(_) => StatusCode::BAD_GATEWAY,
+        StorageError::BackendUnavailable(_) | StorageError::ShuttingDown => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        StorageError::S3Error(_)
+        | StorageError::IpfsError(_)
+        | StorageError::IoError(_)
+        | StorageError::ConfigError(_)
+        | StorageError::InternalError(_)
+        | StorageError::AwsError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if status.is_server_error() {
+        warn!("Upload request failed: {}", error);
+    }
+
+    let body = warp::reply::json(&serde_json::json!({ "error": error.to_string() }));
+    warp::reply::with_status(body, status).into_response()
+}
+
+/// Run the upload pipeline for a single request
+///
+/// # Arguments
+///
+/// * `form` - Multipart form data containing the file to upload
+/// * `ipfs_add` - IPFS add options given with the request
+/// * `tenant` - Tenant the upload belongs to
+/// * `tags` - Tags given for the S3 object
+/// * `state` - Shared application state
+async fn process_upload(
+    form: FormData,
+    ipfs_add: IpfsAddOptions,
+    tenant: Option<String>,
+    tags: BTreeMap<String, String>,
+    state: AppState,
+) -> Result<warp::reply::Response, StorageError> {
+    // Count this upload as in flight until the response is ready
+    let _in_flight = state.tracker.begin();
+
     debug!("Processing upload request");
 
+    // Fail fast, before reading the body, if too few backends are available
+    let policy = &state.config.upload.write_policy;
+    let available = state.breakers.available();
+    if !policy.is_satisfied(&available) {
+        let unavailable: Vec<String> = Backend::ALL
+            .into_iter()
+            .filter(|backend| !available.contains(backend))
+            .map(|backend| backend.to_string())
+            .collect();
+        warn!(
+            "Rejecting upload, circuit open for: {}",
+            unavailable.join(", ")
+        );
+        return Err(StorageError::BackendUnavailable(unavailable.join(", ")));
+    }
+
+    let ipfs_add = state.config.ipfs.add.merged(&ipfs_add);
+    state
+        .config
+        .ipfs
+        .check_add(&ipfs_add)
+        .map_err(|e| StorageError::UploadError(format!("Invalid IPFS add options: {}", e)))?;
+
+    let stored = match state.config.upload.mode {
+        UploadMode::Spooled => {
+            let mut spooled = spool_file(form, &ipfs_add, &state).await?;
+            spooled.object.tags = tags;
+
+            if state.config.jobs.is_async(spooled.size) {
+                let job = jobs::submit(spooled, ipfs_add, tenant, &state)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to submit upload job: {}", e);
+                        e
+                    })?;
+                return Ok(accepted(&job));
+            }
+
+            spooled_upload(spooled, ipfs_add, tenant, &state).await
+        }
+        UploadMode::Streaming => {
+            streaming::streaming_upload(form, ipfs_add, tenant, tags, &state).await?
+        }
+    };
+
+    let response = complete_upload(stored, None, &state).await?;
+
+    Ok(warp::reply::json(&response).into_response())
+}
+
+/// Read the tenant of an upload from the `X-Tenant-Id` header
+///
+/// # Errors
+///
+/// Returns an error if the header is present but not a valid tenant ID
+/// (see [`is_valid_tenant`])
+pub(crate) fn tenant(headers: &HeaderMap) -> Result<Option<String>, StorageError> {
+    match headers.get(TENANT_HEADER).map(|value| value.to_str()) {
+        None => Ok(None),
+        Some(Ok(tenant)) if is_valid_tenant(tenant) => Ok(Some(tenant.to_string())),
+        Some(_) => Err(StorageError::UploadError(
+            "Invalid X-Tenant-Id header".to_string(),
+        )),
+    }
+}
+
+/// Build the `202 Accepted` reply for an upload handed to a background job
+fn accepted(job: &Job) -> warp::reply::Response {
+    let status_url = format!("/jobs/{}", job.id);
+    let body = JobAccepted {
+        job_id: job.id,
+        state: job.state,
+        status_url: status_url.clone(),
+    };
+
+    let reply = warp::reply::with_status(warp::reply::json(&body), StatusCode::ACCEPTED);
+    warp::reply::with_header(reply, LOCATION, status_url).into_response()
+}
+
+/// Check a stored file against the write policy and build the response
+///
+/// If too few backends stored the file, or `IPFS_VERIFY_CID=reject` and the
+/// IPFS hash differs from the CID computed while spooling, the copies that
+/// were stored are rolled back. Otherwise replication is queued for every backend that
+/// missed the file, and the CID, if pinned, is queued for remote pinning.
+/// Either way an `upload.completed` or `upload.failed` webhook event is
+/// emitted.
+///
+/// # Arguments
+///
+/// * `stored` - Outcome of the upload on each backend
+/// * `job_id` - The background job that processed the upload, if any
+/// * `state` - Shared application state
+///
+/// # Errors
+///
+/// Returns an error describing the failure and what was rolled back if the
+/// write policy is not satisfied
+pub(crate) async fn complete_upload(
+    mut stored: StoredFile,
+    job_id: Option<Uuid>,
+    state: &AppState,
+) -> Result<UploadResponse, StorageError> {
+    let policy = &state.config.upload.write_policy;
+    let stored_on = stored.stored_on();
+    let cid_verified = verify_cid(&stored, state);
+    stored.cid_rejected =
+        cid_verified == Some(false) && state.config.ipfs.verify_cid == CidVerification::Reject;
+
+    if !policy.is_satisfied(&stored_on) || stored.cid_rejected {
+        let e = match (&stored.ipfs, &stored.expected_cid) {
+            (Ok(cid), Some(expected)) if stored.cid_rejected => {
+                format!("IPFS returned CID {}, expected {}", cid, expected)
+            }
+            _ => Backend::ALL
+                .into_iter()
+                .find_map(|backend| stored.error(backend))
+                .map(|e| format!("{:#}", e))
+                .unwrap_or_else(|| "Write policy not satisfied".to_string()),
+        };
+        let report = rollback::roll_back(&stored, state).await;
+
+        let message = if report.is_empty() {
+            e
+        } else {
+            format!("{}; {}", e, report)
+        };
+        error!("Failed to upload file: {}", message);
+
+        let failure = UploadFailure {
+            filename: stored.filename,
+            size: stored.size,
+            error: message.clone(),
+        };
+        state
+            .webhooks
+            .emit(Event::upload_failed(failure, job_id))
+            .await;
+
+        return Err(StorageError::BackendFailed(message));
+    }
+
+    let mut backends = Vec::new();
+    for backend in Backend::ALL {
+        let Some(e) = stored.error(backend) else {
+            backends.push(BackendStatus {
+                backend,
+                status: ReplicaStatus::Stored,
+                error: None,
+            });
+            continue;
+        };
+
+        warn!(
+            "Upload to {} failed, queueing replication: {:#}",
+            backend, e
+        );
+        let error = Some(format!("{:#}", e));
+        state
+            .replication
+            .enqueue(ReplicationTask {
+                id: Uuid::new_v4(),
+                filename: stored.filename.clone(),
+                key: stored.key.clone(),
+                cid: stored.ipfs.as_ref().ok().cloned(),
+                ipfs_add: stored.ipfs_add.clone(),
+                tenant: stored.tenant.clone(),
+                object: stored.object.clone(),
+                missing: backend,
+                attempts: 0,
+                next_attempt_at: 0,
+                last_error: error.clone(),
+            })
+            .await;
+        backends.push(BackendStatus {
+            backend,
+            status: ReplicaStatus::Queued,
+            error,
+        });
+    }
+
+    let s3_stored = stored.s3.is_ok();
+    let ipfs_hash = stored.ipfs.ok();
+    let s3_storage = s3_stored
+        .then(|| state.config.s3.storage.for_tenant(stored.tenant.as_deref()))
+        .filter(|storage| *storage != S3Storage::default());
+    let urls = UploadUrls {
+        cdn: if s3_stored {
+            state.bucket.cdn_urls(&stored.key)
+        } else {
+            Vec::new()
+        },
+        ipfs: ipfs_hash
+            .as_ref()
+            .map(|cid| {
+                let entry = stored.ipfs_add.wraps().then_some(stored.filename.as_str());
+                ipfs::gateway_urls(&state.config.ipfs.gateways, cid, entry)
+            })
+            .unwrap_or_default(),
+    };
+
+    // Keep the content available beyond the service's own node
+    if let Some(cid) = ipfs_hash.as_ref().filter(|_| stored.ipfs_add.pins()) {
+        state.pinning.enqueue(RemotePinTask {
+            tenant: stored.tenant.clone(),
+            cid: cid.clone(),
+            filename: stored.filename.clone(),
+        });
+    }
+
+    if let Some(cid) = &ipfs_hash {
+        mfs::place(
+            state,
+            MfsPlacement {
+                tenant: stored.tenant.clone(),
+                cid: cid.clone(),
+                filename: stored.filename.clone(),
+                wrapped: stored.ipfs_add.wraps(),
+            },
+        );
+        record_upload(
+            state,
+            FileRecord {
+                filename: Some(stored.filename.clone()),
+                size: Some(stored.size),
+                tenant: stored.tenant.clone(),
+                pinned: stored.ipfs_add.pins(),
+                ..FileRecord::new(cid.clone(), FileOrigin::Upload)
+            },
+        )
+        .await;
+    }
+
+    let s3_url = stored
+        .s3
+        .unwrap_or_else(|_| state.bucket.object_url(&stored.key));
+    info!(
+        "File '{}' uploaded successfully - S3: {}{}, IPFS: {}",
+        stored.filename,
+        s3_url,
+        if s3_stored { "" } else { " (queued)" },
+        ipfs_hash.as_deref().unwrap_or("queued")
+    );
+
+    let response = UploadResponse {
+        s3_url,
+        // Replicating to IPFS later yields the CID computed while spooling
+        ipfs_hash: ipfs_hash.or(stored.expected_cid).unwrap_or_default(),
+        filename: stored.filename,
+        size: stored.size,
+        backends,
+        cid_verified,
+        s3_storage,
+        urls,
+    };
+    state
+        .webhooks
+        .emit(Event::upload_completed(&response, job_id))
+        .await;
+
+    Ok(response)
+}
+
+/// Compare the IPFS hash of an upload with the CID computed while spooling
+///
+/// # Returns
+///
+/// Returns whether they match, or `None` if either is missing
+fn verify_cid(stored: &StoredFile, state: &AppState) -> Option<bool> {
+    let (Ok(cid), Some(expected)) = (&stored.ipfs, &stored.expected_cid) else {
+        return None;
+    };
+
+    let verified = unixfs::matches(expected, cid);
+    if !verified {
+        let action = match state.config.ipfs.verify_cid {
+            CidVerification::Reject => "rejecting the upload",
+            _ => "keeping it",
+        };
+        warn!(
+            "IPFS returned CID {} for '{}', computed {}; {}",
+            cid, stored.filename, expected, action
+        );
+    }
+
+    Some(verified)
+}
+
+/// Record an upload stored on IPFS in the metadata store
+///
+/// A failure is logged without failing the upload; the content is only
+/// missing from lookups by CID.
+pub(crate) async fn record_upload(state: &AppState, record: FileRecord) {
+    if let Err(e) = state.metadata.put_file(record.clone()).await {
+        warn!("Failed to record upload of {}: {}", record.cid, e);
+    }
+}
+
+/// A file written to temporary storage
+pub(crate) struct SpooledFile {
+    /// Location of the temporary file
+    pub path: PathBuf,
+    /// The original filename
+    pub filename: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// The CID the file will be added under, computed while spooling it
+    pub cid: Option<String>,
+    /// What the request and contents say about the S3 object
+    pub object: ObjectInfo,
+}
+
+/// Save the uploaded file to a temporary location
+///
+/// Unless `IPFS_VERIFY_CID=off` and `IPFS_DEDUP` is disabled, the CID the
+/// file will be added under is computed as it is written.
+///
+/// # Arguments
+///
+/// * `form` - Multipart form data containing the file to upload
+/// * `ipfs_add` - Options the file will be added to IPFS with
+/// * `state` - Shared application state
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be extracted from the form data or
+/// saved
+async fn spool_file(
+    form: FormData,
+    ipfs_add: &IpfsAddOptions,
+    state: &AppState,
+) -> Result<SpooledFile, StorageError> {
+    let ipfs = &state.config.ipfs;
+    let ipfs_add = (ipfs.verify_cid != CidVerification::Off || ipfs.dedup).then_some(ipfs_add);
+
     // Extract file from multipart form data
-    let (filepath, filename, file_size) = extract_and_save_file(form, &config)
+    let spooled = extract_and_save_file(form, &state.config, &state.tracker, ipfs_add)
         .await
         .map_err(|e| {
             error!("Failed to extract file from form data: {}", e);
-            warp::reject::custom(e)
+            e
         })?;
 
     info!(
-        "File saved to temporary location: {} (size: {} bytes)",
-        filepath.display(),
-        file_size
+        "File saved to temporary location: {} (size: {} bytes, CID: {})",
+        spooled.path.display(),
+        spooled.size,
+        spooled.cid.as_deref().unwrap_or("not computed")
     );
 
+    Ok(spooled)
+}
+
+/// Upload a spooled file to both backends, then remove it
+///
+/// # Arguments
+///
+/// * `spooled` - The file saved by [`spool_file`]
+/// * `ipfs_add` - Options of the IPFS add
+/// * `tenant` - Tenant the upload belongs to
+/// * `state` - Shared application state
+///
+/// # Returns
+///
+/// Returns the outcome of the upload on each backend
+async fn spooled_upload(
+    spooled: SpooledFile,
+    ipfs_add: IpfsAddOptions,
+    tenant: Option<String>,
+    state: &AppState,
+) -> StoredFile {
+    let SpooledFile {
+        path: filepath,
+        filename,
+        size: file_size,
+        cid: expected_cid,
+        object,
+    } = spooled;
+
     // Generate unique key for S3
-    let file_key = generate_file_key(&filename, &config.s3.key_prefix);
+    let file_key = generate_file_key(&filename, &state.config.s3.key_prefix);
+    let attributes = objects::attributes(
+        &state.config.s3,
+        &filename,
+        tenant.as_deref(),
+        expected_cid.as_deref(),
+        &object,
+    );
 
     // Upload to S3 and IPFS concurrently
-    let s3_future = s3::upload_to_s3(
-        filepath.to_str().unwrap(),
-        &config.s3.bucket,
-        &file_key,
+    let target = UploadTarget {
+        key: &file_key,
+        filename: &filename,
+        ipfs_add: &ipfs_add,
+        cid: expected_cid.as_deref(),
+        object: &attributes,
+        progress: None,
+    };
+    let (s3, ipfs) = join!(
+        upload_file(state, Backend::S3, &filepath, &target),
+        upload_file(state, Backend::Ipfs, &filepath, &target),
     );
-    let ipfs_future = ipfs::upload_to_ipfs(filepath.to_str().unwrap());
-
-    let (s3_url, ipfs_hash) = try_join!(s3_future, ipfs_future).map_err(|e| {
-        error!("Failed to upload file: {}", e);
-        warp::reject::custom(StorageError::UploadError(e.to_string()))
-    })?;
 
     // Clean up temporary file
-    if let Err(e) = tokio::fs::remove_file(&filepath).await {
+    if let Err(e) = state.tracker.remove_temp_file(&filepath).await {
         warn!(
             "Failed to remove temporary file {}: {}",
             filepath.display(),
@@ -117,102 +722,287 @@ pub async fn handle_upload(
         debug!("Temporary file removed: {}", filepath.display());
     }
 
-    info!(
-        "File '{}' uploaded successfully - S3: {}, IPFS: {}",
-        filename, s3_url, ipfs_hash
-    );
-
-    let response = UploadResponse {
-        s3_url,
-        ipfs_hash,
+    StoredFile {
         filename,
+        key: file_key,
         size: file_size,
-    };
+        s3,
+        ipfs,
+        ipfs_add,
+        expected_cid,
+        cid_rejected: false,
+        tenant,
+        object,
+    }
+}
+
+/// Where and how a file is stored on the backends
+pub(crate) struct UploadTarget<'a> {
+    /// S3 key to upload the file under
+    pub key: &'a str,
+    /// The original filename
+    pub filename: &'a str,
+    /// Options of the IPFS add
+    pub ipfs_add: &'a IpfsAddOptions,
+    /// The CID computed while spooling the file, if any
+    pub cid: Option<&'a str>,
+    /// Headers, metadata and tags of the S3 object
+    pub object: &'a ObjectAttributes,
+    /// Callback receiving the progress of the IPFS add
+    pub progress: Option<&'a AddProgress>,
+}
+
+/// Upload a file on disk to one backend through its circuit breaker
+///
+/// With `IPFS_DEDUP` enabled, a file whose computed CID is already recorded
+/// and pinned is not added to IPFS again.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `backend` - The backend to upload to
+/// * `filepath` - Path of the file to upload
+/// * `target` - Where and how to store the file
+///
+/// # Returns
+///
+/// Returns the S3 URL or IPFS hash of the stored file
+pub(crate) async fn upload_file(
+    state: &AppState,
+    backend: Backend,
+    filepath: &Path,
+    target: &UploadTarget<'_>,
+) -> anyhow::Result<String> {
+    let config = &state.config;
+    let filepath = filepath
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {}", filepath.display()))?;
 
-    Ok(warp::reply::json(&response))
+    match backend {
+        Backend::S3 => {
+            state
+                .breakers
+                .s3
+                .call(s3::upload_to_s3(
+                    &state.s3,
+                    &config.retry.s3,
+                    filepath,
+                    &state.bucket,
+                    target.key,
+                    target.object,
+                ))
+                .await
+        }
+        Backend::Ipfs => {
+            if let Some(cid) = target.cid.filter(|_| config.ipfs.dedup) {
+                let stored = state
+                    .metadata
+                    .file(cid)
+                    .is_some_and(|record| record.pinned || !target.ipfs_add.pins());
+                if stored {
+                    info!("IPFS already holds {}, skipping the add", cid);
+                    return match target.ipfs_add.cid_base {
+                        Some(base) => ipfs::encode_cid(cid, base),
+                        None => Ok(cid.to_string()),
+                    };
+                }
+            }
+
+            let upload = ipfs::upload_to_ipfs(
+                &state.ipfs,
+                &config.retry.ipfs,
+                config.ipfs.timeout(),
+                target.ipfs_add,
+                filepath,
+                target.filename,
+                target.progress,
+            );
+            state.breakers.ipfs.call(upload).await
+        }
+    }
 }
 
 /// Extract file from form data and save to temporary location
 ///
+/// The first part named "file" is written to `temp_dir`. The temporary file
+/// is registered with the tracker so it can be cleaned up if the upload is
+/// interrupted. The media type declared for the part and the SHA-256 of its
+/// contents are kept for the S3 object.
+///
 /// # Arguments
 ///
 /// * `form` - Multipart form data
 /// * `config` - Application configuration
+/// * `tracker` - Registry of temporary files
+/// * `ipfs_add` - Options to compute the file's CID with, if it is computed
 ///
 /// # Returns
 ///
-/// Returns a tuple containing (filepath, filename, file_size)
+/// Returns the saved file, with its CID if it was computed and the options
+/// are supported (see [`UnixfsHasher::new`])
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - No file is found in the form data
 /// - File creation or writing fails
+#[tracing::instrument(skip_all, fields(filename, size))]
 async fn extract_and_save_file(
-    form: FormData,
+    mut form: FormData,
     config: &Config,
-) -> Result<(PathBuf, String, u64), StorageError> {
-    let parts: Vec<Part> = form
-        .try_collect()
+    tracker: &UploadTracker,
+    ipfs_add: Option<&IpfsAddOptions>,
+) -> Result<SpooledFile, StorageError> {
+    let (part, filename) = next_file_part(&mut form).await?;
+    let mut hasher = ipfs_add.and_then(|options| UnixfsHasher::new(options, &filename));
+    let mut sha256 = Sha256::new();
+    let content_type = part.content_type().map(str::to_string);
+
+    // Generate unique temporary filepath
+    let temp_filename = format!("{}_{}", Uuid::new_v4(), sanitize_filename(&filename));
+    let filepath = PathBuf::from(&config.upload.temp_dir).join(temp_filename);
+
+    let mut file = File::create(&filepath)
         .await
-        .map_err(|e| StorageError::MultipartError(e.to_string()))?;
+        .map_err(StorageError::IoError)?;
+    tracker.register_temp_file(&filepath);
 
-    let mut file_data: Option<(PathBuf, String, u64)> = None;
+    let update = |bytes: &[u8]| {
+        sha256.update(bytes);
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(bytes);
+        }
+    };
+    let total_size = match write_part(part, &mut file, config, tracker, update).await {
+        Ok(total_size) => total_size,
+        Err(e) => {
+            // Clean up the partially written file
+            let _ = tracker.remove_temp_file(&filepath).await;
+            return Err(e);
+        }
+    };
 
-    for mut part in parts {
-        if part.name() == "file" {
-            let filename = part
-                .filename()
-                .ok_or(StorageError::NoFileError)?
-                .to_string();
+    debug!("File written successfully: {} bytes", total_size);
 
-            debug!("Processing file: {}", filename);
+    let span = tracing::Span::current();
+    span.record("filename", filename.as_str());
+    span.record("size", total_size);
 
-            // Generate unique temporary filepath
-            let temp_filename = format!("{}_{}", Uuid::new_v4(), filename);
-            let filepath = PathBuf::from(&config.upload.temp_dir).join(temp_filename);
+    Ok(SpooledFile {
+        path: filepath,
+        filename,
+        size: total_size,
+        cid: hasher.map(|hasher| hasher.finish().to_string()),
+        object: ObjectInfo {
+            content_type,
+            sha256: Some(hex::encode(sha256.finalize())),
+            tags: BTreeMap::new(),
+        },
+    })
+}
 
-            let mut file = File::create(&filepath)
-                .await
-                .map_err(StorageError::IoError)?;
-
-            let mut total_size = 0u64;
-
-            // Read and write file chunks
-            while let Some(chunk) = part.data().await {
-                let data = chunk.map_err(|e| {
-                    StorageError::MultipartError(format!("Failed to read chunk: {}", e))
-                })?;
-
-                let bytes = data.chunk();
-                total_size += bytes.len() as u64;
-
-                // Check file size limit
-                if total_size > config.upload.max_file_size as u64 {
-                    // Clean up the partially written file
-                    let _ = tokio::fs::remove_file(&filepath).await;
-                    return Err(StorageError::UploadError(format!(
-                        "File size exceeds maximum allowed size of {} bytes",
-                        config.upload.max_file_size
-                    )));
-                }
+/// Advance the form to the part holding the uploaded file
+///
+/// Parts are consumed in the order they arrive on the wire; parts before
+/// the first one named "file" are skipped.
+///
+/// # Arguments
+///
+/// * `form` - Multipart form data
+///
+/// # Returns
+///
+/// Returns the file part and its original filename
+///
+/// # Errors
+///
+/// Returns an error if the form is malformed, has no "file" part, or the
+/// part has no filename
+pub(crate) async fn next_file_part(form: &mut FormData) -> Result<(Part, String), StorageError> {
+    while let Some(part) = form
+        .try_next()
+        .await
+        .map_err(|e| StorageError::MultipartError(e.to_string()))?
+    {
+        if part.name() != "file" {
+            continue;
+        }
 
-                file.write_all(bytes)
-                    .await
-                    .map_err(StorageError::IoError)?;
-            }
+        let filename = part
+            .filename()
+            .ok_or(StorageError::NoFileError)?
+            .to_string();
 
-            // Ensure all data is written to disk
-            file.flush().await.map_err(StorageError::IoError)?;
+        debug!("Processing file: {}", filename);
 
-            debug!("File written successfully: {} bytes", total_size);
+        return Ok((part, filename));
+    }
 
-            file_data = Some((filepath, filename, total_size));
+    Err(StorageError::NoFileError)
+}
+
+/// Write the contents of a multipart part to a file
+///
+/// # Arguments
+///
+/// * `part` - Multipart part holding the file data
+/// * `file` - Destination file
+/// * `config` - Application configuration
+/// * `tracker` - Registry of in-flight uploads, to stop when they are
+///   cancelled
+/// * `update` - Called with the data written, to hash it
+///
+/// # Returns
+///
+/// Returns the number of bytes written
+///
+/// # Errors
+///
+/// Returns an error if reading the part or writing the file fails, if the
+/// file exceeds the maximum allowed size, or if the upload is cancelled
+async fn write_part(
+    mut part: Part,
+    file: &mut File,
+    config: &Config,
+    tracker: &UploadTracker,
+    mut update: impl FnMut(&[u8]),
+) -> Result<u64, StorageError> {
+    let mut total_size = 0u64;
+    let cancelled = tracker.cancelled();
+    tokio::pin!(cancelled);
+
+    // Read and write file chunks
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = &mut cancelled => return Err(StorageError::ShuttingDown),
+            chunk = part.data() => chunk,
+        };
+        let Some(chunk) = chunk else {
             break;
+        };
+        let data = chunk
+            .map_err(|e| StorageError::MultipartError(format!("Failed to read chunk: {}", e)))?;
+
+        let bytes = data.chunk();
+        total_size += bytes.len() as u64;
+
+        // Check file size limit
+        if total_size > config.upload.max_file_size as u64 {
+            return Err(StorageError::UploadError(format!(
+                "File size exceeds maximum allowed size of {} bytes",
+                config.upload.max_file_size
+            )));
         }
+
+        file.write_all(bytes).await.map_err(StorageError::IoError)?;
+        update(bytes);
     }
 
-    file_data.ok_or(StorageError::NoFileError)
+    // Ensure all data is written to disk
+    file.flush().await.map_err(StorageError::IoError)?;
+
+    Ok(total_size)
 }
 
 /// Generate a unique S3 key for the uploaded file
@@ -225,7 +1015,7 @@ async fn extract_and_save_file(
 /// # Returns
 ///
 /// Returns a unique S3 key combining the prefix, UUID, and filename
-fn generate_file_key(filename: &str, prefix: &str) -> String {
+pub(crate) fn generate_file_key(filename: &str, prefix: &str) -> String {
     let uuid = Uuid::new_v4();
     let sanitized_filename = sanitize_filename(filename);
     format!("{}/{}_{}", prefix, uuid, sanitized_filename)
@@ -261,7 +1051,7 @@ mod tests {
         assert_eq!(sanitize_filename("test@#$.jpg"), "test___.jpg");
         assert_eq!(
             sanitize_filename("../../../etc/passwd"),
-            ".._.._..._etc_passwd"
+            ".._.._.._etc_passwd"
         );
     }
 
@@ -279,12 +1069,155 @@ mod tests {
             ipfs_hash: "QmHash123".to_string(),
             filename: "test.jpg".to_string(),
             size: 1024,
+            backends: Vec::new(),
+            cid_verified: Some(true),
+            s3_storage: Some(S3Storage {
+                storage_class: Some("INTELLIGENT_TIERING".to_string()),
+                encryption: Some(crate::config::Encryption::SseC),
+                customer_key: Some("secret".to_string()),
+                ..S3Storage::default()
+            }),
+            urls: UploadUrls {
+                cdn: Vec::new(),
+                ipfs: vec!["https://ipfs.io/ipfs/QmHash123".to_string()],
+            },
         };
 
         let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"cid_verified\":true"));
+        assert!(json.contains(
+            "\"s3_storage\":{\"storage_class\":\"INTELLIGENT_TIERING\",\"encryption\":\"sse-c\"}"
+        ));
+        assert!(!json.contains("secret"));
+        assert!(json.contains("\"urls\":{\"ipfs\":[\"https://ipfs.io/ipfs/QmHash123\"]}"));
         assert!(json.contains("s3_url"));
         assert!(json.contains("ipfs_hash"));
         assert!(json.contains("filename"));
         assert!(json.contains("size"));
     }
+
+    #[test]
+    fn test_upload_response_reports_missing_replicas() {
+        let response = UploadResponse {
+            s3_url: "https://bucket.s3.amazonaws.com/file".to_string(),
+            ipfs_hash: "QmHash123".to_string(),
+            filename: "test.jpg".to_string(),
+            size: 1024,
+            backends: vec![
+                BackendStatus {
+                    backend: Backend::S3,
+                    status: ReplicaStatus::Stored,
+                    error: None,
+                },
+                BackendStatus {
+                    backend: Backend::Ipfs,
+                    status: ReplicaStatus::Queued,
+                    error: Some("connection refused".to_string()),
+                },
+            ],
+            cid_verified: None,
+            s3_storage: None,
+            urls: UploadUrls::default(),
+        };
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["ipfs_hash"], "QmHash123");
+        assert!(json.get("cid_verified").is_none());
+        assert_eq!(json["backends"][0]["status"], "stored");
+        assert!(json["backends"][0].get("error").is_none());
+        assert_eq!(json["backends"][1]["backend"], "ipfs");
+        assert_eq!(json["backends"][1]["status"], "queued");
+    }
+
+    #[tokio::test]
+    async fn test_failed_backends_are_a_server_error() {
+        let state = AppState::new(Config::default()).await.unwrap();
+        let stored = StoredFile {
+            filename: "test.jpg".to_string(),
+            key: "uploads/abc_test.jpg".to_string(),
+            size: 1024,
+            s3: Err(anyhow::anyhow!("S3 unavailable")),
+            ipfs: Err(anyhow::anyhow!("IPFS unavailable")),
+            ipfs_add: IpfsAddOptions::default(),
+            expected_cid: None,
+            cid_rejected: false,
+            tenant: None,
+            object: ObjectInfo::default(),
+        };
+
+        let error = complete_upload(stored, None, &state).await.unwrap_err();
+        assert!(matches!(error, StorageError::BackendFailed(_)), "{}", error);
+        assert_eq!(error_reply(error).status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_the_workers() {
+        let state = AppState::new(Config::default()).await.unwrap();
+
+        // Idle workers stop straight away
+        tokio::time::timeout(std::time::Duration::from_secs(5), state.shutdown.shutdown())
+            .await
+            .unwrap();
+        assert_eq!(
+            error_reply(StorageError::ShuttingDown).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup_finds_uploads_in_any_base() {
+        let mut config = Config::default();
+        config.metadata.dir = std::env::temp_dir()
+            .join(format!("metadata-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        config.ipfs.dedup = true;
+        let dir = config.metadata.dir.clone();
+        let state = AppState::new(config).await.unwrap();
+
+        // Recorded as the node returned it under IPFS_CID_BASE=base64
+        let record = FileRecord {
+            pinned: true,
+            ..FileRecord::new(
+                "mAXASIFmUhDkGXylhnvQSgMu5Mr5SxW2ZxZZrZeAREjnwmLvv".to_string(),
+                FileOrigin::Upload,
+            )
+        };
+        state.metadata.put_file(record).await.unwrap();
+
+        // No node is listening, so only a dedup hit succeeds
+        let ipfs_add = IpfsAddOptions {
+            cid_base: Some(crate::config::CidBase::Base36),
+            ..IpfsAddOptions::default()
+        };
+        let target = UploadTarget {
+            key: "uploads/abc_hello.txt",
+            filename: "hello.txt",
+            ipfs_add: &ipfs_add,
+            cid: Some("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"),
+            object: &ObjectAttributes::default(),
+            progress: None,
+        };
+        let cid = upload_file(&state, Backend::Ipfs, Path::new("/nonexistent"), &target)
+            .await
+            .unwrap();
+        assert_eq!(
+            cid,
+            "k2jmtxtlhjl3fhmgndf92e48by79ryjuvqp3y2qgehpao6v3lurvnmcv"
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_tenant() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(tenant(&headers).unwrap(), None);
+
+        headers.insert(TENANT_HEADER, "acme".parse().unwrap());
+        assert_eq!(tenant(&headers).unwrap().as_deref(), Some("acme"));
+
+        headers.insert(TENANT_HEADER, "../acme".parse().unwrap());
+        assert!(tenant(&headers).is_err());
+    }
 }
@@ -0,0 +1,313 @@
+//! Streaming upload pipeline
+//!
+//! In streaming mode (`UPLOAD_MODE=streaming`) the request body is never
+//! written to `temp_dir`. Each chunk of the "file" part is handed to an S3
+//! multipart upload and an IPFS add stream as it arrives.
+//!
+//! # Backpressure
+//!
+//! Every backend reads from its own bounded channel of
+//! `stream_buffer_chunks` chunks. When a backend falls behind, its channel
+//! fills up and the producer stops reading the request body until there is
+//! room again, so memory use stays bounded regardless of file size.
+//!
+//! # Failure Handling
+//!
+//! If the request body turns out to be malformed or too large, or the upload
+//! is cancelled because the service is shutting down, an error is sent down
+//! every channel so each backend aborts its upload. A backend whose
+//! circuit is open drops its channel straight away and is skipped. Because the
+//! body cannot be replayed, streamed uploads are not retried.
+//!
+//! The S3 object is created before the body is read, so its user metadata
+//! carries no CID or SHA-256.
+
+use crate::config::IpfsAddOptions;
+use crate::domain::objects::{self, ObjectInfo};
+use crate::domain::services::{generate_file_key, next_file_part, StoredFile};
+use crate::domain::tracker::UploadTracker;
+use crate::error::StorageError;
+use crate::infrastructure::{ipfs, s3};
+use crate::state::AppState;
+use bytes::{Buf, Bytes};
+use log::{debug, error, info};
+use std::collections::BTreeMap;
+use std::io;
+use tokio::join;
+use tokio::sync::mpsc;
+use warp::multipart::{FormData, Part};
+
+/// Upload a file to both backends without writing it to disk
+///
+/// # Arguments
+///
+/// * `form` - Multipart form data containing the file to upload
+/// * `ipfs_add` - Options of the IPFS add
+/// * `tenant` - Tenant the upload belongs to
+/// * `tags` - Tags given for the S3 object
+/// * `state` - Shared application state
+///
+/// # Errors
+///
+/// Returns an error if no file is found in the form data or the body cannot
+/// be read. Backend failures are reported in the returned [`StoredFile`].
+pub async fn streaming_upload(
+    mut form: FormData,
+    ipfs_add: IpfsAddOptions,
+    tenant: Option<String>,
+    tags: BTreeMap<String, String>,
+    state: &AppState,
+) -> Result<StoredFile, StorageError> {
+    let config = &state.config;
+
+    let (part, filename) = next_file_part(&mut form).await?;
+
+    // Generate unique key for S3
+    let file_key = generate_file_key(&filename, &config.s3.key_prefix);
+    let object = ObjectInfo {
+        content_type: part.content_type().map(str::to_string),
+        sha256: None,
+        tags,
+    };
+    let attributes = objects::attributes(&config.s3, &filename, tenant.as_deref(), None, &object);
+
+    let capacity = config.upload.stream_buffer_chunks;
+    let (s3_tx, s3_rx) = mpsc::channel(capacity);
+    let (ipfs_tx, ipfs_rx) = mpsc::channel(capacity);
+
+    // Read the body and upload to S3 and IPFS concurrently
+    let (size, s3, ipfs) = join!(
+        tee_part(
+            part,
+            vec![s3_tx, ipfs_tx],
+            config.upload.max_file_size as u64,
+            &state.tracker
+        ),
+        state.breakers.s3.call(s3::upload_stream_to_s3(
+            &state.s3,
+            &config.retry.s3,
+            &state.bucket,
+            &file_key,
+            &attributes,
+            s3_rx
+        )),
+        state.breakers.ipfs.call(ipfs::upload_stream_to_ipfs(
+            &state.ipfs,
+            &ipfs_add,
+            &filename,
+            ipfs_rx
+        )),
+    );
+
+    let size = size.map_err(|e| {
+        error!("Failed to stream file from form data: {}", e);
+        e
+    })?;
+
+    info!(
+        "File '{}' streamed to backends (size: {} bytes)",
+        filename, size
+    );
+
+    Ok(StoredFile {
+        filename,
+        key: file_key,
+        size,
+        s3,
+        ipfs,
+        ipfs_add,
+        expected_cid: None,
+        cid_rejected: false,
+        tenant,
+        object,
+    })
+}
+
+/// Copy every chunk of a part into each of the sinks
+///
+/// A sink whose receiver has gone away (because that backend already
+/// failed) is skipped; the remaining sinks keep receiving data. On failure
+/// an error is sent to every sink so that the backends abort.
+///
+/// # Arguments
+///
+/// * `part` - Multipart part holding the file data
+/// * `sinks` - One bounded sender per backend
+/// * `max_size` - Maximum allowed file size in bytes
+/// * `tracker` - Registry of in-flight uploads, to stop when they are
+///   cancelled
+///
+/// # Returns
+///
+/// Returns the number of bytes read
+///
+/// # Errors
+///
+/// Returns an error if reading the part fails, the file exceeds the
+/// maximum allowed size or the upload is cancelled
+#[tracing::instrument(skip_all, fields(size))]
+async fn tee_part(
+    mut part: Part,
+    sinks: Vec<mpsc::Sender<io::Result<Bytes>>>,
+    max_size: u64,
+    tracker: &UploadTracker,
+) -> Result<u64, StorageError> {
+    let mut total_size = 0u64;
+    let cancelled = tracker.cancelled();
+    tokio::pin!(cancelled);
+
+    let result = async {
+        loop {
+            let chunk = tokio::select! {
+                biased;
+                _ = &mut cancelled => return Err(StorageError::ShuttingDown),
+                chunk = part.data() => chunk,
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            let mut data = chunk.map_err(|e| {
+                StorageError::MultipartError(format!("Failed to read chunk: {}", e))
+            })?;
+
+            let bytes = data.copy_to_bytes(data.remaining());
+            total_size += bytes.len() as u64;
+
+            // Check file size limit
+            if total_size > max_size {
+                return Err(StorageError::UploadError(format!(
+                    "File size exceeds maximum allowed size of {} bytes",
+                    max_size
+                )));
+            }
+
+            for sink in &sinks {
+                // A closed channel means that backend has already failed
+                let _ = sink.send(Ok(bytes.clone())).await;
+            }
+        }
+
+        Ok(total_size)
+    }
+    .await;
+
+    match &result {
+        Ok(size) => {
+            debug!("File streamed successfully: {} bytes", size);
+            tracing::Span::current().record("size", size);
+        }
+        Err(e) => {
+            for sink in &sinks {
+                let _ = sink.send(Err(io::Error::other(e.to_string()))).await;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a multipart form with a single field
+    async fn form_with_file(name: &str, filename: &str, contents: &str) -> FormData {
+        let body = format!(
+            "--BOUNDARY\r\n\
+             Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             {}\r\n\
+             --BOUNDARY--\r\n",
+            name, filename, contents
+        );
+
+        warp::test::request()
+            .method("POST")
+            .header("content-type", "multipart/form-data; boundary=BOUNDARY")
+            .body(body)
+            .filter(&warp::multipart::form())
+            .await
+            .unwrap()
+    }
+
+    /// Drain a channel into a single buffer
+    async fn collect(mut rx: mpsc::Receiver<io::Result<Bytes>>) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn test_tee_part_copies_to_every_sink() {
+        let mut form = form_with_file("file", "hello.txt", "hello world").await;
+        let (part, filename) = next_file_part(&mut form).await.unwrap();
+        assert_eq!(filename, "hello.txt");
+
+        let (tx1, rx1) = mpsc::channel(1);
+        let (tx2, rx2) = mpsc::channel(1);
+        let tracker = UploadTracker::new();
+        let (size, first, second) = join!(
+            tee_part(part, vec![tx1, tx2], 1024, &tracker),
+            collect(rx1),
+            collect(rx2)
+        );
+
+        assert_eq!(size.unwrap(), 11);
+        assert_eq!(first.unwrap(), b"hello world");
+        assert_eq!(second.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_tee_part_aborts_sinks_when_too_large() {
+        let mut form = form_with_file("file", "big.txt", "hello world").await;
+        let (part, _) = next_file_part(&mut form).await.unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        let tracker = UploadTracker::new();
+        let (size, received) = join!(tee_part(part, vec![tx], 4, &tracker), collect(rx));
+
+        assert!(matches!(size, Err(StorageError::UploadError(_))));
+        assert!(received.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tee_part_continues_after_sink_closes() {
+        let mut form = form_with_file("file", "hello.txt", "hello world").await;
+        let (part, _) = next_file_part(&mut form).await.unwrap();
+
+        let (closed_tx, closed_rx) = mpsc::channel(1);
+        drop(closed_rx);
+        let (tx, rx) = mpsc::channel(1);
+        let tracker = UploadTracker::new();
+        let (size, received) = join!(
+            tee_part(part, vec![closed_tx, tx], 1024, &tracker),
+            collect(rx)
+        );
+
+        assert_eq!(size.unwrap(), 11);
+        assert_eq!(received.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_tee_part_aborts_sinks_when_cancelled() {
+        let mut form = form_with_file("file", "hello.txt", "hello world").await;
+        let (part, _) = next_file_part(&mut form).await.unwrap();
+
+        let tracker = UploadTracker::new();
+        tracker.cancel();
+        let (tx, rx) = mpsc::channel(4);
+        let (size, received) = join!(tee_part(part, vec![tx], 1024, &tracker), collect(rx));
+
+        assert!(matches!(size, Err(StorageError::ShuttingDown)));
+        assert!(received.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_next_file_part_requires_file_field() {
+        let mut form = form_with_file("avatar", "hello.txt", "hello world").await;
+        let result = next_file_part(&mut form).await;
+        assert!(matches!(result, Err(StorageError::NoFileError)));
+    }
+}
@@ -0,0 +1,257 @@
+//! In-flight upload tracking
+//!
+//! This module keeps count of the uploads currently being processed and of
+//! the temporary files they have written. During a graceful shutdown the
+//! server uses it to wait for in-flight uploads to drain, to cancel the
+//! uploads that did not finish in time and to remove the temporary files
+//! they left behind.
+
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Shared registry of in-flight uploads and their temporary files
+///
+/// Cloning the tracker is cheap; all clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct UploadTracker {
+    inner: Arc<TrackerState>,
+}
+
+#[derive(Debug)]
+struct TrackerState {
+    in_flight: watch::Sender<usize>,
+    /// Set once in-flight uploads are told to stop
+    cancelled: watch::Sender<bool>,
+    temp_files: Mutex<HashSet<PathBuf>>,
+}
+
+impl Default for TrackerState {
+    fn default() -> Self {
+        Self {
+            in_flight: watch::channel(0).0,
+            cancelled: watch::channel(false).0,
+            temp_files: Mutex::default(),
+        }
+    }
+}
+
+/// Marks an upload as in flight for as long as it is alive
+///
+/// Returned by [`UploadTracker::begin`]; dropping it marks the upload as
+/// finished, whether it succeeded, failed or was cancelled.
+#[derive(Debug)]
+pub struct UploadGuard {
+    tracker: UploadTracker,
+}
+
+impl UploadTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the start of an upload
+    ///
+    /// # Returns
+    ///
+    /// Returns a guard that marks the upload as finished when dropped
+    pub fn begin(&self) -> UploadGuard {
+        self.inner
+            .in_flight
+            .send_modify(|in_flight| *in_flight += 1);
+        UploadGuard {
+            tracker: self.clone(),
+        }
+    }
+
+    /// Number of uploads currently being processed
+    pub fn in_flight(&self) -> usize {
+        *self.inner.in_flight.borrow()
+    }
+
+    /// Wait until no upload is in flight
+    pub async fn drained(&self) {
+        let mut in_flight = self.inner.in_flight.subscribe();
+        // The tracker keeps the sender alive, so this cannot fail
+        let _ = in_flight.wait_for(|in_flight| *in_flight == 0).await;
+    }
+
+    /// Tell in-flight uploads to stop
+    ///
+    /// Uploads still reading their request body fail, so their multipart
+    /// uploads are aborted and their temporary files removed. Uploads that
+    /// already hold the whole file are left to finish.
+    pub fn cancel(&self) {
+        self.inner.cancelled.send_replace(true);
+    }
+
+    /// Wait until [`UploadTracker::cancel`] is called
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.inner.cancelled.subscribe();
+        // The tracker keeps the sender alive, so this cannot fail
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Record a temporary file so it can be cleaned up on shutdown
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the temporary file
+    pub fn register_temp_file(&self, path: &Path) {
+        self.temp_files().insert(path.to_path_buf());
+    }
+
+    /// Delete a temporary file and stop tracking it
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the temporary file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be removed. The file is no longer
+    /// tracked either way.
+    pub async fn remove_temp_file(&self, path: &Path) -> io::Result<()> {
+        self.temp_files().remove(path);
+        tokio::fs::remove_file(path).await
+    }
+
+    /// Stop tracking a temporary file without deleting it
+    ///
+    /// Used once the file has been handed over to something that outlives
+    /// the request, such as a background job.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the temporary file
+    pub fn forget_temp_file(&self, path: &Path) {
+        self.temp_files().remove(path);
+    }
+
+    /// Delete every temporary file still being tracked
+    ///
+    /// Called once the server has stopped, to clean up after uploads that
+    /// were interrupted.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of files removed
+    pub async fn cleanup(&self) -> usize {
+        let paths: Vec<PathBuf> = self.temp_files().drain().collect();
+        let mut removed = 0;
+
+        for path in paths {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    debug!("Removed leftover temporary file: {}", path.display());
+                    removed += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove temporary file {}: {}", path.display(), e),
+            }
+        }
+
+        removed
+    }
+
+    fn temp_files(&self) -> std::sync::MutexGuard<'_, HashSet<PathBuf>> {
+        // The set stays consistent even if a holder panicked, so recover from poisoning
+        self.inner
+            .temp_files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.tracker
+            .inner
+            .in_flight
+            .send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::file::{generate_temp_filepath, write_string_to_file};
+
+    #[test]
+    fn test_in_flight_count() {
+        let tracker = UploadTracker::new();
+        assert_eq!(tracker.in_flight(), 0);
+
+        let first = tracker.begin();
+        let second = tracker.clone().begin();
+        assert_eq!(tracker.in_flight(), 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(tracker.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drained() {
+        let tracker = UploadTracker::new();
+        tracker.drained().await;
+
+        let upload = tracker.begin();
+        let drained = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.drained().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!drained.is_finished());
+
+        drop(upload);
+        drained.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let tracker = UploadTracker::new();
+        let cancelled = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!cancelled.is_finished());
+
+        tracker.cancel();
+        cancelled.await.unwrap();
+        // Uploads starting afterwards see the cancellation too
+        tracker.cancelled().await;
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_removes_registered_files() -> io::Result<()> {
+        let tracker = UploadTracker::new();
+        let path = generate_temp_filepath("part");
+        write_string_to_file(path.to_str().unwrap(), "partial upload")?;
+
+        tracker.register_temp_file(&path);
+        assert_eq!(tracker.cleanup().await, 1);
+        assert!(!path.exists());
+
+        // Nothing left to clean up
+        assert_eq!(tracker.cleanup().await, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_removed_files_are_not_cleaned_up_again() -> io::Result<()> {
+        let tracker = UploadTracker::new();
+        let path = generate_temp_filepath("part");
+        write_string_to_file(path.to_str().unwrap(), "complete upload")?;
+
+        tracker.register_temp_file(&path);
+        tracker.remove_temp_file(&path).await?;
+        assert_eq!(tracker.cleanup().await, 0);
+        Ok(())
+    }
+}
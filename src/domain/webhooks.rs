@@ -0,0 +1,628 @@
+//! Webhook notifications of upload lifecycle events
+//!
+//! Downstream services can subscribe to events instead of polling:
+//!
+//! - `upload.completed`: a file was stored on enough backends to satisfy the
+//!   write policy; the payload is the [`UploadResponse`]
+//! - `upload.failed`: a file could not be stored; see [`UploadFailure`]
+//! - `file.deleted`: a stored copy of a file was deleted, such as a copy
+//!   rolled back after the upload failed; see [`DeletedFile`]
+//! - `mfs.snapshot`: the MFS root uploads are placed under changed and its
+//!   new CID was pinned; see [`MfsSnapshot`]
+//!
+//! Emitting an event writes one delivery per endpoint to the outbox in
+//! `WEBHOOK_OUTBOX_DIR` before returning, so events are not lost if the
+//! service crashes. A background dispatcher delivers them (see
+//! [`webhook`] for the signing scheme) and removes them once delivered.
+//! Failed deliveries are retried with exponential backoff under the
+//! `WEBHOOK_RETRY_*` policy. Deliveries that fail permanently or run out of
+//! attempts are moved to the `dead` subdirectory of the outbox.
+//!
+//! Delivery is at least once: a delivery interrupted by a crash is sent
+//! again, and receivers should deduplicate on the event ID.
+
+use crate::config::{Backend, WebhookConfig, WebhookEvent};
+use crate::domain::services::UploadResponse;
+use crate::infrastructure::{retry, webhook};
+use crate::utils::file;
+use crate::utils::shutdown::Shutdown;
+use anyhow::Context;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Longest the dispatcher sleeps before checking the outbox again
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Payload of an `upload.failed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadFailure {
+    /// The original filename
+    pub filename: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// Why the upload failed, including what was rolled back
+    pub error: String,
+}
+
+/// Payload of a `file.deleted` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedFile {
+    /// The original filename
+    pub filename: String,
+    /// The backend the copy was deleted from
+    pub backend: Backend,
+    /// The S3 key of the deleted object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The unpinned IPFS CID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+    /// Why the copy was deleted
+    pub reason: String,
+}
+
+/// Payload of an `mfs.snapshot` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfsSnapshot {
+    /// MFS path of the root
+    pub root: String,
+    /// CID of the root
+    pub cid: String,
+    /// CID of the previous snapshot taken since the service started, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+}
+
+/// An event, as delivered in the body of a webhook request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Event ID, stable across redeliveries
+    pub id: Uuid,
+    /// Event type
+    #[serde(rename = "type")]
+    pub event: WebhookEvent,
+    /// Unix time the event occurred, in seconds
+    pub created_at: u64,
+    /// The background job that processed the upload, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<Uuid>,
+    /// Event payload
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    fn new(event: WebhookEvent, job_id: Option<Uuid>, data: impl Serialize) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event,
+            created_at: unix_millis() / 1000,
+            job_id,
+            data: serde_json::to_value(data).unwrap_or_default(),
+        }
+    }
+
+    /// An `upload.completed` event
+    pub fn upload_completed(response: &UploadResponse, job_id: Option<Uuid>) -> Self {
+        Self::new(WebhookEvent::UploadCompleted, job_id, response)
+    }
+
+    /// An `upload.failed` event
+    pub fn upload_failed(failure: UploadFailure, job_id: Option<Uuid>) -> Self {
+        Self::new(WebhookEvent::UploadFailed, job_id, failure)
+    }
+
+    /// A `file.deleted` event
+    pub fn file_deleted(deleted: DeletedFile) -> Self {
+        Self::new(WebhookEvent::FileDeleted, None, deleted)
+    }
+
+    /// An `mfs.snapshot` event
+    pub fn mfs_snapshot(snapshot: MfsSnapshot) -> Self {
+        Self::new(WebhookEvent::MfsSnapshot, None, snapshot)
+    }
+}
+
+/// A pending delivery of an event to one endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    /// Delivery ID, naming the outbox record
+    pub id: Uuid,
+    /// ID of the delivered event
+    pub event_id: Uuid,
+    /// Type of the delivered event
+    pub event: WebhookEvent,
+    /// Endpoint URL
+    pub url: String,
+    /// Serialized event, sent verbatim on every attempt
+    pub body: String,
+    /// Attempts made so far
+    pub attempts: u32,
+    /// Unix time of the next attempt, in milliseconds
+    pub next_attempt_at: u64,
+    /// Why the last attempt failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Durable outbox of pending deliveries
+///
+/// Every delivery is kept in memory and mirrored to `{dir}/{id}.json` until
+/// it succeeds. Cloning the outbox is cheap; all clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    dir: PathBuf,
+    deliveries: Arc<Mutex<HashMap<Uuid, Delivery>>>,
+    /// Wakes the dispatcher when a delivery is added
+    added: Arc<Notify>,
+}
+
+impl Outbox {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            deliveries: Arc::default(),
+            added: Arc::default(),
+        }
+    }
+
+    /// Open the outbox, loading the deliveries found in `dir`
+    ///
+    /// The directory is created when the first delivery is added, so a
+    /// missing directory is an empty outbox.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory holding the pending deliveries
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory exists but cannot be read.
+    /// Unreadable records are skipped with a warning.
+    pub async fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let outbox = Self::new(dir.into());
+
+        let mut entries = match tokio::fs::read_dir(&outbox.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(outbox),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                // Left behind by a write that was interrupted
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+            if !path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+
+            let delivery = tokio::fs::read(&path).await.and_then(|data| {
+                serde_json::from_slice::<Delivery>(&data).map_err(io::Error::other)
+            });
+            match delivery {
+                Ok(delivery) => {
+                    outbox.deliveries().insert(delivery.id, delivery);
+                }
+                Err(e) => warn!("Skipping unreadable delivery {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(outbox)
+    }
+
+    /// Number of deliveries not yet completed
+    pub fn len(&self) -> usize {
+        self.deliveries().len()
+    }
+
+    /// Whether every delivery has completed
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist a delivery and queue it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the delivery cannot be written to disk, in which
+    /// case it is not queued
+    pub async fn push(&self, delivery: Delivery) -> io::Result<()> {
+        self.save(&delivery).await?;
+        self.deliveries().insert(delivery.id, delivery);
+        self.added.notify_one();
+        Ok(())
+    }
+
+    /// Deliveries whose next attempt is due at `now` (Unix milliseconds)
+    fn due(&self, now: u64) -> Vec<Delivery> {
+        self.deliveries()
+            .values()
+            .filter(|delivery| delivery.next_attempt_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// Time until the next delivery is due, if any
+    fn next_due_in(&self, now: u64) -> Option<Duration> {
+        self.deliveries()
+            .values()
+            .map(|delivery| Duration::from_millis(delivery.next_attempt_at.saturating_sub(now)))
+            .min()
+    }
+
+    /// Persist a rescheduled delivery
+    async fn reschedule(&self, delivery: Delivery) -> io::Result<()> {
+        self.save(&delivery).await?;
+        self.deliveries().insert(delivery.id, delivery);
+        Ok(())
+    }
+
+    /// Remove a delivered delivery
+    async fn complete(&self, id: Uuid) -> io::Result<()> {
+        self.deliveries().remove(&id);
+        tokio::fs::remove_file(self.path(id)).await
+    }
+
+    /// Move a delivery that will not be retried to the dead letter directory
+    async fn dead_letter(&self, delivery: Delivery) -> io::Result<()> {
+        self.deliveries().remove(&delivery.id);
+
+        let dead = self.dir.join("dead");
+        tokio::fs::create_dir_all(&dead).await?;
+        let data = serde_json::to_vec_pretty(&delivery).map_err(io::Error::other)?;
+        file::write_file_atomic(&dead.join(format!("{}.json", delivery.id)), &data).await?;
+        tokio::fs::remove_file(self.path(delivery.id)).await
+    }
+
+    async fn save(&self, delivery: &Delivery) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let data = serde_json::to_vec_pretty(delivery).map_err(io::Error::other)?;
+        file::write_file_atomic(&self.path(delivery.id), &data).await
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn deliveries(&self) -> MutexGuard<'_, HashMap<Uuid, Delivery>> {
+        // The map stays consistent even if a holder panicked, so recover from poisoning
+        self.deliveries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Handle for emitting webhook events
+///
+/// Cloning the handle is cheap; all clones share the same outbox.
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    config: Arc<WebhookConfig>,
+    client: reqwest::Client,
+    outbox: Outbox,
+}
+
+impl Webhooks {
+    /// Open the outbox and create the webhook HTTP client
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Webhook settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox directory exists but cannot be read,
+    /// so pending deliveries are never silently dropped, or if the HTTP
+    /// client cannot be created, i.e. the TLS backend cannot be initialized
+    pub async fn open(config: &WebhookConfig) -> anyhow::Result<Self> {
+        let outbox = Outbox::open(&config.outbox_dir)
+            .await
+            .with_context(|| format!("Failed to load webhook outbox from {}", config.outbox_dir))?;
+        if !outbox.is_empty() {
+            info!("Resuming {} pending webhook delivery(ies)", outbox.len());
+        }
+
+        let client = webhook::create_webhook_client(Duration::from_secs(config.timeout_secs))?;
+
+        Ok(Self {
+            config: Arc::new(config.clone()),
+            client,
+            outbox,
+        })
+    }
+
+    /// Queue an event for delivery to every endpoint
+    ///
+    /// Events whose type is not enabled in `WEBHOOK_EVENTS`, or emitted while
+    /// no endpoint is configured, are dropped. Failures to write the outbox
+    /// are logged rather than returned, so they never fail the upload that
+    /// emitted the event.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event to deliver
+    pub async fn emit(&self, event: Event) {
+        if !self.config.is_enabled(event.event) {
+            return;
+        }
+
+        let body = match serde_json::to_string(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize {} event: {}", event.event, e);
+                return;
+            }
+        };
+
+        for url in &self.config.urls {
+            let delivery = Delivery {
+                id: Uuid::new_v4(),
+                event_id: event.id,
+                event: event.event,
+                url: url.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt_at: 0,
+                last_error: None,
+            };
+
+            match self.outbox.push(delivery).await {
+                Ok(()) => debug!("Queued {} {} for {}", event.event, event.id, url),
+                Err(e) => error!(
+                    "Failed to queue {} {} for {}, dropping it: {}",
+                    event.event, event.id, url, e
+                ),
+            }
+        }
+    }
+}
+
+/// Start the background dispatcher delivering the outbox
+///
+/// Due deliveries are sent concurrently. The dispatcher sleeps until the next
+/// delivery is due or a new one is added, and stops after the deliveries in
+/// progress once `shutdown` is stopped; the rest stay in the outbox.
+///
+/// # Arguments
+///
+/// * `webhooks` - The webhook handle whose outbox is delivered
+/// * `shutdown` - Stops the dispatcher when the service shuts down
+pub fn spawn_dispatcher(webhooks: Webhooks, shutdown: &Shutdown) -> JoinHandle<()> {
+    let mut stopping = shutdown.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            if *stopping.borrow() {
+                return;
+            }
+
+            let now = unix_millis();
+            let due = webhooks.outbox.due(now);
+            futures::future::join_all(due.into_iter().map(|delivery| attempt(&webhooks, delivery)))
+                .await;
+
+            let wait = webhooks
+                .outbox
+                .next_due_in(unix_millis())
+                .unwrap_or(IDLE_POLL_INTERVAL)
+                .min(IDLE_POLL_INTERVAL);
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = webhooks.outbox.added.notified() => {}
+                _ = stopping.changed() => {}
+            }
+        }
+    })
+}
+
+/// Attempt a delivery and record the outcome in the outbox
+async fn attempt(webhooks: &Webhooks, mut delivery: Delivery) {
+    let config = &webhooks.config;
+    delivery.attempts += 1;
+
+    let result = webhook::deliver(
+        &webhooks.client,
+        &delivery.url,
+        &config.secret,
+        &delivery.event_id.to_string(),
+        delivery.event,
+        &delivery.body,
+    )
+    .await;
+
+    let saved = match result {
+        Ok(()) => {
+            info!(
+                "Delivered {} {} to {}",
+                delivery.event, delivery.event_id, delivery.url
+            );
+            webhooks.outbox.complete(delivery.id).await
+        }
+        Err(e) if retry::is_transient(&e) && delivery.attempts < config.retry.max_attempts => {
+            let delay = retry::backoff_delay(&config.retry, delivery.attempts);
+            warn!(
+                "Webhook delivery failed (attempt {}/{}), retrying in {:?}: {:#}",
+                delivery.attempts, config.retry.max_attempts, delay, e
+            );
+            delivery.next_attempt_at = unix_millis() + delay.as_millis() as u64;
+            delivery.last_error = Some(format!("{:#}", e));
+            webhooks.outbox.reschedule(delivery.clone()).await
+        }
+        Err(e) => {
+            error!(
+                "Giving up delivering {} {} to {} after {} attempt(s): {:#}",
+                delivery.event, delivery.event_id, delivery.url, delivery.attempts, e
+            );
+            delivery.last_error = Some(format!("{:#}", e));
+            webhooks.outbox.dead_letter(delivery.clone()).await
+        }
+    };
+
+    if let Err(e) = saved {
+        error!("Failed to update webhook outbox for {}: {}", delivery.id, e);
+    }
+}
+
+/// Current Unix time in milliseconds
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RetryPolicy;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use warp::Filter;
+
+    fn config(urls: Vec<String>) -> WebhookConfig {
+        WebhookConfig {
+            urls,
+            secret: "secret".to_string(),
+            outbox_dir: std::env::temp_dir()
+                .join(format!("outbox-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .into_owned(),
+            retry: RetryPolicy {
+                max_attempts: 2,
+                initial_backoff_ms: 0,
+                max_backoff_ms: 0,
+                jitter: false,
+            },
+            ..WebhookConfig::default()
+        }
+    }
+
+    fn spawn_endpoint(status: u16) -> SocketAddr {
+        let routes = warp::any().map(move || {
+            warp::reply::with_status("", warp::http::StatusCode::from_u16(status).unwrap())
+        });
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    fn failure() -> Event {
+        Event::upload_failed(
+            UploadFailure {
+                filename: "test.jpg".to_string(),
+                size: 1024,
+                error: "ipfs unavailable".to_string(),
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let job_id = Uuid::new_v4();
+        let json = serde_json::to_value(Event::upload_failed(
+            UploadFailure {
+                filename: "test.jpg".to_string(),
+                size: 1024,
+                error: "ipfs unavailable".to_string(),
+            },
+            Some(job_id),
+        ))
+        .unwrap();
+
+        assert_eq!(json["type"], "upload.failed");
+        assert_eq!(json["job_id"], job_id.to_string());
+        assert_eq!(json["data"]["filename"], "test.jpg");
+        assert!(json.get("id").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_emit_persists_deliveries() {
+        let config = config(vec![
+            "http://127.0.0.1:9/a".to_string(),
+            "http://127.0.0.1:9/b".to_string(),
+        ]);
+        let webhooks = Webhooks::open(&config).await.unwrap();
+
+        webhooks.emit(failure()).await;
+        assert_eq!(webhooks.outbox.len(), 2);
+
+        // Deliveries survive a restart
+        let reopened = Outbox::open(&config.outbox_dir).await.unwrap();
+        assert_eq!(reopened.len(), 2);
+
+        tokio::fs::remove_dir_all(&config.outbox_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_fails_on_unreadable_outbox() {
+        let mut config = config(Vec::new());
+        let path = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        tokio::fs::write(&path, b"not a directory").await.unwrap();
+        config.outbox_dir = path.to_string_lossy().into_owned();
+
+        assert!(Webhooks::open(&config).await.is_err());
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_emit_skips_disabled_events() {
+        let mut config = config(vec!["http://127.0.0.1:9/a".to_string()]);
+        config.events = vec![WebhookEvent::UploadCompleted];
+        let webhooks = Webhooks::open(&config).await.unwrap();
+
+        webhooks.emit(failure()).await;
+        assert!(webhooks.outbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delivered_events_leave_outbox() {
+        let addr = spawn_endpoint(204);
+        let config = config(vec![format!("http://{}/hook", addr)]);
+        let webhooks = Webhooks::open(&config).await.unwrap();
+
+        webhooks.emit(failure()).await;
+        for delivery in webhooks.outbox.due(unix_millis()) {
+            attempt(&webhooks, delivery).await;
+        }
+
+        assert!(webhooks.outbox.is_empty());
+        assert!(Outbox::open(&config.outbox_dir).await.unwrap().is_empty());
+        tokio::fs::remove_dir_all(&config.outbox_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_are_retried_then_dead_lettered() {
+        let addr = spawn_endpoint(503);
+        let config = config(vec![format!("http://{}/hook", addr)]);
+        let webhooks = Webhooks::open(&config).await.unwrap();
+        webhooks.emit(failure()).await;
+
+        let delivery = webhooks.outbox.due(unix_millis()).pop().unwrap();
+        attempt(&webhooks, delivery).await;
+        let delivery = webhooks.outbox.due(unix_millis()).pop().unwrap();
+        assert_eq!(delivery.attempts, 1);
+        assert!(delivery.last_error.is_some());
+
+        // The second attempt exhausts the policy
+        attempt(&webhooks, delivery.clone()).await;
+        assert!(webhooks.outbox.is_empty());
+
+        let dead = Path::new(&config.outbox_dir)
+            .join("dead")
+            .join(format!("{}.json", delivery.id));
+        assert!(dead.exists());
+        tokio::fs::remove_dir_all(&config.outbox_dir).await.unwrap();
+    }
+}
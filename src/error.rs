@@ -10,12 +10,15 @@ use thiserror::Error;
 /// This enum represents all possible errors that can occur in the application,
 /// providing detailed context for each error scenario.
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum StorageError {
     /// Error occurred while interacting with Amazon S3
+    #[allow(dead_code)]
     #[error("S3 operation failed: {0}")]
     S3Error(String),
 
     /// Error occurred while interacting with IPFS
+    #[allow(dead_code)]
     #[error("IPFS operation failed: {0}")]
     IpfsError(String),
 
@@ -35,11 +38,25 @@ pub enum StorageError {
     #[error("Upload processing failed: {0}")]
     UploadError(String),
 
+    /// A storage backend failed to store an upload, so the write policy is
+    /// not met
+    #[error("Storage backend failed: {0}")]
+    BackendFailed(String),
+
+    /// Too many storage backends are unavailable to accept an upload
+    #[error("Storage backend unavailable: {0}")]
+    BackendUnavailable(String),
+
+    /// The upload was cancelled because the service is shutting down
+    #[error("The service is shutting down")]
+    ShuttingDown,
+
     /// Error occurred due to missing file in the upload request
     #[error("No file found in upload request")]
     NoFileError,
 
     /// Generic error for unexpected failures
+    #[allow(dead_code)]
     #[error("Internal server error: {0}")]
     InternalError(String),
 
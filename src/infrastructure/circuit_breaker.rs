@@ -0,0 +1,393 @@
+//! Circuit breakers for the storage backends
+//!
+//! When a backend is down, waiting for every upload to time out against it
+//! wastes time and connections. Each backend has a [`CircuitBreaker`] that
+//! counts consecutive transient failures (after retries are exhausted):
+//!
+//! - **Closed**: calls go through. Once `failure_threshold` calls in a row
+//!   have failed, the circuit opens.
+//! - **Open**: calls fail immediately with [`CircuitOpen`] for `open_secs`.
+//! - **Half-open**: after the cooldown a single probe call is let through.
+//!   If it succeeds the circuit closes; if it fails the circuit opens again.
+//!
+//! Only transient failures (see [`retry::is_transient`]) count, so client
+//! errors such as an oversized upload never trip a circuit.
+
+use crate::config::{Backend, CircuitBreakerConfig};
+use crate::infrastructure::retry;
+use anyhow::Result;
+use log::{info, warn};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Error returned without calling the backend while its circuit is open
+#[derive(Debug, Error)]
+#[error("{backend} circuit is open, backend is unavailable")]
+pub struct CircuitOpen {
+    /// The unavailable backend
+    pub backend: Backend,
+}
+
+/// Externally visible state of a circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls fail fast
+    Open,
+    /// A probe call decides whether to close the circuit
+    HalfOpen,
+}
+
+/// Snapshot of a circuit, as reported by the health endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    /// Current state
+    pub state: CircuitState,
+    /// Transient failures in a row since the last success
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+enum Phase {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { probing: bool },
+}
+
+#[derive(Debug)]
+struct Inner {
+    phase: Phase,
+    consecutive_failures: u32,
+}
+
+/// Circuit breaker guarding one backend
+///
+/// Cloning the breaker is cheap; all clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    backend: Backend,
+    failure_threshold: u32,
+    open_for: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl CircuitBreaker {
+    /// Create a closed circuit breaker
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The guarded backend
+    /// * `config` - Failure threshold and cooldown
+    pub fn new(backend: Backend, config: &CircuitBreakerConfig) -> Self {
+        Self {
+            backend,
+            failure_threshold: config.failure_threshold,
+            open_for: Duration::from_secs(config.open_secs),
+            inner: Arc::new(Mutex::new(Inner {
+                phase: Phase::Closed,
+                consecutive_failures: 0,
+            })),
+        }
+    }
+
+    /// Run a backend call through the breaker
+    ///
+    /// The call is not started while the circuit is open, or while another
+    /// call is probing a half-open circuit.
+    ///
+    /// # Arguments
+    ///
+    /// * `call` - The backend call
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CircuitOpen`] if the call was not allowed, or the call's
+    /// own error
+    pub async fn call<T, Fut>(&self, call: Fut) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let probe = self.acquire()?;
+        let mut outcome = Outcome {
+            breaker: self,
+            probe,
+            result: None,
+        };
+
+        let result = call.await;
+        outcome.result = match &result {
+            Ok(_) => Some(true),
+            Err(e) if retry::is_transient(e) => Some(false),
+            // Permanent errors say nothing about the backend's health
+            Err(_) => None,
+        };
+
+        result
+    }
+
+    /// Whether a call would currently be allowed through
+    pub fn is_available(&self) -> bool {
+        match self.lock().phase {
+            Phase::Closed => true,
+            Phase::Open { until } => Instant::now() >= until,
+            Phase::HalfOpen { probing } => !probing,
+        }
+    }
+
+    /// Current state of the circuit
+    pub fn status(&self) -> CircuitStatus {
+        let inner = self.lock();
+        let state = match inner.phase {
+            Phase::Closed => CircuitState::Closed,
+            Phase::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            Phase::Open { .. } => CircuitState::Open,
+            Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        };
+
+        CircuitStatus {
+            state,
+            consecutive_failures: inner.consecutive_failures,
+        }
+    }
+
+    /// Admit a call, returning whether it is the half-open probe
+    fn acquire(&self) -> Result<bool, CircuitOpen> {
+        let mut inner = self.lock();
+
+        match inner.phase {
+            Phase::Closed => Ok(false),
+            Phase::Open { until } if Instant::now() >= until => {
+                info!("{} circuit half-open, probing backend", self.backend);
+                inner.phase = Phase::HalfOpen { probing: true };
+                Ok(true)
+            }
+            Phase::HalfOpen { probing: false } => {
+                inner.phase = Phase::HalfOpen { probing: true };
+                Ok(true)
+            }
+            Phase::Open { .. } | Phase::HalfOpen { probing: true } => Err(CircuitOpen {
+                backend: self.backend,
+            }),
+        }
+    }
+
+    /// Record the outcome of an admitted call
+    ///
+    /// `healthy` is `None` when the call says nothing about the backend's
+    /// health: it was cancelled before completing or failed permanently.
+    fn release(&self, probe: bool, healthy: Option<bool>) {
+        let mut inner = self.lock();
+
+        match healthy {
+            Some(true) => {
+                if !matches!(inner.phase, Phase::Closed) {
+                    info!("{} circuit closed, backend recovered", self.backend);
+                }
+                inner.phase = Phase::Closed;
+                inner.consecutive_failures = 0;
+            }
+            Some(false) => {
+                inner.consecutive_failures += 1;
+
+                if probe || inner.consecutive_failures >= self.failure_threshold {
+                    if !matches!(inner.phase, Phase::Open { .. }) {
+                        warn!(
+                            "{} circuit open after {} consecutive failures, failing fast for {}s",
+                            self.backend,
+                            inner.consecutive_failures,
+                            self.open_for.as_secs()
+                        );
+                    }
+                    inner.phase = Phase::Open {
+                        until: Instant::now() + self.open_for,
+                    };
+                }
+            }
+            None => {
+                // A neutral probe proves nothing; let the next call probe
+                if probe {
+                    inner.phase = Phase::HalfOpen { probing: false };
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The state stays consistent even if a holder panicked, so recover from poisoning
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Records the outcome of a call when dropped, including on cancellation
+struct Outcome<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    result: Option<bool>,
+}
+
+impl Drop for Outcome<'_> {
+    fn drop(&mut self) {
+        self.breaker.release(self.probe, self.result);
+    }
+}
+
+/// The circuit breakers of all backends
+#[derive(Debug, Clone)]
+pub struct Breakers {
+    /// Breaker guarding Amazon S3
+    pub s3: CircuitBreaker,
+    /// Breaker guarding the IPFS daemon
+    pub ipfs: CircuitBreaker,
+}
+
+impl Breakers {
+    /// Create closed breakers for every backend
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Failure threshold and cooldown shared by all breakers
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            s3: CircuitBreaker::new(Backend::S3, config),
+            ipfs: CircuitBreaker::new(Backend::Ipfs, config),
+        }
+    }
+
+    /// Breaker guarding `backend`
+    pub fn get(&self, backend: Backend) -> &CircuitBreaker {
+        match backend {
+            Backend::S3 => &self.s3,
+            Backend::Ipfs => &self.ipfs,
+        }
+    }
+
+    /// Backends whose circuit currently lets calls through
+    pub fn available(&self) -> Vec<Backend> {
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| self.get(*backend).is_available())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_secs: u64) -> CircuitBreaker {
+        CircuitBreaker::new(
+            Backend::Ipfs,
+            &CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_secs,
+            },
+        )
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<()> {
+        breaker
+            .call(async { Err(retry::transient(std::io::Error::other("refused"))) })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_opens_after_consecutive_failures() {
+        let breaker = breaker(60);
+
+        assert!(fail(&breaker).await.is_err());
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert!(fail(&breaker).await.is_err());
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(!breaker.is_available());
+
+        // Open circuits fail fast without running the call
+        let result = breaker.call(async { Ok(()) }).await;
+        assert!(result.unwrap_err().is::<CircuitOpen>());
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_do_not_trip() {
+        let breaker = breaker(60);
+
+        for _ in 0..3 {
+            let result: Result<()> = breaker.call(async { Err(anyhow::anyhow!("denied")) }).await;
+            assert!(result.is_err());
+        }
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_neutral() {
+        let breaker = breaker(0);
+
+        // A permanent error neither resets the failure count...
+        fail(&breaker).await.unwrap_err();
+        let denied: Result<()> = breaker.call(async { Err(anyhow::anyhow!("denied")) }).await;
+        denied.unwrap_err();
+        assert_eq!(breaker.status().consecutive_failures, 1);
+
+        // ...nor closes a half-open circuit, but frees the probe slot
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        let denied: Result<()> = breaker.call(async { Err(anyhow::anyhow!("denied")) }).await;
+        denied.unwrap_err();
+        assert!(matches!(
+            breaker.lock().phase,
+            Phase::HalfOpen { probing: false }
+        ));
+        assert_eq!(breaker.status().consecutive_failures, 2);
+        assert!(breaker.is_available());
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_closes_on_success() {
+        let breaker = breaker(0);
+
+        fail(&breaker).await.unwrap_err();
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+
+        breaker.call(async { Ok(()) }).await.unwrap();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_reopens_on_failure() {
+        let breaker = CircuitBreaker::new(
+            Backend::S3,
+            &CircuitBreakerConfig {
+                failure_threshold: 1,
+                open_secs: 0,
+            },
+        );
+
+        fail(&breaker).await.unwrap_err();
+        assert!(breaker.acquire().unwrap());
+
+        // Only one probe at a time
+        assert!(breaker.acquire().is_err());
+        breaker.release(true, Some(false));
+        assert!(matches!(breaker.lock().phase, Phase::Open { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_breakers_available() {
+        let breakers = Breakers::new(&CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_secs: 60,
+        });
+        assert_eq!(breakers.available(), vec![Backend::S3, Backend::Ipfs]);
+
+        fail(&breakers.ipfs).await.unwrap_err();
+        assert_eq!(breakers.available(), vec![Backend::S3]);
+    }
+}
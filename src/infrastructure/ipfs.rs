@@ -19,41 +19,153 @@
 //! ipfs daemon
 //! ```
 //!
+//! A remote node is reached by setting `IPFS_API_URL`, with basic credentials
+//! in `IPFS_API_USERNAME` and `IPFS_API_PASSWORD`, or a bearer token in
+//! `IPFS_API_BEARER_TOKEN`, when it sits behind an authenticating proxy.
+//! HTTPS URLs are verified against the system's root certificates. Adding a
+//! spooled file and unpinning are bounded by `IPFS_TIMEOUT_SECS`; streaming
+//! adds and reads run as long as their stream.
+//!
+//! Requests are made by the async [`IpfsClient`] and never block a runtime
+//! thread. Files are streamed to the node as they are read, and dropping
+//! the future of a call aborts its request.
+//!
+//! # IPFS Cluster
+//!
+//! A single node is a single point of failure. When `IPFS_CLUSTER_API_URL`
+//! is set, files are added through the REST API of an IPFS Cluster instead,
+//! which pins them on `IPFS_CLUSTER_REPLICATION_MIN` to
+//! `IPFS_CLUSTER_REPLICATION_MAX` peers under the original filename, and
+//! rollbacks unpin them from the whole cluster. Content is still read from
+//! the node at `IPFS_API_URL`, which should be one of the cluster's peers.
+//! See [`IpfsBackend`].
+//!
+//! # CAR Files
+//!
+//! DAGs move between IPFS networks as CAR files: [`import_car`] loads one
+//! into the node and pins its roots, and [`export_car`] streams the DAG
+//! below a CID back out, keeping the original chunking and CIDs.
+//!
+//! # IPNS
+//!
+//! Keys of the node's keystore can be generated with [`ensure_key`], and
+//! CIDs published under them with [`publish_name`], giving content a
+//! stable, mutable name. [`resolve_name`] reads where a name points.
+//!
+//! # Add Options
+//!
+//! Adds take [`IpfsAddOptions`] selecting the CID version, chunker, hash
+//! function and so on. The CID the node returns is re-encoded in the
+//! requested multibase (see [`encode_cid`]). The same CID can be computed
+//! without the node by [`UnixfsHasher`](crate::infrastructure::unixfs::UnixfsHasher).
+//!
 //! # Examples
 //!
 //! ```no_run
-//! use memenow_storage_service::infrastructure::ipfs::upload_to_ipfs;
+//! use memenow_storage_service::config::{IpfsConfig, RetryPolicy};
+//! use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, upload_to_ipfs};
 //!
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-//! let cid = upload_to_ipfs("/tmp/myfile.jpg").await?;
+//! let config = IpfsConfig::default();
+//! let client = create_ipfs_client(&config)?;
+//! let policy = RetryPolicy::default();
+//! let cid = upload_to_ipfs(
+//!     &client,
+//!     &policy,
+//!     config.timeout(),
+//!     &config.add,
+//!     "/tmp/myfile.jpg",
+//!     "myfile.jpg",
+//!     None,
+//! )
+//! .await?;
 //! println!("File CID: {}", cid);
 //! println!("Access at: https://ipfs.io/ipfs/{}", cid);
 //! # Ok(())
 //! # }
 //! ```
 
+use crate::config::{CidBase, IpfsAddOptions, IpfsConfig, IpnsConfig, RetryPolicy};
+use crate::infrastructure::ipfs_cluster::ClusterClient;
+use crate::infrastructure::ipfs_rpc::{
+    AddProgress, AddedObject, ImportedRoot, IpfsClient, IpfsError, KeyInfo,
+};
+use crate::infrastructure::retry::{self, retry};
+use crate::utils::urls;
 use anyhow::{Context, Result};
-use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
-use log::{debug, info};
-use std::path::Path;
-use tokio::task;
+use bytes::Bytes;
+use cid::multibase::Base;
+use cid::{Cid, Version};
+use futures::{Stream, TryStreamExt};
+use log::{debug, info, warn};
+use reqwest::Body;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::io::ReaderStream;
+
+/// The IPFS deployment storing uploads
+///
+/// Content is always read from the node. It is added and pinned through the
+/// cluster if one is configured, and through the node otherwise.
+///
+/// Cloning is cheap; clones share the connection pools.
+#[derive(Debug, Clone)]
+pub struct IpfsBackend {
+    /// Client of the node's RPC API
+    pub node: IpfsClient,
+    /// Client of the cluster's REST API, if configured
+    pub cluster: Option<ClusterClient>,
+}
+
+impl IpfsBackend {
+    /// Add a file through the cluster or the node
+    ///
+    /// The cluster names the pin after `filename`.
+    async fn add(
+        &self,
+        body: Body,
+        filename: &str,
+        options: &IpfsAddOptions,
+        timeout: Option<Duration>,
+        progress: Option<&AddProgress>,
+    ) -> Result<Vec<AddedObject>, IpfsError> {
+        let name = entry_name(filename);
+
+        match &self.cluster {
+            Some(cluster) => {
+                cluster
+                    .add(body, &name, filename, options, timeout, progress)
+                    .await
+            }
+            None => self.node.add(body, &name, options, timeout, progress).await,
+        }
+    }
+}
 
 /// Upload a file to IPFS
 ///
 /// This function uploads a file to the InterPlanetary File System (IPFS) and returns
 /// the Content Identifier (CID) that can be used to retrieve the file from any IPFS node.
 ///
-/// The function uses `spawn_blocking` to handle the blocking IPFS client operations
-/// without blocking the async runtime. This ensures good performance even when
-/// uploading large files.
+/// The file is streamed to the node as it is read, so memory use does not
+/// grow with its size. Each attempt reopens the file; dropping the returned
+/// future aborts the request in flight.
 ///
 /// # Arguments
 ///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `options` - Options of the add call
 /// * `filepath` - Path to the local file to upload to IPFS
+/// * `filename` - Name of the file, used when it is wrapped in a directory
+/// * `progress` - Callback receiving the bytes the node has processed, if any
 ///
 /// # Returns
 ///
-/// Returns the IPFS Content Identifier (CID/hash) as a string on success.
+/// Returns the IPFS Content Identifier (CID/hash) as a string on success, or
+/// the CID of the wrapping directory if `options` asks for one.
 /// This hash can be used to retrieve the file from any IPFS gateway:
 /// - Public gateway: `https://ipfs.io/ipfs/{hash}`
 /// - Local gateway: `http://127.0.0.1:8080/ipfs/{hash}`
@@ -62,6 +174,7 @@ use tokio::task;
 ///
 /// This function will return an error if:
 /// - The IPFS daemon is not running or not accessible
+/// - An attempt takes longer than `timeout`
 /// - The local file cannot be read
 /// - The file path is invalid
 /// - Network errors occur during upload
@@ -70,11 +183,25 @@ use tokio::task;
 /// # Examples
 ///
 /// ```no_run
-/// use memenow_storage_service::infrastructure::ipfs::upload_to_ipfs;
+/// use memenow_storage_service::config::{IpfsConfig, RetryPolicy};
+/// use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, upload_to_ipfs};
 ///
 /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
 /// // Upload a file to IPFS
-/// let cid = upload_to_ipfs("/tmp/document.pdf").await?;
+/// let config = IpfsConfig::default();
+/// let client = create_ipfs_client(&config)?;
+/// let policy = RetryPolicy::default();
+/// let progress = |bytes: u64| println!("{} bytes added", bytes);
+/// let cid = upload_to_ipfs(
+///     &client,
+///     &policy,
+///     config.timeout(),
+///     &config.add,
+///     "/tmp/document.pdf",
+///     "document.pdf",
+///     Some(&progress),
+/// )
+/// .await?;
 ///
 /// println!("File uploaded to IPFS");
 /// println!("CID: {}", cid);
@@ -86,7 +213,7 @@ use tokio::task;
 /// # Performance Considerations
 ///
 /// - Large files are chunked automatically by IPFS
-/// - The upload operation runs in a blocking thread pool to avoid blocking the async runtime
+/// - The file is streamed from disk without blocking the async runtime
 /// - Files are deduplicated automatically - uploading the same file twice returns the same CID
 /// - Consider pinning important files to ensure they remain available:
 ///   ```bash
@@ -96,95 +223,1210 @@ use tokio::task;
 /// # IPFS Daemon Configuration
 ///
 /// The default IPFS daemon listens on `http://127.0.0.1:5001` for API requests.
-/// To use a different IPFS node, you can modify the client connection or set
-/// environment variables according to the `ipfs-api` crate documentation.
-pub async fn upload_to_ipfs(filepath: &str) -> Result<String> {
+/// To use a different IPFS node, set `IPFS_API_URL` (see [`create_ipfs_client`]).
+#[tracing::instrument(skip_all, fields(cid))]
+pub async fn upload_to_ipfs(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    options: &IpfsAddOptions,
+    filepath: &str,
+    filename: &str,
+    progress: Option<&AddProgress>,
+) -> Result<String> {
     debug!("Initiating IPFS upload: file={}", filepath);
 
-    // Clone filepath for the blocking task
-    let filepath_owned = filepath.to_string();
+    let started = Instant::now();
 
-    // Spawn a blocking task to handle the IPFS upload
-    // This prevents blocking the async runtime since ipfs-api uses blocking operations
-    let hash = task::spawn_blocking(move || {
-        // Create IPFS client connected to local daemon
-        // Default endpoint: http://127.0.0.1:5001
-        let client = IpfsClient::default();
+    let hash = retry(policy, "ipfs.add", || async {
+        let file = tokio::fs::File::open(filepath)
+            .await
+            .with_context(|| format!("Failed to open file: {}", filepath))?;
+        let body = Body::wrap_stream(ReaderStream::new(file));
 
-        debug!("IPFS client created, adding file to IPFS...");
+        debug!("Adding file to IPFS...");
 
-        // Upload the file to IPFS
-        // This operation may take some time for large files as they are chunked and hashed
-        let add_response = futures::executor::block_on(client.add_path(Path::new(&filepath_owned)))
+        // This may take some time for large files as they are chunked and hashed
+        let added = client
+            .add(body, filename, options, Some(timeout), progress)
+            .await
+            .map_err(classify)
             .context("Failed to add file to IPFS")?;
 
         debug!("IPFS add operation completed, processing response...");
 
-        // Extract the hash (CID) from the response
-        // The response is a vector because add_path can add multiple files (e.g., directories)
-        let hash = add_response
-            .first()
-            .ok_or_else(|| anyhow::anyhow!("Empty response from IPFS - file may not have been added"))?
-            .hash
-            .clone();
+        added_cid(added, options)
+    })
+    .await?;
+
+    tracing::Span::current().record("cid", hash.as_str());
+
+    info!(
+        "File uploaded successfully to IPFS: {} ({} ms)",
+        hash,
+        started.elapsed().as_millis()
+    );
+    info!("Access via gateway: https://ipfs.io/ipfs/{}", hash);
+
+    Ok(hash)
+}
+
+/// Upload a stream of chunks to IPFS
+///
+/// Adds the data received on `chunks` to IPFS without writing it to disk.
+/// The channel is bounded by the caller, so a slow IPFS daemon applies
+/// backpressure to the producer instead of buffering the whole file.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `options` - Options of the add call
+/// * `filename` - Name of the file, used when it is wrapped in a directory
+/// * `chunks` - Receiver of file chunks. The stream ends when all senders are
+///   dropped; an `Err` item aborts the upload.
+///
+/// # Returns
+///
+/// Returns the IPFS Content Identifier (CID/hash) as a string on success, or
+/// the CID of the wrapping directory if `options` asks for one.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The producer sends an error (e.g. the request body was malformed or too large)
+/// - The IPFS daemon is not running or rejects the data
+/// - The IPFS client returns an empty response
+///
+/// # Examples
+///
+/// ```no_run
+/// use bytes::Bytes;
+/// use memenow_storage_service::config::IpfsConfig;
+/// use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, upload_stream_to_ipfs};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = IpfsConfig::default();
+/// let client = create_ipfs_client(&config)?;
+/// let (tx, rx) = tokio::sync::mpsc::channel(8);
+/// tx.send(Ok(Bytes::from_static(b"hello"))).await?;
+/// drop(tx);
+///
+/// let cid = upload_stream_to_ipfs(&client, &config.add, "hello.txt", rx).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip_all, fields(cid))]
+pub async fn upload_stream_to_ipfs(
+    client: &IpfsBackend,
+    options: &IpfsAddOptions,
+    filename: &str,
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+) -> Result<String> {
+    debug!("Initiating streaming IPFS upload");
+
+    let started = Instant::now();
+
+    // The body cannot be replayed, so the add is not retried
+    let body = Body::wrap_stream(channel_stream(chunks));
+    let added = client
+        .add(body, filename, options, None, None)
+        .await
+        .map_err(classify)
+        .context("Failed to add stream to IPFS")?;
+    let hash = added_cid(added, options)?;
+
+    tracing::Span::current().record("cid", hash.as_str());
+
+    info!(
+        "Stream uploaded successfully to IPFS: {} ({} ms)",
+        hash,
+        started.elapsed().as_millis()
+    );
+
+    Ok(hash)
+}
+
+/// Read a file from IPFS as a stream of chunks
+///
+/// Sends the contents of `cid` to `chunks` as they arrive. If the read fails
+/// part way, the error is also sent down the channel so the consumer aborts.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `cid` - Content Identifier of the file
+/// * `chunks` - Sender receiving the file's chunks
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon cannot provide the content or the
+/// consumer went away
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::IpfsConfig;
+/// use memenow_storage_service::infrastructure::ipfs::{cat_stream_from_ipfs, create_ipfs_client};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = create_ipfs_client(&IpfsConfig::default())?;
+/// let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+/// tokio::spawn(async move {
+///     while let Some(chunk) = rx.recv().await {
+///         println!("{} bytes", chunk.unwrap().len());
+///     }
+/// });
+/// cat_stream_from_ipfs(&client, "QmHash123", tx).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, chunks))]
+pub async fn cat_stream_from_ipfs(
+    client: &IpfsBackend,
+    cid: &str,
+    chunks: mpsc::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    debug!("Reading IPFS CID: {}", cid);
+
+    let result: Result<bool, IpfsError> = async {
+        let stream = client.node.cat(cid).await?;
+        futures::pin_mut!(stream);
+        while let Some(bytes) = stream.try_next().await? {
+            if chunks.send(Ok(bytes)).await.is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(anyhow::anyhow!("Read consumer went away")),
+        Err(e) => {
+            let _ = chunks.send(Err(io::Error::other(e.to_string()))).await;
+            Err(classify(e)).context(format!("Failed to read CID: {}", cid))
+        }
+    }
+}
+
+/// Import a CAR file into the node
+///
+/// The blocks are streamed to the node as they arrive and the roots named
+/// in the file's header are pinned recursively on the node. The body cannot
+/// be replayed, so the import is not retried.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `chunks` - Receiver of the file's chunks; an `Err` aborts the import
+///
+/// # Returns
+///
+/// Returns the roots of the file, each with the error that kept it from
+/// being pinned, if any
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or rejects the file
+///
+/// # Examples
+///
+/// ```no_run
+/// use bytes::Bytes;
+/// use memenow_storage_service::config::IpfsConfig;
+/// use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, import_car};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = create_ipfs_client(&IpfsConfig::default())?;
+/// let (tx, rx) = tokio::sync::mpsc::channel(8);
+/// tx.send(Ok(Bytes::from(std::fs::read("archive.car")?))).await?;
+/// drop(tx);
+///
+/// for root in import_car(&client, rx).await? {
+///     println!("Imported {}", root.cid);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip_all)]
+pub async fn import_car(
+    client: &IpfsBackend,
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+) -> Result<Vec<ImportedRoot>> {
+    let started = Instant::now();
+
+    let body = Body::wrap_stream(channel_stream(chunks));
+    let roots = client
+        .node
+        .dag_import(body, None)
+        .await
+        .map_err(classify)
+        .context("Failed to import CAR file")?;
+
+    info!(
+        "Imported CAR file with {} roots ({} ms)",
+        roots.len(),
+        started.elapsed().as_millis()
+    );
 
-        debug!("IPFS hash extracted: {}", hash);
+    Ok(roots)
+}
 
-        Ok(hash) as Result<String>
+/// Export the DAG below a CID as a CARv1 file
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `cid` - Root of the DAG
+///
+/// # Returns
+///
+/// Returns the file as a stream of chunks. If the export fails part way,
+/// the stream ends with the error.
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or cannot start
+/// the export
+pub async fn export_car(
+    client: &IpfsBackend,
+    cid: &str,
+) -> Result<impl Stream<Item = io::Result<Bytes>>> {
+    let stream = client
+        .node
+        .dag_export(cid)
+        .await
+        .map_err(classify)
+        .with_context(|| format!("Failed to export CID: {}", cid))?;
+
+    Ok(stream.map_err(io::Error::other))
+}
+
+/// Pin content already on the node
+///
+/// With a cluster, the pin is allocated to the cluster's peers according
+/// to the replication factors, so content imported into the node is
+/// replicated like uploads.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `cid` - CID to pin
+/// * `name` - Name of the pin in the cluster
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon or cluster is not reachable or
+/// cannot pin the content
+pub async fn pin(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    cid: &str,
+    name: &str,
+) -> Result<()> {
+    retry(policy, "ipfs.pin_add", || async {
+        let pinned = match &client.cluster {
+            Some(cluster) => cluster.pin(cid, name, timeout).await,
+            None => client.node.pin_add(cid, timeout).await,
+        };
+        pinned
+            .map_err(classify)
+            .with_context(|| format!("Failed to pin CID: {}", cid))
     })
     .await
-    .context("IPFS upload task panicked or was cancelled")??;
+}
 
-    info!("File uploaded successfully to IPFS: {}", hash);
-    info!("Access via gateway: https://ipfs.io/ipfs/{}", hash);
+/// Remove the pin of a CID
+///
+/// Used to roll back an upload whose other backends failed. Once unpinned,
+/// the content is removed by the daemon's next garbage collection unless
+/// something else pins it. With a cluster, the pin is removed from every
+/// peer.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `cid` - Content Identifier to unpin
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or the CID is not
+/// pinned
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::{IpfsConfig, RetryPolicy};
+/// use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, unpin};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = IpfsConfig::default();
+/// let client = create_ipfs_client(&config)?;
+/// unpin(&client, &RetryPolicy::default(), config.timeout(), "QmHash123").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy, timeout))]
+pub async fn unpin(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    cid: &str,
+) -> Result<()> {
+    debug!("Unpinning IPFS CID: {}", cid);
 
-    Ok(hash)
+    retry(policy, "ipfs.pin_rm", || async {
+        let unpinned = match &client.cluster {
+            Some(cluster) => cluster.unpin(cid, timeout).await,
+            None => client.node.pin_rm(cid, timeout).await,
+        };
+        unpinned
+            .map_err(classify)
+            .context(format!("Failed to unpin CID: {}", cid))
+    })
+    .await?;
+
+    info!("Unpinned IPFS CID: {}", cid);
+
+    Ok(())
+}
+
+/// Copy content into the node's MFS
+///
+/// The copy references the content's blocks rather than duplicating them,
+/// and parent directories are created as needed. Content is always copied
+/// on the node, even when uploads go through a cluster.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `source` - IPFS path to copy, e.g. `/ipfs/{cid}`
+/// * `path` - MFS path of the copy
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable, cannot find the
+/// content or something already exists at `path`
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::{IpfsConfig, RetryPolicy};
+/// use memenow_storage_service::infrastructure::ipfs::{copy_to_mfs, create_ipfs_client};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = IpfsConfig::default();
+/// let client = create_ipfs_client(&config)?;
+/// let policy = RetryPolicy::default();
+/// let source = "/ipfs/QmHash123";
+/// copy_to_mfs(&client, &policy, config.timeout(), source, "/memenow/photo.jpg").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy, timeout))]
+pub async fn copy_to_mfs(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    source: &str,
+    path: &str,
+) -> Result<()> {
+    retry(policy, "ipfs.files_cp", || async {
+        client
+            .node
+            .files_cp(source, path, timeout)
+            .await
+            .map_err(classify)
+            .with_context(|| format!("Failed to copy {} to MFS path {}", source, path))
+    })
+    .await?;
+
+    debug!("Copied {} to MFS path {}", source, path);
+
+    Ok(())
+}
+
+/// Read the CID of an MFS path
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `path` - MFS path of a file or directory
+///
+/// # Returns
+///
+/// Returns the CID of the path, or `None` if nothing exists there
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable
+pub async fn stat_mfs(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    path: &str,
+) -> Result<Option<String>> {
+    retry(policy, "ipfs.files_stat", || async {
+        client
+            .node
+            .files_stat(path, timeout)
+            .await
+            .map_err(classify)
+            .with_context(|| format!("Failed to stat MFS path {}", path))
+    })
+    .await
+}
+
+/// Pin a CID on the node, moving the pin of a previous version to it
+///
+/// Used to keep an MFS snapshot from being garbage collected once the
+/// directory changes. Moving the pin visits only the blocks that differ.
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `previous` - CID of the previous version, pinned by an earlier call
+/// * `cid` - CID to pin
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or cannot pin the
+/// content
+pub async fn pin_update(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    previous: Option<&str>,
+    cid: &str,
+) -> Result<()> {
+    retry(policy, "ipfs.pin_update", || async {
+        let pinned = match previous {
+            Some(previous) => client.node.pin_update(previous, cid, timeout).await,
+            None => client.node.pin_add(cid, timeout).await,
+        };
+        pinned
+            .map_err(classify)
+            .with_context(|| format!("Failed to pin CID: {}", cid))
+    })
+    .await
+}
+
+/// Remove an MFS path and everything below it
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `path` - MFS path to remove
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or nothing exists
+/// at `path`
+pub async fn remove_from_mfs(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    path: &str,
+) -> Result<()> {
+    retry(policy, "ipfs.files_rm", || async {
+        client
+            .node
+            .files_rm(path, timeout)
+            .await
+            .map_err(classify)
+            .with_context(|| format!("Failed to remove MFS path {}", path))
+    })
+    .await
+}
+
+/// Find a key in the node's keystore, generating it if it does not exist
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `name` - Name of the key
+///
+/// # Returns
+///
+/// Returns the key, whose ID is its IPNS name
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or cannot generate
+/// the key
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::{IpfsConfig, RetryPolicy};
+/// use memenow_storage_service::infrastructure::ipfs::{create_ipfs_client, ensure_key};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = IpfsConfig::default();
+/// let client = create_ipfs_client(&config)?;
+/// let policy = RetryPolicy::default();
+/// let key = ensure_key(&client, &policy, config.timeout(), "favorites").await?;
+/// println!("/ipns/{}", key.id);
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy, timeout))]
+pub async fn ensure_key(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    name: &str,
+) -> Result<KeyInfo> {
+    // Listing first keeps a retried generation from failing on its own key
+    retry(policy, "ipfs.key_gen", || async {
+        let keys = client.node.key_list(timeout).await.map_err(classify)?;
+        if let Some(key) = keys.into_iter().find(|key| key.name == name) {
+            return Ok(key);
+        }
+
+        let key = client.node.key_gen(name, timeout).await.map_err(classify)?;
+        debug!("Generated IPNS key {}: {}", key.name, key.id);
+        Ok(key)
+    })
+    .await
+    .with_context(|| format!("Failed to create IPNS key {}", name))
+}
+
+/// Publish a CID under an IPNS key
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `key` - Name of the key to publish with
+/// * `cid` - CID the name should point to
+/// * `config` - Lifetime and TTL of the record
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or cannot publish
+/// the record
+pub async fn publish_name(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    key: &str,
+    cid: &str,
+    config: &IpnsConfig,
+) -> Result<()> {
+    let path = format!("/ipfs/{}", cid);
+
+    retry(policy, "ipfs.name_publish", || async {
+        client
+            .node
+            .name_publish(&path, key, config.lifetime(), config.ttl(), timeout)
+            .await
+            .map_err(classify)
+            .with_context(|| format!("Failed to publish {} under IPNS key {}", path, key))
+    })
+    .await?;
+
+    debug!("Published {} under IPNS key {}", path, key);
+
+    Ok(())
+}
+
+/// Resolve an IPNS name to the path it points to
+///
+/// # Arguments
+///
+/// * `client` - Shared IPFS node and cluster clients
+/// * `policy` - Retry policy for transient failures
+/// * `timeout` - Timeout of each attempt
+/// * `name` - IPNS name or DNSLink domain
+///
+/// # Returns
+///
+/// Returns the resolved path, e.g. `/ipfs/{cid}`
+///
+/// # Errors
+///
+/// Returns an error if the IPFS daemon is not reachable or the name cannot
+/// be resolved
+pub async fn resolve_name(
+    client: &IpfsBackend,
+    policy: &RetryPolicy,
+    timeout: Duration,
+    name: &str,
+) -> Result<String> {
+    retry(policy, "ipfs.name_resolve", || async {
+        client
+            .node
+            .name_resolve(name, timeout)
+            .await
+            .map_err(classify)
+            .with_context(|| format!("Failed to resolve IPNS name {}", name))
+    })
+    .await
+}
+
+/// Re-encode a CID in a multibase
+///
+/// CIDv0 only exists in base58btc, so a CIDv0 is converted to the
+/// equivalent CIDv1, which addresses the same content, for other bases.
+///
+/// # Arguments
+///
+/// * `cid` - CID as returned by the IPFS node
+/// * `base` - Requested encoding
+///
+/// # Errors
+///
+/// Returns an error if `cid` is not a valid CID
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::CidBase;
+/// use memenow_storage_service::infrastructure::ipfs::encode_cid;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let cid = encode_cid("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", CidBase::Base32)?;
+/// assert_eq!(cid, "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354");
+/// # Ok(())
+/// # }
+/// ```
+pub fn encode_cid(cid: &str, base: CidBase) -> Result<String> {
+    let parsed = Cid::try_from(cid).with_context(|| format!("Invalid CID: {}", cid))?;
+
+    let base = match base {
+        // CIDv0 is always base58btc, CIDv1 needs the multibase prefix
+        CidBase::Base58btc if parsed.version() == Version::V0 => return Ok(parsed.to_string()),
+        CidBase::Base16 => Base::Base16Lower,
+        CidBase::Base32 => Base::Base32Lower,
+        CidBase::Base36 => Base::Base36Lower,
+        CidBase::Base58btc => Base::Base58Btc,
+        CidBase::Base64 => Base::Base64,
+        CidBase::Base64url => Base::Base64Url,
+    };
+
+    let v1 = parsed
+        .into_v1()
+        .with_context(|| format!("Cannot convert CID to version 1: {}", cid))?;
+    v1.to_string_of_base(base)
+        .with_context(|| format!("Failed to encode CID in {:?}", base))
+}
+
+/// Normalize a CID to CIDv1 in base32
+///
+/// Every encoding of the same content, including its CIDv0, normalizes to
+/// the same string, so normalized CIDs can be compared and used as keys.
+///
+/// # Errors
+///
+/// Returns an error if `cid` is not a valid CID
+pub fn normalize_cid(cid: &str) -> Result<String> {
+    encode_cid(cid, CidBase::Base32)
+}
+
+/// Pick the CID of an add from the node's response and encode it
+///
+/// The node reports every object it added; the wrapping directory, if any,
+/// comes last.
+fn added_cid(added: Vec<AddedObject>, options: &IpfsAddOptions) -> Result<String> {
+    let added = added.last().ok_or_else(|| {
+        anyhow::anyhow!("Empty response from IPFS - file may not have been added")
+    })?;
+
+    match options.cid_base {
+        Some(base) => encode_cid(&added.hash, base),
+        None => Ok(added.hash.clone()),
+    }
+}
+
+/// Name of the file inside a wrapping directory
+///
+/// Directory entries cannot contain path separators.
+pub fn entry_name(filename: &str) -> String {
+    filename.replace(['/', '\\'], "_")
+}
+
+/// Build the gateway URLs of a CID from URL templates
+///
+/// `{cid}` in a template is replaced by the CID. A template placing it in
+/// the host, as subdomain gateways do, gets the CID as a base32 CIDv1,
+/// since hostnames are case-insensitive.
+///
+/// # Arguments
+///
+/// * `templates` - Gateway URL templates (`IPFS_GATEWAY_URLS`)
+/// * `cid` - CID of the upload
+/// * `entry` - Name of the file in the directory the CID wraps, if any
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::infrastructure::ipfs::gateway_urls;
+///
+/// let templates = vec!["https://ipfs.io/ipfs/{cid}".to_string()];
+/// let urls = gateway_urls(&templates, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", None);
+/// assert_eq!(urls, vec!["https://ipfs.io/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"]);
+/// ```
+pub fn gateway_urls(templates: &[String], cid: &str, entry: Option<&str>) -> Vec<String> {
+    let path = entry
+        .map(|entry| format!("/{}", urls::encode_path(&entry_name(entry))))
+        .unwrap_or_default();
+
+    templates
+        .iter()
+        .filter_map(|template| {
+            let host = template.split("://").nth(1).unwrap_or(template);
+            let in_host = host
+                .split('/')
+                .next()
+                .is_some_and(|host| host.contains("{cid}"));
+            let cid = if in_host {
+                encode_cid(cid, CidBase::Base32)
+                    .map_err(|e| warn!("Cannot build gateway URL of {}: {:#}", cid, e))
+                    .ok()?
+            } else {
+                cid.to_string()
+            };
+
+            let url = template.replace("{cid}", &cid);
+            Some(format!("{}{}", url.trim_end_matches('/'), path))
+        })
+        .collect()
+}
+
+/// Convert an IPFS client error, marking it transient if it is worth retrying
+///
+/// Connection failures and timeouts talking to the daemon are transient.
+/// Errors reported by the daemon itself are not: it answers every failed
+/// API call with a 500, whatever the cause.
+fn classify(error: IpfsError) -> anyhow::Error {
+    let timed_out = matches!(&error, IpfsError::Request(e) if e.is_timeout());
+    let error = if error.is_transient() {
+        retry::transient(error)
+    } else {
+        anyhow::Error::new(error)
+    };
+
+    if timed_out {
+        error.context("IPFS request timed out")
+    } else {
+        error
+    }
+}
+
+/// Expose a channel of chunks as a stream
+///
+/// Used as the request body of a streaming IPFS add. An `Err` received from
+/// the channel ends the stream with that error, which aborts the request.
+/// The stream ends when all senders are dropped.
+fn channel_stream(
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    futures::stream::unfold(chunks, |mut chunks| async move {
+        chunks.recv().await.map(|chunk| (chunk, chunks))
+    })
 }
 
-/// Create a custom IPFS client with a specific endpoint URL
+/// Create the IPFS clients from the configuration
 ///
-/// This function creates an IPFS client configured to connect to a custom
-/// IPFS daemon endpoint instead of the default localhost.
+/// The node client talks to the API at `api_url`, over HTTPS if the URL
+/// asks for it, and sends the basic credentials or bearer token with every
+/// request when configured. A cluster client is created likewise when
+/// `cluster.api_url` is set.
 ///
 /// # Arguments
 ///
-/// * `url` - The full URL of the IPFS daemon API endpoint (e.g., "http://127.0.0.1:5001")
+/// * `config` - IPFS API connection settings
 ///
 /// # Returns
 ///
-/// Returns a configured `IpfsClient` instance
+/// Returns a configured `IpfsBackend` instance
 ///
-/// # Panics
+/// # Errors
 ///
-/// Panics if the URL is invalid
+/// Returns an error if an API URL is not a valid URL or the credentials
+/// cannot be sent in a header
 ///
 /// # Examples
 ///
 /// ```no_run
+/// use memenow_storage_service::config::IpfsConfig;
 /// use memenow_storage_service::infrastructure::ipfs::create_ipfs_client;
 ///
-/// let client = create_ipfs_client("http://192.168.1.100:5001");
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = IpfsConfig {
+///     api_url: String::from("https://ipfs.example.com:5001"),
+///     bearer_token: Some(String::from("secret")),
+///     ..IpfsConfig::default()
+/// };
+/// let client = create_ipfs_client(&config)?;
+/// # Ok(())
+/// # }
 /// ```
-pub fn create_ipfs_client(url: &str) -> IpfsClient {
-    IpfsClient::from_str(url)
-        .expect("Failed to create IPFS client with custom endpoint")
+pub fn create_ipfs_client(config: &IpfsConfig) -> Result<IpfsBackend> {
+    let cluster = match config.cluster.api_url {
+        Some(_) => Some(ClusterClient::new(&config.cluster)?),
+        None => None,
+    };
+
+    Ok(IpfsBackend {
+        node: IpfsClient::new(config)?,
+        cluster,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_create_ipfs_client_localhost() {
-        let client = create_ipfs_client("http://127.0.0.1:5001");
+        let client = create_ipfs_client(&IpfsConfig::default());
         // Client creation should succeed
         // We can't test actual operations without a running IPFS daemon
-        drop(client);
+        assert!(client.is_ok());
     }
 
     #[test]
     fn test_create_ipfs_client_custom() {
-        let client = create_ipfs_client("http://192.168.1.100:5001");
-        drop(client);
+        let config = IpfsConfig {
+            api_url: String::from("https://192.168.1.100:5001"),
+            username: Some(String::from("storage")),
+            password: Some(String::from("secret")),
+            ..IpfsConfig::default()
+        };
+        assert!(create_ipfs_client(&config).is_ok());
+    }
+
+    #[test]
+    fn test_create_ipfs_client_invalid_url() {
+        let config = IpfsConfig {
+            api_url: String::from("http://ipfs node:5001"),
+            ..IpfsConfig::default()
+        };
+        assert!(create_ipfs_client(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_node_is_transient() {
+        // Nothing listens on the discard port
+        let config = IpfsConfig {
+            api_url: String::from("http://127.0.0.1:9"),
+            ..IpfsConfig::default()
+        };
+        let client = create_ipfs_client(&config).unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        };
+
+        let error = unpin(&client, &policy, config.timeout(), "QmHash123")
+            .await
+            .unwrap_err();
+        assert!(retry::is_transient(&error));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_is_transient() {
+        // Accept connections but never answer
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        let config = IpfsConfig {
+            api_url: format!("http://{}", addr),
+            ..IpfsConfig::default()
+        };
+        let client = create_ipfs_client(&config).unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        };
+
+        let error = unpin(&client, &policy, Duration::from_millis(100), "QmHash123")
+            .await
+            .unwrap_err();
+        assert!(retry::is_transient(&error));
+        assert!(format!("{:#}", error).contains("timed out"));
+    }
+
+    #[test]
+    fn test_encode_cid() {
+        // The empty directory
+        let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        let v1 = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+
+        assert_eq!(encode_cid(v0, CidBase::Base58btc).unwrap(), v0);
+        assert_eq!(encode_cid(v0, CidBase::Base32).unwrap(), v1);
+        assert_eq!(encode_cid(v1, CidBase::Base32).unwrap(), v1);
+
+        let base36 = encode_cid(v1, CidBase::Base36).unwrap();
+        assert!(base36.starts_with('k'));
+        assert_eq!(encode_cid(&base36, CidBase::Base32).unwrap(), v1);
+
+        assert!(encode_cid("not-a-cid", CidBase::Base32).is_err());
+    }
+
+    #[test]
+    fn test_added_cid_prefers_wrapping_directory() {
+        let response: Vec<AddedObject> = serde_json::from_value(serde_json::json!([
+            { "Name": "photo.jpg", "Hash": "QmPhoto", "Size": "10" },
+            { "Name": "", "Hash": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", "Size": "60" }
+        ]))
+        .unwrap();
+        let options = IpfsAddOptions {
+            wrap_with_directory: Some(true),
+            ..IpfsAddOptions::default()
+        };
+
+        assert_eq!(
+            added_cid(response, &options).unwrap(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+        assert!(added_cid(Vec::new(), &options).is_err());
+    }
+
+    #[test]
+    fn test_gateway_urls() {
+        let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        let v1 = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+        let templates = vec![
+            "https://ipfs.io/ipfs/{cid}".to_string(),
+            "https://{cid}.ipfs.dweb.link/".to_string(),
+        ];
+
+        assert_eq!(
+            gateway_urls(&templates, v0, None),
+            vec![
+                format!("https://ipfs.io/ipfs/{}", v0),
+                format!("https://{}.ipfs.dweb.link", v1),
+            ]
+        );
+        assert_eq!(
+            gateway_urls(&templates[..1], v0, Some("my photo.jpg")),
+            vec![format!("https://ipfs.io/ipfs/{}/my%20photo.jpg", v0)]
+        );
+        assert!(gateway_urls(&templates[1..], "not-a-cid", None).is_empty());
+    }
+
+    #[test]
+    fn test_entry_name() {
+        assert_eq!(entry_name("photo.jpg"), "photo.jpg");
+        assert_eq!(entry_name("../etc/passwd"), ".._etc_passwd");
+        assert_eq!(entry_name("a\\b.txt"), "a_b.txt");
+    }
+
+    #[tokio::test]
+    async fn test_channel_stream_reassembles_chunks() {
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(Ok(Bytes::from_static(b"hello "))).await.unwrap();
+        tx.send(Ok(Bytes::new())).await.unwrap();
+        tx.send(Ok(Bytes::from_static(b"world"))).await.unwrap();
+        drop(tx);
+
+        let chunks: Vec<Bytes> = channel_stream(rx).try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_channel_stream_propagates_errors() {
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(Ok(Bytes::from_static(b"partial"))).await.unwrap();
+        tx.send(Err(io::Error::other("aborted"))).await.unwrap();
+        drop(tx);
+
+        let result: io::Result<Vec<Bytes>> = channel_stream(rx).try_collect().await;
+        assert!(result.is_err());
+    }
+
+    /// Serve a fake RPC API that requires `Bearer token` and records the
+    /// query of each add
+    async fn mock_node(queries: Arc<Mutex<Vec<String>>>) -> IpfsConfig {
+        use warp::Filter;
+
+        let auth = warp::header::exact("authorization", "Bearer token");
+        let add = warp::path!("api" / "v0" / "add")
+            .and(warp::query::raw())
+            .and(warp::body::bytes())
+            .map(move |query: String, body: Bytes| {
+                assert!(body.windows(5).any(|window| window == b"hello"));
+                queries.lock().unwrap().push(query);
+                concat!(
+                    "{\"Name\":\"hello.txt\",\"Bytes\":2}\n",
+                    "{\"Name\":\"hello.txt\",\"Bytes\":5}\n",
+                    "{\"Name\":\"hello.txt\",\"Hash\":",
+                    "\"QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn\",\"Size\":\"5\"}\n",
+                )
+            });
+        let cat = warp::path!("api" / "v0" / "cat").map(|| "hello");
+        let pin_rm = warp::path!("api" / "v0" / "pin" / "rm").map(|| {
+            warp::reply::with_status(
+                r#"{"Message":"not pinned or pinned indirectly","Code":0,"Type":"error"}"#,
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        });
+        let routes = warp::post().and(auth).and(add.or(cat).or(pin_rm));
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        IpfsConfig {
+            api_url: format!("http://{}", addr),
+            bearer_token: Some(String::from("token")),
+            ..IpfsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_to_ipfs_streams_file_and_reports_progress() {
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let config = mock_node(queries.clone()).await;
+        let client = create_ipfs_client(&config).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("ipfs-test-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("hello.txt");
+        tokio::fs::write(&path, b"hello").await.unwrap();
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let progress = {
+            let reported = reported.clone();
+            move |bytes| reported.lock().unwrap().push(bytes)
+        };
+        let options = IpfsAddOptions {
+            cid_version: Some(1),
+            cid_base: Some(CidBase::Base32),
+            ..IpfsAddOptions::default()
+        };
+
+        let cid = upload_to_ipfs(
+            &client,
+            &RetryPolicy::default(),
+            config.timeout(),
+            &options,
+            path.to_str().unwrap(),
+            "hello.txt",
+            Some(&progress),
+        )
+        .await
+        .unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(
+            cid,
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"
+        );
+        assert_eq!(*reported.lock().unwrap(), vec![2, 5]);
+        let query = queries.lock().unwrap()[0].clone();
+        assert!(query.contains("cid-version=1"));
+        assert!(query.contains("progress=true"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_and_cat_through_mock_node() {
+        let config = mock_node(Arc::default()).await;
+        let client = create_ipfs_client(&config).unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(Ok(Bytes::from_static(b"hel"))).await.unwrap();
+        tx.send(Ok(Bytes::from_static(b"lo"))).await.unwrap();
+        drop(tx);
+        let cid = upload_stream_to_ipfs(&client, &config.add, "hello.txt", rx)
+            .await
+            .unwrap();
+        assert_eq!(cid, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn");
+
+        let (tx, mut rx) = mpsc::channel(4);
+        cat_stream_from_ipfs(&client, &cid, tx).await.unwrap();
+        let mut data = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_cluster_adds_and_unpins() {
+        use crate::infrastructure::ipfs_cluster::mock;
+
+        let (addr, queries) = mock::spawn();
+        let mut config = mock_node(Arc::default()).await;
+        config.cluster.api_url = Some(format!("http://{}", addr));
+        config.cluster.bearer_token = Some(String::from("token"));
+        config.cluster.replication_min = Some(2);
+        let client = create_ipfs_client(&config).unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        tx.send(Ok(Bytes::from_static(b"hello"))).await.unwrap();
+        drop(tx);
+        let cid = upload_stream_to_ipfs(&client, &config.add, "a/hello.txt", rx)
+            .await
+            .unwrap();
+        assert_eq!(cid, mock::CID);
+
+        // The pin keeps the original filename
+        let query = queries.lock().unwrap()[0].clone();
+        assert!(query.contains("name=a%2Fhello.txt"));
+        assert!(query.contains("replication-min=2"));
+
+        // Rollbacks unpin from the cluster, not the node
+        let policy = RetryPolicy::default();
+        unpin(&client, &policy, config.timeout(), &cid)
+            .await
+            .unwrap();
+        assert!(unpin(&client, &policy, config.timeout(), &cid)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_node_errors_are_permanent() {
+        let config = mock_node(Arc::default()).await;
+        let client = create_ipfs_client(&config).unwrap();
+
+        let error = unpin(
+            &client,
+            &RetryPolicy::default(),
+            config.timeout(),
+            "QmHash123",
+        )
+        .await
+        .unwrap_err();
+        assert!(!retry::is_transient(&error));
+        assert!(format!("{:#}", error).contains("not pinned"));
+
+        // Without the token the node rejects every call
+        let anonymous = create_ipfs_client(&IpfsConfig {
+            bearer_token: None,
+            ..config
+        })
+        .unwrap();
+        let (tx, _rx) = mpsc::channel(4);
+        assert!(cat_stream_from_ipfs(&anonymous, "QmHash123", tx)
+            .await
+            .is_err());
     }
 }
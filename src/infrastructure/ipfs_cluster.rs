@@ -0,0 +1,635 @@
+//! IPFS Cluster REST API client
+//!
+//! An IPFS Cluster coordinates the pins of several IPFS nodes. Content added
+//! through its REST API (`POST /add`) is imported by a peer and pinned by as
+//! many peers as the replication factors ask for, so an upload survives the
+//! loss of a node. The pin is named after the uploaded file.
+//!
+//! The status of a pin on each peer is read with `GET /pins/{cid}`, and a
+//! pin is removed from every peer with `DELETE /pins/{cid}`. Errors are
+//! reported as [`IpfsError`], with the same notion of transient failures as
+//! the node's RPC API.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use memenow_storage_service::config::IpfsClusterConfig;
+//! use memenow_storage_service::infrastructure::ipfs_cluster::{ClusterClient, ClusterPinState};
+//! use std::time::Duration;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = IpfsClusterConfig {
+//!     api_url: Some(String::from("http://127.0.0.1:9094")),
+//!     replication_min: Some(2),
+//!     replication_max: Some(3),
+//!     ..IpfsClusterConfig::default()
+//! };
+//! let cluster = ClusterClient::new(&config)?;
+//! let status = cluster
+//!     .status("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", Duration::from_secs(30))
+//!     .await?;
+//! println!("Pinned on {} peers", status.count(ClusterPinState::Pinned));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::config::{IpfsAddOptions, IpfsClusterConfig};
+use crate::infrastructure::ipfs_rpc::{
+    add_query, authorization, ndjson, AddProgress, AddedObject, IpfsError,
+};
+use anyhow::Context;
+use futures::StreamExt;
+use log::debug;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{multipart, Body, Client, Method, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Status of a pin on a cluster peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterPinState {
+    /// The peer is not allocated the pin
+    Remote,
+    /// The pin is waiting to be processed by the peer
+    PinQueued,
+    /// The peer is fetching the content
+    Pinning,
+    /// The content is pinned on the peer
+    Pinned,
+    /// The peer failed to pin the content
+    PinError,
+    /// The peer's IPFS node cannot be reached
+    ClusterError,
+    /// Any other status, e.g. while the pin is being removed
+    #[serde(other)]
+    Other,
+}
+
+/// Status of a pin on one peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerPinStatus {
+    /// Name of the peer
+    #[serde(default)]
+    pub peername: String,
+    /// Status of the pin on the peer
+    pub status: ClusterPinState,
+    /// Error reported by the peer, if any
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+/// Status of a pin across the cluster
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterPinStatus {
+    /// The pinned CID
+    #[serde(deserialize_with = "cid_string")]
+    pub cid: String,
+    /// Name of the pin
+    #[serde(default)]
+    pub name: String,
+    /// Status of the pin by peer ID
+    #[serde(default)]
+    pub peer_map: BTreeMap<String, PeerPinStatus>,
+}
+
+impl ClusterPinStatus {
+    /// Number of peers on which the pin has a status
+    pub fn count(&self, state: ClusterPinState) -> usize {
+        self.peer_map
+            .values()
+            .filter(|peer| peer.status == state)
+            .count()
+    }
+}
+
+/// A CID as serialized by the cluster: a string, or an IPLD link in older
+/// releases
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ClusterCid {
+    String(String),
+    Link {
+        #[serde(rename = "/")]
+        cid: String,
+    },
+}
+
+impl From<ClusterCid> for String {
+    fn from(cid: ClusterCid) -> Self {
+        match cid {
+            ClusterCid::String(cid) | ClusterCid::Link { cid } => cid,
+        }
+    }
+}
+
+fn cid_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    ClusterCid::deserialize(deserializer).map(String::from)
+}
+
+/// A line of the add response: progress, an added object or an error
+#[derive(Debug, Deserialize)]
+struct AddEvent {
+    #[serde(default)]
+    name: String,
+    cid: Option<ClusterCid>,
+    #[serde(default)]
+    size: u64,
+    bytes: Option<u64>,
+    #[serde(default)]
+    allocations: Vec<String>,
+    message: Option<String>,
+}
+
+/// Error body of a failed call
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+/// Client of an IPFS Cluster's REST API
+///
+/// Cloning is cheap; clones share the connection pool.
+#[derive(Debug, Clone)]
+pub struct ClusterClient {
+    http: Client,
+    /// Base URL of the REST API, e.g. `http://127.0.0.1:9094`
+    base: String,
+    /// Value of the `Authorization` header
+    authorization: Option<HeaderValue>,
+    /// Minimum number of peers pinning an add
+    replication_min: Option<i32>,
+    /// Maximum number of peers pinning an add
+    replication_max: Option<i32>,
+}
+
+impl ClusterClient {
+    /// Create a client from the configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Cluster connection settings, with `api_url` set
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `api_url` is missing or not a valid URL, the
+    /// credentials cannot be sent in a header, or the TLS backend cannot be
+    /// initialized
+    pub fn new(config: &IpfsClusterConfig) -> anyhow::Result<Self> {
+        let api_url = config
+            .api_url
+            .as_deref()
+            .context("No IPFS Cluster API URL configured")?;
+        let url = reqwest::Url::parse(api_url)
+            .with_context(|| format!("Invalid IPFS Cluster API URL: {}", api_url))?;
+        if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+            anyhow::bail!("Invalid IPFS Cluster API URL: {}", api_url);
+        }
+
+        let authorization = authorization(
+            &url,
+            config.username.as_deref(),
+            config.password.as_deref(),
+            config.bearer_token.as_deref(),
+        )?;
+
+        let http = Client::builder()
+            .build()
+            .context("Failed to create IPFS Cluster HTTP client")?;
+
+        Ok(Self {
+            http,
+            base: api_url.trim_end_matches('/').to_string(),
+            authorization,
+            replication_min: config.replication_min,
+            replication_max: config.replication_max,
+        })
+    }
+
+    /// Add a file and pin it on the cluster
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - Contents of the file, streamed to the cluster
+    /// * `filename` - Name of the file in the request
+    /// * `pin_name` - Name of the pin
+    /// * `options` - Options of the add call
+    /// * `timeout` - Timeout of the whole call, if any
+    /// * `progress` - Callback receiving the bytes processed so far, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns every object added, the wrapping directory last
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or the cluster adds nothing
+    pub async fn add(
+        &self,
+        body: Body,
+        filename: &str,
+        pin_name: &str,
+        options: &IpfsAddOptions,
+        timeout: Option<Duration>,
+        progress: Option<&AddProgress>,
+    ) -> Result<Vec<AddedObject>, IpfsError> {
+        // The cluster pins whatever it adds
+        let mut query: Vec<(&str, String)> = add_query(options, progress.is_some())
+            .into_iter()
+            .filter(|(key, _)| *key != "pin")
+            .collect();
+        query.extend(self.pin_query(pin_name));
+
+        let part = multipart::Part::stream(body)
+            .file_name(filename.to_string())
+            .mime_str("application/octet-stream")?;
+        let form = multipart::Form::new().part("file", part);
+
+        let mut request = self
+            .request(Method::POST, "add")
+            .query(&query)
+            .multipart(form);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send(request).await?;
+
+        let mut added = Vec::new();
+        let mut lines = ndjson(response);
+        while let Some(line) = lines.next().await {
+            let event: AddEvent = serde_json::from_slice(&line?)
+                .map_err(|e| IpfsError::InvalidResponse(e.to_string()))?;
+
+            let cid = event.cid.map(String::from).filter(|cid| !cid.is_empty());
+            match (cid, event.bytes, event.message) {
+                (Some(cid), _, _) => {
+                    debug!("Cluster allocated {} to {:?}", cid, event.allocations);
+                    added.push(AddedObject {
+                        name: event.name,
+                        hash: cid,
+                        size: event.size.to_string(),
+                    });
+                }
+                (None, Some(bytes), _) => {
+                    if let Some(progress) = progress {
+                        progress(bytes);
+                    }
+                }
+                // Errors after the response has started arrive as a line
+                (None, None, Some(message)) => {
+                    return Err(IpfsError::Api {
+                        status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                        message,
+                    })
+                }
+                (None, None, None) => {}
+            }
+        }
+
+        if added.is_empty() {
+            return Err(IpfsError::InvalidResponse(
+                "Empty response - file may not have been added".to_string(),
+            ));
+        }
+
+        debug!("Added {} objects to IPFS Cluster", added.len());
+
+        Ok(added)
+    }
+
+    /// Read the status of a pin on every peer
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - The pinned CID
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn status(
+        &self,
+        cid: &str,
+        timeout: Duration,
+    ) -> Result<ClusterPinStatus, IpfsError> {
+        let request = self
+            .request(Method::GET, &format!("pins/{}", cid))
+            .timeout(timeout);
+        let response = send(request).await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| IpfsError::InvalidResponse(e.to_string()))
+    }
+
+    /// Pin content already held by the cluster's peers
+    ///
+    /// The pin is allocated to peers according to the configured
+    /// replication factors.
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - CID to pin
+    /// * `name` - Name of the pin
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn pin(&self, cid: &str, name: &str, timeout: Duration) -> Result<(), IpfsError> {
+        let request = self
+            .request(Method::POST, &format!("pins/{}", cid))
+            .query(&self.pin_query(name))
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Remove a pin from every peer
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - CID to unpin
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the CID is not pinned
+    pub async fn unpin(&self, cid: &str, timeout: Duration) -> Result<(), IpfsError> {
+        let request = self
+            .request(Method::DELETE, &format!("pins/{}", cid))
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Query parameters naming a pin and setting its replication factors
+    fn pin_query(&self, name: &str) -> Vec<(&'static str, String)> {
+        let mut query = vec![("name", name.to_string())];
+        if let Some(min) = self.replication_min {
+            query.push(("replication-min", min.to_string()));
+        }
+        if let Some(max) = self.replication_max {
+            query.push(("replication-max", max.to_string()));
+        }
+
+        query
+    }
+
+    /// Start a call to a REST endpoint
+    fn request(&self, method: Method, endpoint: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}/{}", self.base, endpoint));
+
+        match &self.authorization {
+            Some(authorization) => request.header(AUTHORIZATION, authorization.clone()),
+            None => request,
+        }
+    }
+}
+
+/// Send a request, turning error responses into [`IpfsError::Api`]
+async fn send(request: RequestBuilder) -> Result<Response, IpfsError> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ApiError>(&body)
+        .map(|error| error.message)
+        .unwrap_or(body);
+
+    Err(IpfsError::Api { status, message })
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    //! A fake cluster REST API for tests
+
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    /// CID of every file added to the fake cluster
+    pub const CID: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+
+    /// Serve a fake cluster requiring `Bearer token`
+    ///
+    /// # Returns
+    ///
+    /// Returns the address of the cluster and the query of each add and pin;
+    /// a pin exists after the first add or pin of [`CID`] and is pinned on
+    /// one of two peers.
+    pub fn spawn() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let queries = Arc::new(Mutex::new(Vec::<String>::new()));
+        let pinned = Arc::new(Mutex::new(false));
+
+        let auth = warp::header::exact("authorization", "Bearer token");
+        let add = {
+            let (queries, pinned) = (queries.clone(), pinned.clone());
+            warp::path!("add")
+                .and(warp::post())
+                .and(warp::query::raw())
+                .and(warp::body::bytes())
+                .map(move |query: String, body: Bytes| {
+                    assert!(body.windows(5).any(|window| window == b"hello"));
+                    queries.lock().unwrap().push(query);
+                    *pinned.lock().unwrap() = true;
+                    format!(
+                        "{{\"name\":\"hello.txt\",\"bytes\":5}}\n\
+                         {{\"name\":\"hello.txt\",\"cid\":\"{}\",\"size\":5,\
+                         \"allocations\":[\"peer-a\",\"peer-b\"]}}\n",
+                        CID
+                    )
+                })
+        };
+        let status = {
+            let pinned = pinned.clone();
+            warp::path!("pins" / String)
+                .and(warp::get())
+                .map(move |cid: String| {
+                    if !*pinned.lock().unwrap() || cid != CID {
+                        return warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "code": 404,
+                                "message": "pin not found"
+                            })),
+                            warp::http::StatusCode::NOT_FOUND,
+                        );
+                    }
+                    warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({
+                            "cid": { "/": CID },
+                            "name": "hello.txt",
+                            "peer_map": {
+                                "peer-a": { "peername": "a", "status": "pinned", "error": "" },
+                                "peer-b": { "peername": "b", "status": "pinning" }
+                            }
+                        })),
+                        warp::http::StatusCode::OK,
+                    )
+                })
+        };
+        let pin = {
+            let (queries, pinned) = (queries.clone(), pinned.clone());
+            warp::path!("pins" / String)
+                .and(warp::post())
+                .and(warp::query::raw())
+                .map(move |cid: String, query: String| {
+                    queries.lock().unwrap().push(query);
+                    *pinned.lock().unwrap() = cid == CID;
+                    warp::reply::json(&serde_json::json!({ "cid": { "/": cid } }))
+                })
+        };
+        let unpin = warp::path!("pins" / String)
+            .and(warp::delete())
+            .map(move |_cid: String| {
+                let was_pinned = std::mem::replace(&mut *pinned.lock().unwrap(), false);
+                let status = if was_pinned {
+                    warp::http::StatusCode::OK
+                } else {
+                    warp::http::StatusCode::NOT_FOUND
+                };
+                warp::reply::with_status(r#"{"code":404,"message":"pin not found"}"#, status)
+            });
+        let routes = auth.and(add.or(status).or(pin).or(unpin));
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::sync::{Arc, Mutex};
+
+    fn config(addr: std::net::SocketAddr) -> IpfsClusterConfig {
+        IpfsClusterConfig {
+            api_url: Some(format!("http://{}", addr)),
+            bearer_token: Some(String::from("token")),
+            replication_min: Some(2),
+            replication_max: Some(3),
+            ..IpfsClusterConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_status_and_unpin() {
+        let (addr, queries) = mock::spawn();
+        let cluster = ClusterClient::new(&config(addr)).unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let error = cluster.status(mock::CID, timeout).await.unwrap_err();
+        assert!(matches!(
+            error,
+            IpfsError::Api {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }
+        ));
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let progress = {
+            let reported = reported.clone();
+            move |bytes| reported.lock().unwrap().push(bytes)
+        };
+        let options = IpfsAddOptions {
+            pin: Some(true),
+            ..IpfsAddOptions::default()
+        };
+        let added = cluster
+            .add(
+                Body::from("hello"),
+                "hello.txt",
+                "My Photo.jpg",
+                &options,
+                Some(timeout),
+                Some(&progress),
+            )
+            .await
+            .unwrap();
+        assert_eq!(added.last().unwrap().hash, mock::CID);
+        assert_eq!(*reported.lock().unwrap(), vec![5]);
+
+        let query = queries.lock().unwrap()[0].clone();
+        assert!(query.contains("name=My+Photo.jpg") || query.contains("name=My%20Photo.jpg"));
+        assert!(query.contains("replication-min=2"));
+        assert!(query.contains("replication-max=3"));
+        assert!(!query.contains("pin="));
+
+        let status = cluster.status(mock::CID, timeout).await.unwrap();
+        assert_eq!(status.cid, mock::CID);
+        assert_eq!(status.count(ClusterPinState::Pinned), 1);
+        assert_eq!(status.count(ClusterPinState::Pinning), 1);
+
+        cluster.unpin(mock::CID, timeout).await.unwrap();
+        let error = cluster.unpin(mock::CID, timeout).await.unwrap_err();
+        assert!(format!("{}", error).contains("pin not found"));
+        // Content already on the peers can be pinned again
+        cluster
+            .pin(mock::CID, "archive.car", timeout)
+            .await
+            .unwrap();
+        assert!(queries.lock().unwrap()[1].contains("name=archive.car"));
+        assert!(cluster.status(mock::CID, timeout).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_requires_credentials() {
+        let (addr, _queries) = mock::spawn();
+        let cluster = ClusterClient::new(&IpfsClusterConfig {
+            bearer_token: None,
+            ..config(addr)
+        })
+        .unwrap();
+
+        let error = cluster
+            .status(mock::CID, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(!error.is_transient());
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_urls() {
+        assert!(ClusterClient::new(&IpfsClusterConfig::default()).is_err());
+        for api_url in ["http://cluster node:9094", "ftp://127.0.0.1:9094"] {
+            let config = IpfsClusterConfig {
+                api_url: Some(api_url.to_string()),
+                ..IpfsClusterConfig::default()
+            };
+            assert!(ClusterClient::new(&config).is_err(), "{}", api_url);
+        }
+    }
+
+    #[test]
+    fn test_status_deserializes_string_cids() {
+        let status: ClusterPinStatus = serde_json::from_value(serde_json::json!({
+            "cid": mock::CID,
+            "peer_map": {
+                "peer-a": { "status": "pin_error", "error": "context deadline exceeded" },
+                "peer-b": { "status": "unpin_queued" }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(status.cid, mock::CID);
+        assert_eq!(status.count(ClusterPinState::PinError), 1);
+        assert_eq!(status.count(ClusterPinState::Other), 1);
+    }
+}
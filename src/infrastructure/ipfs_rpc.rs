@@ -0,0 +1,925 @@
+//! IPFS RPC API client
+//!
+//! A minimal client for the RPC API of an IPFS node (`/api/v0`, as served by
+//! Kubo), covering the calls this service makes. It runs on the Tokio
+//! runtime like the other backends:
+//!
+//! - Request bodies are streams, so a file is never read into memory
+//! - Dropping a call's future aborts the request, e.g. when the client of an
+//!   upload disconnects
+//! - Adds can report how many bytes the node has processed
+//!
+//! Basic and bearer credentials from [`IpfsConfig`] are sent with every
+//! request.
+
+use crate::config::{IpfsAddOptions, IpfsConfig};
+use anyhow::Context;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
+use log::debug;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{multipart, Body, Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Callback receiving the number of bytes of a file an add has processed
+pub type AddProgress = dyn Fn(u64) + Send + Sync;
+
+/// Error returned by an IPFS RPC call
+#[derive(Debug, Error)]
+pub enum IpfsError {
+    /// The request could not be sent or the response not received
+    #[error("IPFS request failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    /// The node, or a proxy in front of it, rejected the call
+    #[error("IPFS API responded with {status}: {message}")]
+    Api {
+        /// The response status
+        status: StatusCode,
+        /// The error message of the node
+        message: String,
+    },
+
+    /// The node answered with something that is not a valid response
+    #[error("Invalid IPFS API response: {0}")]
+    InvalidResponse(String),
+}
+
+impl IpfsError {
+    /// Whether the call may succeed if it is made again
+    ///
+    /// Connection failures and timeouts are transient, as are the `408`,
+    /// `429`, `502`, `503` and `504` responses of proxies. The node itself
+    /// answers every failed call with a `500`, whatever the cause, so those
+    /// are not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(e) => !e.is_builder() && !e.is_decode(),
+            Self::Api { status, .. } => matches!(
+                *status,
+                StatusCode::REQUEST_TIMEOUT
+                    | StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Self::InvalidResponse(_) => false,
+        }
+    }
+}
+
+/// An object added to IPFS
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddedObject {
+    /// Name of the object; empty for a wrapping directory
+    pub name: String,
+    /// CID of the object
+    pub hash: String,
+    /// Cumulative size of the object in bytes
+    #[serde(default)]
+    pub size: String,
+}
+
+/// A line of the add response: progress, an added object, or an error the
+/// node ran into after the response started
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AddEvent {
+    #[serde(default)]
+    name: String,
+    hash: Option<String>,
+    #[serde(default)]
+    size: String,
+    bytes: Option<u64>,
+    message: Option<String>,
+    #[serde(rename = "Type")]
+    kind: Option<String>,
+}
+
+/// A root of an imported CAR file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImportedRoot {
+    /// CID of the root
+    #[serde(deserialize_with = "cid_link")]
+    pub cid: String,
+    /// Why the root could not be pinned; empty if it was
+    #[serde(default)]
+    pub pin_error_msg: String,
+}
+
+/// A line of the `dag/import` response: a root or an error
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DagImportEvent {
+    root: Option<ImportedRoot>,
+    message: Option<String>,
+}
+
+/// A key of the node's keystore
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KeyInfo {
+    /// Name of the key
+    pub name: String,
+    /// ID of the key, which is its IPNS name
+    pub id: String,
+}
+
+/// Response of a `key/list` call
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct KeyList {
+    keys: Vec<KeyInfo>,
+}
+
+/// Response of a `name/resolve` call
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Resolved {
+    path: String,
+}
+
+/// Response of a `files/stat` call
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FileStat {
+    hash: String,
+}
+
+/// Error body of a failed call
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiError {
+    message: String,
+}
+
+/// Client of an IPFS node's RPC API
+///
+/// Cloning is cheap; clones share the connection pool.
+#[derive(Debug, Clone)]
+pub struct IpfsClient {
+    http: Client,
+    /// Base URL of the RPC API, e.g. `http://127.0.0.1:5001/api/v0`
+    base: String,
+    /// Value of the `Authorization` header
+    authorization: Option<HeaderValue>,
+}
+
+impl IpfsClient {
+    /// Create a client from the configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - IPFS API connection settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `api_url` is not a valid URL, the credentials
+    /// cannot be sent in a header, or the TLS backend cannot be initialized
+    pub fn new(config: &IpfsConfig) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(&config.api_url)
+            .with_context(|| format!("Invalid IPFS API URL: {}", config.api_url))?;
+        if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+            anyhow::bail!("Invalid IPFS API URL: {}", config.api_url);
+        }
+
+        let authorization = authorization(
+            &url,
+            config.username.as_deref(),
+            config.password.as_deref(),
+            config.bearer_token.as_deref(),
+        )?;
+
+        let http = Client::builder()
+            .build()
+            .context("Failed to create IPFS HTTP client")?;
+
+        Ok(Self {
+            http,
+            base: format!("{}/api/v0", config.api_url.trim_end_matches('/')),
+            authorization,
+        })
+    }
+
+    /// Add a file
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - Contents of the file, streamed to the node
+    /// * `filename` - Name of the file in the request
+    /// * `options` - Options of the add call
+    /// * `timeout` - Timeout of the whole call, if any
+    /// * `progress` - Callback receiving the bytes processed so far, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns every object added, the wrapping directory last
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or the node adds nothing
+    pub async fn add(
+        &self,
+        body: Body,
+        filename: &str,
+        options: &IpfsAddOptions,
+        timeout: Option<Duration>,
+        progress: Option<&AddProgress>,
+    ) -> Result<Vec<AddedObject>, IpfsError> {
+        let query = add_query(options, progress.is_some());
+
+        let part = multipart::Part::stream(body)
+            .file_name(filename.to_string())
+            .mime_str("application/octet-stream")?;
+        let form = multipart::Form::new().part("file", part);
+
+        let mut request = self.post("add").query(&query).multipart(form);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send(request).await?;
+
+        let mut added = Vec::new();
+        let mut lines = ndjson(response);
+        while let Some(line) = lines.next().await {
+            let event: AddEvent = serde_json::from_slice(&line?)
+                .map_err(|e| IpfsError::InvalidResponse(e.to_string()))?;
+
+            // The status is already sent, so the node reports errors inline
+            if event.kind.as_deref() == Some("error") {
+                return Err(IpfsError::Api {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: event.message.unwrap_or_default(),
+                });
+            }
+
+            match (event.hash, event.bytes) {
+                (Some(hash), _) => added.push(AddedObject {
+                    name: event.name,
+                    hash,
+                    size: event.size,
+                }),
+                (None, Some(bytes)) => {
+                    if let Some(progress) = progress {
+                        progress(bytes);
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+
+        if added.is_empty() {
+            return Err(IpfsError::InvalidResponse(
+                "Empty response - file may not have been added".to_string(),
+            ));
+        }
+
+        debug!("Added {} objects to IPFS", added.len());
+
+        Ok(added)
+    }
+
+    /// Read a file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - CID of the file, or a path below a directory CID
+    ///
+    /// # Returns
+    ///
+    /// Returns the contents of the file as they arrive
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the node cannot provide the content
+    pub async fn cat(
+        &self,
+        path: &str,
+    ) -> Result<impl Stream<Item = Result<Bytes, IpfsError>>, IpfsError> {
+        let response = send(self.post("cat").query(&[("arg", path)])).await?;
+
+        Ok(response.bytes_stream().map_err(IpfsError::from))
+    }
+
+    /// Remove the recursive pin of a CID
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - CID to unpin
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the CID is not pinned
+    pub async fn pin_rm(&self, cid: &str, timeout: Duration) -> Result<(), IpfsError> {
+        let request = self
+            .post("pin/rm")
+            .query(&[("arg", cid), ("recursive", "true")])
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Pin a CID recursively
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - CID to pin
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the node cannot
+    /// fetch the content in time
+    pub async fn pin_add(&self, cid: &str, timeout: Duration) -> Result<(), IpfsError> {
+        let request = self
+            .post("pin/add")
+            .query(&[("arg", cid), ("recursive", "true")])
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Move a recursive pin from one CID to another
+    ///
+    /// Cheaper than pinning the new CID and unpinning the old one, since
+    /// only the blocks that differ are visited.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Pinned CID
+    /// * `to` - CID to pin in its place
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because `from` is not pinned
+    pub async fn pin_update(
+        &self,
+        from: &str,
+        to: &str,
+        timeout: Duration,
+    ) -> Result<(), IpfsError> {
+        let request = self
+            .post("pin/update")
+            .query(&[("arg", from), ("arg", to), ("unpin", "true")])
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Copy an IPFS path into the node's MFS, creating parent directories
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - IPFS path to copy, e.g. `/ipfs/{cid}`
+    /// * `path` - MFS path of the copy
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because `path` exists
+    pub async fn files_cp(
+        &self,
+        source: &str,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<(), IpfsError> {
+        let request = self
+            .post("files/cp")
+            .query(&[("arg", source), ("arg", path), ("parents", "true")])
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Read the CID of an MFS path
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - MFS path of a file or directory
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Returns
+    ///
+    /// Returns the CID, or `None` if nothing exists at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn files_stat(
+        &self,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<Option<String>, IpfsError> {
+        let request = self
+            .post("files/stat")
+            .query(&[("arg", path), ("hash", "true")])
+            .timeout(timeout);
+
+        let response = match send(request).await {
+            Ok(response) => response,
+            Err(IpfsError::Api { message, .. }) if message.contains("does not exist") => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let stat: FileStat = json(response).await?;
+
+        Ok(Some(stat.hash))
+    }
+
+    /// Import the blocks of a CAR file
+    ///
+    /// CARv1 and CARv2 files are accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The CAR file, streamed to the node
+    /// * `timeout` - Timeout of the whole call, if any
+    ///
+    /// # Returns
+    ///
+    /// Returns the roots named in the file's header, which the node pins
+    /// recursively
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the file is not a
+    /// valid CAR file or a root's DAG is incomplete
+    pub async fn dag_import(
+        &self,
+        body: Body,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ImportedRoot>, IpfsError> {
+        let part = multipart::Part::stream(body)
+            .file_name("import.car")
+            .mime_str("application/vnd.ipld.car")?;
+        let form = multipart::Form::new().part("file", part);
+
+        let mut request = self
+            .post("dag/import")
+            .query(&[("pin-roots", "true")])
+            .multipart(form);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = send(request).await?;
+
+        let mut roots = Vec::new();
+        let mut lines = ndjson(response);
+        while let Some(line) = lines.next().await {
+            let event: DagImportEvent = serde_json::from_slice(&line?)
+                .map_err(|e| IpfsError::InvalidResponse(e.to_string()))?;
+
+            match (event.root, event.message) {
+                (Some(root), _) => roots.push(root),
+                (None, Some(message)) => {
+                    return Err(IpfsError::Api {
+                        status: StatusCode::INTERNAL_SERVER_ERROR,
+                        message,
+                    })
+                }
+                (None, None) => {}
+            }
+        }
+
+        debug!("Imported CAR file with {} roots", roots.len());
+
+        Ok(roots)
+    }
+
+    /// Export the DAG below a CID as a CARv1 file
+    ///
+    /// # Arguments
+    ///
+    /// * `cid` - Root of the DAG
+    ///
+    /// # Returns
+    ///
+    /// Returns the CAR file as it arrives
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the node cannot start the export
+    pub async fn dag_export(
+        &self,
+        cid: &str,
+    ) -> Result<impl Stream<Item = Result<Bytes, IpfsError>>, IpfsError> {
+        let response = send(self.post("dag/export").query(&[("arg", cid)])).await?;
+
+        Ok(response.bytes_stream().map_err(IpfsError::from))
+    }
+
+    /// Remove an MFS path recursively
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - MFS path to remove
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn files_rm(&self, path: &str, timeout: Duration) -> Result<(), IpfsError> {
+        let request = self
+            .post("files/rm")
+            .query(&[("arg", path), ("recursive", "true")])
+            .timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Generate an Ed25519 IPNS key
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the key in the node's keystore
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Returns
+    ///
+    /// Returns the key, its ID being the IPNS name in base36
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the key exists
+    pub async fn key_gen(&self, name: &str, timeout: Duration) -> Result<KeyInfo, IpfsError> {
+        let request = self
+            .post("key/gen")
+            .query(&[("arg", name), ("type", "ed25519"), ("ipns-base", "base36")])
+            .timeout(timeout);
+
+        json(send(request).await?).await
+    }
+
+    /// List the keys of the node's keystore
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn key_list(&self, timeout: Duration) -> Result<Vec<KeyInfo>, IpfsError> {
+        let request = self
+            .post("key/list")
+            .query(&[("ipns-base", "base36")])
+            .timeout(timeout);
+        let keys: KeyList = json(send(request).await?).await?;
+
+        Ok(keys.keys)
+    }
+
+    /// Publish an IPFS path under an IPNS key
+    ///
+    /// The record is kept by the node even while it has no peers, and
+    /// announced once it has.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - IPFS path to publish, e.g. `/ipfs/{cid}`
+    /// * `key` - Name of the key to publish with
+    /// * `lifetime` - How long the record stays valid
+    /// * `ttl` - How long resolvers may cache the record, if set
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails, e.g. because the key does not
+    /// exist
+    pub async fn name_publish(
+        &self,
+        path: &str,
+        key: &str,
+        lifetime: Duration,
+        ttl: Option<Duration>,
+        timeout: Duration,
+    ) -> Result<(), IpfsError> {
+        let mut query = vec![
+            ("arg", path.to_string()),
+            ("key", key.to_string()),
+            ("lifetime", format!("{}s", lifetime.as_secs())),
+            ("allow-offline", String::from("true")),
+        ];
+        if let Some(ttl) = ttl {
+            query.push(("ttl", format!("{}s", ttl.as_secs())));
+        }
+
+        let request = self.post("name/publish").query(&query).timeout(timeout);
+        send(request).await?;
+
+        Ok(())
+    }
+
+    /// Resolve an IPNS name to the path it points to
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - IPNS name or DNSLink domain
+    /// * `timeout` - Timeout of the call
+    ///
+    /// # Returns
+    ///
+    /// Returns the resolved path, e.g. `/ipfs/{cid}`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or the name cannot be resolved
+    pub async fn name_resolve(&self, name: &str, timeout: Duration) -> Result<String, IpfsError> {
+        let request = self
+            .post("name/resolve")
+            .query(&[
+                ("arg", format!("/ipns/{}", name).as_str()),
+                ("recursive", "true"),
+            ])
+            .timeout(timeout);
+        let resolved: Resolved = json(send(request).await?).await?;
+
+        Ok(resolved.path)
+    }
+
+    /// Start a call to an RPC endpoint; every endpoint is a POST
+    fn post(&self, endpoint: &str) -> RequestBuilder {
+        let request = self.http.post(format!("{}/{}", self.base, endpoint));
+
+        match &self.authorization {
+            Some(authorization) => request.header(AUTHORIZATION, authorization.clone()),
+            None => request,
+        }
+    }
+}
+
+/// Build the `Authorization` header of an API
+///
+/// # Arguments
+///
+/// * `url` - URL of the API
+/// * `username` - Username for basic authentication
+/// * `password` - Password for basic authentication
+/// * `bearer_token` - Token for bearer authentication, used without basic
+///   credentials
+///
+/// # Errors
+///
+/// Returns an error if the credentials cannot be sent in a header
+pub(crate) fn authorization(
+    url: &reqwest::Url,
+    username: Option<&str>,
+    password: Option<&str>,
+    bearer_token: Option<&str>,
+) -> anyhow::Result<Option<HeaderValue>> {
+    let value = match (username, password, bearer_token) {
+        (Some(username), Some(password), _) => {
+            let request = Client::new()
+                .get(url.clone())
+                .basic_auth(username, Some(password));
+            request.build()?.headers().get(AUTHORIZATION).cloned()
+        }
+        (_, _, Some(token)) => {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("Invalid bearer token")?;
+            value.set_sensitive(true);
+            Some(value)
+        }
+        _ => None,
+    };
+
+    Ok(value)
+}
+
+/// Read a CID encoded as an IPLD link, i.e. `{"/": "bafy..."}`
+fn cid_link<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    struct Link {
+        #[serde(rename = "/")]
+        cid: String,
+    }
+
+    Link::deserialize(deserializer).map(|link| link.cid)
+}
+
+/// Query parameters of an add call
+///
+/// Options left unset are not sent, so the node's defaults apply.
+pub(crate) fn add_query(options: &IpfsAddOptions, progress: bool) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(version) = options.cid_version {
+        query.push(("cid-version", version.to_string()));
+    }
+    if let Some(raw_leaves) = options.raw_leaves {
+        query.push(("raw-leaves", raw_leaves.to_string()));
+    }
+    if let Some(chunker) = &options.chunker {
+        query.push(("chunker", chunker.clone()));
+    }
+    if let Some(hash) = &options.hash {
+        query.push(("hash", hash.clone()));
+    }
+    if let Some(pin) = options.pin {
+        query.push(("pin", pin.to_string()));
+    }
+    if let Some(wrap) = options.wrap_with_directory {
+        query.push(("wrap-with-directory", wrap.to_string()));
+    }
+    query.push(("progress", progress.to_string()));
+
+    query
+}
+
+/// Send a request, turning error responses into [`IpfsError::Api`]
+async fn send(request: RequestBuilder) -> Result<Response, IpfsError> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ApiError>(&body)
+        .map(|error| error.message)
+        .unwrap_or(body);
+
+    Err(IpfsError::Api { status, message })
+}
+
+/// Parse the JSON body of a response
+async fn json<T: serde::de::DeserializeOwned>(response: Response) -> Result<T, IpfsError> {
+    response
+        .json()
+        .await
+        .map_err(|e| IpfsError::InvalidResponse(e.to_string()))
+}
+
+/// Split a streamed response body into its non-empty lines
+pub(crate) fn ndjson(response: Response) -> impl Stream<Item = Result<Bytes, IpfsError>> + Unpin {
+    let chunks = response.bytes_stream().map_err(IpfsError::from);
+    Box::pin(lines(chunks))
+}
+
+/// Split a stream of chunks into non-empty lines
+fn lines<S>(chunks: S) -> impl Stream<Item = Result<Bytes, IpfsError>>
+where
+    S: Stream<Item = Result<Bytes, IpfsError>> + Unpin,
+{
+    futures::stream::unfold(
+        (chunks, BytesMut::new(), false),
+        |(mut chunks, mut buffer, mut done)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                    let line = buffer.split_to(end + 1).freeze().slice(..end);
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    return Some((Ok(line), (chunks, buffer, done)));
+                }
+
+                if done {
+                    // The last line may lack a newline
+                    if buffer.iter().all(u8::is_ascii_whitespace) {
+                        return None;
+                    }
+                    let line = buffer.split().freeze();
+                    return Some((Ok(line), (chunks, buffer, done)));
+                }
+
+                match chunks.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(e)) => return Some((Err(e), (chunks, buffer, true))),
+                    None => done = true,
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CidBase;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn test_lines_reassembles_split_lines() {
+        let chunks = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"{\"Bytes\":1}\n{\"Na")),
+            Ok(Bytes::from_static(b"me\":\"a\"}\n\n")),
+            Ok(Bytes::from_static(b"{\"Hash\":\"Qm\"}")),
+        ]);
+
+        let lines: Vec<Bytes> = lines(chunks).try_collect().await.unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                Bytes::from_static(b"{\"Bytes\":1}"),
+                Bytes::from_static(b"{\"Name\":\"a\"}"),
+                Bytes::from_static(b"{\"Hash\":\"Qm\"}"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_fails_on_an_inline_error() {
+        let add = warp::path!("api" / "v0" / "add").map(|| {
+            concat!(
+                "{\"Name\":\"hello.txt\",\"Bytes\":5}\n",
+                "{\"Message\":\"context deadline exceeded\",\"Code\":0,\"Type\":\"error\"}\n",
+            )
+        });
+        let (addr, server) = warp::serve(warp::post().and(add)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let client = IpfsClient::new(&IpfsConfig {
+            api_url: format!("http://{}", addr),
+            ..IpfsConfig::default()
+        })
+        .unwrap();
+        let result = client
+            .add(
+                Body::from("hello"),
+                "hello.txt",
+                &IpfsAddOptions::default(),
+                None,
+                None,
+            )
+            .await;
+
+        match result {
+            Err(IpfsError::Api { message, .. }) => assert_eq!(message, "context deadline exceeded"),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_add_query() {
+        let options = IpfsAddOptions {
+            cid_version: Some(1),
+            raw_leaves: Some(true),
+            chunker: Some(String::from("size-1048576")),
+            hash: Some(String::from("blake2b-256")),
+            pin: Some(false),
+            wrap_with_directory: None,
+            cid_base: Some(CidBase::Base32),
+        };
+
+        let query = add_query(&options, true);
+        let value = |name| {
+            query
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(value("cid-version"), Some("1"));
+        assert_eq!(value("raw-leaves"), Some("true"));
+        assert_eq!(value("chunker"), Some("size-1048576"));
+        assert_eq!(value("hash"), Some("blake2b-256"));
+        assert_eq!(value("pin"), Some("false"));
+        assert_eq!(value("wrap-with-directory"), None);
+        assert_eq!(value("progress"), Some("true"));
+
+        assert_eq!(
+            add_query(&IpfsAddOptions::default(), false),
+            vec![("progress", String::from("false"))]
+        );
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let api = |status| IpfsError::Api {
+            status,
+            message: String::new(),
+        };
+
+        assert!(api(StatusCode::SERVICE_UNAVAILABLE).is_transient());
+        assert!(api(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(!api(StatusCode::INTERNAL_SERVER_ERROR).is_transient());
+        assert!(!api(StatusCode::UNAUTHORIZED).is_transient());
+        assert!(!IpfsError::InvalidResponse(String::new()).is_transient());
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_urls() {
+        for api_url in [
+            "http://ipfs node:5001",
+            "ftp://127.0.0.1:5001",
+            "127.0.0.1:5001",
+        ] {
+            let config = IpfsConfig {
+                api_url: api_url.to_string(),
+                ..IpfsConfig::default()
+            };
+            assert!(IpfsClient::new(&config).is_err(), "{}", api_url);
+        }
+    }
+}
@@ -7,7 +7,14 @@
 //! # Submodules
 //!
 //! - `s3`: Amazon S3 cloud storage integration
+//! - `circuit_breaker`: Per-backend circuit breakers that fail fast while a backend is down
 //! - `ipfs`: InterPlanetary File System (IPFS) decentralized storage integration
+//! - `ipfs_cluster`: Client of the IPFS Cluster REST API, pinning uploads on several peers
+//! - `ipfs_rpc`: Async client of the IPFS node's RPC API
+//! - `pinning`: Client of remote IPFS pinning services
+//! - `retry`: Retries with exponential backoff for transient backend failures
+//! - `unixfs`: Local computation of the CIDs the IPFS node assigns to files
+//! - `webhook`: Signed delivery of webhook events
 //!
 //! # Design Pattern
 //!
@@ -21,17 +28,45 @@
 //! # Examples
 //!
 //! ```no_run
+//! use memenow_storage_service::state::AppState;
 //! use memenow_storage_service::infrastructure::{s3, ipfs};
+//! use memenow_storage_service::infrastructure::s3::ObjectAttributes;
 //!
-//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! # async fn example(state: AppState) -> Result<(), Box<dyn std::error::Error>> {
 //! // Upload to S3
-//! let s3_url = s3::upload_to_s3("/tmp/file.jpg", "my-bucket", "uploads/file.jpg").await?;
+//! let retry = &state.config.retry;
+//! let s3_url = s3::upload_to_s3(
+//!     &state.s3,
+//!     &retry.s3,
+//!     "/tmp/file.jpg",
+//!     "my-bucket",
+//!     "uploads/file.jpg",
+//!     &ObjectAttributes::default(),
+//! )
+//! .await?;
 //!
 //! // Upload to IPFS
-//! let ipfs_hash = ipfs::upload_to_ipfs("/tmp/file.jpg").await?;
+//! let ipfs = &state.config.ipfs;
+//! let ipfs_hash = ipfs::upload_to_ipfs(
+//!     &state.ipfs,
+//!     &retry.ipfs,
+//!     ipfs.timeout(),
+//!     &ipfs.add,
+//!     "/tmp/file.jpg",
+//!     "file.jpg",
+//!     None,
+//! )
+//! .await?;
 //! # Ok(())
 //! # }
 //! ```
 
+pub mod circuit_breaker;
 pub mod ipfs;
+pub mod ipfs_cluster;
+pub mod ipfs_rpc;
+pub mod pinning;
+pub mod retry;
 pub mod s3;
+pub mod unixfs;
+pub mod webhook;
@@ -0,0 +1,518 @@
+//! IPFS Pinning Service API client
+//!
+//! Remote pinning services (Pinata, Filebase, web3.storage and the like)
+//! implement a common HTTP API: a pin is requested with `POST /pins`, which
+//! answers with a request ID whose status moves from `queued` through
+//! `pinning` to `pinned` or `failed`, and is checked with
+//! `GET /pins/{requestid}`. Existing pins are listed with `GET /pins` and
+//! removed with `DELETE /pins/{requestid}`. Every request carries the
+//! caller's access token as a bearer token.
+//!
+//! Connection failures, timeouts, `408`, `429` and `5xx` responses are
+//! marked as transient (see [`retry::transient`]), other error responses are
+//! permanent.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use memenow_storage_service::infrastructure::pinning::{PinRequest, PinningService};
+//! use std::time::Duration;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let service = PinningService::new("https://api.example.com/psa", Duration::from_secs(30))?;
+//! let request = PinRequest {
+//!     cid: String::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"),
+//!     name: Some(String::from("photo.jpg")),
+//!     ..PinRequest::default()
+//! };
+//! let status = service.add_pin("token", &request).await?;
+//! let status = service.get_pin("token", &status.requestid).await?;
+//! println!("{}: {}", status.requestid, status.status);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::infrastructure::retry;
+use anyhow::{Context, Result};
+use log::debug;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Status of a pin request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinState {
+    /// Waiting to be processed by the service
+    Queued,
+    /// The service is fetching the content
+    Pinning,
+    /// The content is pinned
+    Pinned,
+    /// The service gave up on the pin
+    Failed,
+}
+
+impl PinState {
+    /// Whether the service is done with the request
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Pinned | Self::Failed)
+    }
+}
+
+impl fmt::Display for PinState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Queued => write!(f, "queued"),
+            Self::Pinning => write!(f, "pinning"),
+            Self::Pinned => write!(f, "pinned"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A pin, as sent to and returned by the service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinRequest {
+    /// CID to pin
+    pub cid: String,
+    /// Optional name of the pin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Multiaddrs of nodes known to have the content
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub origins: Vec<String>,
+    /// Free-form metadata stored with the pin
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+/// A pin request and its status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinStatus {
+    /// ID of the request, used to check or remove it
+    pub requestid: String,
+    /// Status of the request
+    pub status: PinState,
+    /// When the request was made (RFC 3339)
+    pub created: String,
+    /// The requested pin
+    pub pin: PinRequest,
+    /// Multiaddrs of the service's nodes, to connect to for faster transfer
+    #[serde(default)]
+    pub delegates: Vec<String>,
+    /// Additional information from the service
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub info: BTreeMap<String, String>,
+}
+
+/// Page of pins returned by `GET /pins`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinResults {
+    /// Total number of pins matching the query
+    pub count: u64,
+    /// The pins on this page
+    pub results: Vec<PinStatus>,
+}
+
+/// Error returned when the service rejects a request
+#[derive(Debug, Error)]
+#[error("Pinning service responded with {status}: {reason}")]
+pub struct ServiceError {
+    /// The response status
+    pub status: StatusCode,
+    /// Reason given by the service, e.g. `NOT_FOUND`
+    pub reason: String,
+    /// Details given by the service
+    pub details: Option<String>,
+}
+
+/// Error body defined by the Pinning Service API
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetails {
+    reason: String,
+    details: Option<String>,
+}
+
+/// Client of a remote pinning service
+///
+/// Cloning is cheap; clones share the connection pool. Access tokens are
+/// passed per call, so one client serves every tenant.
+#[derive(Debug, Clone)]
+pub struct PinningService {
+    http: Client,
+    /// Base URL of the API, without a trailing slash
+    endpoint: String,
+}
+
+impl PinningService {
+    /// Create a client of the service at `endpoint`
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Base URL of the service's API
+    /// * `timeout` - Timeout of a single request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TLS backend cannot be initialized
+    pub fn new(endpoint: &str, timeout: Duration) -> Result<Self> {
+        let http = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to create pinning service HTTP client")?;
+
+        Ok(Self {
+            http,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Request a pin
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Access token of the account to pin with
+    /// * `pin` - The pin to request
+    ///
+    /// # Returns
+    ///
+    /// Returns the new request, usually still `queued`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the service rejects it
+    pub async fn add_pin(&self, token: &str, pin: &PinRequest) -> Result<PinStatus> {
+        let request = self.http.post(self.url("pins")).json(pin);
+        let response = send(request, token)
+            .await
+            .with_context(|| format!("Failed to request remote pin of {}", pin.cid))?;
+        let status: PinStatus = json(response).await?;
+
+        debug!("Requested remote pin {} of {}", status.requestid, pin.cid);
+
+        Ok(status)
+    }
+
+    /// Get the status of a pin request
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Access token of the account that made the request
+    /// * `requestid` - ID of the request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or is unknown to the service
+    pub async fn get_pin(&self, token: &str, requestid: &str) -> Result<PinStatus> {
+        let request = self.http.get(self.url(&format!("pins/{}", requestid)));
+        let response = send(request, token)
+            .await
+            .with_context(|| format!("Failed to get remote pin {}", requestid))?;
+
+        json(response).await
+    }
+
+    /// List the pin requests of a CID
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Access token of the account
+    /// * `cid` - CID the requests pin
+    /// * `statuses` - Only return requests in one of these states
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the service rejects it
+    pub async fn list_pins(
+        &self,
+        token: &str,
+        cid: &str,
+        statuses: &[PinState],
+    ) -> Result<Vec<PinStatus>> {
+        let status = statuses
+            .iter()
+            .map(PinState::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let request = self
+            .http
+            .get(self.url("pins"))
+            .query(&[("cid", cid), ("status", status.as_str())]);
+        let response = send(request, token)
+            .await
+            .with_context(|| format!("Failed to list remote pins of {}", cid))?;
+        let results: PinResults = json(response).await?;
+
+        Ok(results.results)
+    }
+
+    /// Remove a pin request, unpinning its content
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Access token of the account that made the request
+    /// * `requestid` - ID of the request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or is unknown to the service
+    pub async fn remove_pin(&self, token: &str, requestid: &str) -> Result<()> {
+        let request = self.http.delete(self.url(&format!("pins/{}", requestid)));
+        send(request, token)
+            .await
+            .with_context(|| format!("Failed to remove remote pin {}", requestid))?;
+
+        debug!("Removed remote pin {}", requestid);
+
+        Ok(())
+    }
+
+    /// URL of an API path
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.endpoint, path)
+    }
+}
+
+/// Send an authenticated request, turning error responses into
+/// [`ServiceError`]s
+async fn send(request: RequestBuilder, token: &str) -> Result<Response> {
+    let response = request.bearer_auth(token).send().await.map_err(|e| {
+        if e.is_builder() {
+            anyhow::Error::new(e)
+        } else {
+            retry::transient(e)
+        }
+    })?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let (reason, details) = match serde_json::from_str::<ErrorBody>(&body) {
+        Ok(body) => (body.error.reason, body.error.details),
+        Err(_) => (status.canonical_reason().unwrap_or("").to_string(), None),
+    };
+    let error = ServiceError {
+        status,
+        reason,
+        details,
+    };
+
+    if status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+    {
+        Err(retry::transient(error))
+    } else {
+        Err(anyhow::Error::new(error))
+    }
+}
+
+/// Parse the JSON body of a successful response
+async fn json<T: serde::de::DeserializeOwned>(response: Response) -> Result<T> {
+    let url = response.url().clone();
+
+    response
+        .json()
+        .await
+        .with_context(|| format!("Invalid pinning service response from {}", url))
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    //! In-memory Pinning Service API for tests
+    //!
+    //! Pins are `queued` when requested and advance one state every time
+    //! their status is read, so pollers see them go through `pinning` to
+    //! `pinned`. Pins named `fail` end up `failed` instead. Only requests
+    //! carrying one of the accepted tokens are served.
+
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use warp::http::StatusCode as WarpStatus;
+    use warp::{Filter, Reply};
+
+    /// Pins held by the mock, by request ID
+    pub type Pins = Arc<Mutex<BTreeMap<String, PinStatus>>>;
+
+    fn error(status: WarpStatus, reason: &str) -> warp::reply::Response {
+        let body = serde_json::json!({ "error": { "reason": reason } });
+        warp::reply::with_status(warp::reply::json(&body), status).into_response()
+    }
+
+    /// Serve the mock, accepting the given tokens
+    pub fn spawn(tokens: &[&str]) -> (SocketAddr, Pins) {
+        let pins = Pins::default();
+        let tokens: Vec<String> = tokens
+            .iter()
+            .map(|token| format!("Bearer {}", token))
+            .collect();
+
+        let auth = warp::header::optional::<String>("authorization")
+            .and_then(move |header: Option<String>| {
+                let accepted = header.is_some_and(|header| tokens.contains(&header));
+                async move {
+                    if accepted {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::not_found())
+                    }
+                }
+            })
+            .untuple_one();
+        let with_pins = {
+            let pins = pins.clone();
+            warp::any().map(move || pins.clone())
+        };
+
+        let add = warp::post()
+            .and(warp::path!("pins"))
+            .and(warp::body::json())
+            .and(with_pins.clone())
+            .map(|pin: PinRequest, pins: Pins| {
+                let mut pins = pins.lock().unwrap();
+                let status = PinStatus {
+                    requestid: format!("req-{}", pins.len() + 1),
+                    status: PinState::Queued,
+                    created: String::from("2026-01-01T00:00:00Z"),
+                    pin,
+                    delegates: Vec::new(),
+                    info: BTreeMap::new(),
+                };
+                pins.insert(status.requestid.clone(), status.clone());
+                warp::reply::with_status(warp::reply::json(&status), WarpStatus::ACCEPTED)
+                    .into_response()
+            });
+        let get = warp::get()
+            .and(warp::path!("pins" / String))
+            .and(with_pins.clone())
+            .map(|id: String, pins: Pins| {
+                let mut pins = pins.lock().unwrap();
+                let Some(status) = pins.get_mut(&id) else {
+                    return error(WarpStatus::NOT_FOUND, "NOT_FOUND");
+                };
+                status.status = match status.status {
+                    PinState::Queued => PinState::Pinning,
+                    PinState::Pinning if status.pin.name.as_deref() == Some("fail") => {
+                        PinState::Failed
+                    }
+                    PinState::Pinning => PinState::Pinned,
+                    finished => finished,
+                };
+                warp::reply::json(&*status).into_response()
+            });
+        let list = warp::get()
+            .and(warp::path!("pins"))
+            .and(warp::query::<BTreeMap<String, String>>())
+            .and(with_pins.clone())
+            .map(|query: BTreeMap<String, String>, pins: Pins| {
+                let pins = pins.lock().unwrap();
+                let results: Vec<PinStatus> = pins
+                    .values()
+                    .filter(|status| query.get("cid").map_or(true, |cid| status.pin.cid == *cid))
+                    .filter(|status| {
+                        query.get("status").map_or(true, |statuses| {
+                            statuses.split(',').any(|s| s == status.status.to_string())
+                        })
+                    })
+                    .cloned()
+                    .collect();
+                let body = PinResults {
+                    count: results.len() as u64,
+                    results,
+                };
+                warp::reply::json(&body).into_response()
+            });
+        let remove = warp::delete()
+            .and(warp::path!("pins" / String))
+            .and(with_pins)
+            .map(
+                |id: String, pins: Pins| match pins.lock().unwrap().remove(&id) {
+                    Some(_) => WarpStatus::ACCEPTED.into_response(),
+                    None => error(WarpStatus::NOT_FOUND, "NOT_FOUND"),
+                },
+            );
+
+        let routes = auth.and(add.or(get).unify().or(list).unify().or(remove).unify());
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, pins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pin_lifecycle() {
+        let (addr, pins) = mock::spawn(&["token"]);
+        let service =
+            PinningService::new(&format!("http://{}/", addr), Duration::from_secs(5)).unwrap();
+        let cid = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+
+        let request = PinRequest {
+            cid: cid.to_string(),
+            name: Some(String::from("photo.jpg")),
+            origins: vec![String::from("/ip4/127.0.0.1/tcp/4001/p2p/QmNode")],
+            meta: BTreeMap::from([(String::from("tenant"), String::from("acme"))]),
+        };
+        let added = service.add_pin("token", &request).await.unwrap();
+        assert_eq!(added.status, PinState::Queued);
+        assert_eq!(added.pin, request);
+
+        let pinning = service.get_pin("token", &added.requestid).await.unwrap();
+        assert_eq!(pinning.status, PinState::Pinning);
+        let pinned = service.get_pin("token", &added.requestid).await.unwrap();
+        assert_eq!(pinned.status, PinState::Pinned);
+
+        let listed = service
+            .list_pins("token", cid, &[PinState::Pinned])
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        let listed = service
+            .list_pins("token", cid, &[PinState::Failed])
+            .await
+            .unwrap();
+        assert!(listed.is_empty());
+
+        service.remove_pin("token", &added.requestid).await.unwrap();
+        assert!(pins.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let (addr, _pins) = mock::spawn(&["token"]);
+        let service =
+            PinningService::new(&format!("http://{}", addr), Duration::from_secs(5)).unwrap();
+
+        let error = service.get_pin("token", "missing").await.unwrap_err();
+        let rejected = error.downcast_ref::<ServiceError>().unwrap();
+        assert_eq!(rejected.status, StatusCode::NOT_FOUND);
+        assert_eq!(rejected.reason, "NOT_FOUND");
+        assert!(!retry::is_transient(&error));
+
+        // Unknown tokens are not served
+        assert!(service.get_pin("other", "missing").await.is_err());
+
+        // Nothing listens on the discard port
+        let unreachable =
+            PinningService::new("http://127.0.0.1:9", Duration::from_secs(5)).unwrap();
+        let error = unreachable.remove_pin("token", "req-1").await.unwrap_err();
+        assert!(retry::is_transient(&error));
+    }
+}
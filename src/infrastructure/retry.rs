@@ -0,0 +1,271 @@
+//! Retries with exponential backoff for storage backend calls
+//!
+//! Calls to S3 and IPFS are wrapped with [`retry`], which repeats an
+//! operation that failed with a transient error (a timeout, a dropped
+//! connection, a 5xx or throttling response) according to the backend's
+//! [`RetryPolicy`]. Errors are classified where they are produced: the
+//! backend modules wrap retryable errors with [`transient`], and everything
+//! else fails immediately.
+//!
+//! # Backoff
+//!
+//! The delay before attempt `n + 1` is `initial_backoff * 2^(n - 1)`, capped
+//! at `max_backoff`. With jitter enabled the actual delay is drawn uniformly
+//! from zero up to that value ("full jitter"), which spreads out retries from
+//! concurrent uploads hitting the same failing backend.
+//!
+//! # Observability
+//!
+//! Every call runs in a `retry` span recording the operation and the number
+//! of attempts made. The attempt count is also recorded in the
+//! `storage.backend.attempts` histogram, tagged with the operation and
+//! whether it finally succeeded.
+
+use crate::config::RetryPolicy;
+use crate::telemetry::INSTRUMENTATION_SCOPE;
+use anyhow::Result;
+use log::{debug, warn};
+use opentelemetry::metrics::Histogram;
+use opentelemetry::{global, KeyValue};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::Instrument;
+
+/// Marker for errors that are worth retrying
+///
+/// Wraps the original error transparently, so messages and sources are
+/// unchanged.
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct Transient(Box<dyn StdError + Send + Sync>);
+
+/// Wrap an error to mark it as transient
+///
+/// # Arguments
+///
+/// * `error` - The original error
+pub fn transient<E>(error: E) -> anyhow::Error
+where
+    E: StdError + Send + Sync + 'static,
+{
+    anyhow::Error::new(Transient(Box::new(error)))
+}
+
+/// Check whether an error, or any error it was caused by, is transient
+///
+/// # Arguments
+///
+/// * `error` - The error to classify
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Transient>())
+}
+
+/// Run an operation, retrying transient failures
+///
+/// # Arguments
+///
+/// * `policy` - Retry policy of the backend
+/// * `operation` - Name of the operation, used in logs and metrics
+///   (e.g. "s3.put_object")
+/// * `op` - Produces a fresh attempt of the operation each time it is called
+///
+/// # Returns
+///
+/// Returns the result of the first successful attempt
+///
+/// # Errors
+///
+/// Returns the last error if it was not transient or the policy's attempts
+/// are exhausted
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::RetryPolicy;
+/// use memenow_storage_service::infrastructure::retry::retry;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let value = retry(&RetryPolicy::default(), "example.op", || async { Ok(42) }).await?;
+/// assert_eq!(value, 42);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, operation: &str, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let span = tracing::debug_span!("retry", operation, attempts = tracing::field::Empty);
+
+    async {
+        let mut attempt = 1;
+
+        loop {
+            let result = op().await;
+
+            match result {
+                Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                    let delay = backoff_delay(policy, attempt);
+                    warn!(
+                        "{} failed (attempt {}/{}), retrying in {} ms: {:#}",
+                        operation,
+                        attempt,
+                        policy.max_attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => {
+                    tracing::Span::current().record("attempts", attempt);
+                    record_attempts(operation, attempt, result.is_ok());
+
+                    if attempt > 1 {
+                        match &result {
+                            Ok(_) => debug!("{} succeeded after {} attempts", operation, attempt),
+                            Err(_) => warn!("{} failed after {} attempts", operation, attempt),
+                        }
+                    }
+
+                    return result;
+                }
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// Compute the delay before the attempt following `attempt`
+///
+/// # Arguments
+///
+/// * `policy` - Retry policy of the backend
+/// * `attempt` - Number of the attempt that just failed, starting at 1
+pub fn backoff_delay(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(31);
+    let backoff = policy
+        .initial_backoff_ms
+        .saturating_mul(1 << exponent)
+        .min(policy.max_backoff_ms);
+
+    if policy.jitter {
+        Duration::from_millis(fastrand::u64(0..=backoff))
+    } else {
+        Duration::from_millis(backoff)
+    }
+}
+
+/// Record the number of attempts an operation took
+fn record_attempts(operation: &str, attempts: u32, succeeded: bool) {
+    static ATTEMPTS: OnceLock<Histogram<u64>> = OnceLock::new();
+
+    let histogram = ATTEMPTS.get_or_init(|| {
+        global::meter(INSTRUMENTATION_SCOPE)
+            .u64_histogram("storage.backend.attempts")
+            .with_description("Attempts made per storage backend operation")
+            .with_boundaries(vec![1.0, 2.0, 3.0, 5.0, 10.0])
+            .build()
+    });
+
+    histogram.record(
+        u64::from(attempts),
+        &[
+            KeyValue::new("operation", operation.to_string()),
+            KeyValue::new("outcome", if succeeded { "success" } else { "failure" }),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 4,
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_up_to_max() {
+        let policy = policy(10);
+        let delays: Vec<u128> = (1..=5)
+            .map(|attempt| backoff_delay(&policy, attempt).as_millis())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 4, 4]);
+    }
+
+    #[test]
+    fn test_backoff_delay_with_jitter_stays_below_cap() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy(10)
+        };
+        for attempt in 1..=10 {
+            assert!(backoff_delay(&policy, attempt) <= Duration::from_millis(4));
+        }
+    }
+
+    #[test]
+    fn test_is_transient_through_context() {
+        use anyhow::Context;
+
+        let error: Result<()> = Err(transient(std::io::Error::other("reset")));
+        let error = error.context("Failed to upload").unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(format!("{:#}", error), "Failed to upload: reset");
+
+        assert!(!is_transient(&anyhow::anyhow!("access denied")));
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let calls = AtomicU32::new(0);
+        let result = retry(&policy(3), "test.op", || async {
+            if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(transient(std::io::Error::other("unavailable")))
+            } else {
+                Ok("done")
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_after_max_attempts() {
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry(&policy(2), "test.op", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(transient(std::io::Error::other("unavailable")))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_does_not_repeat_permanent_errors() {
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry(&policy(5), "test.op", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow::anyhow!("access denied"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -3,6 +3,14 @@
 //! This module provides functionality for uploading files to Amazon S3.
 //! It handles AWS SDK initialization, authentication, and file upload operations.
 //!
+//! # S3-Compatible Stores
+//!
+//! Setting `S3_ENDPOINT_URL` points the client at an S3-compatible store
+//! such as MinIO, Ceph or Cloudflare R2 instead of AWS. Stores that do not
+//! serve buckets on subdomains need `S3_FORCE_PATH_STYLE=true`, which
+//! addresses objects as `{endpoint}/{bucket}/{key}`. [`Bucket`] builds the
+//! URLs of uploaded objects the same way the client addresses them.
+//!
 //! # Authentication
 //!
 //! Authentication is handled automatically through the AWS SDK, which looks for
@@ -11,28 +19,365 @@
 //! 2. AWS credentials file (~/.aws/credentials)
 //! 3. IAM instance profile (when running on EC2)
 //!
+//! # Object Attributes
+//!
+//! Uploads carry [`ObjectAttributes`]: the `Content-Type`, `Cache-Control`
+//! and `Content-Disposition` S3 serves the object with, user metadata
+//! (`x-amz-meta-*`) and object tags. They are set when the object is
+//! created, whether by a single `PutObject` or a multipart upload.
+//!
+//! They also carry the storage class and server-side encryption of the
+//! object. With SSE-C the customer-provided key is sent with every request
+//! writing or reading the object's data, including each multipart part and
+//! downloads for replication.
+//!
 //! # Examples
 //!
 //! ```no_run
-//! use memenow_storage_service::infrastructure::s3::upload_to_s3;
+//! use memenow_storage_service::config::Config;
+//! use memenow_storage_service::infrastructure::s3::{
+//!     create_s3_client, upload_to_s3, Bucket, ObjectAttributes,
+//! };
 //!
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = Config::default();
+//! let client = create_s3_client(&config.s3).await;
 //! let url = upload_to_s3(
+//!     &client,
+//!     &config.retry.s3,
 //!     "/tmp/myfile.jpg",
-//!     "my-bucket",
-//!     "uploads/myfile.jpg"
+//!     &Bucket::new(&config.s3),
+//!     "uploads/myfile.jpg",
+//!     &ObjectAttributes::default(),
 //! ).await?;
 //! println!("File uploaded to: {}", url);
 //! # Ok(())
 //! # }
 //! ```
 
+use crate::config::{Encryption, RetryPolicy, S3Config, S3Storage};
+use crate::infrastructure::retry::{self, retry};
+use crate::utils::urls;
 use anyhow::{Context, Result};
-use aws_config::meta::region::RegionProviderChain;
+use aws_config::retry::RetryConfig;
+use aws_config::Region;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::create_multipart_upload::builders::CreateMultipartUploadFluentBuilder;
+use aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder;
 use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{
+    CompletedMultipartUpload, CompletedPart, ServerSideEncryption, StorageClass,
+};
 use aws_sdk_s3::Client;
-use log::{debug, info};
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use log::{debug, info, warn};
+use md5::{Digest, Md5};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
 use std::path::Path;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+/// Size of each part in a streaming multipart upload (8 MiB)
+///
+/// S3 requires every part except the last to be at least 5 MiB.
+pub const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Characters escaped in the URL query string of object tags
+const TAG_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_');
+
+/// Algorithm of SSE-C encryption, the only one S3 supports
+const SSE_C_ALGORITHM: &str = "AES256";
+
+/// Apply [`ObjectAttributes`] to a request creating an object
+///
+/// `PutObject` and `CreateMultipartUpload` builders share the setters but
+/// no trait, so a macro keeps the two requests from drifting apart.
+macro_rules! set_attributes {
+    ($attributes:expr, $request:expr) => {{
+        let attributes: &ObjectAttributes = $attributes;
+        let key = CustomerKey::new(&attributes.storage);
+        $request
+            .set_content_type(attributes.content_type.clone())
+            .set_cache_control(attributes.cache_control.clone())
+            .set_content_disposition(attributes.content_disposition.clone())
+            .set_metadata(attributes.user_metadata())
+            .set_tagging(attributes.tagging())
+            .set_storage_class(attributes.storage_class())
+            .set_server_side_encryption(attributes.server_side_encryption())
+            .set_ssekms_key_id(attributes.storage.kms_key_id.clone())
+            .set_bucket_key_enabled(attributes.storage.bucket_key)
+            .set_sse_customer_algorithm(key.as_ref().map(|_| SSE_C_ALGORITHM.to_string()))
+            .set_sse_customer_key(key.as_ref().map(|key| key.key.clone()))
+            .set_sse_customer_key_md5(key.map(|key| key.md5))
+    }};
+}
+
+/// Headers, metadata and tags an object is created with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectAttributes {
+    /// Media type S3 serves the object as
+    pub content_type: Option<String>,
+    /// `Cache-Control` S3 serves the object with
+    pub cache_control: Option<String>,
+    /// `Content-Disposition` S3 serves the object with
+    pub content_disposition: Option<String>,
+    /// User metadata, sent as `x-amz-meta-{key}` headers
+    pub metadata: BTreeMap<String, String>,
+    /// Object tags
+    pub tags: BTreeMap<String, String>,
+    /// Storage class and server-side encryption
+    pub storage: S3Storage,
+}
+
+impl ObjectAttributes {
+    /// Encode the tags as the URL query string S3 expects, if there are any
+    pub fn tagging(&self) -> Option<String> {
+        if self.tags.is_empty() {
+            return None;
+        }
+
+        let pairs: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(key, TAG_ESCAPES),
+                    utf8_percent_encode(value, TAG_ESCAPES)
+                )
+            })
+            .collect();
+        Some(pairs.join("&"))
+    }
+
+    /// User metadata in the form the SDK takes
+    fn user_metadata(&self) -> Option<HashMap<String, String>> {
+        (!self.metadata.is_empty()).then(|| self.metadata.clone().into_iter().collect())
+    }
+
+    /// Storage class in the form the SDK takes
+    fn storage_class(&self) -> Option<StorageClass> {
+        self.storage
+            .storage_class
+            .as_deref()
+            .map(StorageClass::from)
+    }
+
+    /// S3-managed encryption in the form the SDK takes; SSE-C is sent as a
+    /// [`CustomerKey`] instead
+    fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        match self.storage.encryption? {
+            Encryption::SseS3 => Some(ServerSideEncryption::Aes256),
+            Encryption::SseKms => Some(ServerSideEncryption::AwsKms),
+            Encryption::SseC => None,
+        }
+    }
+
+    /// Apply the attributes to a `PutObject` request
+    fn put_object(&self, request: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        set_attributes!(self, request)
+    }
+
+    /// Apply the attributes to a `CreateMultipartUpload` request
+    fn create_multipart_upload(
+        &self,
+        request: CreateMultipartUploadFluentBuilder,
+    ) -> CreateMultipartUploadFluentBuilder {
+        set_attributes!(self, request)
+    }
+}
+
+/// Customer-provided key of an object encrypted with SSE-C
+///
+/// S3 does not keep the key, so it is sent with every request writing or
+/// reading the object's data.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CustomerKey {
+    /// The key, base64 encoded
+    key: String,
+    /// MD5 of the key, base64 encoded, so S3 can check it arrived intact
+    md5: String,
+}
+
+impl CustomerKey {
+    /// The customer-provided key of objects stored with these settings
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` unless the settings select SSE-C with a valid key
+    pub fn new(storage: &S3Storage) -> Option<Self> {
+        let key = storage.customer_key_bytes()?;
+        let base64 = base64::engine::general_purpose::STANDARD;
+
+        Some(Self {
+            key: base64.encode(&key),
+            md5: base64.encode(Md5::digest(&key)),
+        })
+    }
+}
+
+impl std::fmt::Debug for CustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomerKey")
+            .field("key", &"<redacted>")
+            .field("md5", &self.md5)
+            .finish()
+    }
+}
+
+/// The bucket uploads are stored in, and how its objects are addressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    /// Name of the bucket
+    pub name: String,
+    /// AWS region of the bucket
+    pub region: String,
+    /// Base URL of an S3-compatible endpoint, if the bucket is not on AWS
+    pub endpoint: Option<String>,
+    /// Address objects as `{endpoint}/{bucket}/{key}` rather than on a
+    /// subdomain of the endpoint named after the bucket
+    pub path_style: bool,
+    /// Base URLs of CDNs or custom domains serving the bucket
+    pub cdn_base_urls: Vec<String>,
+}
+
+impl Bucket {
+    /// The bucket of the S3 configuration
+    pub fn new(config: &S3Config) -> Self {
+        let base_url = |url: &String| url.trim_end_matches('/').to_string();
+
+        Self {
+            name: config.bucket.clone(),
+            region: config.region.clone(),
+            endpoint: config.endpoint_url.as_ref().map(base_url),
+            path_style: config.force_path_style,
+            cdn_base_urls: config.cdn_base_urls.iter().map(base_url).collect(),
+        }
+    }
+
+    /// Construct the URL of an object
+    ///
+    /// The key is percent-encoded. Buckets on AWS are addressed in their
+    /// region (see [`get_s3_url`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Object key within the bucket
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use memenow_storage_service::infrastructure::s3::Bucket;
+    ///
+    /// let bucket = Bucket {
+    ///     name: "uploads".to_string(),
+    ///     region: "us-east-1".to_string(),
+    ///     endpoint: Some("http://localhost:9000".to_string()),
+    ///     path_style: true,
+    ///     cdn_base_urls: Vec::new(),
+    /// };
+    /// assert_eq!(bucket.object_url("a b.jpg"), "http://localhost:9000/uploads/a%20b.jpg");
+    /// ```
+    pub fn object_url(&self, key: &str) -> String {
+        let key = urls::encode_path(key);
+        let endpoint = match (&self.endpoint, self.path_style) {
+            (Some(endpoint), _) => endpoint.clone(),
+            (None, false) => return get_s3_url(&self.name, &key, &self.region),
+            (None, true) if self.region == "us-east-1" => "https://s3.amazonaws.com".to_string(),
+            (None, true) => format!("https://s3.{}.amazonaws.com", self.region),
+        };
+
+        if self.path_style {
+            return format!("{}/{}/{}", endpoint, self.name, key);
+        }
+        match endpoint.split_once("://") {
+            Some((scheme, rest)) => format!("{}://{}.{}/{}", scheme, self.name, rest, key),
+            None => format!("{}.{}/{}", self.name, endpoint, key),
+        }
+    }
+
+    /// Construct the URLs of an object on each CDN serving the bucket
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Object key within the bucket
+    pub fn cdn_urls(&self, key: &str) -> Vec<String> {
+        let key = urls::encode_path(key);
+        self.cdn_base_urls
+            .iter()
+            .map(|base| format!("{}/{}", base, key))
+            .collect()
+    }
+}
+
+/// Create an S3 client for the configured region
+///
+/// Loads the AWS configuration (credentials chain and timeout settings)
+/// once. With `S3_ENDPOINT_URL` set, requests go to that S3-compatible
+/// endpoint, addressing buckets by path if `S3_FORCE_PATH_STYLE` is set.
+/// The returned client is meant to be created at startup and shared, so
+/// its connection pool and credential cache are reused across uploads.
+///
+/// The SDK's own retries are disabled; calls are retried by this module
+/// according to the configured [`RetryPolicy`] instead, so attempts are not
+/// multiplied and are visible in logs and metrics.
+///
+/// # Arguments
+///
+/// * `config` - S3 configuration holding the region, endpoint and, if
+///   configured, the credentials used instead of the SDK's credential chain
+///
+/// # Returns
+///
+/// Returns a configured S3 `Client`
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::infrastructure::s3::create_s3_client;
+///
+/// # async fn example() {
+/// let client = create_s3_client(&Config::default().s3).await;
+/// # }
+/// ```
+pub async fn create_s3_client(config: &S3Config) -> Client {
+    let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(Region::new(config.region.clone()))
+        .retry_config(RetryConfig::disabled());
+    if let (Some(access_key_id), Some(secret_access_key)) =
+        (&config.access_key_id, &config.secret_access_key)
+    {
+        debug!("Using configured AWS credentials");
+        loader = loader.credentials_provider(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            config.session_token.clone(),
+            None,
+            "memenow-config",
+        ));
+    }
+    let aws_config = loader.load().await;
+
+    debug!(
+        "AWS configuration loaded, region: {:?}",
+        aws_config.region()
+    );
+
+    let mut s3_config =
+        aws_sdk_s3::config::Builder::from(&aws_config).force_path_style(config.force_path_style);
+    if let Some(endpoint) = &config.endpoint_url {
+        debug!("Using S3-compatible endpoint {}", endpoint);
+        s3_config = s3_config.endpoint_url(endpoint);
+    }
+
+    Client::from_conf(s3_config.build())
+}
 
 /// Upload a file to Amazon S3
 ///
@@ -42,13 +387,17 @@ use std::path::Path;
 ///
 /// # Arguments
 ///
+/// * `client` - Shared S3 client (see [`create_s3_client`])
+/// * `policy` - Retry policy for transient failures
 /// * `filepath` - Path to the local file to upload
-/// * `bucket` - Name of the S3 bucket (must already exist)
+/// * `bucket` - The S3 bucket (must already exist)
 /// * `key` - S3 object key (path within the bucket)
+/// * `object` - Headers, metadata and tags of the object
 ///
 /// # Returns
 ///
-/// Returns the public HTTPS URL of the uploaded file on success.
+/// Returns the URL of the uploaded file on success (see
+/// [`Bucket::object_url`]).
 /// Note: The file may not be publicly accessible depending on bucket permissions.
 ///
 /// # Errors
@@ -63,14 +412,25 @@ use std::path::Path;
 /// # Examples
 ///
 /// ```no_run
-/// use memenow_storage_service::infrastructure::s3::upload_to_s3;
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::infrastructure::s3::{
+///     create_s3_client, upload_to_s3, Bucket, ObjectAttributes,
+/// };
 ///
 /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-/// // Upload a file with automatic region detection
+/// let config = Config::default();
+/// let client = create_s3_client(&config.s3).await;
+/// let object = ObjectAttributes {
+///     content_type: Some("image/jpeg".to_string()),
+///     ..ObjectAttributes::default()
+/// };
 /// let url = upload_to_s3(
+///     &client,
+///     &config.retry.s3,
 ///     "/tmp/image.jpg",
-///     "my-photos-bucket",
-///     "2024/01/image.jpg"
+///     &Bucket::new(&config.s3),
+///     "2024/01/image.jpg",
+///     &object,
 /// ).await?;
 ///
 /// assert!(url.starts_with("https://"));
@@ -83,64 +443,503 @@ use std::path::Path;
 /// - Files are streamed from disk, minimizing memory usage
 /// - The AWS SDK automatically uses multipart uploads for large files
 /// - Consider using AWS Transfer Acceleration for large files or global uploads
-pub async fn upload_to_s3(filepath: &str, bucket: &str, key: &str) -> Result<String> {
+#[tracing::instrument(skip(client, policy, filepath, object))]
+pub async fn upload_to_s3(
+    client: &Client,
+    policy: &RetryPolicy,
+    filepath: &str,
+    bucket: &Bucket,
+    key: &str,
+    object: &ObjectAttributes,
+) -> Result<String> {
     debug!(
         "Initiating S3 upload: file={}, bucket={}, key={}",
-        filepath, bucket, key
+        filepath, bucket.name, key
     );
 
-    // Configure AWS region
-    // Tries to detect region from environment, config file, or defaults to us-east-1
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let started = Instant::now();
 
-    // Load AWS configuration from environment
-    // This includes credentials, region, and other AWS settings
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
+    // Upload the file to S3, re-reading it from disk on every attempt
+    // The body is streamed in chunks rather than loaded entirely into memory
+    retry(policy, "s3.put_object", || async {
+        let body = ByteStream::from_path(Path::new(filepath))
+            .await
+            .context(format!("Failed to read file: {}", filepath))?;
 
-    debug!("AWS configuration loaded, region: {:?}", config.region());
+        debug!("File stream created, uploading to S3...");
 
-    // Create S3 client
-    let client = Client::new(&config);
+        object
+            .put_object(client.put_object())
+            .bucket(&bucket.name)
+            .key(key)
+            .body(body)
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!(
+                "Failed to upload file to S3: bucket={}, key={}",
+                bucket.name, key
+            ))
+    })
+    .await?;
 
-    // Create a byte stream from the file
-    // This streams the file in chunks rather than loading it entirely into memory
-    let file_path = Path::new(filepath);
-    let body = ByteStream::from_path(file_path)
-        .await
-        .context(format!("Failed to read file: {}", filepath))?;
+    let url = bucket.object_url(key);
+
+    info!(
+        "File uploaded successfully to S3: {} ({} ms)",
+        url,
+        started.elapsed().as_millis()
+    );
+
+    Ok(url)
+}
+
+/// Upload a stream of chunks to Amazon S3
+///
+/// Streams the data received on `chunks` into an S3 multipart upload without
+/// writing it to disk. Chunks are accumulated into parts of
+/// [`MULTIPART_PART_SIZE`] bytes, so at most one part is held in memory in
+/// addition to the caller's bounded channel. Because each part is buffered,
+/// a part that fails transiently is retried on its own. If the producer or
+/// S3 fails for good, the multipart upload is aborted so no partial object is
+/// left behind.
+///
+/// # Arguments
+///
+/// * `client` - Shared S3 client (see [`create_s3_client`])
+/// * `policy` - Retry policy for transient failures of each request
+/// * `bucket` - The S3 bucket (must already exist)
+/// * `key` - S3 object key (path within the bucket)
+/// * `object` - Headers, metadata and tags of the object
+/// * `chunks` - Receiver of file chunks. The stream ends when all senders are
+///   dropped; an `Err` item aborts the upload.
+///
+/// # Returns
+///
+/// Returns the URL of the uploaded file on success (see
+/// [`Bucket::object_url`]).
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - The producer sends an error (e.g. the request body was malformed or too large)
+/// - Creating, uploading a part of, or completing the multipart upload fails
+///
+/// # Examples
+///
+/// ```no_run
+/// use bytes::Bytes;
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::infrastructure::s3::{
+///     create_s3_client, upload_stream_to_s3, Bucket, ObjectAttributes,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Config::default();
+/// let client = create_s3_client(&config.s3).await;
+/// let (tx, rx) = tokio::sync::mpsc::channel(8);
+/// tx.send(Ok(Bytes::from_static(b"hello"))).await?;
+/// drop(tx);
+///
+/// let object = ObjectAttributes::default();
+/// let url = upload_stream_to_s3(
+///     &client,
+///     &config.retry.s3,
+///     &Bucket::new(&config.s3),
+///     "uploads/hello.txt",
+///     &object,
+///     rx,
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy, object, chunks))]
+pub async fn upload_stream_to_s3(
+    client: &Client,
+    policy: &RetryPolicy,
+    bucket: &Bucket,
+    key: &str,
+    object: &ObjectAttributes,
+    mut chunks: mpsc::Receiver<io::Result<Bytes>>,
+) -> Result<String> {
+    debug!(
+        "Initiating streaming S3 upload: bucket={}, key={}",
+        bucket.name, key
+    );
+    let url = bucket.object_url(key);
+    let bucket = bucket.name.as_str();
+
+    let started = Instant::now();
+
+    let upload = retry(policy, "s3.create_multipart_upload", || async {
+        object
+            .create_multipart_upload(client.create_multipart_upload())
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!(
+                "Failed to create multipart upload: bucket={}, key={}",
+                bucket, key
+            ))
+    })
+    .await?;
+    let upload_id = upload
+        .upload_id()
+        .context("S3 did not return a multipart upload ID")?
+        .to_string();
+
+    let customer_key = CustomerKey::new(&object.storage);
+    let multipart = MultipartUpload {
+        bucket,
+        key,
+        upload_id: &upload_id,
+        customer_key: customer_key.as_ref(),
+    };
+    let parts = match upload_parts(client, policy, &multipart, &mut chunks).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            abort_multipart_upload(client, bucket, key, &upload_id).await;
+            return Err(e);
+        }
+    };
+
+    let completed = retry(policy, "s3.complete_multipart_upload", || async {
+        client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts.clone()))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!(
+                "Failed to complete multipart upload: bucket={}, key={}",
+                bucket, key
+            ))
+    })
+    .await;
+
+    if let Err(e) = completed {
+        abort_multipart_upload(client, bucket, key, &upload_id).await;
+        return Err(e);
+    }
+
+    info!(
+        "Stream uploaded successfully to S3: {} ({} ms)",
+        url,
+        started.elapsed().as_millis()
+    );
 
-    debug!("File stream created, uploading to S3...");
+    Ok(url)
+}
+
+/// A multipart upload in progress
+struct MultipartUpload<'a> {
+    /// Name of the S3 bucket
+    bucket: &'a str,
+    /// S3 object key
+    key: &'a str,
+    /// ID of the multipart upload
+    upload_id: &'a str,
+    /// Key of SSE-C encryption, sent with every part
+    customer_key: Option<&'a CustomerKey>,
+}
 
-    // Upload the file to S3
-    client
-        .put_object()
+/// Read chunks and upload them as multipart parts
+///
+/// # Returns
+///
+/// Returns the completed parts in order
+async fn upload_parts(
+    client: &Client,
+    policy: &RetryPolicy,
+    upload: &MultipartUpload<'_>,
+    chunks: &mut mpsc::Receiver<io::Result<Bytes>>,
+) -> Result<Vec<CompletedPart>> {
+    let mut parts = Vec::new();
+    let mut buffer = BytesMut::with_capacity(MULTIPART_PART_SIZE);
+
+    while let Some(chunk) = chunks.recv().await {
+        let chunk = chunk.context("Upload stream aborted")?;
+        buffer.extend_from_slice(&chunk);
+
+        while buffer.len() >= MULTIPART_PART_SIZE {
+            let body = buffer.split_to(MULTIPART_PART_SIZE).freeze();
+            let part_number = parts.len() + 1;
+            parts.push(upload_part(client, policy, upload, part_number, body).await?);
+        }
+    }
+
+    // The last part may be smaller than the minimum; an empty file still needs one part
+    if !buffer.is_empty() || parts.is_empty() {
+        let body = buffer.freeze();
+        let part_number = parts.len() + 1;
+        parts.push(upload_part(client, policy, upload, part_number, body).await?);
+    }
+
+    Ok(parts)
+}
+
+/// Upload a single multipart part, retrying transient failures
+async fn upload_part(
+    client: &Client,
+    policy: &RetryPolicy,
+    upload: &MultipartUpload<'_>,
+    part_number: usize,
+    body: Bytes,
+) -> Result<CompletedPart> {
+    debug!("Uploading part {} ({} bytes)", part_number, body.len());
+
+    let part_number = i32::try_from(part_number).context("Too many multipart parts")?;
+    let customer_key = upload.customer_key;
+    let output = retry(policy, "s3.upload_part", || async {
+        client
+            .upload_part()
+            .bucket(upload.bucket)
+            .key(upload.key)
+            .upload_id(upload.upload_id)
+            .part_number(part_number)
+            .set_sse_customer_algorithm(customer_key.map(|_| SSE_C_ALGORITHM.to_string()))
+            .set_sse_customer_key(customer_key.map(|key| key.key.clone()))
+            .set_sse_customer_key_md5(customer_key.map(|key| key.md5.clone()))
+            .body(ByteStream::from(body.clone()))
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!("Failed to upload part {}", part_number))
+    })
+    .await?;
+
+    Ok(CompletedPart::builder()
+        .set_e_tag(output.e_tag().map(str::to_string))
+        .part_number(part_number)
+        .build())
+}
+
+/// Abort a multipart upload, logging rather than returning failures
+///
+/// Uploads that cannot be aborted (e.g. because S3 is unreachable) are best
+/// cleaned up with an `AbortIncompleteMultipartUpload` bucket lifecycle rule.
+async fn abort_multipart_upload(client: &Client, bucket: &str, key: &str, upload_id: &str) {
+    match client
+        .abort_multipart_upload()
         .bucket(bucket)
         .key(key)
-        .body(body)
+        .upload_id(upload_id)
         .send()
         .await
-        .context(format!(
-            "Failed to upload file to S3: bucket={}, key={}",
-            bucket, key
-        ))?;
+    {
+        Ok(_) => debug!("Aborted multipart upload for key {}", key),
+        Err(e) => warn!("Failed to abort multipart upload for key {}: {}", key, e),
+    }
+}
+
+/// Download an object from Amazon S3 as a stream of chunks
+///
+/// Sends the object's contents to `chunks` as they arrive, so the object is
+/// never held in memory as a whole. If the download fails part way, the
+/// error is also sent down the channel so the consumer aborts.
+///
+/// # Arguments
+///
+/// * `client` - Shared S3 client (see [`create_s3_client`])
+/// * `policy` - Retry policy for transient failures of the request
+/// * `bucket` - Name of the S3 bucket
+/// * `key` - S3 object key to download
+/// * `customer_key` - Key the object is encrypted with, if it uses SSE-C
+/// * `chunks` - Sender receiving the object's chunks
+///
+/// # Errors
+///
+/// Returns an error if the object cannot be fetched or the consumer went away
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::infrastructure::s3::{create_s3_client, download_stream_from_s3};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Config::default();
+/// let client = create_s3_client(&config.s3).await;
+/// let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+/// tokio::spawn(async move {
+///     while let Some(chunk) = rx.recv().await {
+///         println!("{} bytes", chunk.unwrap().len());
+///     }
+/// });
+/// download_stream_from_s3(
+///     &client,
+///     &config.retry.s3,
+///     "my-bucket",
+///     "uploads/hello.txt",
+///     None,
+///     tx,
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy, customer_key, chunks))]
+pub async fn download_stream_from_s3(
+    client: &Client,
+    policy: &RetryPolicy,
+    bucket: &str,
+    key: &str,
+    customer_key: Option<&CustomerKey>,
+    chunks: mpsc::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    debug!("Downloading S3 object: bucket={}, key={}", bucket, key);
 
-    // Construct the public URL
-    // Note: This URL format works for standard buckets in most regions
-    // For buckets in some regions or with special configurations, the format may differ
-    let url = format!("https://{}.s3.amazonaws.com/{}", bucket, key);
+    let output = retry(policy, "s3.get_object", || async {
+        client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .set_sse_customer_algorithm(customer_key.map(|_| SSE_C_ALGORITHM.to_string()))
+            .set_sse_customer_key(customer_key.map(|key| key.key.clone()))
+            .set_sse_customer_key_md5(customer_key.map(|key| key.md5.clone()))
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!(
+                "Failed to download object from S3: bucket={}, key={}",
+                bucket, key
+            ))
+    })
+    .await;
 
-    info!("File uploaded successfully to S3: {}", url);
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = chunks.send(Err(io::Error::other(e.to_string()))).await;
+            return Err(e);
+        }
+    };
 
-    Ok(url)
+    let mut body = output.body;
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(bytes) => chunks
+                .send(Ok(bytes))
+                .await
+                .map_err(|_| anyhow::anyhow!("Download consumer went away"))?,
+            Err(e) => {
+                let _ = chunks.send(Err(io::Error::other(e.to_string()))).await;
+                return Err(e).context(format!("Failed to read S3 object body: key={}", key));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete an object from Amazon S3
+///
+/// Used to roll back an upload whose other backends failed. Deleting a key
+/// that does not exist succeeds.
+///
+/// # Arguments
+///
+/// * `client` - Shared S3 client (see [`create_s3_client`])
+/// * `policy` - Retry policy for transient failures
+/// * `bucket` - Name of the S3 bucket
+/// * `key` - S3 object key to delete
+///
+/// # Errors
+///
+/// Returns an error if the delete request fails
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::Config;
+/// use memenow_storage_service::infrastructure::s3::{create_s3_client, delete_object};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Config::default();
+/// let client = create_s3_client(&config.s3).await;
+/// delete_object(&client, &config.retry.s3, "my-bucket", "uploads/hello.txt").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[tracing::instrument(skip(client, policy))]
+pub async fn delete_object(
+    client: &Client,
+    policy: &RetryPolicy,
+    bucket: &str,
+    key: &str,
+) -> Result<()> {
+    debug!("Deleting S3 object: bucket={}, key={}", bucket, key);
+
+    retry(policy, "s3.delete_object", || async {
+        client
+            .delete_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(classify)
+            .context(format!(
+                "Failed to delete object from S3: bucket={}, key={}",
+                bucket, key
+            ))
+    })
+    .await?;
+
+    info!("Deleted S3 object: {}", key);
+
+    Ok(())
+}
+
+/// Convert an SDK error, marking it transient if it is worth retrying
+///
+/// Timeouts, connection failures, unparseable responses, 5xx responses and
+/// throttling (429 or a throttling error code) are transient; anything else,
+/// such as access denied or a missing bucket, is not.
+fn classify<E>(error: SdkError<E, HttpResponse>) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if is_transient_sdk_error(&error) {
+        retry::transient(error)
+    } else {
+        anyhow::Error::new(error)
+    }
+}
+
+/// Decide whether an SDK error is transient
+fn is_transient_sdk_error<E: ProvideErrorMetadata>(error: &SdkError<E, HttpResponse>) -> bool {
+    match error {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(context) => {
+            let status = context.raw().status().as_u16();
+            status >= 500
+                || status == 429
+                || matches!(
+                    context.err().code(),
+                    Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestTimeout")
+                )
+        }
+        _ => false,
+    }
 }
 
 /// Get the region-specific S3 URL for a bucket
 ///
 /// Different AWS regions use different URL formats. This function generates
-/// the correct URL based on the bucket and region.
+/// the correct URL based on the bucket and region. The key is used as given;
+/// [`Bucket::object_url`] percent-encodes it first.
 ///
 /// # Arguments
 ///
@@ -190,6 +989,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_object_url() {
+        let mut bucket = Bucket {
+            name: "media".to_string(),
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            path_style: false,
+            cdn_base_urls: Vec::new(),
+        };
+        assert_eq!(
+            bucket.object_url("a/b.jpg"),
+            "https://media.s3.amazonaws.com/a/b.jpg"
+        );
+        bucket.path_style = true;
+        assert_eq!(
+            bucket.object_url("a/b.jpg"),
+            "https://s3.amazonaws.com/media/a/b.jpg"
+        );
+
+        bucket.region = "eu-west-1".to_string();
+        assert_eq!(
+            bucket.object_url("a/b c.jpg"),
+            "https://s3.eu-west-1.amazonaws.com/media/a/b%20c.jpg"
+        );
+        bucket.path_style = false;
+        assert_eq!(
+            bucket.object_url("a/#1.jpg"),
+            "https://media.s3.eu-west-1.amazonaws.com/a/%231.jpg"
+        );
+
+        bucket.endpoint = Some("http://localhost:9000".to_string());
+        bucket.path_style = true;
+        assert_eq!(
+            bucket.object_url("a/b.jpg"),
+            "http://localhost:9000/media/a/b.jpg"
+        );
+        bucket.endpoint = Some("https://acct.r2.cloudflarestorage.com".to_string());
+        bucket.path_style = false;
+        assert_eq!(
+            bucket.object_url("a/b.jpg"),
+            "https://media.acct.r2.cloudflarestorage.com/a/b.jpg"
+        );
+    }
+
+    #[test]
+    fn test_cdn_urls() {
+        let mut config = crate::config::Config::default().s3;
+        config.cdn_base_urls = vec![
+            "https://d111111abcdef8.cloudfront.net".to_string(),
+            "https://media.example.com/files/".to_string(),
+        ];
+
+        let bucket = Bucket::new(&config);
+        assert_eq!(
+            bucket.cdn_urls("uploads/my photo.jpg"),
+            vec![
+                "https://d111111abcdef8.cloudfront.net/uploads/my%20photo.jpg",
+                "https://media.example.com/files/uploads/my%20photo.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tagging() {
+        let mut object = ObjectAttributes::default();
+        assert_eq!(object.tagging(), None);
+
+        object.tags.insert("team".to_string(), "media".to_string());
+        object
+            .tags
+            .insert("owner".to_string(), "a b/c@d".to_string());
+        assert_eq!(
+            object.tagging().as_deref(),
+            Some("owner=a%20b%2Fc%40d&team=media")
+        );
+    }
+
+    #[test]
+    fn test_customer_key() {
+        let mut storage = S3Storage {
+            encryption: Some(Encryption::SseC),
+            customer_key: Some("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+            ..S3Storage::default()
+        };
+        let key = CustomerKey::new(&storage).unwrap();
+        assert_eq!(key.key, "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=");
+        assert_eq!(key.md5, "hRasmdxgYDKV3nvbahU1MA==");
+        assert!(!format!("{:?}", key).contains(&key.key));
+
+        storage.encryption = Some(Encryption::SseKms);
+        assert_eq!(CustomerKey::new(&storage), None);
+    }
+
+    #[test]
+    fn test_object_requests_share_attributes() {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        let client = Client::from_conf(config);
+        let mut object = ObjectAttributes {
+            content_type: Some("image/png".to_string()),
+            storage: S3Storage {
+                storage_class: Some("STANDARD_IA".to_string()),
+                encryption: Some(Encryption::SseC),
+                customer_key: Some("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+                ..S3Storage::default()
+            },
+            ..ObjectAttributes::default()
+        };
+        object.tags.insert("team".to_string(), "media".to_string());
+
+        let put = object.put_object(client.put_object());
+        let multipart = object.create_multipart_upload(client.create_multipart_upload());
+        assert_eq!(put.get_content_type(), multipart.get_content_type());
+        assert_eq!(put.get_tagging(), multipart.get_tagging());
+        assert_eq!(put.get_storage_class(), multipart.get_storage_class());
+        assert_eq!(put.get_server_side_encryption(), &None);
+        assert_eq!(put.get_sse_customer_key(), multipart.get_sse_customer_key());
+        assert_eq!(
+            multipart.get_sse_customer_key_md5().as_deref(),
+            Some("hRasmdxgYDKV3nvbahU1MA==")
+        );
+    }
+
+    /// Compare upload latency with a client created per upload against the
+    /// shared client, using a local S3-compatible endpoint that accepts every
+    /// `PutObject`. Run with
+    /// `cargo test --release shared_client -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_shared_client() {
+        use warp::Filter;
+
+        const UPLOADS: u32 = 200;
+
+        let routes = warp::put().map(|| warp::reply::with_header("", "etag", "\"0\""));
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let config = S3Config {
+            bucket: "bench".to_string(),
+            endpoint_url: Some(format!("http://{}", addr)),
+            force_path_style: true,
+            access_key_id: Some("key".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            ..S3Config::default()
+        };
+        let bucket = Bucket::new(&config);
+        let policy = RetryPolicy::default();
+        let object = ObjectAttributes::default();
+        let path = std::env::temp_dir().join(format!("bench-{}.bin", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, vec![0u8; 64 * 1024]).await.unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let started = Instant::now();
+        for i in 0..UPLOADS {
+            let client = create_s3_client(&config).await;
+            let key = format!("fresh/{}", i);
+            upload_to_s3(&client, &policy, &path, &bucket, &key, &object)
+                .await
+                .unwrap();
+        }
+        let fresh = started.elapsed() / UPLOADS;
+
+        let client = create_s3_client(&config).await;
+        let started = Instant::now();
+        for i in 0..UPLOADS {
+            let key = format!("shared/{}", i);
+            upload_to_s3(&client, &policy, &path, &bucket, &key, &object)
+                .await
+                .unwrap();
+        }
+        let shared = started.elapsed() / UPLOADS;
+
+        println!(
+            "{} uploads of 64 KiB: fresh client {:?}/upload, shared client {:?}/upload",
+            UPLOADS, fresh, shared
+        );
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(shared < fresh);
+    }
+
     #[test]
     fn test_get_s3_url_special_characters() {
         let url = get_s3_url("test-bucket", "path/to/file with spaces.jpg", "us-west-2");
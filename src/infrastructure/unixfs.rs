@@ -0,0 +1,523 @@
+//! Local computation of UnixFS CIDs
+//!
+//! The CID the IPFS node returns for an add is otherwise taken on trust.
+//! [`UnixfsHasher`] rebuilds the DAG the node's importer produces for a
+//! file, without storing any block, so the CID can be known before the node
+//! is contacted and checked against what it returns:
+//!
+//! - Data is split into fixed-size chunks (`size-{bytes}`, by default 256
+//!   KiB)
+//! - Chunks become raw blocks or UnixFS file nodes (`raw-leaves`, by
+//!   default only with CIDv1)
+//! - Leaves are linked in a balanced tree of at most 174 links per node
+//! - Blocks are hashed with `sha2-256`
+//! - With `wrap-with-directory` the file is linked from a directory
+//!
+//! Content-defined chunkers (`rabin`, `buzhash`) and other hash functions
+//! are not supported. Options left unset are assumed to be the node's
+//! defaults, so a node configured with different import defaults yields
+//! different CIDs.
+
+use crate::config::IpfsAddOptions;
+use cid::multihash::Multihash;
+use cid::{Cid, Version};
+use sha2::{Digest, Sha256};
+
+/// Chunk size of the node's default `size-262144` chunker
+const DEFAULT_CHUNK_SIZE: usize = 262_144;
+
+/// Maximum number of links of a file node
+const MAX_LINKS: usize = 174;
+
+/// Multicodec of raw blocks
+const RAW: u64 = 0x55;
+
+/// Multicodec of dag-pb blocks
+const DAG_PB: u64 = 0x70;
+
+/// Multihash code of sha2-256
+const SHA2_256: u64 = 0x12;
+
+/// Types of UnixFS nodes
+const DIRECTORY: u64 = 1;
+const FILE: u64 = 2;
+
+/// Computes the CID of a file as the IPFS node would add it
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::config::IpfsAddOptions;
+/// use memenow_storage_service::infrastructure::unixfs::UnixfsHasher;
+///
+/// let mut hasher = UnixfsHasher::new(&IpfsAddOptions::default(), "hello.txt").unwrap();
+/// hasher.update(b"hello world\n");
+/// let cid = hasher.finish();
+/// assert_eq!(cid.to_string(), "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+/// ```
+#[derive(Debug)]
+pub struct UnixfsHasher {
+    chunk_size: usize,
+    version: Version,
+    raw_leaves: bool,
+    /// Name of the entry in the wrapping directory, if the file is wrapped
+    wrap: Option<String>,
+    /// Data not yet making up a whole chunk
+    buffer: Vec<u8>,
+    leaves: Vec<Link>,
+}
+
+/// Link to a node of the DAG
+#[derive(Debug, Clone)]
+struct Link {
+    cid: Cid,
+    /// Size of the node and every node below it in bytes
+    tsize: u64,
+    /// Size of the file data below the node in bytes
+    filesize: u64,
+}
+
+impl UnixfsHasher {
+    /// Create a hasher for a file added with the given options
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options of the add, merged with the configured ones
+    /// * `filename` - Name of the file, used when it is wrapped in a
+    ///   directory
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the options ask for a chunker or hash function the
+    /// hasher does not implement, or the file is wrapped under a name the
+    /// node may rewrite
+    pub fn new(options: &IpfsAddOptions, filename: &str) -> Option<Self> {
+        if options
+            .hash
+            .as_deref()
+            .is_some_and(|hash| hash != "sha2-256")
+        {
+            return None;
+        }
+
+        let chunk_size = match options.chunker.as_deref() {
+            None => DEFAULT_CHUNK_SIZE,
+            Some(chunker) => chunker.strip_prefix("size-")?.parse().ok()?,
+        };
+        if chunk_size == 0 {
+            return None;
+        }
+
+        let version = match options.cid_version {
+            Some(1) => Version::V1,
+            _ => Version::V0,
+        };
+
+        let wrap = if options.wraps() {
+            let plain = filename
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+            if !plain || matches!(filename, "" | "." | "..") {
+                return None;
+            }
+            Some(filename.to_string())
+        } else {
+            None
+        };
+
+        Some(Self {
+            chunk_size,
+            version,
+            raw_leaves: options.raw_leaves.unwrap_or(version == Version::V1),
+            wrap,
+            buffer: Vec::new(),
+            leaves: Vec::new(),
+        })
+    }
+
+    /// Feed the next bytes of the file
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (self.chunk_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.buffer.len() == self.chunk_size {
+                let chunk = std::mem::take(&mut self.buffer);
+                let leaf = self.leaf(&chunk);
+                self.leaves.push(leaf);
+            }
+        }
+    }
+
+    /// Compute the CID of the file fed so far
+    ///
+    /// # Returns
+    ///
+    /// Returns the CID of the file, or of the wrapping directory
+    pub fn finish(mut self) -> Cid {
+        // An empty file is a single empty leaf
+        if !self.buffer.is_empty() || self.leaves.is_empty() {
+            let chunk = std::mem::take(&mut self.buffer);
+            let leaf = self.leaf(&chunk);
+            self.leaves.push(leaf);
+        }
+
+        let mut level = std::mem::take(&mut self.leaves);
+        while level.len() > 1 {
+            level = level
+                .chunks(MAX_LINKS)
+                .map(|children| self.file_node(children))
+                .collect();
+        }
+        let root = level.remove(0);
+
+        match &self.wrap {
+            Some(name) => self.directory(name, &root).cid,
+            None => root.cid,
+        }
+    }
+
+    /// Build the leaf holding a chunk
+    fn leaf(&self, chunk: &[u8]) -> Link {
+        let size = chunk.len() as u64;
+        if self.raw_leaves {
+            return Link {
+                // Raw blocks cannot be addressed by a CIDv0
+                cid: Cid::new_v1(RAW, sha2_256(chunk)),
+                tsize: size,
+                filesize: size,
+            };
+        }
+
+        let mut data = Vec::new();
+        put_uint(&mut data, 1, FILE);
+        if !chunk.is_empty() {
+            put_bytes(&mut data, 2, chunk);
+        }
+        put_uint(&mut data, 3, size);
+
+        let mut block = Vec::new();
+        put_bytes(&mut block, 1, &data);
+
+        Link {
+            cid: self.dag_pb(&block),
+            tsize: block.len() as u64,
+            filesize: size,
+        }
+    }
+
+    /// Build the file node linking a run of nodes
+    fn file_node(&self, children: &[Link]) -> Link {
+        let filesize = children.iter().map(|child| child.filesize).sum();
+
+        let mut data = Vec::new();
+        put_uint(&mut data, 1, FILE);
+        put_uint(&mut data, 3, filesize);
+        for child in children {
+            put_uint(&mut data, 4, child.filesize);
+        }
+
+        let mut link = self.node(children.iter().map(|child| ("", child)), &data);
+        link.filesize = filesize;
+        link
+    }
+
+    /// Build the directory holding a single file
+    fn directory(&self, name: &str, file: &Link) -> Link {
+        let mut data = Vec::new();
+        put_uint(&mut data, 1, DIRECTORY);
+
+        self.node([(name, file)], &data)
+    }
+
+    /// Encode a dag-pb node; links come before the data
+    fn node<'a>(&self, links: impl IntoIterator<Item = (&'a str, &'a Link)>, data: &[u8]) -> Link {
+        let mut block = Vec::new();
+        let mut tsize = 0;
+        for (name, child) in links {
+            let mut link = Vec::new();
+            put_bytes(&mut link, 1, &child.cid.to_bytes());
+            put_bytes(&mut link, 2, name.as_bytes());
+            put_uint(&mut link, 3, child.tsize);
+            put_bytes(&mut block, 2, &link);
+            tsize += child.tsize;
+        }
+        put_bytes(&mut block, 1, data);
+
+        Link {
+            cid: self.dag_pb(&block),
+            tsize: tsize + block.len() as u64,
+            filesize: 0,
+        }
+    }
+
+    /// Address a dag-pb block
+    fn dag_pb(&self, block: &[u8]) -> Cid {
+        let hash = sha2_256(block);
+        match self.version {
+            Version::V0 => Cid::new_v0(hash).expect("sha2-256 is a valid CIDv0 hash"),
+            Version::V1 => Cid::new_v1(DAG_PB, hash),
+        }
+    }
+}
+
+/// Check whether two CIDs address the same content
+///
+/// CIDs are compared by codec and hash, so their multibase, and a CIDv0
+/// given for the equivalent CIDv1, do not matter.
+///
+/// # Arguments
+///
+/// * `computed` - CID computed by a [`UnixfsHasher`]
+/// * `returned` - CID returned by the node
+///
+/// # Returns
+///
+/// Returns `false` if either is not a valid CID
+pub fn matches(computed: &str, returned: &str) -> bool {
+    match (Cid::try_from(computed), Cid::try_from(returned)) {
+        (Ok(a), Ok(b)) => a.codec() == b.codec() && a.hash() == b.hash(),
+        _ => false,
+    }
+}
+
+fn sha2_256(data: &[u8]) -> Multihash<64> {
+    Multihash::wrap(SHA2_256, &Sha256::digest(data)).expect("a sha2-256 digest fits a multihash")
+}
+
+/// Append a varint field of a protobuf message
+fn put_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3);
+    put_varint(buf, value);
+}
+
+/// Append a length-delimited field of a protobuf message
+fn put_bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(buf, (field << 3) | 2);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CidBase;
+    use crate::infrastructure::ipfs::encode_cid;
+    use crate::infrastructure::ipfs_rpc::add_query;
+
+    fn cid(options: &IpfsAddOptions, data: &[u8]) -> String {
+        let mut hasher = UnixfsHasher::new(options, "hello.txt").unwrap();
+        hasher.update(data);
+        hasher.finish().to_string()
+    }
+
+    #[test]
+    fn test_matches_the_node() {
+        let v0 = IpfsAddOptions::default();
+        assert_eq!(
+            cid(&v0, b"hello world\n"),
+            "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"
+        );
+        assert_eq!(
+            cid(&v0, b""),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+
+        let v1 = IpfsAddOptions {
+            cid_version: Some(1),
+            ..IpfsAddOptions::default()
+        };
+        assert_eq!(
+            cid(&v1, b"hello world\n"),
+            "bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4"
+        );
+    }
+
+    /// Multi-chunk files, as these commands name them:
+    ///
+    /// ```text
+    /// head -c 1048576 /dev/zero | ipfs add --only-hash -Q [--cid-version=1]
+    /// seq 1 100000 | ipfs add --only-hash -Q --chunker=size-1024 [--cid-version=1]
+    /// ```
+    ///
+    /// The CIDs were computed with a separate port of the node's balanced
+    /// layout, not by kubo, so they only pin the layout down; the
+    /// comparison with kubo itself is [`test_matches_kubo`].
+    #[test]
+    fn test_matches_the_node_across_chunks() {
+        let v0 = IpfsAddOptions::default();
+        let v1 = IpfsAddOptions {
+            cid_version: Some(1),
+            ..IpfsAddOptions::default()
+        };
+
+        // Four 256 KiB leaves under one file node
+        let zeros = vec![0u8; 1 << 20];
+        assert_eq!(
+            cid(&v0, &zeros),
+            "QmVkbauSDEaMP4Tkq6Epm9uW75mWm136n81YH8fGtfwdHU"
+        );
+        assert_eq!(
+            cid(&v1, &zeros),
+            "bafybeiggzq4ryi7hscq5hzvzcnk4urnxt3asp37dhgvnjilf7exskximla"
+        );
+
+        // 576 leaves, more than one node can link, so the tree has two levels
+        let seq: Vec<u8> = (1..=100_000)
+            .flat_map(|i| format!("{}\n", i).into_bytes())
+            .collect();
+        let small = |options: &IpfsAddOptions| IpfsAddOptions {
+            chunker: Some("size-1024".to_string()),
+            ..options.clone()
+        };
+        assert_eq!(
+            cid(&small(&v0), &seq),
+            "QmNhL56e5NXjF45nJGNGttqvfYJESkCoEu91HEBprrUbag"
+        );
+        assert_eq!(
+            cid(&small(&v1), &seq),
+            "bafybeibnwqlypla5ubifbbg2xmo4lnx3577ohq3p36kve2ofbk7mxr7w7y"
+        );
+    }
+
+    /// Compare with `ipfs add --only-hash -Q` of the `ipfs` binary on the
+    /// `PATH`, which needs an initialized repository (`ipfs init`)
+    ///
+    /// ```text
+    /// cargo test test_matches_kubo -- --ignored
+    /// ```
+    #[test]
+    #[ignore = "needs the ipfs binary"]
+    fn test_matches_kubo() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let zeros = vec![0u8; 1 << 20];
+        let seq: Vec<u8> = (1..=100_000)
+            .flat_map(|i| format!("{}\n", i).into_bytes())
+            .collect();
+        let small = Some("size-1024".to_string());
+        let variants = [
+            IpfsAddOptions::default(),
+            IpfsAddOptions {
+                cid_version: Some(1),
+                ..IpfsAddOptions::default()
+            },
+            IpfsAddOptions {
+                raw_leaves: Some(true),
+                ..IpfsAddOptions::default()
+            },
+            IpfsAddOptions {
+                chunker: small.clone(),
+                ..IpfsAddOptions::default()
+            },
+            IpfsAddOptions {
+                cid_version: Some(1),
+                chunker: small,
+                ..IpfsAddOptions::default()
+            },
+        ];
+
+        for data in [&zeros, &seq] {
+            for options in &variants {
+                let args = add_query(options, false)
+                    .into_iter()
+                    .filter(|(name, _)| *name != "progress")
+                    .map(|(name, value)| format!("--{}={}", name, value));
+                let mut ipfs = Command::new("ipfs")
+                    .args(["add", "--only-hash", "-Q"])
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .expect("the ipfs binary is on the PATH");
+                ipfs.stdin.take().unwrap().write_all(data).unwrap();
+                let output = ipfs.wait_with_output().unwrap();
+                assert!(output.status.success(), "{:?}", options);
+
+                let kubo = String::from_utf8(output.stdout).unwrap();
+                assert_eq!(cid(options, data), kubo.trim(), "{:?}", options);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunking_is_independent_of_writes() {
+        let options = IpfsAddOptions {
+            chunker: Some("size-4".to_string()),
+            ..IpfsAddOptions::default()
+        };
+        // Enough chunks for two levels of file nodes
+        let data: Vec<u8> = (0..4 * (MAX_LINKS + 3)).map(|i| i as u8).collect();
+
+        let mut hasher = UnixfsHasher::new(&options, "data.bin").unwrap();
+        for write in data.chunks(7) {
+            hasher.update(write);
+        }
+        let computed = hasher.finish();
+
+        assert_eq!(computed.to_string(), cid(&options, &data));
+        assert_ne!(computed.to_string(), cid(&options, &data[1..]));
+        assert_eq!(computed.codec(), DAG_PB);
+    }
+
+    #[test]
+    fn test_wraps_in_a_directory() {
+        let options = IpfsAddOptions {
+            wrap_with_directory: Some(true),
+            ..IpfsAddOptions::default()
+        };
+        let wrapped = cid(&options, b"hello world\n");
+        assert_ne!(wrapped, cid(&IpfsAddOptions::default(), b"hello world\n"));
+        assert!(wrapped.starts_with("Qm"));
+
+        assert!(UnixfsHasher::new(&options, "photos/cat.jpg").is_none());
+    }
+
+    #[test]
+    fn test_unsupported_options() {
+        for options in [
+            IpfsAddOptions {
+                chunker: Some("rabin".to_string()),
+                ..IpfsAddOptions::default()
+            },
+            IpfsAddOptions {
+                hash: Some("blake2b-256".to_string()),
+                ..IpfsAddOptions::default()
+            },
+        ] {
+            assert!(UnixfsHasher::new(&options, "hello.txt").is_none());
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut hasher = UnixfsHasher::new(&IpfsAddOptions::default(), "empty").unwrap();
+        hasher.update(b"");
+        let computed = hasher.finish().to_string();
+
+        assert!(matches(
+            &computed,
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        ));
+        let v1 = encode_cid(
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH",
+            CidBase::Base36,
+        );
+        assert!(matches(&computed, &v1.unwrap()));
+        assert!(!matches(
+            &computed,
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        ));
+        assert!(!matches(&computed, "not a cid"));
+    }
+}
@@ -0,0 +1,238 @@
+//! Webhook delivery
+//!
+//! Events are POSTed to webhook endpoints as JSON. Each request is signed so
+//! the receiver can check it came from this service and was not replayed:
+//!
+//! - `X-Webhook-Id`: ID of the event, stable across redeliveries
+//! - `X-Webhook-Event`: Event type, e.g. `upload.completed`
+//! - `X-Webhook-Timestamp`: Unix time the request was sent, in seconds
+//! - `X-Webhook-Signature`: `sha256=` followed by the hex HMAC-SHA256 of
+//!   `{timestamp}.{body}`, keyed with `WEBHOOK_SECRET`
+//!
+//! Receivers should recompute the signature over the raw body, compare it in
+//! constant time and reject stale timestamps.
+//!
+//! A delivery is attempted once per call; redelivery is scheduled by the
+//! outbox. Connection failures, timeouts, `408`, `429` and `5xx` responses
+//! are marked as transient (see [`retry::transient`]), other error responses
+//! are permanent.
+
+use crate::config::WebhookEvent;
+use crate::infrastructure::retry;
+use anyhow::{Context, Result};
+use hmac::{Hmac, KeyInit, Mac};
+use log::debug;
+use reqwest::{Client, StatusCode};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Header carrying the event ID
+pub const ID_HEADER: &str = "x-webhook-id";
+
+/// Header carrying the event type
+pub const EVENT_HEADER: &str = "x-webhook-event";
+
+/// Header carrying the time the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+
+/// Header carrying the payload signature
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Error returned when an endpoint rejects a delivery
+#[derive(Debug, Error)]
+#[error("Webhook endpoint responded with {status}")]
+pub struct EndpointError {
+    /// The response status
+    pub status: StatusCode,
+}
+
+/// Create the HTTP client used for webhook deliveries
+///
+/// # Arguments
+///
+/// * `timeout` - Timeout of a single delivery
+///
+/// # Errors
+///
+/// Returns an error if the TLS backend cannot be initialized
+pub fn create_webhook_client(timeout: Duration) -> Result<Client> {
+    Client::builder()
+        .timeout(timeout)
+        .build()
+        .context("Failed to create webhook HTTP client")
+}
+
+/// Sign a payload
+///
+/// # Arguments
+///
+/// * `secret` - Shared webhook secret
+/// * `timestamp` - Unix time sent in the timestamp header
+/// * `body` - Raw request body
+///
+/// # Returns
+///
+/// Returns the value of the signature header, `sha256=<hex digest>`
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC key");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Deliver an event to a webhook endpoint
+///
+/// # Arguments
+///
+/// * `client` - Webhook HTTP client (see [`create_webhook_client`])
+/// * `url` - Endpoint URL
+/// * `secret` - Shared webhook secret
+/// * `event_id` - ID of the event
+/// * `event` - Event type
+/// * `body` - Serialized event
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the endpoint does not respond
+/// with a success status. Errors worth redelivering are marked transient.
+pub async fn deliver(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    event_id: &str,
+    event: WebhookEvent,
+    body: &str,
+) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(ID_HEADER, event_id)
+        .header(EVENT_HEADER, event.to_string())
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, sign(secret, timestamp, body))
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_builder() {
+                anyhow::Error::new(e)
+            } else {
+                retry::transient(e)
+            }
+        })
+        .with_context(|| format!("Failed to deliver {} to {}", event, url))?;
+
+    let status = response.status();
+    if status.is_success() {
+        debug!("Delivered {} {} to {}", event, event_id, url);
+        return Ok(());
+    }
+
+    let error = EndpointError { status };
+    let error = if status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+    {
+        retry::transient(error)
+    } else {
+        anyhow::Error::new(error)
+    };
+
+    Err(error.context(format!("Failed to deliver {} to {}", event, url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use warp::http::HeaderMap;
+    use warp::Filter;
+
+    /// Serve an endpoint that verifies signatures and answers `/{status}`
+    /// with that status
+    fn spawn_endpoint() -> SocketAddr {
+        let routes = warp::path::param::<u16>()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(|status: u16, headers: HeaderMap, body: bytes::Bytes| {
+                let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+                let timestamp: u64 = header(TIMESTAMP_HEADER).parse().unwrap();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+
+                let status = if header(SIGNATURE_HEADER) == sign("secret", timestamp, &body) {
+                    warp::http::StatusCode::from_u16(status).unwrap()
+                } else {
+                    warp::http::StatusCode::UNAUTHORIZED
+                };
+                warp::reply::with_status(header(EVENT_HEADER), status)
+            });
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("secret", 1_700_000_000, r#"{"ok":true}"#),
+            "sha256=c1afc7c2df3db0690d7d75954610ed1a1d959ce96355ccb8c0a8bc09fd0cfc27"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let addr = spawn_endpoint();
+        let client = create_webhook_client(Duration::from_secs(5)).unwrap();
+        let event = WebhookEvent::UploadCompleted;
+        let body = r#"{"ok":true}"#;
+
+        let url = format!("http://{}/200", addr);
+        deliver(&client, &url, "secret", "evt-1", event, body)
+            .await
+            .unwrap();
+
+        // A wrong secret is rejected by the endpoint, permanently
+        let error = deliver(&client, &url, "wrong", "evt-1", event, body)
+            .await
+            .unwrap_err();
+        assert!(!retry::is_transient(&error));
+        assert_eq!(
+            error.downcast_ref::<EndpointError>().unwrap().status,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let url = format!("http://{}/503", addr);
+        let error = deliver(&client, &url, "secret", "evt-1", event, body)
+            .await
+            .unwrap_err();
+        assert!(retry::is_transient(&error));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_endpoint_is_transient() {
+        let client = create_webhook_client(Duration::from_secs(5)).unwrap();
+        // Nothing listens on the discard port
+        let error = deliver(
+            &client,
+            "http://127.0.0.1:9/hook",
+            "secret",
+            "evt-1",
+            WebhookEvent::FileDeleted,
+            "{}",
+        )
+        .await
+        .unwrap_err();
+
+        assert!(retry::is_transient(&error));
+    }
+}
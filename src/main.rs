@@ -9,22 +9,42 @@
 //!
 //! - Concurrent uploads to Amazon S3 and IPFS
 //! - Asynchronous request processing using Tokio
-//! - Comprehensive error handling and logging
-//! - Environment-based configuration
+//! - Comprehensive error handling and structured logging with per-request IDs
+//! - Configuration from a TOML, YAML or JSON file and environment variables
 //! - File size validation and limits
+//! - Graceful shutdown on SIGTERM/SIGINT that drains in-flight uploads
+//!
+//! # Configuration
+//!
+//! Settings can be given in a config file, named by `--config <path>` or
+//! `CONFIG_FILE`, and are overridden by environment variables. Any variable
+//! can instead be read from the file named by `{NAME}_FILE`, e.g.
+//! `AWS_SECRET_ACCESS_KEY_FILE=/run/secrets/aws_secret_access_key`.
 //!
 //! # Environment Variables
 //!
-//! The following environment variables are required:
+//! The following environment variables are required, unless set in the
+//! config file:
 //!
 //! - `S3_BUCKET`: Amazon S3 bucket name
-//! - `AWS_ACCESS_KEY_ID`: AWS access key
+//! - `AWS_ACCESS_KEY_ID`: AWS access key (optional, defaults to the AWS SDK's credential chain)
 //! - `AWS_SECRET_ACCESS_KEY`: AWS secret key
 //! - `AWS_REGION`: AWS region (optional, defaults to us-east-1)
 //! - `S3_KEY`: S3 key prefix (optional, defaults to "uploads")
+//! - `S3_ENDPOINT_URL`: Endpoint of an S3-compatible store such as MinIO (optional)
+//! - `S3_FORCE_PATH_STYLE`: Address buckets by path, as MinIO expects (optional, defaults to
+//!   false)
 //! - `SERVER_HOST`: Server host (optional, defaults to "0.0.0.0")
 //! - `SERVER_PORT`: Server port (optional, defaults to 8080)
 //! - `MAX_FILE_SIZE`: Maximum file size in bytes (optional, defaults to 5MB)
+//! - `SHUTDOWN_TIMEOUT_SECS`: Seconds to wait for in-flight uploads on shutdown (optional,
+//!   defaults to 30)
+//! - `LOG_FORMAT`: Log line format, `text` or `json` (optional, defaults to text)
+//! - `OTEL_TRACES_EXPORTER`: Span exporter, `none`, `otlp` or `stdout` (optional, defaults to
+//!   none)
+//! - `OTEL_EXPORTER_OTLP_ENDPOINT`: OTLP/HTTP collector URL (optional, defaults to
+//!   `http://localhost:4318`)
+//! - `OTEL_SERVICE_NAME`: Service name reported with spans (optional)
 //!
 //! # Examples
 //!
@@ -32,6 +52,7 @@
 //!
 //! ```bash
 //! cargo run
+//! cargo run -- --config config.toml
 //! ```
 //!
 //! To upload a file:
@@ -40,47 +61,71 @@
 //! curl -X POST -F "file=@example.jpg" http://localhost:8080/upload
 //! ```
 
+// Background jobs nest the AWS SDK's deeply nested futures beneath several
+// layers of our own, exceeding the default limit when checking they are `Send`
+#![recursion_limit = "256"]
+
 mod api;
 mod config;
 mod domain;
 mod error;
 mod infrastructure;
+mod state;
+mod telemetry;
 mod utils;
 
-use config::Config;
-use log::{error, info};
+use config::{config_path_from_args, Config, TelemetryConfig};
+use log::{error, info, warn};
+use state::AppState;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use utils::shutdown::shutdown_signal;
+use warp::Filter;
+
+/// How long cancelled uploads get to abort their multipart uploads and
+/// remove their temporary files once the shutdown deadline has passed
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Main entry point for the MemeNow Storage Service
 ///
 /// This function initializes the application by:
-/// 1. Setting up the logging system
+/// 1. Setting up logging and distributed tracing
 /// 2. Loading and validating configuration
-/// 3. Setting up API routes
-/// 4. Starting the HTTP server
+/// 3. Building the shared application state (storage clients)
+/// 4. Setting up API routes
+/// 5. Starting the HTTP server
+/// 6. On SIGTERM/SIGINT, stopping new connections, waiting up to the
+///    configured deadline for in-flight uploads and the background workers,
+///    cancelling the uploads still in flight, and removing leftover
+///    temporary files
 ///
 /// # Panics
 ///
 /// The function will panic if:
 /// - Required environment variables are missing
 /// - Configuration validation fails
-/// - The server fails to bind to the specified address
 ///
 /// # Errors
 ///
-/// Returns an error if configuration loading or validation fails.
+/// Returns an error if configuration loading or validation fails, or if the
+/// server fails to bind to the specified address.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize the logger with default settings
+    // The config file is given with --config, or CONFIG_FILE
+    let config_path = config_path_from_args(std::env::args().skip(1))?;
+
+    // Initialize logging and span export before loading the rest of the
+    // configuration so that configuration errors are logged
     // Log level can be controlled via RUST_LOG environment variable
-    // Example: RUST_LOG=debug cargo run
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    // Example: RUST_LOG=debug LOG_FORMAT=json cargo run
+    let telemetry_providers = telemetry::init(&TelemetryConfig::load(config_path.as_deref())?)?;
 
     info!("Starting MemeNow Storage Service...");
 
-    // Load configuration from environment variables
-    let config = Config::from_env().map_err(|e| {
+    // Load configuration from the config file and environment variables
+    let config = Config::load(config_path.as_deref()).map_err(|e| {
         error!("Failed to load configuration: {}", e);
         e
     })?;
@@ -96,21 +141,117 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("S3 Region: {}", config.s3.region);
     info!("Max file size: {} bytes", config.upload.max_file_size);
 
-    // Set up API routes with the configuration
-    let routes = api::upload::upload_routes(config.clone());
-
-    // Parse the host address
+    // Parse the host address before the configuration moves into the state
     let host = IpAddr::from_str(&config.server.host).unwrap_or(IpAddr::V4(Ipv4Addr::new(
         0, 0, 0, 0,
     )));
     let addr = SocketAddr::new(host, config.server.port);
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+
+    // Build the storage clients once and share them across requests
+    let state = AppState::new(config).await.map_err(|e| {
+        error!("Failed to initialize the service: {:#}", e);
+        e
+    })?;
+    let tracker = state.tracker.clone();
+    let replication = state.replication.clone();
+    let shutdown = state.shutdown.clone();
+
+    // Set up API routes with the application state
+    let routes = api::upload::upload_routes(state.clone())
+        .or(api::jobs::job_routes(state.clone()))
+        .or(api::cluster::cluster_routes(state.clone()))
+        .or(api::ipns::ipns_routes(state.clone()))
+        .or(api::car::car_routes(state.clone()))
+        .or(api::health::health_routes(state));
+
+    // Stop accepting new connections once a shutdown signal arrives
+    let (signalled_tx, signalled_rx) = oneshot::channel();
+    let (addr, server) = warp::serve(routes).try_bind_with_graceful_shutdown(addr, async move {
+        shutdown_signal().await;
+        let _ = signalled_tx.send(());
+    })?;
 
     info!("Server starting on http://{}", addr);
     info!("Upload endpoint: http://{}/upload", addr);
+    info!("Job status endpoint: http://{}/jobs/{{id}}", addr);
+    info!(
+        "Cluster pin status endpoint: http://{}/cluster/pins/{{cid}}",
+        addr
+    );
+    info!(
+        "CAR endpoints: http://{}/car, http://{}/files/{{cid}}.car",
+        addr, addr
+    );
+    info!(
+        "IPNS endpoints: http://{}/ipns/keys, http://{}/ipns/resolve/{{name}}",
+        addr, addr
+    );
+    info!(
+        "Health endpoints: http://{}/health/live, http://{}/health/ready",
+        addr, addr
+    );
     info!("Ready to accept requests");
 
-    // Start the server
-    warp::serve(routes).run(addr).await;
+    // Start the server and wait for it to drain, bounded by the shutdown deadline
+    let deadline = async {
+        if signalled_rx.await.is_ok() {
+            info!(
+                "Shutting down, waiting up to {}s for {} in-flight upload(s)",
+                shutdown_timeout.as_secs(),
+                tracker.in_flight()
+            );
+            tokio::time::sleep(shutdown_timeout).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    // Let the background workers finish their current work within the same deadline
+    let drained = async {
+        server.await;
+        info!("All connections closed");
+        shutdown.shutdown().await;
+    };
+
+    tokio::select! {
+        _ = drained => {}
+        _ = deadline => {
+            warn!(
+                "Shutdown deadline exceeded, cancelling {} in-flight upload(s)",
+                tracker.in_flight()
+            );
+            // Let the cancelled uploads abort their multipart uploads and
+            // roll back before their temporary files are removed
+            shutdown.stop();
+            tracker.cancel();
+            if tokio::time::timeout(CANCEL_TIMEOUT, tracker.drained())
+                .await
+                .is_err()
+            {
+                warn!(
+                    "Abandoning {} upload(s) that did not stop in time",
+                    tracker.in_flight()
+                );
+            }
+        }
+    }
+    if !replication.is_empty() {
+        info!(
+            "{} replication(s) still queued, resuming them on the next start",
+            replication.len()
+        );
+    }
+
+    let removed = tracker.cleanup().await;
+    if removed > 0 {
+        info!(
+            "Removed {} temporary file(s) left by interrupted uploads",
+            removed
+        );
+    }
+
+    telemetry::shutdown(telemetry_providers);
 
     Ok(())
 }
@@ -0,0 +1,130 @@
+//! Shared application state
+//!
+//! This module defines the state built once at startup and shared by every
+//! request handler: the configuration, long-lived storage clients and their
+//! circuit breakers, the in-flight upload tracker, the replication queue,
+//! the background jobs, the webhook outbox, the remote pinning queue, the
+//! metadata store and the handle stopping the background workers.
+//! Building the clients once keeps their connection pools and credential
+//! caches alive across requests instead of recreating them for every upload.
+
+use crate::config::Config;
+use crate::domain::jobs::{self, JobQueue};
+use crate::domain::metadata::MetadataStore;
+use crate::domain::mfs;
+use crate::domain::pinning::{self, RemotePins};
+use crate::domain::replication::{self, ReplicationQueue};
+use crate::domain::tracker::UploadTracker;
+use crate::domain::webhooks::{self, Webhooks};
+use crate::infrastructure::circuit_breaker::Breakers;
+use crate::infrastructure::ipfs::{self, IpfsBackend};
+use crate::infrastructure::s3;
+use crate::utils::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use std::sync::Arc;
+
+/// State shared by all request handlers
+///
+/// Cloning is cheap: the configuration is reference counted and the clients
+/// share their underlying connection pools.
+#[derive(Clone)]
+pub struct AppState {
+    /// Application configuration
+    pub config: Arc<Config>,
+    /// Amazon S3 client
+    pub s3: aws_sdk_s3::Client,
+    /// The S3 bucket uploads are stored in
+    pub bucket: s3::Bucket,
+    /// IPFS node client, and the cluster client if one is configured
+    pub ipfs: IpfsBackend,
+    /// Registry of in-flight uploads and their temporary files
+    pub tracker: UploadTracker,
+    /// Queue of replicas missed by a backend, copied in the background
+    pub replication: ReplicationQueue,
+    /// Circuit breakers of the storage backends
+    pub breakers: Breakers,
+    /// Background upload jobs
+    pub jobs: JobQueue,
+    /// Webhook notifications of upload lifecycle events
+    pub webhooks: Webhooks,
+    /// Queue of CIDs to pin with the remote pinning service
+    pub pinning: RemotePins,
+    /// Records of IPNS names and other metadata
+    pub metadata: MetadataStore,
+    /// Stops the background workers on shutdown
+    pub shutdown: Shutdown,
+}
+
+impl AppState {
+    /// Build the application state from the configuration
+    ///
+    /// Loads the AWS configuration and credentials chain once, creates the
+    /// storage clients, loads the replication queue, job store, webhook
+    /// outbox and metadata store, and starts the background replication, job, remote pinning and
+    /// MFS snapshot workers and the webhook dispatcher, all stopped by
+    /// [`AppState::shutdown`].
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Validated application configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IPFS or IPFS Cluster API URL or credentials
+    /// are invalid, the replication queue or webhook outbox cannot be read,
+    /// or an HTTP client cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use memenow_storage_service::config::Config;
+    /// use memenow_storage_service::state::AppState;
+    ///
+    /// # async fn example() -> anyhow::Result<()> {
+    /// let state = AppState::new(Config::default()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn new(config: Config) -> Result<Self> {
+        let s3 = s3::create_s3_client(&config.s3).await;
+        let ipfs =
+            ipfs::create_ipfs_client(&config.ipfs).context("Failed to create IPFS client")?;
+        let replication = ReplicationQueue::open(&config.replication)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to load replication queue from {}",
+                    config.replication.dir
+                )
+            })?;
+        let breakers = Breakers::new(&config.circuit_breaker);
+        let (jobs, pending) = JobQueue::open(&config.jobs).await;
+        let webhooks = Webhooks::open(&config.webhooks).await?;
+        let (pinning, pins) = RemotePins::new(&config.pinning)?;
+        let metadata = MetadataStore::open(&config.metadata).await;
+
+        let state = Self {
+            bucket: s3::Bucket::new(&config.s3),
+            config: Arc::new(config),
+            s3,
+            ipfs,
+            tracker: UploadTracker::new(),
+            replication,
+            breakers,
+            jobs,
+            webhooks,
+            pinning,
+            metadata,
+            shutdown: Shutdown::new(),
+        };
+
+        replication::spawn_worker(state.clone());
+        jobs::spawn_workers(state.clone(), pending);
+        webhooks::spawn_dispatcher(state.webhooks.clone(), &state.shutdown);
+        pinning::spawn_worker(state.pinning.clone(), pins, &state.shutdown);
+        mfs::spawn_snapshots(state.clone());
+
+        Ok(state)
+    }
+}
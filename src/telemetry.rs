@@ -0,0 +1,271 @@
+//! Logging and distributed tracing for the MemeNow Storage Service
+//!
+//! This module installs the global `tracing` subscriber. Log lines are written
+//! to stderr as text or JSON, and `log` records from the application and its
+//! dependencies are routed through the same subscriber. Every line carries the
+//! fields of its enclosing spans, so lines emitted while handling a request
+//! include that request's `request_id`.
+//!
+//! The spans and metrics emitted by the upload pipeline can additionally be
+//! exported to OpenTelemetry, either to an OTLP collector (HTTP/protobuf) or
+//! to stdout for local debugging.
+//!
+//! # Context Propagation
+//!
+//! Incoming requests carrying a W3C `traceparent` header are joined to the
+//! caller's trace, so a slow upload can be attributed end to end.
+//!
+//! # Examples
+//!
+//! Export spans to a local collector:
+//!
+//! ```bash
+//! OTEL_TRACES_EXPORTER=otlp OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4318 cargo run
+//! ```
+
+use crate::config::{LogFormat, TelemetryConfig, TraceExporter};
+use crate::error::{StorageError, StorageResult};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, Context};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+use warp::http::HeaderMap;
+
+/// Name of the instrumentation scope used for spans and metrics
+pub const INSTRUMENTATION_SCOPE: &str = "memenow-storage-service";
+
+/// Providers installed by [`init`]
+///
+/// Must be passed to [`shutdown`] so that buffered spans and metrics are
+/// flushed before the process exits. Both are `None` when export is disabled.
+#[derive(Debug, Default)]
+pub struct Providers {
+    tracer: Option<SdkTracerProvider>,
+    meter: Option<SdkMeterProvider>,
+}
+
+/// Initialize logging and the tracing pipeline
+///
+/// Installs the W3C trace context propagator and the global `tracing`
+/// subscriber. The log level is controlled by `RUST_LOG` (default: info).
+/// Unless the exporter is disabled, spans are also forwarded to the
+/// configured OpenTelemetry exporter, and a global meter provider exporting
+/// to the same destination is installed.
+///
+/// # Arguments
+///
+/// * `config` - Telemetry configuration
+///
+/// # Returns
+///
+/// Returns the installed providers, which must be passed to [`shutdown`].
+///
+/// # Errors
+///
+/// Returns a `StorageError::ConfigError` if an exporter cannot be built or
+/// a global subscriber is already installed.
+pub fn init(config: &TelemetryConfig) -> StorageResult<Providers> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = build_tracer_provider(config)?;
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(INSTRUMENTATION_SCOPE))
+    });
+
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(fmt_layer)
+        .with(otel_layer)
+        .try_init()
+        .map_err(|e| {
+            StorageError::ConfigError(format!("Failed to install tracing subscriber: {}", e))
+        })?;
+
+    let meter = build_meter_provider(config)?;
+    if let Some(meter) = &meter {
+        global::set_meter_provider(meter.clone());
+    }
+
+    Ok(Providers {
+        tracer: provider,
+        meter,
+    })
+}
+
+/// Build the tracer provider for the configured span exporter
+///
+/// # Returns
+///
+/// Returns `None` when span export is disabled.
+fn build_tracer_provider(config: &TelemetryConfig) -> StorageResult<Option<SdkTracerProvider>> {
+    let builder = SdkTracerProvider::builder().with_resource(
+        Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .build(),
+    );
+
+    let provider = match config.exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Stdout => builder
+            .with_simple_exporter(opentelemetry_stdout::SpanExporter::default())
+            .build(),
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(otlp_signal_endpoint(&config.otlp_endpoint, "traces"))
+                .build()
+                .map_err(|e| {
+                    StorageError::ConfigError(format!("Failed to build OTLP exporter: {}", e))
+                })?;
+            builder.with_batch_exporter(exporter).build()
+        }
+    };
+
+    Ok(Some(provider))
+}
+
+/// Build the meter provider for the configured exporter
+///
+/// # Returns
+///
+/// Returns `None` when export is disabled.
+fn build_meter_provider(config: &TelemetryConfig) -> StorageResult<Option<SdkMeterProvider>> {
+    let builder = SdkMeterProvider::builder().with_resource(
+        Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .build(),
+    );
+
+    let provider = match config.exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Stdout => builder
+            .with_periodic_exporter(opentelemetry_stdout::MetricExporter::default())
+            .build(),
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::MetricExporter::builder()
+                .with_http()
+                .with_endpoint(otlp_signal_endpoint(&config.otlp_endpoint, "metrics"))
+                .build()
+                .map_err(|e| {
+                    StorageError::ConfigError(format!(
+                        "Failed to build OTLP metric exporter: {}",
+                        e
+                    ))
+                })?;
+            builder.with_periodic_exporter(exporter).build()
+        }
+    };
+
+    Ok(Some(provider))
+}
+
+/// Flush and shut down the providers
+///
+/// # Arguments
+///
+/// * `providers` - Providers returned by [`init`]
+pub fn shutdown(providers: Providers) {
+    if let Some(provider) = providers.tracer {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("Failed to flush pending spans: {}", e);
+        }
+    }
+
+    if let Some(provider) = providers.meter {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("Failed to flush pending metrics: {}", e);
+        }
+    }
+}
+
+/// Extract the remote trace context from request headers
+///
+/// # Arguments
+///
+/// * `headers` - HTTP headers of the incoming request
+///
+/// # Returns
+///
+/// Returns the parent context described by the `traceparent`/`tracestate`
+/// headers, or an empty context if the request is not part of a trace.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Build the OTLP/HTTP URL of a signal from a collector base endpoint
+///
+/// Follows the `OTEL_EXPORTER_OTLP_ENDPOINT` convention of appending the
+/// signal path (`traces`, `metrics`) to the base URL.
+fn otlp_signal_endpoint(endpoint: &str, signal: &str) -> String {
+    format!("{}/v1/{}", endpoint.trim_end_matches('/'), signal)
+}
+
+/// Adapter exposing warp's header map to the OpenTelemetry propagator
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+
+    #[test]
+    fn test_otlp_signal_endpoint() {
+        assert_eq!(
+            otlp_signal_endpoint("http://localhost:4318", "traces"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            otlp_signal_endpoint("http://collector:4318/", "metrics"),
+            "http://collector:4318/v1/metrics"
+        );
+    }
+
+    #[test]
+    fn test_extract_context_from_traceparent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let cx = extract_context(&headers);
+        let span_context = cx.span().span_context().clone();
+        assert!(span_context.is_remote());
+        assert_eq!(
+            span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+    }
+
+    #[test]
+    fn test_extract_context_without_headers() {
+        let cx = extract_context(&HeaderMap::new());
+        assert!(!cx.span().span_context().is_valid());
+    }
+}
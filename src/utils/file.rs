@@ -65,6 +65,7 @@ use uuid::Uuid;
 /// # Ok(())
 /// # }
 /// ```
+#[allow(dead_code)]
 pub fn create_dir_if_not_exists(dir: &str) -> io::Result<()> {
     let path = Path::new(dir);
     if !path.exists() {
@@ -95,6 +96,7 @@ pub fn create_dir_if_not_exists(dir: &str) -> io::Result<()> {
 /// let temp_path = generate_temp_filepath("jpg");
 /// assert!(temp_path.to_string_lossy().ends_with(".jpg"));
 /// ```
+#[allow(dead_code)]
 pub fn generate_temp_filepath(extension: &str) -> PathBuf {
     let temp_dir = env::temp_dir();
     let filename = format!("{}.{}", Uuid::new_v4(), extension);
@@ -132,6 +134,7 @@ pub fn generate_temp_filepath(extension: &str) -> PathBuf {
 /// # Ok(())
 /// # }
 /// ```
+#[allow(dead_code)]
 pub fn delete_file(filepath: &str) -> io::Result<()> {
     fs::remove_file(filepath)
 }
@@ -173,6 +176,7 @@ pub fn delete_file(filepath: &str) -> io::Result<()> {
 ///
 /// This function loads the entire file into memory. For large files,
 /// consider using streaming approaches instead.
+#[allow(dead_code)]
 pub fn read_file_to_string(filepath: &str) -> io::Result<String> {
     let path = Path::new(filepath);
     let mut file = File::open(path)?;
@@ -218,17 +222,57 @@ pub fn read_file_to_string(filepath: &str) -> io::Result<String> {
 ///
 /// This function will overwrite existing files without warning.
 /// Ensure you want to replace the file before calling this function.
+#[allow(dead_code)]
 pub fn write_string_to_file(filepath: &str, contents: &str) -> io::Result<()> {
     let path = Path::new(filepath);
     let mut file = File::create(path)?;
     file.write_all(contents.as_bytes())
 }
 
+/// Durably replace the contents of a file
+///
+/// The data is written to a temporary file next to `path`, synced to disk and
+/// renamed over `path`, so readers see either the old or the new contents
+/// even if the process crashes midway. The temporary file has a `.tmp`
+/// extension and may be left behind by a crash.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file to replace
+/// * `data` - New contents
+///
+/// # Errors
+///
+/// Returns an error if the temporary file cannot be written or renamed
+pub async fn write_file_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, data).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
 
+    #[tokio::test]
+    async fn test_write_file_atomic() -> io::Result<()> {
+        let path = generate_temp_filepath("json");
+
+        write_file_atomic(&path, b"first").await?;
+        write_file_atomic(&path, b"second").await?;
+
+        assert_eq!(fs::read_to_string(&path)?, "second");
+        assert!(!path.with_extension("json.tmp").exists());
+
+        fs::remove_file(path)
+    }
+
     #[test]
     fn test_generate_temp_filepath() {
         let path = generate_temp_filepath("txt");
@@ -6,5 +6,9 @@
 //! # Submodules
 //!
 //! - `file`: File system utility functions for reading, writing, and managing files
+//! - `shutdown`: Signal handling for graceful shutdown
+//! - `urls`: Percent-encoding of URL paths and checks of base URLs
 
 pub mod file;
+pub mod shutdown;
+pub mod urls;
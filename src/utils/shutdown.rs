@@ -0,0 +1,144 @@
+//! Shutdown Signal Handling
+//!
+//! This module provides a future that resolves when the process is asked to
+//! stop, so the HTTP server can shut down gracefully instead of being killed
+//! mid-request, and the handle telling the background workers to stop.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use memenow_storage_service::utils::shutdown::shutdown_signal;
+//!
+//! # async fn example() {
+//! shutdown_signal().await;
+//! println!("Shutting down");
+//! # }
+//! ```
+
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Handle telling the background workers to stop
+///
+/// Every worker subscribes when it is spawned and drops its receiver once it
+/// has stopped, so [`Shutdown::shutdown`] can wait for all of them. Cloning
+/// the handle is cheap; all clones stop the same workers.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    stopping: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    /// Create a handle with no workers
+    pub fn new() -> Self {
+        Self {
+            stopping: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Subscribe a worker
+    ///
+    /// The receiver holds `true` once the workers are told to stop. The
+    /// worker must drop it, and every clone of it, when it stops.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.stopping.subscribe()
+    }
+
+    /// Tell the workers to stop without waiting for them
+    pub fn stop(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// Tell the workers to stop and wait until all of them have stopped
+    pub async fn shutdown(&self) {
+        self.stop();
+        self.stopping.closed().await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wait for a shutdown signal
+///
+/// Resolves on the first SIGINT (Ctrl+C) or, on Unix, SIGTERM as sent by
+/// container orchestrators during a deploy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use memenow_storage_service::utils::shutdown::shutdown_signal;
+///
+/// # async fn example() {
+/// tokio::spawn(async {
+///     shutdown_signal().await;
+///     println!("Stop accepting new connections");
+/// });
+/// # }
+/// ```
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_shutdown_without_workers() {
+        let shutdown = Shutdown::new();
+        shutdown.shutdown().await;
+        assert!(*shutdown.subscribe().borrow());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_workers() {
+        let shutdown = Shutdown::new();
+        let mut stopping = shutdown.subscribe();
+        let finished = Arc::new(AtomicBool::new(false));
+        let worker = finished.clone();
+        tokio::spawn(async move {
+            stopping.changed().await.unwrap();
+            // Finish the current work before stopping
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            worker.store(true, Ordering::SeqCst);
+        });
+
+        shutdown.shutdown().await;
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
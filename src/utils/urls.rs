@@ -0,0 +1,59 @@
+//! URL helpers
+//!
+//! Object keys and filenames may contain spaces, `#`, `?` and non-ASCII
+//! characters, so they are percent-encoded before they are placed in the
+//! path of a URL.
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters escaped in a URL path: everything but RFC 3986 unreserved
+/// characters and the `/` separating segments
+const PATH_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Percent-encode a path, keeping its `/` separators
+///
+/// # Examples
+///
+/// ```
+/// use memenow_storage_service::utils::urls::encode_path;
+///
+/// assert_eq!(encode_path("uploads/my photo#1.jpg"), "uploads/my%20photo%231.jpg");
+/// ```
+pub fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, PATH_ESCAPES).to_string()
+}
+
+/// Check that a URL is an http(s) URL with a host and no query or fragment,
+/// so a path can be appended to it
+pub fn is_base_url(url: &str) -> bool {
+    url.strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .is_some_and(|rest| {
+            !rest.is_empty() && !rest.starts_with('/') && !rest.contains(['?', '#'])
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_path("a/b_c-d.e~f"), "a/b_c-d.e~f");
+        assert_eq!(encode_path("café?.png"), "caf%C3%A9%3F.png");
+    }
+
+    #[test]
+    fn test_is_base_url() {
+        assert!(is_base_url("https://cdn.example.com"));
+        assert!(is_base_url("http://localhost:9000/prefix/"));
+        assert!(!is_base_url("cdn.example.com"));
+        assert!(!is_base_url("https://"));
+        assert!(!is_base_url("https://cdn.example.com/?v=1"));
+    }
+}